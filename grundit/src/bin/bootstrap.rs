@@ -1,6 +1,44 @@
 fn main() {
     let connection = sqlite::open("test.db").unwrap();
     let query = "
+        DROP TABLE IF EXISTS roles;
+
+        DROP TABLE IF EXISTS api_tokens;
+
+        DROP TABLE IF EXISTS password_resets;
+
+        DROP TABLE IF EXISTS credentials;
+
+        DROP TABLE IF EXISTS identities;
+
+        DROP TABLE IF EXISTS punch_locations;
+
+        DROP TABLE IF EXISTS punches;
+
+        DROP TABLE IF EXISTS geofences;
+
+        DROP TABLE IF EXISTS jobs;
+
+        DROP TABLE IF EXISTS idempotency_keys;
+
+        DROP TABLE IF EXISTS webhook_deliveries;
+
+        DROP TABLE IF EXISTS webhooks;
+
+        DROP TABLE IF EXISTS changes;
+
+        DROP TABLE IF EXISTS notifications;
+
+        DROP TABLE IF EXISTS thumbnails;
+
+        DROP TABLE IF EXISTS attachments;
+
+        DROP VIEW IF EXISTS tag_counts;
+
+        DROP TABLE IF EXISTS note_tags;
+
+        DROP TABLE IF EXISTS tags;
+
         DROP TABLE IF EXISTS comments;
 
         DROP TABLE IF EXISTS notes;
@@ -18,21 +56,265 @@ fn main() {
             id integer primary key autoincrement,
             owner_id integer,
             contents text,
+            version integer not null default 1,
             foreign key(owner_id) references users(id));
 
         CREATE TABLE comments (
             id integer primary key autoincrement,
             owner_id integer,
             note_id integer,
+            parent_comment_id integer,
             contents text,
             foreign key(owner_id) references users(id),
+            foreign key(note_id) references notes(id),
+            foreign key(parent_comment_id) references comments(id));
+
+        CREATE INDEX comments_parent ON comments(parent_comment_id);
+
+        CREATE TABLE tags (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            name text not null,
+            unique(owner_id, name),
+            foreign key(owner_id) references users(id));
+
+        CREATE TABLE note_tags (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            note_id integer not null,
+            tag_id integer not null,
+            unique(note_id, tag_id),
+            foreign key(owner_id) references users(id),
+            foreign key(note_id) references notes(id),
+            foreign key(tag_id) references tags(id));
+
+        CREATE INDEX note_tags_tag ON note_tags(tag_id);
+
+        CREATE TRIGGER note_tags_tag_delete AFTER DELETE ON tags BEGIN
+            DELETE FROM note_tags where tag_id = old.id;
+        END;
+
+        CREATE TRIGGER note_tags_note_delete AFTER DELETE ON notes BEGIN
+            DELETE FROM note_tags where note_id = old.id;
+        END;
+
+        CREATE VIEW tag_counts AS
+            SELECT tags.id, tags.owner_id, tags.name, count(note_tags.id) as count
+            FROM tags LEFT JOIN note_tags ON note_tags.tag_id = tags.id
+            GROUP BY tags.id;
+
+        CREATE TABLE attachments (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            note_id integer not null,
+            name text not null,
+            content_type text not null,
+            size integer not null,
+            digest text not null,
+            created_at integer not null,
+            thumbnails text not null default 'unsupported',
+            foreign key(owner_id) references users(id),
             foreign key(note_id) references notes(id));
 
+        CREATE INDEX attachments_note ON attachments(note_id);
+
+        CREATE INDEX attachments_digest ON attachments(digest);
+
+        CREATE TABLE thumbnails (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            attachment_id integer not null,
+            size text not null,
+            width integer not null,
+            height integer not null,
+            digest text not null,
+            unique(attachment_id, size),
+            foreign key(owner_id) references users(id),
+            foreign key(attachment_id) references attachments(id));
+
+        CREATE INDEX thumbnails_digest ON thumbnails(digest);
+
+        CREATE TABLE notifications (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            kind text not null,
+            actor_id integer not null,
+            note_id integer not null,
+            comment_id integer not null,
+            read integer not null default 0,
+            created_at integer not null,
+            foreign key(owner_id) references users(id),
+            foreign key(actor_id) references users(id),
+            foreign key(note_id) references notes(id),
+            foreign key(comment_id) references comments(id));
+
+        CREATE INDEX notifications_owner_read ON notifications(owner_id, read);
+
+        CREATE TABLE changes (
+            seq integer primary key autoincrement,
+            table_name text not null,
+            row_id integer not null,
+            kind text not null);
+
+        CREATE INDEX changes_table ON changes(table_name, seq);
+
+        CREATE TABLE webhooks (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            url text not null,
+            data_types text not null default '',
+            operations text not null default '',
+            secret text not null,
+            active integer not null default 1,
+            last_seq integer not null default 0,
+            created_at integer not null,
+            foreign key(owner_id) references users(id));
+
+        CREATE TABLE webhook_deliveries (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            webhook_id integer not null,
+            seq integer not null,
+            event text not null,
+            payload text not null,
+            status text not null,
+            attempts integer not null default 0,
+            next_attempt_at integer not null,
+            response_status integer not null default 0,
+            error text not null default '',
+            created_at integer not null,
+            delivered_at integer not null default 0,
+            foreign key(owner_id) references users(id),
+            foreign key(webhook_id) references webhooks(id));
+
+        CREATE INDEX webhook_deliveries_due ON webhook_deliveries(status, next_attempt_at);
+
+        CREATE INDEX webhook_deliveries_webhook ON webhook_deliveries(webhook_id);
+
+        CREATE TRIGGER webhook_deliveries_webhook_delete AFTER DELETE ON webhooks BEGIN
+            DELETE FROM webhook_deliveries where webhook_id = old.id;
+        END;
+
+        CREATE TABLE jobs (
+            id integer primary key autoincrement,
+            kind text not null,
+            payload text not null,
+            status text not null,
+            attempts integer not null default 0,
+            max_attempts integer not null,
+            run_at integer not null,
+            schedule text not null default '',
+            last_error text not null default '',
+            created_at integer not null,
+            updated_at integer not null);
+
+        CREATE INDEX jobs_due ON jobs(status, run_at);
+
+        CREATE TABLE idempotency_keys (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            key text not null,
+            request text not null,
+            status integer not null,
+            body text not null,
+            created_at integer not null,
+            unique(owner_id, key),
+            foreign key(owner_id) references users(id));
+
+        CREATE TRIGGER notifications_comment_delete AFTER DELETE ON comments BEGIN
+            DELETE FROM notifications where comment_id = old.id;
+        END;
+
+        CREATE TRIGGER notifications_note_delete AFTER DELETE ON notes BEGIN
+            DELETE FROM notifications where note_id = old.id;
+        END;
+
+        CREATE TABLE geofences (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            name text not null,
+            shape text not null,
+            latitude real,
+            longitude real,
+            radius real,
+            polygon text,
+            user_ids text not null default '',
+            foreign key(owner_id) references users(id));
+
         CREATE TABLE punches (
             id integer primary key autoincrement,
             owner_id integer,
-            geo text,
+            direction text not null,
+            latitude real not null,
+            longitude real not null,
+            accuracy real,
+            altitude real,
+            captured_at integer not null,
+            received_at integer not null,
+            geofence_id integer,
+            flagged integer not null default 0,
+            foreign key(owner_id) references users(id),
+            foreign key(geofence_id) references geofences(id));
+
+        CREATE VIRTUAL TABLE punch_locations USING rtree(
+            id, min_lat, max_lat, min_lon, max_lon);
+
+        CREATE TRIGGER punch_locations_insert AFTER INSERT ON punches BEGIN
+            INSERT INTO punch_locations VALUES (
+                new.id, new.latitude, new.latitude, new.longitude, new.longitude);
+        END;
+
+        CREATE TRIGGER punch_locations_update AFTER UPDATE OF latitude, longitude ON punches BEGIN
+            UPDATE punch_locations SET
+                min_lat = new.latitude, max_lat = new.latitude,
+                min_lon = new.longitude, max_lon = new.longitude
+                where id = new.id;
+        END;
+
+        CREATE TRIGGER punch_locations_delete AFTER DELETE ON punches BEGIN
+            DELETE FROM punch_locations where id = old.id;
+        END;
+
+        CREATE TABLE identities (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            guid text not null unique,
+            provider text not null,
+            email text,
             foreign key(owner_id) references users(id));
+
+        CREATE TABLE credentials (
+            id integer primary key autoincrement,
+            owner_id integer not null unique,
+            username text not null unique,
+            password_hash text not null,
+            foreign key(owner_id) references users(id));
+
+        CREATE TABLE password_resets (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            code_hash text not null,
+            expires_at integer not null,
+            used integer not null default 0,
+            foreign key(owner_id) references users(id));
+
+        CREATE TABLE api_tokens (
+            id integer primary key autoincrement,
+            owner_id integer not null,
+            name text not null,
+            token_hash text not null unique,
+            scopes text not null default '',
+            expires_at integer,
+            created_at integer not null,
+            revoked integer not null default 0,
+            foreign key(owner_id) references users(id));
+
+        CREATE TABLE roles (
+            id integer primary key autoincrement,
+            user_id integer not null,
+            role text not null,
+            unique(user_id, role),
+            foreign key(user_id) references users(id));
     ";
     connection.execute(query).unwrap();
 }
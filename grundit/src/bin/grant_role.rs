@@ -0,0 +1,33 @@
+// Grant a role to a user, e.g. `admin`.
+//
+//   grant_role <user_id> <role>
+//
+// Roles are read when a session starts, so the user has to sign in again.
+use std::{env, process};
+
+use lib_glonk::store::{SqliteStore, Store};
+use lib_grundit::types::{RequestRole, Role};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let (user_id, role) = match (args.get(1).map(|id| id.parse::<i64>()), args.get(2)) {
+        (Some(Ok(user_id)), Some(role)) => (user_id, role.clone()),
+        _ => {
+            eprintln!("usage: grant_role <user_id> <role>");
+            process::exit(1);
+        }
+    };
+    let store = SqliteStore::new();
+    let request = RequestRole {
+        id: None,
+        user_id: Some(user_id),
+        role: Some(role.clone()),
+    };
+    match store.create::<_, Role>(request) {
+        Ok(role) => println!("{:?}", role),
+        Err(e) => {
+            eprintln!("could not grant {} to {}: {:?}", role, user_id, e);
+            process::exit(1);
+        }
+    }
+}
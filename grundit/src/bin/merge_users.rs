@@ -0,0 +1,235 @@
+// Fold a duplicate account into the one being kept.
+//
+//   merge_users <keep_id> <duplicate_id>
+//
+// Everything the duplicate owns is reassigned to the kept user before the
+// duplicate is deleted, so nothing is left pointing at a user that's gone.
+// Tags with a name the kept user already has are folded into theirs, a
+// password only moves if the kept user has none, roles are added to the
+// kept user's, and pending reset codes and idempotency keys are dropped.
+//
+// Tables are checked for user columns first, and one this doesn't know how
+// to move stops the merge before anything changes. Stop the server while it
+// runs, sessions it holds for the duplicate aren't touched.
+use std::{env, process};
+
+use sqlite::{Connection, State};
+
+// (table, column) moved straight to the kept user
+const MOVED: [(&str, &str); 13] = [
+    ("notes", "owner_id"),
+    ("comments", "owner_id"),
+    ("punches", "owner_id"),
+    ("identities", "owner_id"),
+    ("note_tags", "owner_id"),
+    ("attachments", "owner_id"),
+    ("thumbnails", "owner_id"),
+    ("notifications", "owner_id"),
+    ("notifications", "actor_id"),
+    ("webhooks", "owner_id"),
+    ("webhook_deliveries", "owner_id"),
+    ("geofences", "owner_id"),
+    ("api_tokens", "owner_id"),
+];
+
+// handled on their own below
+const SPECIAL: [(&str, &str); 7] = [
+    ("punches_geo", "owner_id"),
+    ("tags", "owner_id"),
+    ("credentials", "owner_id"),
+    ("roles", "user_id"),
+    ("geofences", "user_ids"),
+    ("password_resets", "owner_id"),
+    ("idempotency_keys", "owner_id"),
+];
+
+// columns that hold a user id
+const USER_COLUMNS: [&str; 4] = ["owner_id", "user_id", "actor_id", "user_ids"];
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let (keep_id, duplicate_id) = match (args.get(1), args.get(2)) {
+        (Some(keep), Some(duplicate)) => match (keep.parse::<i64>(), duplicate.parse::<i64>()) {
+            (Ok(keep), Ok(duplicate)) if keep != duplicate => (keep, duplicate),
+            _ => {
+                eprintln!("user ids must be two different integers");
+                process::exit(1);
+            }
+        },
+        _ => {
+            eprintln!("usage: merge_users <keep_id> <duplicate_id>");
+            process::exit(1);
+        }
+    };
+
+    let connection = sqlite::open("test.db").unwrap();
+    for id in [keep_id, duplicate_id] {
+        let mut statement = connection
+            .prepare("SELECT id FROM users where id = ?")
+            .unwrap();
+        statement.bind((1, id)).unwrap();
+        if let Ok(State::Done) = statement.next() {
+            eprintln!("no user with id {}", id);
+            process::exit(1);
+        }
+    }
+    let unknown = unknown_user_columns(&connection);
+    if !unknown.is_empty() {
+        for (table, column) in unknown {
+            eprintln!("don't know how to merge {}.{}", table, column);
+        }
+        process::exit(1);
+    }
+
+    let ids = Ids {
+        keep: keep_id,
+        duplicate: duplicate_id,
+    };
+    connection.execute("BEGIN").unwrap();
+    // the duplicate's tags onto the kept user's of the same name first
+    ids.run(
+        &connection,
+        "UPDATE note_tags SET tag_id = (
+            SELECT kept.id FROM tags kept, tags dup
+            where dup.id = note_tags.tag_id and kept.owner_id = :keep and kept.name = dup.name)
+        where tag_id in (
+            SELECT dup.id FROM tags dup JOIN tags kept
+            ON kept.name = dup.name and kept.owner_id = :keep
+            where dup.owner_id = :dup)",
+    );
+    let folded = ids.run(
+        &connection,
+        "DELETE FROM tags where owner_id = :dup
+        and name in (SELECT name FROM tags where owner_id = :keep)",
+    );
+    println!("tags: folded {} rows", folded);
+    ids.moved(&connection, "tags", "owner_id");
+    for (table, column) in MOVED {
+        ids.moved(&connection, table, column);
+    }
+    // only there if migrate_punches left rows it couldn't parse
+    if table_exists(&connection, "punches_geo") {
+        ids.moved(&connection, "punches_geo", "owner_id");
+    }
+    // one password per user, the kept user's stays
+    ids.run(
+        &connection,
+        "DELETE FROM credentials where owner_id = :dup
+        and exists (SELECT 1 FROM credentials where owner_id = :keep)",
+    );
+    ids.moved(&connection, "credentials", "owner_id");
+    ids.run(
+        &connection,
+        "UPDATE OR IGNORE roles SET user_id = :keep where user_id = :dup",
+    );
+    ids.run(&connection, "DELETE FROM roles where user_id = :dup");
+    for table in ["password_resets", "idempotency_keys"] {
+        let dropped = ids.run(
+            &connection,
+            &format!("DELETE FROM {} where owner_id = :dup", table),
+        );
+        println!("{}: dropped {} rows", table, dropped);
+    }
+    merge_geofence_users(&connection, &ids);
+    ids.run(&connection, "DELETE FROM users where id = :dup");
+    connection.execute("COMMIT").unwrap();
+    println!("merged user {} into {}", duplicate_id, keep_id);
+}
+
+struct Ids {
+    keep: i64,
+    duplicate: i64,
+}
+
+impl Ids {
+    // runs `query` with `:keep` and `:dup` bound, returns the rows changed
+    fn run(&self, connection: &Connection, query: &str) -> usize {
+        let mut statement = connection.prepare(query).unwrap();
+        for (name, id) in [(":keep", self.keep), (":dup", self.duplicate)] {
+            if statement.parameter_index(name).unwrap().is_some() {
+                statement.bind((name, id)).unwrap();
+            }
+        }
+        statement.next().unwrap();
+        connection.change_count()
+    }
+
+    fn moved(&self, connection: &Connection, table: &str, column: &str) {
+        let query = format!(
+            "UPDATE {} SET {} = :keep where {} = :dup",
+            table, column, column
+        );
+        let moved = self.run(connection, &query);
+        println!("{}.{}: moved {} rows", table, column, moved);
+    }
+}
+
+fn table_exists(connection: &Connection, table: &str) -> bool {
+    let mut statement = connection
+        .prepare("SELECT name FROM sqlite_master where type = 'table' and name = ?")
+        .unwrap();
+    statement.bind((1, table)).unwrap();
+    matches!(statement.next(), Ok(State::Row))
+}
+
+// user id columns in tables this doesn't know about
+fn unknown_user_columns(connection: &Connection) -> Vec<(String, String)> {
+    let mut tables = connection
+        .prepare("SELECT name FROM sqlite_master where type = 'table'")
+        .unwrap();
+    let mut unknown = vec![];
+    while let Ok(State::Row) = tables.next() {
+        let table = tables.read::<String, _>("name").unwrap();
+        let mut columns = connection
+            .prepare(format!("PRAGMA table_info({})", table))
+            .unwrap();
+        while let Ok(State::Row) = columns.next() {
+            let column = columns.read::<String, _>("name").unwrap();
+            let known = MOVED
+                .iter()
+                .chain(SPECIAL.iter())
+                .any(|(t, c)| *t == table && *c == column);
+            if USER_COLUMNS.contains(&column.as_str()) && !known {
+                unknown.push((table.clone(), column));
+            }
+        }
+    }
+    unknown
+}
+
+// geofences list the users they apply to
+fn merge_geofence_users(connection: &Connection, ids: &Ids) {
+    let mut statement = connection
+        .prepare("SELECT id, user_ids FROM geofences where user_ids != ''")
+        .unwrap();
+    let mut fences = vec![];
+    while let Ok(State::Row) = statement.next() {
+        fences.push((
+            statement.read::<i64, _>("id").unwrap(),
+            statement.read::<String, _>("user_ids").unwrap(),
+        ));
+    }
+    drop(statement);
+    for (id, user_ids) in fences {
+        let mut merged: Vec<String> = vec![];
+        for user_id in user_ids.split(',').map(str::trim) {
+            let user_id = match user_id.parse::<i64>() {
+                Ok(user_id) if user_id == ids.duplicate => ids.keep.to_string(),
+                _ => user_id.to_string(),
+            };
+            if !merged.contains(&user_id) {
+                merged.push(user_id);
+            }
+        }
+        let merged = merged.join(",");
+        if merged != user_ids {
+            let mut statement = connection
+                .prepare("UPDATE geofences SET user_ids = ? where id = ?")
+                .unwrap();
+            statement.bind((1, merged.as_str())).unwrap();
+            statement.bind((2, id)).unwrap();
+            statement.next().unwrap();
+            println!("geofences.user_ids: moved geofence {}", id);
+        }
+    }
+}
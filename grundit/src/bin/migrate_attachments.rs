@@ -0,0 +1,70 @@
+// Add note attachments to an existing database, safe to run more than once.
+//
+//   migrate_attachments
+//
+// Only the metadata lives in the database, the files themselves go under
+// BLOB_DIR (default `blobs`) once they're uploaded.
+//
+// Images attached before there were thumbnails are marked pending, and get
+// theirs the next time the server starts.
+use sqlite::State;
+
+fn main() {
+    let connection = sqlite::open("test.db").unwrap();
+    connection
+        .execute(
+            "
+            CREATE TABLE IF NOT EXISTS attachments (
+                id integer primary key autoincrement,
+                owner_id integer not null,
+                note_id integer not null,
+                name text not null,
+                content_type text not null,
+                size integer not null,
+                digest text not null,
+                created_at integer not null,
+                thumbnails text not null default 'unsupported',
+                foreign key(owner_id) references users(id),
+                foreign key(note_id) references notes(id));
+
+            CREATE INDEX IF NOT EXISTS attachments_note ON attachments(note_id);
+
+            CREATE INDEX IF NOT EXISTS attachments_digest ON attachments(digest);
+
+            CREATE TABLE IF NOT EXISTS thumbnails (
+                id integer primary key autoincrement,
+                owner_id integer not null,
+                attachment_id integer not null,
+                size text not null,
+                width integer not null,
+                height integer not null,
+                digest text not null,
+                unique(attachment_id, size),
+                foreign key(owner_id) references users(id),
+                foreign key(attachment_id) references attachments(id));
+
+            CREATE INDEX IF NOT EXISTS thumbnails_digest ON thumbnails(digest);
+            ",
+        )
+        .unwrap();
+
+    let mut statement = connection
+        .prepare("SELECT name FROM pragma_table_info('attachments') where name = 'thumbnails'")
+        .unwrap();
+    if let Ok(State::Done) = statement.next() {
+        drop(statement);
+        connection
+            .execute(
+                "
+                ALTER TABLE attachments
+                    ADD COLUMN thumbnails text not null default 'unsupported';
+
+                UPDATE attachments SET thumbnails = 'pending'
+                    where content_type in ('image/jpeg', 'image/png');
+                ",
+            )
+            .unwrap();
+        println!("attachments: added thumbnails");
+    }
+    println!("attachments: ready");
+}
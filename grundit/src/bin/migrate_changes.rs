@@ -0,0 +1,22 @@
+// Add the change log behind `/data/{type}/events` to an existing database,
+// safe to run more than once. Streams can only resume from changes made
+// after this.
+//
+//   migrate_changes
+fn main() {
+    let connection = sqlite::open("test.db").unwrap();
+    connection
+        .execute(
+            "
+            CREATE TABLE IF NOT EXISTS changes (
+                seq integer primary key autoincrement,
+                table_name text not null,
+                row_id integer not null,
+                kind text not null);
+
+            CREATE INDEX IF NOT EXISTS changes_table ON changes(table_name, seq);
+            ",
+        )
+        .unwrap();
+    println!("changes: ready");
+}
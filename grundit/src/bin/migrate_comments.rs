@@ -0,0 +1,29 @@
+// Bring an existing comments table up to date for threads, safe to run more
+// than once.
+//
+//   migrate_comments
+//
+// Existing comments become top level ones.
+use sqlite::State;
+
+fn main() {
+    let connection = sqlite::open("test.db").unwrap();
+
+    let mut statement = connection
+        .prepare("SELECT name FROM pragma_table_info('comments') where name = 'parent_comment_id'")
+        .unwrap();
+    if let Ok(State::Done) = statement.next() {
+        drop(statement);
+        connection
+            .execute(
+                "ALTER TABLE comments ADD COLUMN parent_comment_id integer references comments(id)",
+            )
+            .unwrap();
+        println!("comments: added parent_comment_id");
+    }
+
+    connection
+        .execute("CREATE INDEX IF NOT EXISTS comments_parent ON comments(parent_comment_id)")
+        .unwrap();
+    println!("comments_parent: indexed");
+}
@@ -0,0 +1,24 @@
+// Add the responses kept for `Idempotency-Key` creates to an existing
+// database, safe to run more than once.
+//
+//   migrate_idempotency_keys
+fn main() {
+    let connection = sqlite::open("test.db").unwrap();
+    connection
+        .execute(
+            "
+            CREATE TABLE IF NOT EXISTS idempotency_keys (
+                id integer primary key autoincrement,
+                owner_id integer not null,
+                key text not null,
+                request text not null,
+                status integer not null,
+                body text not null,
+                created_at integer not null,
+                unique(owner_id, key),
+                foreign key(owner_id) references users(id));
+            ",
+        )
+        .unwrap();
+    println!("idempotency_keys: ready");
+}
@@ -0,0 +1,39 @@
+// Add the job queue to an existing database, safe to run more than once.
+// Images still waiting for thumbnails are queued for them, as they used to
+// be picked up on start.
+//
+//   migrate_jobs
+fn main() {
+    let connection = sqlite::open("test.db").unwrap();
+    connection
+        .execute(
+            "
+            CREATE TABLE IF NOT EXISTS jobs (
+                id integer primary key autoincrement,
+                kind text not null,
+                payload text not null,
+                status text not null,
+                attempts integer not null default 0,
+                max_attempts integer not null,
+                run_at integer not null,
+                schedule text not null default '',
+                last_error text not null default '',
+                created_at integer not null,
+                updated_at integer not null);
+
+            CREATE INDEX IF NOT EXISTS jobs_due ON jobs(status, run_at);
+
+            INSERT INTO jobs (kind, payload, status, max_attempts, run_at, created_at, updated_at)
+                SELECT 'make_thumbnails', '{\"attachment_id\":' || id || '}', 'queued', 5,
+                    unixepoch(), unixepoch(), unixepoch()
+                FROM attachments
+                WHERE thumbnails = 'pending'
+                    AND NOT EXISTS (
+                        SELECT 1 FROM jobs
+                        WHERE kind = 'make_thumbnails'
+                            AND payload = '{\"attachment_id\":' || attachments.id || '}');
+            ",
+        )
+        .unwrap();
+    println!("jobs: ready");
+}
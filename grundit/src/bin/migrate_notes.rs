@@ -0,0 +1,24 @@
+// Give an existing notes table versions, safe to run more than once.
+//
+//   migrate_notes
+//
+// Existing notes start at version 1. Their plain text contents are read as
+// CommonMark from now on, which leaves most of them looking the same.
+use sqlite::State;
+
+fn main() {
+    let connection = sqlite::open("test.db").unwrap();
+
+    let mut statement = connection
+        .prepare("SELECT name FROM pragma_table_info('notes') where name = 'version'")
+        .unwrap();
+    if let Ok(State::Done) = statement.next() {
+        drop(statement);
+        connection
+            .execute("ALTER TABLE notes ADD COLUMN version integer not null default 1")
+            .unwrap();
+        println!("notes: added version");
+    } else {
+        println!("notes already have versions");
+    }
+}
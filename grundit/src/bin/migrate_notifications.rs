@@ -0,0 +1,37 @@
+// Add comment notifications to an existing database, safe to run more than
+// once. Comments made before this aren't notified about.
+//
+//   migrate_notifications
+fn main() {
+    let connection = sqlite::open("test.db").unwrap();
+    connection
+        .execute(
+            "
+            CREATE TABLE IF NOT EXISTS notifications (
+                id integer primary key autoincrement,
+                owner_id integer not null,
+                kind text not null,
+                actor_id integer not null,
+                note_id integer not null,
+                comment_id integer not null,
+                read integer not null default 0,
+                created_at integer not null,
+                foreign key(owner_id) references users(id),
+                foreign key(actor_id) references users(id),
+                foreign key(note_id) references notes(id),
+                foreign key(comment_id) references comments(id));
+
+            CREATE INDEX IF NOT EXISTS notifications_owner_read ON notifications(owner_id, read);
+
+            CREATE TRIGGER IF NOT EXISTS notifications_comment_delete AFTER DELETE ON comments BEGIN
+                DELETE FROM notifications where comment_id = old.id;
+            END;
+
+            CREATE TRIGGER IF NOT EXISTS notifications_note_delete AFTER DELETE ON notes BEGIN
+                DELETE FROM notifications where note_id = old.id;
+            END;
+            ",
+        )
+        .unwrap();
+    println!("notifications: ready");
+}
@@ -0,0 +1,187 @@
+// Bring an existing punches table up to date, safe to run more than once.
+//
+//   migrate_punches
+//
+// Punches first move from the free-form `geo` column to typed columns. Old
+// rows look like `timestamp: 1700000000000, lat: 51.5 lon: -0.12`, with the
+// timestamp in milliseconds. Rows that can't be parsed are listed and left
+// in the old table, kept as `punches_geo` for a look by hand, which is
+// dropped when every row moved.
+//
+// Punches from before there was a direction are taken to be clock ins, and
+// ones from before geofences are left unclassified.
+//
+// Then builds the `punch_locations` index behind the geographic queries.
+use sqlite::{Connection, State};
+
+const GEOFENCES: &str = "
+    CREATE TABLE IF NOT EXISTS geofences (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        name text not null,
+        shape text not null,
+        latitude real,
+        longitude real,
+        radius real,
+        polygon text,
+        user_ids text not null default '',
+        foreign key(owner_id) references users(id));
+";
+
+const LOCATION_INDEX: &str = "
+    CREATE VIRTUAL TABLE IF NOT EXISTS punch_locations USING rtree(
+        id, min_lat, max_lat, min_lon, max_lon);
+
+    CREATE TRIGGER IF NOT EXISTS punch_locations_insert AFTER INSERT ON punches BEGIN
+        INSERT INTO punch_locations VALUES (
+            new.id, new.latitude, new.latitude, new.longitude, new.longitude);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS punch_locations_update
+    AFTER UPDATE OF latitude, longitude ON punches BEGIN
+        UPDATE punch_locations SET
+            min_lat = new.latitude, max_lat = new.latitude,
+            min_lon = new.longitude, max_lon = new.longitude
+            where id = new.id;
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS punch_locations_delete AFTER DELETE ON punches BEGIN
+        DELETE FROM punch_locations where id = old.id;
+    END;
+
+    INSERT OR REPLACE INTO punch_locations
+        SELECT id, latitude, latitude, longitude, longitude FROM punches;
+";
+
+// pulls the number following `key`, e.g. `lat:`
+fn field(geo: &str, key: &str) -> Option<f64> {
+    let (_, rest) = geo.split_once(key)?;
+    rest.split_whitespace()
+        .next()?
+        .trim_end_matches(',')
+        .parse()
+        .ok()
+        .filter(|val: &f64| val.is_finite())
+}
+
+// (latitude, longitude, captured_at)
+fn parse(geo: &str) -> Option<(f64, f64, i64)> {
+    let latitude = field(geo, "lat:").filter(|lat| (-90.0..=90.0).contains(lat))?;
+    let longitude = field(geo, "lon:").filter(|lon| (-180.0..=180.0).contains(lon))?;
+    let captured_at = (field(geo, "timestamp:")? / 1000.0) as i64;
+    Some((latitude, longitude, captured_at))
+}
+
+fn main() {
+    let connection = sqlite::open("test.db").unwrap();
+
+    let mut statement = connection
+        .prepare("SELECT name FROM pragma_table_info('punches') where name = 'geo'")
+        .unwrap();
+    if let Ok(State::Row) = statement.next() {
+        drop(statement);
+        migrate_geo(&connection);
+    } else {
+        println!("punches has no geo column, skipping to the new columns");
+    }
+
+    connection.execute(GEOFENCES).unwrap();
+    for (column, definition) in [
+        ("direction", "text not null default 'in'"),
+        ("geofence_id", "integer references geofences(id)"),
+        ("flagged", "integer not null default 0"),
+    ] {
+        add_column(&connection, column, definition);
+    }
+
+    connection.execute(LOCATION_INDEX).unwrap();
+    println!("punch_locations: indexed");
+}
+
+fn add_column(connection: &Connection, column: &str, definition: &str) {
+    let mut statement = connection
+        .prepare("SELECT name FROM pragma_table_info('punches') where name = ?")
+        .unwrap();
+    statement.bind((1, column)).unwrap();
+    if let Ok(State::Done) = statement.next() {
+        drop(statement);
+        connection
+            .execute(format!(
+                "ALTER TABLE punches ADD COLUMN {} {}",
+                column, definition
+            ))
+            .unwrap();
+        println!("punches: added {}", column);
+    }
+}
+
+fn migrate_geo(connection: &Connection) {
+    connection.execute("BEGIN").unwrap();
+    connection
+        .execute(
+            "
+            ALTER TABLE punches RENAME TO punches_geo;
+
+            CREATE TABLE punches (
+                id integer primary key autoincrement,
+                owner_id integer,
+                direction text not null default 'in',
+                latitude real not null,
+                longitude real not null,
+                accuracy real,
+                altitude real,
+                captured_at integer not null,
+                received_at integer not null,
+                foreign key(owner_id) references users(id));
+            ",
+        )
+        .unwrap();
+
+    let mut rows = connection
+        .prepare("SELECT id, owner_id, geo FROM punches_geo")
+        .unwrap();
+    let (mut moved, mut skipped) = (0, vec![]);
+    while let Ok(State::Row) = rows.next() {
+        let id = rows.read::<i64, _>("id").unwrap();
+        let owner_id = rows.read::<Option<i64>, _>("owner_id").unwrap();
+        let geo = rows
+            .read::<Option<String>, _>("geo")
+            .unwrap()
+            .unwrap_or_default();
+        let (latitude, longitude, captured_at) = match parse(&geo) {
+            Some(parsed) => parsed,
+            None => {
+                skipped.push(id);
+                continue;
+            }
+        };
+        // the receive time was never recorded, the capture time is the best guess
+        let mut statement = connection
+            .prepare(
+                "INSERT INTO punches (id, owner_id, latitude, longitude, captured_at, received_at)
+                 VALUES (?, ?, ?, ?, ?, ?)",
+            )
+            .unwrap();
+        statement.bind((1, id)).unwrap();
+        statement.bind((2, owner_id)).unwrap();
+        statement.bind((3, latitude)).unwrap();
+        statement.bind((4, longitude)).unwrap();
+        statement.bind((5, captured_at)).unwrap();
+        statement.bind((6, captured_at)).unwrap();
+        statement.next().unwrap();
+        moved += 1;
+    }
+    drop(rows);
+    if skipped.is_empty() {
+        connection.execute("DROP TABLE punches_geo").unwrap();
+    }
+    connection.execute("COMMIT").unwrap();
+
+    println!("punches: moved {} rows", moved);
+    if !skipped.is_empty() {
+        println!(
+            "could not parse punches {:?}, they are still in punches_geo",
+            skipped
+        );
+    }
+}
@@ -0,0 +1,44 @@
+// Add note tags to an existing database, safe to run more than once.
+//
+//   migrate_tags
+fn main() {
+    let connection = sqlite::open("test.db").unwrap();
+    connection
+        .execute(
+            "
+            CREATE TABLE IF NOT EXISTS tags (
+                id integer primary key autoincrement,
+                owner_id integer not null,
+                name text not null,
+                unique(owner_id, name),
+                foreign key(owner_id) references users(id));
+
+            CREATE TABLE IF NOT EXISTS note_tags (
+                id integer primary key autoincrement,
+                owner_id integer not null,
+                note_id integer not null,
+                tag_id integer not null,
+                unique(note_id, tag_id),
+                foreign key(owner_id) references users(id),
+                foreign key(note_id) references notes(id),
+                foreign key(tag_id) references tags(id));
+
+            CREATE INDEX IF NOT EXISTS note_tags_tag ON note_tags(tag_id);
+
+            CREATE TRIGGER IF NOT EXISTS note_tags_tag_delete AFTER DELETE ON tags BEGIN
+                DELETE FROM note_tags where tag_id = old.id;
+            END;
+
+            CREATE TRIGGER IF NOT EXISTS note_tags_note_delete AFTER DELETE ON notes BEGIN
+                DELETE FROM note_tags where note_id = old.id;
+            END;
+
+            CREATE VIEW IF NOT EXISTS tag_counts AS
+                SELECT tags.id, tags.owner_id, tags.name, count(note_tags.id) as count
+                FROM tags LEFT JOIN note_tags ON note_tags.tag_id = tags.id
+                GROUP BY tags.id;
+            ",
+        )
+        .unwrap();
+    println!("tags: ready");
+}
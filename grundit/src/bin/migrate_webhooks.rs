@@ -0,0 +1,50 @@
+// Add webhooks and their delivery log to an existing database, safe to run
+// more than once. Needs the change log from `migrate_changes`.
+//
+//   migrate_webhooks
+fn main() {
+    let connection = sqlite::open("test.db").unwrap();
+    connection
+        .execute(
+            "
+            CREATE TABLE IF NOT EXISTS webhooks (
+                id integer primary key autoincrement,
+                owner_id integer not null,
+                url text not null,
+                data_types text not null default '',
+                operations text not null default '',
+                secret text not null,
+                active integer not null default 1,
+                last_seq integer not null default 0,
+                created_at integer not null,
+                foreign key(owner_id) references users(id));
+
+            CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                id integer primary key autoincrement,
+                owner_id integer not null,
+                webhook_id integer not null,
+                seq integer not null,
+                event text not null,
+                payload text not null,
+                status text not null,
+                attempts integer not null default 0,
+                next_attempt_at integer not null,
+                response_status integer not null default 0,
+                error text not null default '',
+                created_at integer not null,
+                delivered_at integer not null default 0,
+                foreign key(owner_id) references users(id),
+                foreign key(webhook_id) references webhooks(id));
+
+            CREATE INDEX IF NOT EXISTS webhook_deliveries_due ON webhook_deliveries(status, next_attempt_at);
+
+            CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook ON webhook_deliveries(webhook_id);
+
+            CREATE TRIGGER IF NOT EXISTS webhook_deliveries_webhook_delete AFTER DELETE ON webhooks BEGIN
+                DELETE FROM webhook_deliveries where webhook_id = old.id;
+            END;
+            ",
+        )
+        .unwrap();
+    println!("webhooks: ready");
+}
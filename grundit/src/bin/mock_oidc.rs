@@ -0,0 +1,97 @@
+// Minimal OpenID Connect provider for exercising the login flow locally.
+//
+// Run it, then start grundit with
+//   OIDC_ISSUER_URL=http://localhost:8090 OIDC_CLIENT_ID=mock OIDC_CLIENT_SECRET=mock
+// and visit /auth/oidc/login. Every authorization request is approved immediately
+// for the user described by the MOCK_OIDC_* env vars.
+//
+// MOCK_OIDC_FAIL=deny|token|userinfo makes that step of the flow fail.
+use axum::{
+    Json, Router,
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+};
+use serde_json::json;
+use std::{collections::HashMap, env};
+use tokio::net::TcpListener;
+
+fn issuer() -> String {
+    env::var("MOCK_OIDC_ISSUER").unwrap_or_else(|_| format!("http://localhost:{}", port()))
+}
+
+fn fails(step: &str) -> bool {
+    env::var("MOCK_OIDC_FAIL").is_ok_and(|fail| fail == step)
+}
+
+fn port() -> String {
+    env::var("MOCK_OIDC_PORT").unwrap_or_else(|_| "8090".to_string())
+}
+
+async fn discovery() -> impl IntoResponse {
+    let issuer = issuer();
+    Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "userinfo_endpoint": format!("{}/userinfo", issuer),
+    }))
+}
+
+async fn authorize(Query(params): Query<HashMap<String, String>>) -> impl IntoResponse {
+    let redirect_uri = params.get("redirect_uri").cloned().unwrap_or_default();
+    let state = params.get("state").cloned().unwrap_or_default();
+    if fails("deny") {
+        return Redirect::temporary(&format!(
+            "{}?error=access_denied&state={}",
+            redirect_uri, state
+        ));
+    }
+    Redirect::temporary(&format!("{}?code=mock-code&state={}", redirect_uri, state))
+}
+
+async fn token() -> Response {
+    if fails("token") {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": "invalid_grant" })),
+        )
+            .into_response();
+    }
+    Json(json!({
+        "access_token": "mock-access-token",
+        "token_type": "bearer",
+        "expires_in": 3600,
+    }))
+    .into_response()
+}
+
+async fn userinfo() -> Response {
+    if fails("userinfo") {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "userinfo unavailable").into_response();
+    }
+    Json(json!({
+        "sub": env::var("MOCK_OIDC_SUB").unwrap_or_else(|_| "mock-user".to_string()),
+        "email": env::var("MOCK_OIDC_EMAIL").unwrap_or_else(|_| "mock@example.com".to_string()),
+        "email_verified": env::var("MOCK_OIDC_EMAIL_VERIFIED").map(|v| v != "false").unwrap_or(true),
+        "name": env::var("MOCK_OIDC_NAME").unwrap_or_else(|_| "Mock User".to_string()),
+        "picture": "",
+    }))
+    .into_response()
+}
+
+#[tokio::main]
+async fn main() {
+    let app = Router::new()
+        .route("/.well-known/openid-configuration", get(discovery))
+        .route("/authorize", get(authorize))
+        .route("/token", post(token))
+        .route("/userinfo", get(userinfo));
+
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port()))
+        .await
+        .expect("Failed to bind address");
+    println!("mock oidc issuer at {}", issuer());
+    axum::serve(listener, app).await.unwrap();
+}
@@ -0,0 +1,27 @@
+// Issue a one time password reset code for a local account.
+//
+//   reset_code <username>
+//
+// The code is valid for an hour and is redeemed at /auth/local/reset.
+use std::{env, process};
+
+use lib_glonk::store::SqliteStore;
+use lib_grundit::auth::local_auth::issue_reset_code;
+
+fn main() {
+    let username = match env::args().nth(1) {
+        Some(username) => username,
+        None => {
+            eprintln!("usage: reset_code <username>");
+            process::exit(1);
+        }
+    };
+    let store = SqliteStore::new();
+    match issue_reset_code(&store, &username) {
+        Ok(code) => println!("{}", code),
+        Err(e) => {
+            eprintln!("could not issue a reset code for {}: {}", username, e);
+            process::exit(1);
+        }
+    }
+}
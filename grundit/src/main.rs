@@ -3,15 +3,11 @@ use tokio::net::TcpListener;
 use tracing_subscriber::prelude::*;
 
 use lib_glonk::store::SqliteStore;
-use lib_grundit::{AuthrState, auth::google_auth::GoogleAuthClient, run};
+use lib_grundit::{AuthrState, auth::providers_from_env, run};
 use tracing::info;
 
 #[tokio::main]
 async fn main() {
-    let client = GoogleAuthClient::from_env();
-    let store = SqliteStore::new();
-    let state = AuthrState::new(client, store);
-
     if env::var("RUST_LOG").is_err() {
         panic!("RUST_LOG not set!");
     }
@@ -22,6 +18,10 @@ async fn main() {
         .init();
     info!("{:?}", env::var("RUST_LOG"));
 
+    let providers = providers_from_env().await;
+    let store = SqliteStore::new();
+    let state = AuthrState::new(providers, store);
+
     let address = "0.0.0.0:8080".to_string();
     let listener = TcpListener::bind(address)
         .await
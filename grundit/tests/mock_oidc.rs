@@ -0,0 +1,203 @@
+// Signing in through the mock_oidc binary, the whole way from the login
+// redirect to a session.
+use std::{
+    env,
+    net::TcpListener,
+    process::{self, Child, Command},
+    sync::Arc,
+    time::Duration,
+};
+
+use lib_glonk::store::SqliteStore;
+use lib_grundit::{AuthrState, auth::oidc_auth::OidcAuthClient, run};
+use oauth2::{
+    reqwest::{Client, Response, StatusCode, header, redirect::Policy},
+    url::Url,
+};
+use serde_json::Value;
+
+// just the tables signing in and whoami touch, and those the job runner and
+// webhook dispatcher poll
+const SCHEMA: &str = "
+    CREATE TABLE users (
+        id integer primary key autoincrement,
+        guid text not null,
+        name text,
+        email text,
+        picture text);
+
+    CREATE TABLE changes (
+        seq integer primary key autoincrement,
+        table_name text not null,
+        row_id integer not null,
+        kind text not null);
+
+    CREATE TABLE webhooks (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        url text not null,
+        data_types text not null default '',
+        operations text not null default '',
+        secret text not null,
+        active integer not null default 1,
+        last_seq integer not null default 0,
+        created_at integer not null);
+
+    CREATE TABLE webhook_deliveries (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        webhook_id integer not null,
+        seq integer not null,
+        event text not null,
+        payload text not null,
+        status text not null,
+        attempts integer not null default 0,
+        next_attempt_at integer not null,
+        response_status integer not null default 0,
+        error text not null default '',
+        created_at integer not null,
+        delivered_at integer not null default 0);
+
+    CREATE TABLE jobs (
+        id integer primary key autoincrement,
+        kind text not null,
+        payload text not null,
+        status text not null,
+        attempts integer not null default 0,
+        max_attempts integer not null,
+        run_at integer not null,
+        schedule text not null default '',
+        last_error text not null default '',
+        created_at integer not null,
+        updated_at integer not null);
+
+    CREATE TABLE identities (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        guid text not null unique,
+        provider text not null,
+        email text);
+
+    CREATE TABLE roles (
+        id integer primary key autoincrement,
+        user_id integer not null,
+        role text not null);
+";
+
+// returns the base url of grundit on a random port, backed by a scratch database
+async fn start(provider: OidcAuthClient) -> String {
+    let path = env::temp_dir().join(format!("grundit-mock-oidc-{}.db", process::id()));
+    let _ = std::fs::remove_file(&path);
+    sqlite::open(&path).unwrap().execute(SCHEMA).unwrap();
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let state = AuthrState::new(vec![Arc::new(provider)], SqliteStore::open(&path));
+    tokio::spawn(run(listener, state));
+    base
+}
+
+fn client() -> Client {
+    Client::builder().redirect(Policy::none()).build().unwrap()
+}
+
+// killed with the test
+struct Mock(Child);
+
+impl Drop for Mock {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+// the mock running, and a provider discovered from it
+async fn start_mock() -> (Mock, OidcAuthClient) {
+    let port = free_port();
+    let issuer = format!("http://127.0.0.1:{}", port);
+    let mock = Mock(
+        Command::new(env!("CARGO_BIN_EXE_mock_oidc"))
+            .env("MOCK_OIDC_PORT", port.to_string())
+            .env("MOCK_OIDC_ISSUER", &issuer)
+            .env("MOCK_OIDC_SUB", "mock-subject")
+            .env("MOCK_OIDC_EMAIL", "mock@example.com")
+            .env("MOCK_OIDC_NAME", "Mock User")
+            .spawn()
+            .unwrap(),
+    );
+    for _ in 0..50 {
+        let discovered = OidcAuthClient::discover(
+            "oidc".to_string(),
+            &issuer,
+            "mock".to_string(),
+            "mock".to_string(),
+        )
+        .await;
+        if let Ok(provider) = discovered {
+            return (mock, provider);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("mock_oidc never came up on {}", issuer);
+}
+
+async fn fetch(url: &str) -> Response {
+    client().get(url).send().await.unwrap()
+}
+
+fn location(res: &Response) -> Url {
+    assert!(res.status().is_redirection(), "{}", res.status());
+    Url::parse(res.headers()[header::LOCATION].to_str().unwrap()).unwrap()
+}
+
+// name=value of each cookie `res` sets
+fn cookies(res: &Response) -> Vec<String> {
+    res.headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|c| c.to_str().unwrap().split(';').next().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn login_through_mock_provider() {
+    let (_mock, provider) = start_mock().await;
+    let base = start(provider).await;
+
+    // to the provider, which approves straight away and sends us back
+    let res = fetch(&format!("{}/auth/oidc/login", base)).await;
+    let nonce = cookies(&res).join("; ");
+    let authorize = location(&res);
+    let callback = location(&fetch(authorize.as_str()).await);
+    assert_eq!(callback.path(), "/auth/oidc/callback");
+    let query = callback.query().unwrap();
+
+    let res = client()
+        .get(format!("{}/auth/oidc/callback?{}", base, query))
+        .header(header::COOKIE, nonce)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    let cookies = cookies(&res);
+    assert!(cookies.iter().any(|c| c.starts_with("session_id=")));
+
+    let res = client()
+        .get(format!("{}/data/whoami", base))
+        .header(header::COOKIE, cookies.join("; "))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let user: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(user["name"], "Mock User");
+    assert_eq!(user["email"], "mock@example.com");
+}
@@ -1,2 +1,3 @@
 pub mod store;
 pub mod types;
+pub mod validation;
@@ -5,10 +5,65 @@ pub use sqlitestore::SqliteStore;
 
 use crate::types::{DataObject, Query, RequestObject};
 
+// a row written through a store, handed to its `on_change` listeners
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub table: String,
+    pub id: i64,
+    pub kind: ChangeKind,
+    // its place in the change log, for stores keeping one
+    pub seq: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Created => "created",
+            ChangeKind::Updated => "updated",
+            ChangeKind::Deleted => "deleted",
+        }
+    }
+}
+
+impl std::str::FromStr for ChangeKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "created" => Ok(ChangeKind::Created),
+            "updated" => Ok(ChangeKind::Updated),
+            "deleted" => Ok(ChangeKind::Deleted),
+            _ => Err(()),
+        }
+    }
+}
+
+pub type ChangeListener = Box<dyn Fn(&Change) + Send + Sync>;
+
 pub trait Store {
     fn create<R: RequestObject, T: DataObject>(&self, data: R) -> StoreResult<T>;
+    // creates every row or, if any fails, none of them
+    fn create_all<R: RequestObject, T: DataObject>(&self, data: Vec<R>) -> StoreResult<Vec<T>>;
     fn update<R: RequestObject, T: DataObject>(&self, data: R) -> StoreResult<T>;
+    // updates only while `field` still holds `expected`, so a row changed
+    // since it was read is left alone and an error
+    fn update_if<R: RequestObject, T: DataObject>(
+        &self,
+        data: R,
+        field: &str,
+        expected: sqlite::Value,
+    ) -> StoreResult<T>;
     fn get<T: DataObject>(&self, id: i64) -> Option<T>;
     fn get_queries<T: DataObject>(&self, queries: Vec<Box<dyn Query>>) -> Vec<T>;
     fn delete<T: DataObject>(&self, id: i64, owner_id: Option<i64>) -> StoreResult<T>;
+    // called after every successful create, update and delete, outside any
+    // lock, so listeners may use the store
+    fn on_change(&self, listener: ChangeListener);
 }
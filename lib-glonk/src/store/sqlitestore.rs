@@ -1,26 +1,105 @@
-use sqlite::{Connection, Value};
+use sqlite::{Connection, State, Value};
 use std::sync::Mutex;
-use tracing::debug;
+use tracing::{debug, error};
 
 use crate::types::{DataObject, Query, RequestObject};
 
-use super::{Store, error::StoreResult};
+use super::{Change, ChangeKind, ChangeListener, Store, error::StoreResult};
 
 pub struct SqliteStore {
     conn: Mutex<Connection>,
+    listeners: Mutex<Vec<ChangeListener>>,
+    // table numbering every change, see `with_change_log`
+    change_log: Option<String>,
 }
 
 impl SqliteStore {
     pub fn new() -> Self {
-        let connection = sqlite::open("test.db").unwrap();
+        Self::open("test.db")
+    }
+
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Self {
+        let connection = sqlite::open(path).unwrap();
         Self {
             conn: Mutex::new(connection),
+            listeners: Mutex::new(vec![]),
+            change_log: None,
         }
     }
-}
 
-impl Store for SqliteStore {
-    fn create<R: RequestObject, T: DataObject>(&self, data: R) -> StoreResult<T> {
+    // Also writes each change to `table`, which needs the columns
+    // `seq integer primary key autoincrement, table_name, row_id, kind`.
+    // The seq it gets is on the `Change` handed to listeners.
+    pub fn with_change_log(mut self, table: &str) -> Self {
+        self.change_log = Some(table.to_string());
+        self
+    }
+
+    // Drops change log entries up to and including `seq`, returning how many
+    // went. Nothing is logged or told of it, they aren't changes to data.
+    pub fn prune_change_log(&self, seq: i64) -> StoreResult<usize> {
+        let Some(table) = self.change_log.as_ref() else {
+            return Ok(0);
+        };
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(_) => return Err(super::error::StoreError::NotFound),
+        };
+        let query = format!("DELETE FROM {} where seq <= ?", table);
+        let pruned = conn.prepare(query).and_then(|mut statement| {
+            statement.bind((1, seq))?;
+            statement.next()?;
+            Ok(conn.change_count())
+        });
+        pruned.map_err(|e| {
+            error!("change log: {:?}", e);
+            super::error::StoreError::NotFound
+        })
+    }
+
+    // called with the connection still held, and inside the write's
+    // transaction, so entries go in change order and a change that can't be
+    // logged isn't made
+    fn log<T: DataObject>(
+        &self,
+        conn: &Connection,
+        id: i64,
+        kind: ChangeKind,
+    ) -> StoreResult<Option<i64>> {
+        let Some(table) = self.change_log.as_ref() else {
+            return Ok(None);
+        };
+        let query = format!(
+            "INSERT INTO {}(table_name,row_id,kind) VALUES (?,?,?) returning seq",
+            table
+        );
+        let logged = conn.prepare(query).and_then(|mut statement| {
+            statement.bind::<&[(_, Value)]>(
+                &[
+                    (1, Value::String(T::table_name())),
+                    (2, Value::Integer(id)),
+                    (3, Value::String(kind.as_str().to_string())),
+                ][..],
+            )?;
+            statement.next()?;
+            statement.read::<i64, _>("seq")
+        });
+        match logged {
+            Ok(seq) => Ok(Some(seq)),
+            Err(e) => {
+                error!("change log: {:?}", e);
+                Err(super::error::StoreError::NotCreated)
+            }
+        }
+    }
+
+    // inserts a row with the connection held, returning it, its id and its
+    // change log seq
+    fn insert<R: RequestObject, T: DataObject>(
+        &self,
+        conn: &Connection,
+        data: R,
+    ) -> StoreResult<(T, Option<i64>, Option<i64>)> {
         let query = format!(
             "INSERT INTO {}({}) VALUES ({}) returning {}",
             T::table_name(),
@@ -29,21 +108,46 @@ impl Store for SqliteStore {
             T::sql_cols()
         );
         debug!("{}", query);
-        if let Ok(conn) = self.conn.lock() {
-            let mut statement = conn.prepare(query).unwrap();
-            statement.bind(data).unwrap();
-            let data: Vec<T> = T::from_rows(&mut statement);
-            if data.len() >= 1 {
-                Ok(data[0].clone())
-            } else {
-                Err(super::error::StoreError::NotCreated)
-            }
+        let mut statement = conn.prepare(query).unwrap();
+        statement.bind(data).unwrap();
+        let data: Vec<T> = T::from_rows(&mut statement);
+        drop(statement);
+        // `DataObject` has no id accessor, so ask the connection
+        let mut statement = conn.prepare("SELECT last_insert_rowid() as id").unwrap();
+        let id = match statement.next() {
+            Ok(State::Row) => statement.read::<i64, _>("id").ok(),
+            _ => None,
+        };
+        drop(statement);
+        if data.len() >= 1 {
+            let seq = match id {
+                Some(id) => self.log::<T>(conn, id, ChangeKind::Created)?,
+                None => None,
+            };
+            Ok((data[0].clone(), id, seq))
         } else {
             Err(super::error::StoreError::NotCreated)
         }
     }
 
-    fn update<R: RequestObject, T: DataObject>(&self, data: R) -> StoreResult<T> {
+    fn changed<T: DataObject>(&self, id: i64, kind: ChangeKind, seq: Option<i64>) {
+        let change = Change {
+            table: T::table_name(),
+            id,
+            kind,
+            seq,
+        };
+        if let Ok(listeners) = self.listeners.lock() {
+            listeners.iter().for_each(|listener| listener(&change));
+        }
+    }
+
+    // the row is only updated if `condition`'s field holds its value
+    fn update_where<R: RequestObject, T: DataObject>(
+        &self,
+        data: R,
+        condition: Option<(&str, Value)>,
+    ) -> StoreResult<T> {
         let id = match data.id() {
             Some(id) => id,
             None => {
@@ -58,30 +162,115 @@ impl Store for SqliteStore {
                 return Err(crate::store::error::StoreError::NotCreated);
             }
         };
+        let condition =
+            condition.map(|(field, expected)| (format!(" and {} = :expected", field), expected));
         let query = format!(
-            "UPDATE {} SET ({}) = ({}) where ({} = :id and {} = :owner_id) returning {}",
+            "UPDATE {} SET ({}) = ({}) where ({} = :id and {} = :owner_id{}) returning {}",
             T::table_name(),
             data.sql_cols(),
             data.sql_placeholders(),
             T::id_col(),
             T::owner_id_col(),
+            condition.as_ref().map_or("", |(sql, _)| sql.as_str()),
             T::sql_cols()
         );
         debug!("{:?}", query);
-        if let Ok(conn) = self.conn.lock() {
-            let mut statement = conn.prepare(query).unwrap();
-            statement.bind(data).unwrap();
-            statement.bind((":id", id)).unwrap();
-            statement.bind((":owner_id", owner_id)).unwrap();
-            let data: Vec<T> = T::from_rows(&mut statement);
-            if data.len() >= 1 {
-                Ok(data[0].clone())
-            } else {
-                Err(super::error::StoreError::NotCreated)
-            }
+        let updated = if let Ok(conn) = self.conn.lock() {
+            transaction(&conn, || {
+                let mut statement = conn.prepare(query).unwrap();
+                statement.bind(data).unwrap();
+                statement.bind((":id", id)).unwrap();
+                statement.bind((":owner_id", owner_id)).unwrap();
+                if let Some((_, expected)) = &condition {
+                    statement.bind((":expected", expected)).unwrap();
+                }
+                let data: Vec<T> = T::from_rows(&mut statement);
+                drop(statement);
+                if data.len() >= 1 {
+                    let seq = self.log::<T>(&conn, id, ChangeKind::Updated)?;
+                    Ok((data[0].clone(), seq))
+                } else {
+                    Err(super::error::StoreError::NotCreated)
+                }
+            })
         } else {
             Err(super::error::StoreError::NotCreated)
+        };
+        updated.map(|(data, seq)| {
+            self.changed::<T>(id, ChangeKind::Updated, seq);
+            data
+        })
+    }
+}
+
+// runs `f` in a transaction, rolled back if it fails
+fn transaction<V>(conn: &Connection, f: impl FnOnce() -> StoreResult<V>) -> StoreResult<V> {
+    if let Err(e) = conn.execute("BEGIN") {
+        error!("begin: {:?}", e);
+        return Err(super::error::StoreError::NotCreated);
+    }
+    let done = f().and_then(|val| match conn.execute("COMMIT") {
+        Ok(()) => Ok(val),
+        Err(e) => {
+            error!("commit: {:?}", e);
+            Err(super::error::StoreError::NotCreated)
         }
+    });
+    if done.is_err() {
+        let _ = conn.execute("ROLLBACK");
+    }
+    done
+}
+
+impl Store for SqliteStore {
+    fn create<R: RequestObject, T: DataObject>(&self, data: R) -> StoreResult<T> {
+        let created = if let Ok(conn) = self.conn.lock() {
+            transaction(&conn, || self.insert::<R, T>(&conn, data))
+        } else {
+            Err(super::error::StoreError::NotCreated)
+        };
+        created.map(|(data, id, seq)| {
+            if let Some(id) = id {
+                self.changed::<T>(id, ChangeKind::Created, seq);
+            }
+            data
+        })
+    }
+
+    fn create_all<R: RequestObject, T: DataObject>(&self, data: Vec<R>) -> StoreResult<Vec<T>> {
+        let created = if let Ok(conn) = self.conn.lock() {
+            transaction(&conn, || {
+                data.into_iter()
+                    .map(|data| self.insert::<R, T>(&conn, data))
+                    .collect::<StoreResult<Vec<_>>>()
+            })
+        } else {
+            Err(super::error::StoreError::NotCreated)
+        };
+        created.map(|created| {
+            created
+                .into_iter()
+                .map(|(data, id, seq)| {
+                    if let Some(id) = id {
+                        self.changed::<T>(id, ChangeKind::Created, seq);
+                    }
+                    data
+                })
+                .collect()
+        })
+    }
+
+    fn update<R: RequestObject, T: DataObject>(&self, data: R) -> StoreResult<T> {
+        self.update_where(data, None)
+    }
+
+    fn update_if<R: RequestObject, T: DataObject>(
+        &self,
+        data: R,
+        field: &str,
+        expected: Value,
+    ) -> StoreResult<T> {
+        self.update_where(data, Some((field, expected)))
     }
 
     fn get<T: DataObject>(&self, id: i64) -> Option<T> {
@@ -121,6 +310,16 @@ impl Store for SqliteStore {
             let clauses_str = format!(" where {}", clauses.join(" and "));
             query.push_str(clauses_str.as_str());
         }
+        if let Some((order_by, vals)) = queries.iter().find_map(|q| q.order_by()) {
+            query.push_str(format!(" order by {}", order_by).as_str());
+            vals.into_iter().for_each(|v| {
+                bindables.push((i + 1, v));
+                i += 1;
+            });
+        }
+        if let Some(limit) = queries.iter().filter_map(|q| q.limit()).min() {
+            query.push_str(format!(" limit {}", limit).as_str());
+        }
         debug!("{} {:?}", query, queries);
         if let Ok(conn) = self.conn.lock() {
             let mut statement = conn.prepare(query).unwrap();
@@ -151,19 +350,33 @@ impl Store for SqliteStore {
             clauses,
             T::sql_cols()
         );
-        if let Ok(conn) = self.conn.lock() {
-            let mut statement = conn.prepare(query).unwrap();
-            statement
-                .bind::<&[(_, Value)]>(&params.as_slice()[..])
-                .unwrap();
-            let data: Vec<T> = T::from_rows(&mut statement);
-            if data.len() >= 1 {
-                Ok(data[0].clone())
-            } else {
-                Err(super::error::StoreError::NotFound)
-            }
+        let deleted = if let Ok(conn) = self.conn.lock() {
+            transaction(&conn, || {
+                let mut statement = conn.prepare(query).unwrap();
+                statement
+                    .bind::<&[(_, Value)]>(&params.as_slice()[..])
+                    .unwrap();
+                let data: Vec<T> = T::from_rows(&mut statement);
+                drop(statement);
+                if data.len() >= 1 {
+                    let seq = self.log::<T>(&conn, id, ChangeKind::Deleted)?;
+                    Ok((data[0].clone(), seq))
+                } else {
+                    Err(super::error::StoreError::NotFound)
+                }
+            })
         } else {
             Err(super::error::StoreError::NotFound)
+        };
+        deleted.map(|(data, seq)| {
+            self.changed::<T>(id, ChangeKind::Deleted, seq);
+            data
+        })
+    }
+
+    fn on_change(&self, listener: ChangeListener) {
+        if let Ok(mut listeners) = self.listeners.lock() {
+            listeners.push(listener);
         }
     }
 }
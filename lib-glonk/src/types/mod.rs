@@ -1,6 +1,8 @@
 use sqlite::{Bindable, Statement, Value};
 use std::fmt;
 
+use crate::validation::{Validator, Violation};
+
 // framework
 pub trait DataObject: Sized + Bindable + std::fmt::Debug + Clone {
     fn from_rows(statement: &mut Statement) -> Vec<Self>;
@@ -17,6 +19,13 @@ pub trait RequestObject: Sized + Bindable + std::fmt::Debug + Clone {
     fn sql_placeholders(&self) -> String;
     fn id(&self) -> Option<i64>;
     fn owner_id(&self) -> Option<i64>;
+    // format, length and range rules, run after the presence checks
+    fn field_rules(&self, _v: &mut Validator) {}
+    fn validate_fields(&self) -> Result<(), ValidationError> {
+        let mut v = Validator::new();
+        self.field_rules(&mut v);
+        v.finish()
+    }
 }
 
 // validation
@@ -26,6 +35,7 @@ pub enum ValidationError {
     MissingRequiredOnCreate(String),
     InvalidOwnerId(String),
     IdProvidedOnCreate,
+    Violations(Vec<Violation>),
 }
 
 impl fmt::Display for ValidationError {
@@ -43,6 +53,13 @@ impl fmt::Display for ValidationError {
             ValidationError::IdProvidedOnCreate => {
                 write!(fmt, "id must not be provided for create")
             }
+            ValidationError::Violations(ref violations) => {
+                let fields: Vec<String> = violations
+                    .iter()
+                    .map(|v| format!("`{}` {}", v.field, v.message))
+                    .collect();
+                write!(fmt, "{}", fields.join(", "))
+            }
         }
     }
 }
@@ -54,6 +71,7 @@ impl std::error::Error for ValidationError {
             ValidationError::MissingRequiredOnCreate(_) => "Missing required field error",
             ValidationError::InvalidOwnerId(_) => "Invalid owner_id in request error",
             ValidationError::IdProvidedOnCreate => "Id provided on create error",
+            ValidationError::Violations(_) => "Field validation error",
         }
     }
 
@@ -63,6 +81,7 @@ impl std::error::Error for ValidationError {
             ValidationError::MissingRequiredOnCreate(_) => None,
             ValidationError::InvalidOwnerId(_) => None,
             ValidationError::IdProvidedOnCreate => None,
+            ValidationError::Violations(_) => None,
         }
     }
 }
@@ -72,8 +91,23 @@ pub trait Criteria: Send + Sync + std::fmt::Debug {
     fn build(&self) -> (String, Vec<Value>);
 }
 
+// so criteria can be combined a runtime number of times
+impl Criteria for Box<dyn Criteria> {
+    fn build(&self) -> (String, Vec<Value>) {
+        (**self).build()
+    }
+}
+
 pub trait Query: Send + Sync + std::fmt::Debug {
     fn build(&self) -> (String, Vec<Value>);
+    // an ordering expression and its values, the first query with one wins
+    fn order_by(&self) -> Option<(String, Vec<Value>)> {
+        None
+    }
+    // the smallest limit of all the queries applies
+    fn limit(&self) -> Option<i64> {
+        None
+    }
 }
 
 #[derive(Debug)]
@@ -103,6 +137,30 @@ impl Criteria for EqualsCriteria {
     }
 }
 
+#[derive(Debug)]
+pub struct GreaterOrEqualCriteria {
+    pub field: String,
+    pub val: Value,
+}
+
+impl Criteria for GreaterOrEqualCriteria {
+    fn build(&self) -> (String, Vec<Value>) {
+        (format!("{} >= ?", self.field), vec![self.val.clone()])
+    }
+}
+
+#[derive(Debug)]
+pub struct LessThanCriteria {
+    pub field: String,
+    pub val: Value,
+}
+
+impl Criteria for LessThanCriteria {
+    fn build(&self) -> (String, Vec<Value>) {
+        (format!("{} < ?", self.field), vec![self.val.clone()])
+    }
+}
+
 #[derive(Debug)]
 pub struct AndCriteria<L, R>
 where
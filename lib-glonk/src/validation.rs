@@ -0,0 +1,127 @@
+// Field level validation.
+//
+// Request objects describe their rules in `RequestObject::field_rules`, which
+// feeds a `Validator`. Every rule is checked and all the violations are
+// returned together, rather than stopping at the first one.
+use regex::Regex;
+use serde::Serialize;
+use std::sync::LazyLock;
+
+use crate::types::ValidationError;
+
+static EMAIL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[^@\s]+@[^@\s]+\.[^@\s]+$").unwrap());
+static URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^https?://[^\s/?#]+\.?[^\s/?#]*(?:[/?#]\S*)?$").unwrap());
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    pub field: String,
+    pub rule: String,
+    pub message: String,
+}
+
+// Rules for string fields. Format rules (`Email`, `Url`, `Matches`) skip
+// empty strings, pair them with `NotBlank` when a value is required.
+#[derive(Debug, Clone, Copy)]
+pub enum Rule {
+    NotBlank,
+    MinLen(usize),
+    MaxLen(usize),
+    Email,
+    Url,
+    // a pattern and a description of it for the message
+    Matches(&'static LazyLock<Regex>, &'static str),
+}
+
+impl Rule {
+    fn check(&self, val: &str) -> Option<(&'static str, String)> {
+        let len = val.chars().count();
+        match *self {
+            Rule::NotBlank if val.trim().is_empty() => {
+                Some(("not_blank", "must not be blank".to_string()))
+            }
+            Rule::MinLen(min) if len < min => {
+                Some(("min_len", format!("must be at least {} characters", min)))
+            }
+            Rule::MaxLen(max) if len > max => {
+                Some(("max_len", format!("must be at most {} characters", max)))
+            }
+            Rule::Email if !val.is_empty() && !EMAIL.is_match(val) => {
+                Some(("email", "must be an email address".to_string()))
+            }
+            Rule::Url if !val.is_empty() && !URL.is_match(val) => {
+                Some(("url", "must be an http(s) url".to_string()))
+            }
+            Rule::Matches(re, description) if !val.is_empty() && !re.is_match(val) => {
+                Some(("matches", format!("must be {}", description)))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Validator {
+    violations: Vec<Violation>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // string field, absent values are left to the presence checks
+    pub fn text(&mut self, field: &str, val: Option<&str>, rules: &[Rule]) -> &mut Self {
+        if let Some(val) = val {
+            for rule in rules {
+                if let Some((rule, message)) = rule.check(val) {
+                    self.violation(field, rule, message);
+                }
+            }
+        }
+        self
+    }
+
+    // numeric field, inclusive on both ends
+    pub fn range<T>(&mut self, field: &str, val: Option<T>, min: T, max: T) -> &mut Self
+    where
+        T: PartialOrd + std::fmt::Display + Copy,
+    {
+        if let Some(val) = val {
+            // written so NaN fails too
+            if !(val >= min && val <= max) {
+                self.violation(
+                    field,
+                    "range",
+                    format!("must be between {} and {}", min, max),
+                );
+            }
+        }
+        self
+    }
+
+    // anything else, including rules spanning several fields
+    pub fn check(&mut self, field: &str, rule: &str, valid: bool, message: &str) -> &mut Self {
+        if !valid {
+            self.violation(field, rule, message.to_string());
+        }
+        self
+    }
+
+    fn violation(&mut self, field: &str, rule: &str, message: String) {
+        self.violations.push(Violation {
+            field: field.to_string(),
+            rule: rule.to_string(),
+            message,
+        });
+    }
+
+    pub fn finish(self) -> Result<(), ValidationError> {
+        if self.violations.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError::Violations(self.violations))
+        }
+    }
+}
@@ -1,102 +1,260 @@
 // internal imports
+use crate::attachments;
 use crate::auth;
+pub use crate::auth::AuthenticatedUser;
+pub use crate::auth::IdentityProvider;
+use crate::auth::SweepSessions;
 pub use crate::auth::google_auth::GoogleAuthClient;
+use crate::auth::local_auth::LoginThrottle;
+use crate::auth::provider::EmailPolicy;
+use crate::blob::{BlobStore, LocalBlobStore};
 pub use crate::error::AuthrError;
+use crate::events;
+use crate::feed;
+use crate::gis;
+use crate::images::{self, MakeThumbnails};
+use crate::jobs::{self, Registry};
+use crate::markdown::{self, RenderCache};
+use crate::notifications;
+use crate::ratelimit::{self, RateLimiter};
+use crate::sync::{self, PruneChangeLog, SweepIdempotencyKeys};
+use crate::timesheet;
 pub use crate::types::ExtractGlonkQueries;
-use crate::types::{Comment, Note, Punch, User};
+use crate::types::{
+    Attachment, Comment, Geofence, Identity, Note, Punch, RequestGeofence, User, classify,
+};
+use crate::types::{
+    ChangeRecord, DeliveryByOwnerId, LatestChange, RequestWebhook, Webhook, WebhookByOwnerId,
+    WebhookDelivery,
+};
+use crate::types::{CommentByNoteId, CommentByParentId, CommentOrder, CommentSort, threads};
 pub use crate::types::{DataType, RequestComment, RequestNote, RequestPunch, RequestUser};
+use crate::types::{IdentityByOwnerId, IdentityQuery, QueryTypes};
+use crate::types::{
+    NoteTag, NoteTagByNoteId, NoteTagByOwnerId, NoteTagByTagId, RequestNoteTag, RequestTag, Tag,
+    TagByName, TagByOwnerId, TagCloud, TagCount,
+};
+use crate::types::{Notification, NotificationByOwnerId};
+use crate::webhooks;
 
 // imports
-use axum::extract::FromRequestParts;
-use axum::http::StatusCode;
 use axum::http::header::{LOCATION, SET_COOKIE};
-use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
 use axum::middleware;
 use axum::response::AppendHeaders;
 use axum::{
     Json, Router,
-    extract::{Path, State},
+    extract::{DefaultBodyLimit, Path, Query as UrlQuery, State},
     handler::HandlerWithoutStateExt,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     routing::{delete, get, post, put},
 };
 use axum_extra::extract::CookieJar;
 use axum_extra::extract::cookie::Cookie;
-use lib_glonk::store::{SqliteStore, Store};
-use lib_glonk::types::{DataObject, RequestObject};
-use serde::Serialize;
+use lib_glonk::store::{Change, SqliteStore, Store};
+use lib_glonk::types::{DataObject, Query, RequestObject, ValidationError};
+use lib_glonk::validation::Validator;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
+    env,
+    net::SocketAddr,
     sync::{Arc, Mutex},
 };
 use tokio::net::TcpListener;
 use tower_http::services::ServeDir;
 use tracing::{debug, error, info};
 
+const DEFAULT_MAX_BODY_BYTES: usize = 64 * 1024;
+const MAX_COMMENT_DEPTH: usize = 6;
+
 // state type
 pub struct AuthrState {
     pub auth: Arc<AuthState>,
     pub data: Arc<DataState>,
+    // what the job worker can run, add to it before `run`
+    pub jobs: Registry,
 }
 
 pub struct AuthState {
-    pub(crate) oauth_sessions: Mutex<HashMap<String, String>>,
-    pub(crate) sessions: Mutex<HashMap<String, (User, time::OffsetDateTime)>>,
-    pub(crate) google_client: GoogleAuthClient,
+    pub(crate) oauth_sessions: Mutex<HashMap<String, PendingLogin>>,
+    pub(crate) sessions: Mutex<HashMap<String, Session>>,
+    pub(crate) providers: HashMap<String, Arc<dyn IdentityProvider>>,
+    pub(crate) login_throttle: LoginThrottle,
+    pub(crate) rate_limiter: RateLimiter,
+    pub(crate) email_policy: EmailPolicy,
     pub(crate) store: Arc<SqliteStore>,
 }
 
+// a signed in browser, keyed by the session_id cookie
+pub(crate) struct Session {
+    pub(crate) user: User,
+    // loaded when the session starts
+    pub(crate) roles: Vec<String>,
+    // echoed back by the page on unsafe requests, see auth::csrf
+    pub(crate) csrf_token: String,
+    pub(crate) expires: time::OffsetDateTime,
+}
+
+// login started at a provider, keyed by its csrf state
+pub(crate) struct PendingLogin {
+    pub(crate) provider: String,
+    pub(crate) pkce_verifier: String,
+    // set when an already signed in user is linking another identity
+    pub(crate) link_user: Option<i64>,
+    // validated same origin path to land on once signed in
+    pub(crate) return_to: String,
+    // also in the browser's LOGIN_COOKIE, so only the browser that started
+    // the login can finish it
+    pub(crate) nonce: String,
+    pub(crate) expires: time::OffsetDateTime,
+}
+
 pub struct DataState {
-    store: Arc<SqliteStore>,
+    pub(crate) store: Arc<SqliteStore>,
+    pub(crate) rendered: RenderCache,
+    pub(crate) blobs: Arc<dyn BlobStore>,
+    // taken while attachments and their blobs change together
+    pub(crate) blob_lock: tokio::sync::Mutex<()>,
+    // everything the store writes, for the feed
+    pub(crate) changes: tokio::sync::broadcast::Sender<Change>,
+    pub(crate) jobs: jobs::Queue,
+    // one for each `Idempotency-Key` in use, held while its create is checked
+    // and made
+    pub(crate) idempotency_locks: sync::IdempotencyLocks,
 }
 
 impl AuthrState {
-    pub fn new(google_client: GoogleAuthClient, store: SqliteStore) -> Self {
-        let store = Arc::new(store);
+    pub fn new(providers: Vec<Arc<dyn IdentityProvider>>, store: SqliteStore) -> Self {
+        let store = Arc::new(store.with_change_log("changes"));
+        let changes = feed::channel();
+        {
+            let changes = changes.clone();
+            // no sockets, nobody to tell
+            store.on_change(Box::new(move |change| {
+                let _ = changes.send(change.clone());
+            }));
+        }
+        let providers = providers
+            .into_iter()
+            .map(|p| (p.name().to_string(), p))
+            .collect::<HashMap<String, Arc<dyn IdentityProvider>>>();
+        let mut jobs = Registry::default();
+        jobs.register::<MakeThumbnails>()
+            .recurring("*/10 * * * *", SweepSessions)
+            .recurring("@hourly", SweepIdempotencyKeys)
+            .recurring("@hourly", PruneChangeLog::from_env());
         Self {
             auth: Arc::new(AuthState {
-                oauth_sessions: Mutex::new(HashMap::<String, String>::new()),
-                sessions: Mutex::new(HashMap::<String, (User, time::OffsetDateTime)>::new()),
-                google_client,
+                oauth_sessions: Mutex::new(HashMap::<String, PendingLogin>::new()),
+                sessions: Mutex::new(HashMap::<String, Session>::new()),
+                providers,
+                login_throttle: LoginThrottle::default(),
+                rate_limiter: RateLimiter::from_env(),
+                email_policy: EmailPolicy::from_env(),
                 store: store.clone(),
             }),
-            data: Arc::new(DataState { store }),
+            data: Arc::new(DataState {
+                store,
+                rendered: RenderCache::default(),
+                blobs: Arc::new(LocalBlobStore::from_env()),
+                blob_lock: tokio::sync::Mutex::new(()),
+                changes,
+                jobs: jobs::Queue::default(),
+                idempotency_locks: Default::default(),
+            }),
+            jobs,
         }
     }
+
+    // in place of the limits read from the environment, before `run`
+    pub fn set_rate_limiter(&mut self, rate_limiter: RateLimiter) {
+        Arc::get_mut(&mut self.auth)
+            .expect("rate limiter set after run")
+            .rate_limiter = rate_limiter;
+    }
 }
 
 async fn data_get_queries(
     Path(data_type): Path<DataType>,
+    user: AuthenticatedUser,
     ExtractGlonkQueries(queries): ExtractGlonkQueries,
     State(state): State<Arc<DataState>>,
 ) -> impl IntoResponse {
     debug!("extracted queries {:?}", queries);
+    Json(list(&state, data_type, user.id, queries)).into_response()
+}
+
+// what a user is shown of a type for some queries, the feed reads through
+// this too so both agree
+pub(crate) fn list(
+    state: &DataState,
+    data_type: DataType,
+    user_id: i64,
+    mut queries: Vec<Box<dyn Query>>,
+) -> Vec<serde_json::Value> {
+    fn values<T: Serialize>(data: Vec<T>) -> Vec<serde_json::Value> {
+        data.iter()
+            .filter_map(|data| serde_json::to_value(data).ok())
+            .collect()
+    }
     match data_type {
-        DataType::User => {
-            let data = state.store.clone().get_queries::<User>(queries);
-            Json(data.clone()).into_response()
+        DataType::User => values(state.store.get_queries::<User>(queries)),
+        DataType::Note => values(state.store.get_queries::<Note>(queries)),
+        DataType::Comment => values(state.store.get_queries::<Comment>(queries)),
+        DataType::Punch => values(state.store.get_queries::<Punch>(queries)),
+        // which providers and emails someone signs in with is theirs alone
+        DataType::Identity => {
+            queries.push(Box::new(IdentityByOwnerId::new(user_id)));
+            values(state.store.get_queries::<Identity>(queries))
         }
-        DataType::Note => {
-            let data = state.store.clone().get_queries::<Note>(queries);
-            Json(data.clone()).into_response()
+        DataType::Geofence => values(state.store.get_queries::<Geofence>(queries)),
+        // tags are a private way of filing notes
+        DataType::Tag => {
+            queries.push(Box::new(TagByOwnerId::new(user_id)));
+            values(state.store.get_queries::<Tag>(queries))
         }
-        DataType::Comment => {
-            let data = state.store.clone().get_queries::<Comment>(queries);
-            Json(data.clone()).into_response()
+        DataType::NoteTag => {
+            queries.push(Box::new(NoteTagByOwnerId::new(user_id)));
+            values(state.store.get_queries::<NoteTag>(queries))
         }
-        DataType::Punch => {
-            let data = state.store.clone().get_queries::<Punch>(queries);
-            Json(data.clone()).into_response()
+        DataType::Attachment => values(state.store.get_queries::<Attachment>(queries)),
+        // only ever your own
+        DataType::Notification => {
+            queries.push(Box::new(NotificationByOwnerId::new(user_id)));
+            values(state.store.get_queries::<Notification>(queries))
+        }
+        DataType::Webhook => {
+            queries.push(Box::new(WebhookByOwnerId::new(user_id)));
+            values(state.store.get_queries::<Webhook>(queries))
+        }
+        DataType::WebhookDelivery => {
+            queries.push(Box::new(DeliveryByOwnerId::new(user_id)));
+            values(state.store.get_queries::<WebhookDelivery>(queries))
         }
     }
 }
 
+// whether anyone may be told a row of the type is gone, as `list` can't
+// check a row that isn't there any more
+pub(crate) fn deletes_public(data_type: DataType) -> bool {
+    !matches!(
+        data_type,
+        DataType::Identity
+            | DataType::Tag
+            | DataType::NoteTag
+            | DataType::Notification
+            | DataType::Webhook
+            | DataType::WebhookDelivery
+    )
+}
+
 async fn data_get(
     Path((data_type, id)): Path<(DataType, i64)>,
-    OwnerIdHeader(owner_id): OwnerIdHeader,
+    user: AuthenticatedUser,
     State(state): State<Arc<DataState>>,
 ) -> impl IntoResponse {
-    info!("{:?}", owner_id);
+    info!("{:?}", user);
     match data_type {
         DataType::User => {
             let data: Option<User> = state.store.clone().get(id);
@@ -126,14 +284,71 @@ async fn data_get(
                 None => AuthrError::NotFound.into_response(),
             }
         }
+        DataType::Identity => {
+            let data: Option<Identity> = state.store.clone().get(id);
+            match data {
+                Some(data) if data.owner_id == user.id => Json(data.clone()).into_response(),
+                Some(_) | None => AuthrError::NotFound.into_response(),
+            }
+        }
+        DataType::Geofence => {
+            let data: Option<Geofence> = state.store.clone().get(id);
+            match data {
+                Some(data) => Json(data.clone()).into_response(),
+                None => AuthrError::NotFound.into_response(),
+            }
+        }
+        DataType::Tag => {
+            let data: Option<Tag> = state.store.clone().get(id);
+            match data {
+                Some(data) if data.owner_id == user.id => Json(data.clone()).into_response(),
+                Some(_) | None => AuthrError::NotFound.into_response(),
+            }
+        }
+        DataType::NoteTag => {
+            let data: Option<NoteTag> = state.store.clone().get(id);
+            match data {
+                Some(data) if data.owner_id == user.id => Json(data.clone()).into_response(),
+                Some(_) | None => AuthrError::NotFound.into_response(),
+            }
+        }
+        DataType::Attachment => {
+            let data: Option<Attachment> = state.store.clone().get(id);
+            match data {
+                Some(data) => Json(data.clone()).into_response(),
+                None => AuthrError::NotFound.into_response(),
+            }
+        }
+        DataType::Notification => {
+            let data: Option<Notification> = state.store.clone().get(id);
+            match data {
+                Some(data) if data.owner_id == user.id => Json(data.clone()).into_response(),
+                Some(_) | None => AuthrError::NotFound.into_response(),
+            }
+        }
+        DataType::Webhook => {
+            let data: Option<Webhook> = state.store.clone().get(id);
+            match data {
+                Some(data) if data.owner_id == user.id => Json(data.clone()).into_response(),
+                Some(_) | None => AuthrError::NotFound.into_response(),
+            }
+        }
+        DataType::WebhookDelivery => {
+            let data: Option<WebhookDelivery> = state.store.clone().get(id);
+            match data {
+                Some(data) if data.owner_id == user.id => Json(data.clone()).into_response(),
+                Some(_) | None => AuthrError::NotFound.into_response(),
+            }
+        }
     }
 }
 
 async fn data_delete(
     Path((data_type, id)): Path<(DataType, i64)>,
-    OwnerIdHeader(owner_id): OwnerIdHeader,
+    user: AuthenticatedUser,
     State(state): State<Arc<DataState>>,
 ) -> impl IntoResponse {
+    let owner_id = Some(user.id);
     match data_type {
         DataType::User => {
             let data = state.store.clone().delete::<User>(id, owner_id);
@@ -143,26 +358,85 @@ async fn data_delete(
             }
         }
         DataType::Note => {
-            let data = state.store.clone().delete::<Note>(id, owner_id);
-            match data {
-                Ok(data) => Json(data.clone()).into_response(),
-                Err(_) => AuthrError::NotFound.into_response(),
+            let response = delete_owned(&state, id, user.id, |note: &Note| note.owner_id);
+            // its attachments go with it
+            if response.status().is_success() {
+                attachments::note_deleted(&state, id).await;
             }
+            response
         }
         DataType::Comment => {
-            let data = state.store.clone().delete::<Comment>(id, owner_id);
+            delete_owned(&state, id, user.id, |comment: &Comment| comment.owner_id)
+        }
+        DataType::Punch => delete_owned(&state, id, user.id, |punch: &Punch| punch.owner_id),
+        DataType::Identity => unlink_identity(id, owner_id, state).into_response(),
+        DataType::Geofence if !user.has_role("admin") => AuthrError::NotAuthorized.into_response(),
+        DataType::Geofence => {
+            let data = state.store.clone().delete::<Geofence>(id, owner_id);
             match data {
                 Ok(data) => Json(data.clone()).into_response(),
                 Err(_) => AuthrError::NotFound.into_response(),
             }
         }
-        DataType::Punch => {
-            let data = state.store.clone().delete::<Punch>(id, owner_id);
-            match data {
-                Ok(data) => Json(data.clone()).into_response(),
-                Err(_) => AuthrError::NotFound.into_response(),
+        DataType::Tag => delete_owned(&state, id, user.id, |tag: &Tag| tag.owner_id),
+        DataType::NoteTag => delete_owned(&state, id, user.id, |tagged: &NoteTag| tagged.owner_id),
+        DataType::Attachment => attachments::delete(&state, id, user.id).await,
+        DataType::Notification => {
+            delete_owned(&state, id, user.id, |notification: &Notification| {
+                notification.owner_id
+            })
+        }
+        DataType::Webhook => {
+            let response = delete_owned(&state, id, user.id, |hook: &Webhook| hook.owner_id);
+            // its deliveries go with it
+            if response.status().is_success() {
+                webhooks::delete_deliveries(&state, id);
             }
+            response
         }
+        // the delivery log is the server's record
+        DataType::WebhookDelivery => AuthrError::NotAuthorized.into_response(),
+    }
+}
+
+// the store only narrows deletes to the owner for users themselves
+fn delete_owned<T: DataObject + Serialize>(
+    state: &DataState,
+    id: i64,
+    owner_id: i64,
+    owner: impl Fn(&T) -> i64,
+) -> Response {
+    match state.store.get::<T>(id) {
+        Some(data) if owner(&data) != owner_id => AuthrError::NotAuthorized.into_response(),
+        Some(_) => match state.store.delete::<T>(id, Some(owner_id)) {
+            Ok(data) => Json(data).into_response(),
+            Err(_) => AuthrError::NotFound.into_response(),
+        },
+        None => AuthrError::NotFound.into_response(),
+    }
+}
+
+// unlinking must leave the user with at least one way to sign in
+fn unlink_identity(id: i64, owner_id: Option<i64>, state: Arc<DataState>) -> impl IntoResponse {
+    let identity: Identity = match state.store.clone().get(id) {
+        Some(identity) => identity,
+        None => return AuthrError::NotFound.into_response(),
+    };
+    if Some(identity.owner_id) != owner_id {
+        return AuthrError::NotAuthorized.into_response();
+    }
+    let linked: Vec<Identity> = state.store.clone().get_queries(vec![
+        QueryTypes::IdentityQuery(IdentityQuery::ByOwnerId(IdentityByOwnerId::new(
+            identity.owner_id,
+        )))
+        .into(),
+    ]);
+    if linked.len() <= 1 {
+        return (StatusCode::CONFLICT, "Cannot unlink the last identity").into_response();
+    }
+    match state.store.clone().delete::<Identity>(id, owner_id) {
+        Ok(data) => Json(data).into_response(),
+        Err(_) => AuthrError::NotFound.into_response(),
     }
 }
 
@@ -171,23 +445,137 @@ async fn handle_create<R: RequestObject + Clone, T: DataObject + Serialize>(
     state: Arc<DataState>,
     owner_id: Option<i64>,
 ) -> impl IntoResponse {
-    if let Err(e) = payload.validate_create(owner_id) {
-        error!("{:?}", e);
-        return AuthrError::NotFound.into_response();
-    }
-    let data = state.store.clone().create::<_, T>(payload);
-    match data {
+    match create_checked::<_, T>(payload, &state, owner_id) {
         Ok(data) => Json(data.clone()).into_response(),
-        Err(_) => AuthrError::NotFound.into_response(),
+        Err(e) => e.into_response(),
     }
 }
 
+// handle_create for handlers with more to do once the data is stored
+fn create_checked<R: RequestObject + Clone, T: DataObject>(
+    payload: R,
+    state: &DataState,
+    owner_id: Option<i64>,
+) -> Result<T, AuthrError> {
+    if let Err(e) = payload
+        .validate_create(owner_id)
+        .and_then(|_| payload.validate_fields())
+    {
+        debug!("{}", e);
+        return Err(AuthrError::from(e));
+    }
+    state
+        .store
+        .create::<_, T>(payload)
+        .map_err(|_| AuthrError::NotFound)
+}
+
+// Which geofence a punch is in, and whether it's outside all of the user's.
+// Only worked out when the punch's position is new or changed.
+fn classify_punch(state: &DataState, payload: &mut RequestPunch, user_id: i64) {
+    if payload.latitude.is_none() && payload.longitude.is_none() {
+        return;
+    }
+    let existing: Option<Punch> = payload.id.and_then(|id| state.store.get(id));
+    let position = (
+        payload.latitude.or(existing.as_ref().map(|p| p.latitude)),
+        payload.longitude.or(existing.as_ref().map(|p| p.longitude)),
+    );
+    if let (Some(lat), Some(lon)) = position {
+        let fences: Vec<Geofence> = state.store.get_queries(vec![]);
+        let (geofence_id, flagged) = classify(&fences, user_id, lat, lon);
+        payload.geofence_id = Some(geofence_id);
+        payload.flagged = Some(flagged);
+    }
+}
+
+// Replies go on their parent's note, at most MAX_COMMENT_DEPTH deep, and keep
+// their place in the thread once posted.
+fn check_thread(state: &DataState, payload: &RequestComment) -> Result<(), ValidationError> {
+    let mut v = Validator::new();
+    if let Some(existing) = payload.id.and_then(|id| state.store.get::<Comment>(id)) {
+        v.check(
+            "parent_comment_id",
+            "immutable",
+            payload.parent_comment_id.is_none()
+                || payload.parent_comment_id == existing.parent_comment_id,
+            "can't be changed",
+        );
+        if payload
+            .note_id
+            .is_some_and(|note_id| note_id != existing.note_id)
+        {
+            let replies: Vec<Comment> = state
+                .store
+                .get_queries(vec![Box::new(CommentByParentId::new(existing.id))]);
+            v.check(
+                "note_id",
+                "thread",
+                existing.parent_comment_id.is_none() && replies.is_empty(),
+                "can't be changed on a comment in a thread",
+            );
+        }
+        return v.finish();
+    }
+
+    let (Some(parent_id), Some(note_id)) = (payload.parent_comment_id, payload.note_id) else {
+        return v.finish();
+    };
+    match state.store.get::<Comment>(parent_id) {
+        Some(parent) => {
+            v.check(
+                "parent_comment_id",
+                "same_note",
+                parent.note_id == note_id,
+                "must be a comment on the same note",
+            );
+            let mut depth = 1;
+            let mut next = parent.parent_comment_id;
+            while let Some(id) = next.filter(|_| depth <= MAX_COMMENT_DEPTH) {
+                depth += 1;
+                next = state
+                    .store
+                    .get::<Comment>(id)
+                    .and_then(|c| c.parent_comment_id);
+            }
+            v.check(
+                "parent_comment_id",
+                "depth",
+                depth <= MAX_COMMENT_DEPTH,
+                &format!("replies go at most {} deep", MAX_COMMENT_DEPTH),
+            );
+        }
+        None => {
+            v.check(
+                "parent_comment_id",
+                "exists",
+                false,
+                "must be an existing comment",
+            );
+        }
+    }
+    v.finish()
+}
+
+// made once for each `Idempotency-Key`, see `sync`
 async fn data_create(
     Path(data_type): Path<DataType>,
-    OwnerIdHeader(owner_id): OwnerIdHeader,
+    user: AuthenticatedUser,
+    headers: HeaderMap,
     State(state): State<Arc<DataState>>,
     body: String,
 ) -> impl IntoResponse {
+    let create = create(data_type, user.clone(), state.clone(), body.clone());
+    sync::create_once(&state, user.id, &headers, data_type, &body, create).await
+}
+
+async fn create(
+    data_type: DataType,
+    user: AuthenticatedUser,
+    state: Arc<DataState>,
+    body: String,
+) -> Response {
+    let owner_id = Some(user.id);
     match data_type {
         DataType::User => match serde_json::from_str::<RequestUser>(body.as_str()) {
             Ok(payload) => handle_create::<_, User>(payload, state, owner_id)
@@ -199,30 +587,101 @@ async fn data_create(
             }
         },
         DataType::Note => match serde_json::from_str::<RequestNote>(body.as_str()) {
-            Ok(payload) => handle_create::<_, Note>(payload, state, owner_id)
-                .await
-                .into_response(),
+            Ok(mut payload) => {
+                payload.version = Some(1);
+                handle_create::<_, Note>(payload, state, owner_id)
+                    .await
+                    .into_response()
+            }
             Err(e) => {
                 error!("{:?}", e);
                 return (StatusCode::BAD_REQUEST, "Bad Request").into_response();
             }
         },
         DataType::Comment => match serde_json::from_str::<RequestComment>(body.as_str()) {
-            Ok(payload) => handle_create::<_, Comment>(payload, state, owner_id)
-                .await
-                .into_response(),
+            Ok(payload) => match check_thread(&state, &payload) {
+                Ok(()) => match create_checked::<_, Comment>(payload, &state, owner_id) {
+                    Ok(comment) => {
+                        notifications::comment_created(&state, &comment);
+                        Json(comment).into_response()
+                    }
+                    Err(e) => e.into_response(),
+                },
+                Err(e) => AuthrError::from(e).into_response(),
+            },
             Err(e) => {
                 error!("{:?}", e);
                 return (StatusCode::BAD_REQUEST, "Bad Request").into_response();
             }
         },
         DataType::Punch => match serde_json::from_str::<RequestPunch>(body.as_str()) {
-            Ok(payload) => handle_create::<_, Punch>(payload, state, owner_id)
+            Ok(mut payload) => {
+                payload.received_at = Some(time::OffsetDateTime::now_utc().unix_timestamp());
+                classify_punch(&state, &mut payload, user.id);
+                handle_create::<_, Punch>(payload, state, owner_id)
+                    .await
+                    .into_response()
+            }
+            Err(e) => {
+                error!("{:?}", e);
+                return (StatusCode::BAD_REQUEST, "Bad Request").into_response();
+            }
+        },
+        // identities are only linked through a provider login
+        DataType::Identity => AuthrError::NotAuthorized.into_response(),
+        // uploaded through `/data/note/{id}/attachments`, and fixed after that
+        DataType::Attachment => AuthrError::NotAuthorized.into_response(),
+        // made by the server, and marked read through their own routes
+        DataType::Notification => AuthrError::NotAuthorized.into_response(),
+        DataType::WebhookDelivery => AuthrError::NotAuthorized.into_response(),
+        // only told about changes from now on
+        DataType::Webhook => match serde_json::from_str::<RequestWebhook>(body.as_str()) {
+            Ok(mut payload) => {
+                let latest: Vec<ChangeRecord> =
+                    state.store.get_queries(vec![Box::new(LatestChange)]);
+                payload.last_seq = Some(latest.first().map(|record| record.seq).unwrap_or(0));
+                payload.created_at = Some(time::OffsetDateTime::now_utc().unix_timestamp());
+                handle_create::<_, Webhook>(payload, state, owner_id)
+                    .await
+                    .into_response()
+            }
+            Err(e) => {
+                error!("{:?}", e);
+                (StatusCode::BAD_REQUEST, "Bad Request").into_response()
+            }
+        },
+        DataType::Geofence if !user.has_role("admin") => AuthrError::NotAuthorized.into_response(),
+        DataType::Geofence => match serde_json::from_str::<RequestGeofence>(body.as_str()) {
+            Ok(payload) => handle_create::<_, Geofence>(payload, state, owner_id)
                 .await
                 .into_response(),
             Err(e) => {
                 error!("{:?}", e);
-                return (StatusCode::BAD_REQUEST, "Bad Request").into_response();
+                (StatusCode::BAD_REQUEST, "Bad Request").into_response()
+            }
+        },
+        DataType::Tag => match serde_json::from_str::<RequestTag>(body.as_str()) {
+            Ok(payload) => match check_tag(&state, &payload, user.id) {
+                Ok(()) => handle_create::<_, Tag>(payload, state, owner_id)
+                    .await
+                    .into_response(),
+                Err(e) => AuthrError::from(e).into_response(),
+            },
+            Err(e) => {
+                error!("{:?}", e);
+                (StatusCode::BAD_REQUEST, "Bad Request").into_response()
+            }
+        },
+        DataType::NoteTag => match serde_json::from_str::<RequestNoteTag>(body.as_str()) {
+            Ok(payload) => match check_note_tag(&state, &payload, user.id) {
+                Ok(()) => handle_create::<_, NoteTag>(payload, state, owner_id)
+                    .await
+                    .into_response(),
+                Err(e) => AuthrError::from(e).into_response(),
+            },
+            Err(e) => {
+                error!("{:?}", e);
+                (StatusCode::BAD_REQUEST, "Bad Request").into_response()
             }
         },
     }
@@ -233,11 +692,27 @@ async fn handle_update<R: RequestObject + Clone, T: DataObject + Serialize>(
     state: Arc<DataState>,
     owner_id: Option<i64>,
 ) -> impl IntoResponse {
-    if let Err(e) = payload.validate_update(owner_id) {
-        error!("{:?}", e);
-        return AuthrError::NotFound.into_response();
+    handle_update_if::<R, T>(payload, state, owner_id, None).await
+}
+
+// only updates while the condition's field still holds its value
+async fn handle_update_if<R: RequestObject + Clone, T: DataObject + Serialize>(
+    payload: R,
+    state: Arc<DataState>,
+    owner_id: Option<i64>,
+    condition: Option<(&str, sqlite::Value)>,
+) -> Response {
+    if let Err(e) = payload
+        .validate_update(owner_id)
+        .and_then(|_| payload.validate_fields())
+    {
+        debug!("{}", e);
+        return AuthrError::from(e).into_response();
     }
-    let data = state.store.clone().update::<_, T>(payload);
+    let data = match condition {
+        Some((field, expected)) => state.store.update_if::<_, T>(payload, field, expected),
+        None => state.store.update::<_, T>(payload),
+    };
     match data {
         Ok(data) => Json(data.clone()).into_response(),
         Err(_) => AuthrError::NotFound.into_response(),
@@ -246,10 +721,11 @@ async fn handle_update<R: RequestObject + Clone, T: DataObject + Serialize>(
 
 async fn data_update(
     Path(data_type): Path<DataType>,
-    OwnerIdHeader(owner_id): OwnerIdHeader,
+    user: AuthenticatedUser,
     State(state): State<Arc<DataState>>,
     body: String,
 ) -> impl IntoResponse {
+    let owner_id = Some(user.id);
     match data_type {
         DataType::User => match serde_json::from_str::<RequestUser>(body.as_str()) {
             Ok(payload) => handle_update::<_, User>(payload, state, owner_id)
@@ -261,53 +737,239 @@ async fn data_update(
             }
         },
         DataType::Note => match serde_json::from_str::<RequestNote>(body.as_str()) {
-            Ok(payload) => handle_update::<_, Note>(payload, state, owner_id)
-                .await
-                .into_response(),
+            // a new version for new contents, which also moves the render cache on
+            Ok(mut payload) => {
+                let existing: Option<Note> = payload.id.and_then(|id| state.store.get(id));
+                if let (Some(version), Some(note)) = (payload.version, &existing) {
+                    // made to an older version, the client has the note as it is now
+                    // to decide what to keep
+                    if version != note.version && note.owner_id == user.id {
+                        return (StatusCode::CONFLICT, Json(note.clone())).into_response();
+                    }
+                }
+                let expected = match payload.contents {
+                    Some(_) => existing.map(|note| note.version),
+                    None => None,
+                };
+                payload.version = expected.map(|version| version + 1);
+                // and only if nobody else has moved it on since it was read
+                let condition =
+                    expected.map(|version| ("version", sqlite::Value::Integer(version)));
+                let response = handle_update_if::<_, Note>(
+                    payload.clone(),
+                    state.clone(),
+                    owner_id,
+                    condition,
+                )
+                .await;
+                if expected.is_none() || response.status() != StatusCode::NOT_FOUND {
+                    return response;
+                }
+                match payload.id.and_then(|id| state.store.get::<Note>(id)) {
+                    Some(note) if expected != Some(note.version) && note.owner_id == user.id => {
+                        (StatusCode::CONFLICT, Json(note)).into_response()
+                    }
+                    _ => response,
+                }
+            }
             Err(e) => {
                 error!("{:?}", e);
                 return (StatusCode::BAD_REQUEST, "Bad Request").into_response();
             }
         },
         DataType::Comment => match serde_json::from_str::<RequestComment>(body.as_str()) {
-            Ok(payload) => handle_update::<_, Comment>(payload, state, owner_id)
-                .await
-                .into_response(),
+            Ok(payload) => match check_thread(&state, &payload) {
+                Ok(()) => handle_update::<_, Comment>(payload, state, owner_id)
+                    .await
+                    .into_response(),
+                Err(e) => AuthrError::from(e).into_response(),
+            },
             Err(e) => {
                 error!("{:?}", e);
                 return (StatusCode::BAD_REQUEST, "Bad Request").into_response();
             }
         },
         DataType::Punch => match serde_json::from_str::<RequestPunch>(body.as_str()) {
-            Ok(payload) => handle_update::<_, Punch>(payload, state, owner_id)
+            // the receive time is the server's to set
+            Ok(mut payload) => {
+                payload.received_at = None;
+                classify_punch(&state, &mut payload, user.id);
+                handle_update::<_, Punch>(payload, state, owner_id)
+                    .await
+                    .into_response()
+            }
+            Err(e) => {
+                error!("{:?}", e);
+                return (StatusCode::BAD_REQUEST, "Bad Request").into_response();
+            }
+        },
+        // identities are only linked through a provider login
+        DataType::Identity => AuthrError::NotAuthorized.into_response(),
+        // uploaded through `/data/note/{id}/attachments`, and fixed after that
+        DataType::Attachment => AuthrError::NotAuthorized.into_response(),
+        // made by the server, and marked read through their own routes
+        DataType::Notification => AuthrError::NotAuthorized.into_response(),
+        DataType::WebhookDelivery => AuthrError::NotAuthorized.into_response(),
+        DataType::Webhook => match serde_json::from_str::<RequestWebhook>(body.as_str()) {
+            Ok(payload) => handle_update::<_, Webhook>(payload, state, owner_id)
                 .await
                 .into_response(),
             Err(e) => {
                 error!("{:?}", e);
-                return (StatusCode::BAD_REQUEST, "Bad Request").into_response();
+                (StatusCode::BAD_REQUEST, "Bad Request").into_response()
+            }
+        },
+        DataType::Geofence if !user.has_role("admin") => AuthrError::NotAuthorized.into_response(),
+        DataType::Geofence => match serde_json::from_str::<RequestGeofence>(body.as_str()) {
+            Ok(payload) => handle_update::<_, Geofence>(payload, state, owner_id)
+                .await
+                .into_response(),
+            Err(e) => {
+                error!("{:?}", e);
+                (StatusCode::BAD_REQUEST, "Bad Request").into_response()
+            }
+        },
+        // a rename, which every note with the tag picks up
+        DataType::Tag => match serde_json::from_str::<RequestTag>(body.as_str()) {
+            Ok(payload) => match check_tag(&state, &payload, user.id) {
+                Ok(()) => handle_update::<_, Tag>(payload, state, owner_id)
+                    .await
+                    .into_response(),
+                Err(e) => AuthrError::from(e).into_response(),
+            },
+            Err(e) => {
+                error!("{:?}", e);
+                (StatusCode::BAD_REQUEST, "Bad Request").into_response()
             }
         },
+        DataType::NoteTag => match serde_json::from_str::<RequestNoteTag>(body.as_str()) {
+            Ok(payload) => match check_note_tag(&state, &payload, user.id) {
+                Ok(()) => handle_update::<_, NoteTag>(payload, state, owner_id)
+                    .await
+                    .into_response(),
+                Err(e) => AuthrError::from(e).into_response(),
+            },
+            Err(e) => {
+                error!("{:?}", e);
+                (StatusCode::BAD_REQUEST, "Bad Request").into_response()
+            }
+        },
+    }
+}
+
+// tag names are unique per owner
+fn check_tag(
+    state: &DataState,
+    payload: &RequestTag,
+    owner_id: i64,
+) -> Result<(), ValidationError> {
+    let mut v = Validator::new();
+    if let Some(name) = &payload.name {
+        let taken: Vec<Tag> = state.store.get_queries(vec![
+            Box::new(TagByOwnerId::new(owner_id)),
+            Box::new(TagByName::new(name.clone())),
+        ]);
+        v.check(
+            "name",
+            "unique",
+            taken.iter().all(|tag| Some(tag.id) == payload.id),
+            "is already one of your tags",
+        );
     }
+    v.finish()
 }
 
-async fn whoami(
-    OwnerIdHeader(owner_id): OwnerIdHeader,
+// Only your own tags go on your own notes, and each one only once.
+fn check_note_tag(
+    state: &DataState,
+    payload: &RequestNoteTag,
+    owner_id: i64,
+) -> Result<(), ValidationError> {
+    let existing: Option<NoteTag> = payload.id.and_then(|id| state.store.get(id));
+    let mut v = Validator::new();
+    if let Some(note_id) = payload.note_id {
+        let note: Option<Note> = state.store.get(note_id);
+        v.check(
+            "note_id",
+            "owned",
+            note.is_some_and(|note| note.owner_id == owner_id),
+            "must be one of your notes",
+        );
+    }
+    if let Some(tag_id) = payload.tag_id {
+        let tag: Option<Tag> = state.store.get(tag_id);
+        v.check(
+            "tag_id",
+            "owned",
+            tag.is_some_and(|tag| tag.owner_id == owner_id),
+            "must be one of your tags",
+        );
+    }
+    let note_id = payload.note_id.or(existing.as_ref().map(|t| t.note_id));
+    let tag_id = payload.tag_id.or(existing.as_ref().map(|t| t.tag_id));
+    if let (Some(note_id), Some(tag_id)) = (note_id, tag_id) {
+        let tagged: Vec<NoteTag> = state.store.get_queries(vec![
+            Box::new(NoteTagByNoteId::new(note_id)),
+            Box::new(NoteTagByTagId::new(tag_id)),
+        ]);
+        v.check(
+            "tag_id",
+            "unique",
+            tagged.iter().all(|t| Some(t.id) == payload.id),
+            "is already on the note",
+        );
+    }
+    v.finish()
+}
+
+// the caller's tags and how many notes carry each, most used first
+async fn tag_cloud(
+    user: AuthenticatedUser,
     State(state): State<Arc<DataState>>,
 ) -> impl IntoResponse {
-    let owner_id = match owner_id {
-        Some(oid) => oid,
-        None => {
-            error!("No owner_id specified for whoami endpoint");
-            return AuthrError::NotFound.into_response();
-        }
-    };
-    let data: Option<User> = state.store.clone().get(owner_id);
+    let data: Vec<TagCount> = state
+        .store
+        .get_queries(vec![Box::new(TagCloud::new(user.id))]);
+    Json(data).into_response()
+}
+
+async fn whoami(user: AuthenticatedUser, State(state): State<Arc<DataState>>) -> impl IntoResponse {
+    let data: Option<User> = state.store.clone().get(user.id);
     match data {
         Some(data) => Json(data.clone()).into_response(),
         None => AuthrError::NotFound.into_response(),
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct ThreadParams {
+    sort: Option<String>,
+}
+
+// a note's comments with replies nested under their parents
+async fn comment_thread(
+    Path(note_id): Path<i64>,
+    UrlQuery(params): UrlQuery<ThreadParams>,
+    State(state): State<Arc<DataState>>,
+) -> impl IntoResponse {
+    let order = match params.sort.as_deref().map(str::parse::<CommentOrder>) {
+        Some(Ok(order)) => order,
+        Some(Err(_)) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "sort must be oldest, newest or mostReplies",
+            )
+                .into_response();
+        }
+        None => CommentOrder::Oldest,
+    };
+    let comments: Vec<Comment> = state.store.get_queries(vec![
+        Box::new(CommentByNoteId::new(note_id)),
+        Box::new(CommentSort::new(order)),
+    ]);
+    Json(threads(&comments)).into_response()
+}
+
 // helper functions
 async fn handle_not_found() -> impl IntoResponse {
     AuthrError::NotFound.into_response()
@@ -321,9 +983,42 @@ fn data_routes(state: Arc<DataState>) -> Router {
         .route("/{type}", post(data_create))
         .route("/{type}", put(data_update))
         .route("/whoami", get(whoami))
+        .route("/jobs", get(jobs::list))
+        .route("/changes", get(sync::changes))
+        .route("/subscribe", get(feed::subscribe))
+        .route("/{type}/events", get(events::events))
+        .route("/timesheet", get(timesheet::timesheet))
+        .route("/comment/thread/{note_id}", get(comment_thread))
+        .route("/note/{id}/rendered", get(markdown::rendered))
+        .route("/tag/cloud", get(tag_cloud))
+        .route("/notification/{id}/read", post(notifications::mark_read))
+        .route("/notification/read-all", post(notifications::mark_all_read))
+        .route("/attachment/{id}/content", get(attachments::content))
+        .route("/attachment/{id}/thumb/{size}", get(images::thumb))
+        .route(
+            "/note/{id}/attachments",
+            post(attachments::upload)
+                .layer(DefaultBodyLimit::max(attachments::max_attachment_bytes())),
+        )
+        .route("/punch/export", get(gis::export))
+        .route(
+            "/punch/import",
+            post(gis::import).layer(DefaultBodyLimit::max(gis::max_import_bytes())),
+        )
+        .layer(DefaultBodyLimit::max(max_body_bytes()))
         .with_state(state)
 }
 
+// MAX_BODY_BYTES caps data request bodies, larger ones get a 413
+fn max_body_bytes() -> usize {
+    match env::var("MAX_BODY_BYTES") {
+        Ok(val) => val
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid MAX_BODY_BYTES: {}", val)),
+        Err(_) => DEFAULT_MAX_BODY_BYTES,
+    }
+}
+
 pub async fn logout(State(state): State<Arc<AuthState>>, jar: CookieJar) -> impl IntoResponse {
     // get session id
     let session_id = match jar.get("session_id") {
@@ -335,8 +1030,8 @@ pub async fn logout(State(state): State<Arc<AuthState>>, jar: CookieJar) -> impl
     // invalidate session cache
     match state.sessions.lock() {
         Ok(mut sessions) => {
-            if let Some((user, _)) = sessions.remove(session_id) {
-                debug!("Logging out {:?}", user);
+            if let Some(session) = sessions.remove(session_id) {
+                debug!("Logging out {:?}", session.user);
             } else {
                 error!(
                     "invalid session id made it through auth phase: {}",
@@ -355,16 +1050,23 @@ pub async fn logout(State(state): State<Arc<AuthState>>, jar: CookieJar) -> impl
         .max_age(time::Duration::minutes(-1))
         .http_only(true)
         .build();
+    let csrf_cookie = auth::csrf::cookie("", time::Duration::minutes(-1));
 
     (
         StatusCode::TEMPORARY_REDIRECT,
-        AppendHeaders([(SET_COOKIE, cookie.to_string().as_str()), (LOCATION, "/")]),
+        AppendHeaders([
+            (SET_COOKIE, cookie.to_string().as_str()),
+            (SET_COOKIE, csrf_cookie.to_string().as_str()),
+            (LOCATION, "/"),
+        ]),
     )
         .into_response()
 }
 
 pub async fn run(listener: TcpListener, state: AuthrState) {
     let state = Arc::new(state);
+    let worker = jobs::start(state.clone());
+    webhooks::start(state.data.clone());
     let app = Router::new()
         // routes behind auth
         // data
@@ -374,9 +1076,26 @@ pub async fn run(listener: TcpListener, state: AuthrState) {
             "/web",
             ServeDir::new("./splunge/home").not_found_service(handle_not_found.into_service()),
         )
-        // logout gets auth state
+        // logout and linking get auth state
         .route("/auth/logout", get(logout))
+        .route("/auth/link/{provider}", get(auth::provider::link))
+        .route("/auth/password", post(auth::local_auth::change_password))
+        .route(
+            "/auth/tokens",
+            get(auth::api_tokens::list_tokens).post(auth::api_tokens::create_token),
+        )
+        .route("/auth/tokens/{id}", delete(auth::api_tokens::revoke_token))
         .with_state(state.auth.clone())
+        // csrf token check, needs the user from the auth layer
+        .route_layer(middleware::from_fn_with_state(
+            state.auth.clone(),
+            auth::csrf::verify_token,
+        ))
+        // rate limits, keyed by the user from the auth layer
+        .route_layer(middleware::from_fn_with_state(
+            state.auth.clone(),
+            ratelimit::rate_limit,
+        ))
         // auth layer
         .route_layer(middleware::from_fn_with_state(
             state.auth.clone(),
@@ -387,36 +1106,44 @@ pub async fn run(listener: TcpListener, state: AuthrState) {
         // fallback to static
         .fallback_service(
             ServeDir::new("./splunge/auth").not_found_service(handle_not_found.into_service()),
-        );
+        )
+        // covers the login forms too
+        .layer(middleware::from_fn(auth::csrf::verify_origin));
 
     info!("Listening on {:?}", listener.local_addr());
-    axum::serve(listener, app).await.unwrap();
-}
-
-pub struct OwnerIdHeader(Option<i64>);
-
-impl<S> FromRequestParts<S> for OwnerIdHeader
-where
-    S: Send + Sync,
-{
-    type Rejection = ();
-
-    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
-        match parts.headers.get("Owner-Id") {
-            Some(val) => {
-                if let Ok(v_str) = val.to_str() {
-                    match v_str.parse::<i64>() {
-                        Ok(v) => Ok(OwnerIdHeader(Some(v))),
-                        Err(e) => {
-                            error!("{:?}", e);
-                            Err(())
-                        }
-                    }
-                } else {
-                    Err(())
-                }
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+    .unwrap();
+    state.data.jobs.shutdown();
+    let _ = worker.await;
+}
+
+// ctrl-c, or SIGTERM from a service manager
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!("{:?}", e);
+                std::future::pending::<()>().await;
             }
-            None => Ok(OwnerIdHeader(None)),
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
     }
+    info!("shutting down");
 }
@@ -0,0 +1,371 @@
+// Files attached to notes.
+//
+//   POST /data/note/{id}/attachments      multipart, one or more `file` parts
+//   GET  /data/attachment/{id}/content    honours a single `Range: bytes=...`
+//
+// The metadata is an ordinary `Attachment`, so `/data/attachment?byNoteId=`
+// and `DELETE /data/attachment/{id}` work like the other types. The bytes go
+// to the blob store, and a blob is removed once no attachment or thumbnail
+// points at it, which includes when its note is deleted. Images also get
+// thumbnails, see `images`.
+//
+// Uploads count against their owner's ATTACHMENT_QUOTA_BYTES (default 100MB)
+// and a single upload request is capped at MAX_ATTACHMENT_BYTES (default
+// 10MB).
+use std::{env, sync::Arc};
+
+use axum::{
+    Json,
+    body::{Body, Bytes},
+    extract::{Multipart, Path, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{
+            ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG,
+            IF_RANGE, RANGE, X_CONTENT_TYPE_OPTIONS,
+        },
+    },
+    response::{IntoResponse, Response},
+};
+use lib_glonk::{store::Store, types::RequestObject, validation::Validator};
+use time::OffsetDateTime;
+use tracing::error;
+
+use crate::{
+    app::DataState,
+    auth::AuthenticatedUser,
+    error::AuthrError,
+    images::{self, MakeThumbnails},
+    jobs,
+    types::{
+        Attachment, AttachmentByDigest, AttachmentByNoteId, AttachmentByOwnerId, Note,
+        RequestAttachment, Thumbnail, ThumbnailByAttachmentId, ThumbnailByDigest, Thumbnails,
+    },
+};
+
+const DEFAULT_MAX_ATTACHMENT_BYTES: usize = 10 * 1024 * 1024;
+const DEFAULT_QUOTA_BYTES: i64 = 100 * 1024 * 1024;
+
+pub async fn upload(
+    Path(note_id): Path<i64>,
+    user: AuthenticatedUser,
+    State(state): State<Arc<DataState>>,
+    mut multipart: Multipart,
+) -> Response {
+    let note: Note = match state.store.get(note_id) {
+        Some(note) => note,
+        None => return AuthrError::NotFound.into_response(),
+    };
+    if note.owner_id != user.id {
+        return AuthrError::NotAuthorized.into_response();
+    }
+
+    let mut files: Vec<(RequestAttachment, Bytes)> = vec![];
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return e.into_response(),
+        };
+        if field.name() != Some("file") {
+            continue;
+        }
+        let name = field.file_name().unwrap_or("attachment").to_string();
+        let content_type = content_type(field.content_type(), &name);
+        let bytes = match field.bytes().await {
+            Ok(bytes) => images::strip_gps(bytes, &content_type),
+            Err(e) => return e.into_response(),
+        };
+        let thumbnails = match images::is_image(&content_type) {
+            true => Thumbnails::Pending,
+            false => Thumbnails::Unsupported,
+        };
+        let payload = RequestAttachment {
+            id: None,
+            owner_id: Some(user.id),
+            note_id: Some(note_id),
+            name: Some(name),
+            content_type: Some(content_type),
+            size: Some(bytes.len() as i64),
+            digest: Some(crate::blob::digest(&bytes)),
+            created_at: Some(OffsetDateTime::now_utc().unix_timestamp()),
+            thumbnails: Some(thumbnails),
+        };
+        files.push((payload, bytes));
+    }
+    if files.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Missing file").into_response();
+    }
+
+    let mut violations = vec![];
+    for (i, (payload, _)) in files.iter().enumerate() {
+        let valid = payload
+            .validate_create(Some(user.id))
+            .and_then(|_| payload.validate_fields());
+        if let Err(AuthrError::Invalid(found)) = valid.map_err(AuthrError::from) {
+            violations.extend(found.into_iter().map(|mut v| {
+                v.field = format!("files[{}].{}", i, v.field);
+                v
+            }));
+        }
+    }
+    if !violations.is_empty() {
+        return AuthrError::Invalid(violations).into_response();
+    }
+
+    // held until the rows are in, so a blob can't be released under an upload
+    let _guard = state.blob_lock.lock().await;
+    let used: i64 = state
+        .store
+        .get_queries::<Attachment>(vec![Box::new(AttachmentByOwnerId::new(user.id))])
+        .iter()
+        .map(|attachment| attachment.size)
+        .sum();
+    let adding: i64 = files.iter().map(|(_, bytes)| bytes.len() as i64).sum();
+    let mut v = Validator::new();
+    v.check(
+        "file",
+        "quota",
+        used + adding <= quota_bytes(),
+        "would go over your attachment quota",
+    );
+    if let Err(e) = v.finish() {
+        return AuthrError::from(e).into_response();
+    }
+
+    let mut created = vec![];
+    for (mut payload, bytes) in files {
+        match state.blobs.put(bytes).await {
+            Ok(digest) => payload.digest = Some(digest),
+            Err(e) => {
+                error!("storing attachment: {}", e);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+        match state.store.create::<_, Attachment>(payload) {
+            Ok(attachment) => {
+                if attachment.thumbnails == Thumbnails::Pending {
+                    let job = MakeThumbnails {
+                        attachment_id: attachment.id,
+                    };
+                    if let Err(e) = jobs::enqueue(&state, &job) {
+                        error!("queueing thumbnails: {}", e);
+                    }
+                }
+                created.push(attachment);
+            }
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+    Json(created).into_response()
+}
+
+pub async fn content(
+    Path(id): Path<i64>,
+    State(state): State<Arc<DataState>>,
+    headers: HeaderMap,
+) -> Response {
+    let attachment: Attachment = match state.store.get(id) {
+        Some(attachment) => attachment,
+        None => return AuthrError::NotFound.into_response(),
+    };
+    let size = attachment.size as u64;
+    let etag = format!("\"{}\"", attachment.digest);
+
+    // a stale If-Range means the whole file
+    let range = headers
+        .get(RANGE)
+        .and_then(|val| val.to_str().ok())
+        .filter(|_| {
+            headers
+                .get(IF_RANGE)
+                .is_none_or(|val| val.as_bytes() == etag.as_bytes())
+        });
+    let (status, start, end) = match range.map(|range| parse_range(range, size)) {
+        Some(Ok(Some((start, end)))) => (StatusCode::PARTIAL_CONTENT, start, end),
+        Some(Err(())) => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(CONTENT_RANGE, format!("bytes */{}", size))],
+            )
+                .into_response();
+        }
+        Some(Ok(None)) | None => (StatusCode::OK, 0, size.saturating_sub(1)),
+    };
+    let len = if size == 0 { 0 } else { end - start + 1 };
+
+    let stream = match state.blobs.get(&attachment.digest, start, len).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!("reading attachment {}: {}", id, e);
+            return AuthrError::NotFound.into_response();
+        }
+    };
+    let mut response = (
+        status,
+        [
+            (CONTENT_TYPE, attachment.content_type.clone()),
+            (CONTENT_LENGTH, len.to_string()),
+            (ACCEPT_RANGES, "bytes".to_string()),
+            (ETAG, etag),
+            (
+                CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}\"",
+                    disposition_name(&attachment.name)
+                ),
+            ),
+            // uploads are served as given, never as whatever they look like
+            (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response();
+    if status == StatusCode::PARTIAL_CONTENT
+        && let Ok(val) = format!("bytes {}-{}/{}", start, end, size).parse()
+    {
+        response.headers_mut().insert(CONTENT_RANGE, val);
+    }
+    response
+}
+
+// `DELETE /data/attachment/{id}`
+pub(crate) async fn delete(state: &DataState, id: i64, owner_id: i64) -> Response {
+    let attachment: Attachment = match state.store.get(id) {
+        Some(attachment) => attachment,
+        None => return AuthrError::NotFound.into_response(),
+    };
+    if attachment.owner_id != owner_id {
+        return AuthrError::NotAuthorized.into_response();
+    }
+    let _guard = state.blob_lock.lock().await;
+    match remove(state, &attachment).await {
+        Ok(attachment) => Json(attachment).into_response(),
+        Err(_) => AuthrError::NotFound.into_response(),
+    }
+}
+
+// a deleted note takes its attachments with it
+pub(crate) async fn note_deleted(state: &DataState, note_id: i64) {
+    let _guard = state.blob_lock.lock().await;
+    let attachments: Vec<Attachment> = state
+        .store
+        .get_queries(vec![Box::new(AttachmentByNoteId::new(note_id))]);
+    for attachment in attachments {
+        let _ = remove(state, &attachment).await;
+    }
+}
+
+// the attachment, its thumbnails, and whichever of their blobs that leaves
+// unused, callers hold `blob_lock`
+async fn remove(state: &DataState, attachment: &Attachment) -> Result<Attachment, ()> {
+    let removed = state
+        .store
+        .delete::<Attachment>(attachment.id, Some(attachment.owner_id))
+        .map_err(|_| ())?;
+    let mut digests = vec![removed.digest.clone()];
+    let thumbnails: Vec<Thumbnail> = state
+        .store
+        .get_queries(vec![Box::new(ThumbnailByAttachmentId::new(attachment.id))]);
+    for thumbnail in thumbnails {
+        if let Ok(thumbnail) = state
+            .store
+            .delete::<Thumbnail>(thumbnail.id, Some(thumbnail.owner_id))
+        {
+            digests.push(thumbnail.digest);
+        }
+    }
+    for digest in digests {
+        release(state, &digest).await;
+    }
+    Ok(removed)
+}
+
+// drops the blob once nothing points at it
+async fn release(state: &DataState, digest: &str) {
+    let attachments: Vec<Attachment> = state
+        .store
+        .get_queries(vec![Box::new(AttachmentByDigest::new(digest.to_string()))]);
+    let thumbnails: Vec<Thumbnail> = state
+        .store
+        .get_queries(vec![Box::new(ThumbnailByDigest::new(digest.to_string()))]);
+    if attachments.is_empty()
+        && thumbnails.is_empty()
+        && let Err(e) = state.blobs.delete(digest).await
+    {
+        error!("deleting blob {}: {}", digest, e);
+    }
+}
+
+// what the client said, unless it said nothing useful
+fn content_type(given: Option<&str>, name: &str) -> String {
+    match given {
+        Some(given) if !given.is_empty() && given != "application/octet-stream" => {
+            given.to_string()
+        }
+        _ => mime_guess::from_path(name)
+            .first_or_octet_stream()
+            .to_string(),
+    }
+}
+
+// Inclusive (start, end) of a single `bytes=` range. Ok(None) for ranges we
+// don't serve, several at once or other units, which get the whole file.
+fn parse_range(range: &str, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let spec = match range.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (start, end) = spec.split_once('-').ok_or(())?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        // the last `n` bytes
+        ("", n) => {
+            let n: u64 = n.parse().map_err(|_| ())?;
+            if n == 0 || size == 0 {
+                return Err(());
+            }
+            (size.saturating_sub(n), size - 1)
+        }
+        (start, "") => (start.parse().map_err(|_| ())?, size.saturating_sub(1)),
+        (start, end) => {
+            let end: u64 = end.parse().map_err(|_| ())?;
+            (
+                start.parse().map_err(|_| ())?,
+                end.min(size.saturating_sub(1)),
+            )
+        }
+    };
+    if start >= size || start > end {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+fn disposition_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+// MAX_ATTACHMENT_BYTES caps upload bodies
+pub(crate) fn max_attachment_bytes() -> usize {
+    match env::var("MAX_ATTACHMENT_BYTES") {
+        Ok(val) => val
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid MAX_ATTACHMENT_BYTES: {}", val)),
+        Err(_) => DEFAULT_MAX_ATTACHMENT_BYTES,
+    }
+}
+
+fn quota_bytes() -> i64 {
+    match env::var("ATTACHMENT_QUOTA_BYTES") {
+        Ok(val) => val
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid ATTACHMENT_QUOTA_BYTES: {}", val)),
+        Err(_) => DEFAULT_QUOTA_BYTES,
+    }
+}
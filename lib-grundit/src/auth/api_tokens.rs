@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use lib_glonk::store::Store;
+use oauth2::CsrfToken;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, error};
+
+use crate::{
+    app::AuthState,
+    auth::AuthenticatedUser,
+    error::AuthrError,
+    types::{ApiToken, ApiTokenByHash, ApiTokenByOwnerId, DataType, RequestApiToken, User},
+};
+
+const TOKEN_PREFIX: &str = "grundit_";
+
+pub(crate) fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// `{type}:{read|write}` where type is a DataType or `*`
+fn valid_scope(scope: &str) -> bool {
+    match scope.split_once(':') {
+        Some((data_type, access)) => {
+            (data_type == "*"
+                || serde_json::from_value::<DataType>(serde_json::Value::from(data_type)).is_ok())
+                && matches!(access, "read" | "write")
+        }
+        None => false,
+    }
+}
+
+// The scope a request to `path` needs, None if tokens may not be used for it.
+// /data/changes, /data/subscribe and /data/jobs span every type, so their
+// `changes`, `subscribe` and `jobs` scopes can't be granted alone and only
+// `*:read` reaches them.
+fn required_scope(method: &Method, path: &str) -> Option<String> {
+    let data_type = match path.strip_prefix("/data/")?.split('/').next()? {
+        "whoami" => "user",
+        "timesheet" => "punch",
+        data_type => data_type,
+    };
+    let access = match *method {
+        Method::GET | Method::HEAD => "read",
+        _ => "write",
+    };
+    Some(format!("{}:{}", data_type, access))
+}
+
+impl ApiToken {
+    pub fn allows(&self, method: &Method, path: &str) -> bool {
+        let required = match required_scope(method, path) {
+            Some(required) => required,
+            None => return false,
+        };
+        let (_, access) = required.split_once(':').unwrap_or_default();
+        self.scopes
+            .split(',')
+            .any(|scope| scope == required || scope == format!("*:{}", access))
+    }
+}
+
+// Resolve a presented bearer token to its live token record and user
+pub(crate) fn authenticate_bearer(state: &AuthState, token: &str) -> Option<(ApiToken, User)> {
+    let api_token = state
+        .store
+        .get_queries::<ApiToken>(vec![Box::new(ApiTokenByHash::new(hash_token(token)))])
+        .pop()?;
+    if api_token.revoked {
+        debug!("revoked token {} presented", api_token.id);
+        return None;
+    }
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    if matches!(api_token.expires_at, Some(expires_at) if expires_at <= now) {
+        debug!("expired token {} presented", api_token.id);
+        return None;
+    }
+    let user = state.store.get::<User>(api_token.owner_id)?;
+    Some((api_token, user))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    name: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    // shown once, only the hash is stored
+    token: String,
+    api_token: ApiToken,
+}
+
+pub async fn create_token(
+    user: AuthenticatedUser,
+    State(state): State<Arc<AuthState>>,
+    Json(request): Json<CreateTokenRequest>,
+) -> Response {
+    let owner_id = user.id;
+    if request.name.trim().is_empty() {
+        return (StatusCode::BAD_REQUEST, "Missing token name").into_response();
+    }
+    // a token for everything has to say so, `*:read,*:write`
+    if request.scopes.is_empty() {
+        return (StatusCode::BAD_REQUEST, "At least one scope is required").into_response();
+    }
+    if let Some(scope) = request.scopes.iter().find(|s| !valid_scope(s)) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid scope `{}`", scope),
+        )
+            .into_response();
+    }
+    let now = time::OffsetDateTime::now_utc();
+    let expires_at = match request.expires_in_days {
+        Some(days) if days > 0 => Some((now + time::Duration::days(days)).unix_timestamp()),
+        Some(_) => return (StatusCode::BAD_REQUEST, "Invalid expiry").into_response(),
+        None => None,
+    };
+
+    let token = format!(
+        "{}{}",
+        TOKEN_PREFIX,
+        CsrfToken::new_random_len(32).into_secret()
+    );
+    let api_token = RequestApiToken {
+        id: None,
+        owner_id: Some(owner_id),
+        name: Some(request.name),
+        token_hash: Some(hash_token(&token)),
+        scopes: Some(request.scopes.join(",")),
+        expires_at,
+        created_at: Some(now.unix_timestamp()),
+        revoked: Some(false),
+    };
+    match state.store.create::<_, ApiToken>(api_token) {
+        Ok(api_token) => Json(CreatedToken { token, api_token }).into_response(),
+        Err(e) => {
+            error!("Could not create token: {:?}", e);
+            AuthrError::NotFound.into_response()
+        }
+    }
+}
+
+pub async fn list_tokens(user: AuthenticatedUser, State(state): State<Arc<AuthState>>) -> Response {
+    Json(
+        state
+            .store
+            .get_queries::<ApiToken>(vec![Box::new(ApiTokenByOwnerId::new(user.id))]),
+    )
+    .into_response()
+}
+
+pub async fn revoke_token(
+    Path(id): Path<i64>,
+    user: AuthenticatedUser,
+    State(state): State<Arc<AuthState>>,
+) -> Response {
+    let owner_id = user.id;
+    let revoked = RequestApiToken {
+        id: Some(id),
+        owner_id: Some(owner_id),
+        name: None,
+        token_hash: None,
+        scopes: None,
+        expires_at: None,
+        created_at: None,
+        revoked: Some(true),
+    };
+    // update is scoped to the owner, so other users' tokens come back not found
+    match state.store.update::<_, ApiToken>(revoked) {
+        Ok(api_token) => Json(api_token).into_response(),
+        Err(_) => AuthrError::NotFound.into_response(),
+    }
+}
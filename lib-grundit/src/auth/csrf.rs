@@ -0,0 +1,148 @@
+// Cross site request forgery protection.
+//
+// Every session gets a random token, handed to the page in a cookie that
+// scripts can read. Unsafe requests authenticated by the session cookie must
+// echo it back in the `X-CSRF-Token` header (the synchronizer token is kept
+// server side in the session). Independently, browsers sending an `Origin`
+// or `Referer` on an unsafe request must be on our own origin.
+use std::{
+    env,
+    sync::{Arc, OnceLock},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        Method,
+        header::{ORIGIN, REFERER, UPGRADE},
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::cookie::{Cookie, SameSite};
+use oauth2::{CsrfToken, url::Url};
+use tracing::{debug, error};
+
+use super::AuthenticatedUser;
+use crate::{app::AuthState, error::AuthrError};
+
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+static PUBLIC_ORIGIN: OnceLock<String> = OnceLock::new();
+
+// PUBLIC_ORIGIN, falling back to the base url used for oauth redirects
+fn public_origin() -> &'static str {
+    PUBLIC_ORIGIN.get_or_init(|| {
+        let base = env::var("PUBLIC_ORIGIN")
+            .or_else(|_| env::var("AUTH_REDIRECT_BASE_URL"))
+            .unwrap_or_else(|_| "http://localhost:8080".to_string());
+        match Url::parse(&base) {
+            Ok(url) => url.origin().ascii_serialization(),
+            Err(e) => panic!("invalid public origin {}: {:?}", base, e),
+        }
+    })
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+pub(crate) fn new_token() -> String {
+    CsrfToken::new_random_len(32).into_secret()
+}
+
+// readable by scripts, never sent along with cross site requests
+pub(crate) fn cookie(token: &str, max_age: time::Duration) -> Cookie<'_> {
+    Cookie::build((CSRF_COOKIE, token))
+        .path("/")
+        .max_age(max_age)
+        .same_site(SameSite::Strict)
+        .build()
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Requires the session's token on unsafe requests, must run inside
+// `request_authorizer`. API token requests carry no cookies and are exempt.
+pub async fn verify_token(
+    State(state): State<Arc<AuthState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if is_safe(req.method()) {
+        return next.run(req).await;
+    }
+    let session_id = match req.extensions().get::<AuthenticatedUser>() {
+        Some(AuthenticatedUser {
+            session_id: Some(session_id),
+            ..
+        }) => session_id.clone(),
+        Some(_) => return next.run(req).await,
+        None => {
+            error!("verify_token ran without an authenticated user");
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+    let presented = req
+        .headers()
+        .get(CSRF_HEADER)
+        .map(|v| v.as_bytes().to_vec())
+        .unwrap_or_default();
+    let valid = match state.sessions.lock() {
+        Ok(sessions) => sessions
+            .get(&session_id)
+            .is_some_and(|s| constant_time_eq(s.csrf_token.as_bytes(), &presented)),
+        Err(e) => {
+            error!("{:?}", e);
+            false
+        }
+    };
+    if !valid {
+        debug!(
+            "missing or invalid csrf token on {} {}",
+            req.method(),
+            req.uri()
+        );
+        return AuthrError::NotAuthorized.into_response();
+    }
+    next.run(req).await
+}
+
+// Rejects unsafe requests a browser says came from another origin
+pub async fn verify_origin(req: Request, next: Next) -> Response {
+    // websocket handshakes are GETs, but any page can open one with our
+    // cookies attached
+    if is_safe(req.method()) && !req.headers().contains_key(UPGRADE) {
+        return next.run(req).await;
+    }
+    let origin = match req.headers().get(ORIGIN) {
+        Some(origin) => origin.to_str().ok().map(|o| o.to_string()),
+        None => match req.headers().get(REFERER) {
+            Some(referer) => referer
+                .to_str()
+                .ok()
+                .and_then(|r| Url::parse(r).ok())
+                .map(|url| url.origin().ascii_serialization()),
+            // not a browser, cookies and tokens still apply
+            None => return next.run(req).await,
+        },
+    };
+    match origin {
+        Some(origin) if origin == public_origin() => next.run(req).await,
+        origin => {
+            debug!(
+                "rejected {} {} from origin {:?}",
+                req.method(),
+                req.uri(),
+                origin
+            );
+            AuthrError::NotAuthorized.into_response()
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use futures_util::future::BoxFuture;
+use oauth2::{AuthUrl, ClientId, ClientSecret, Scope, TokenUrl, basic::BasicClient};
+use serde::Deserialize;
+use std::env;
+
+use super::provider::{IdentityProvider, ProviderUser, SetClient, get_json, redirect_url};
+
+#[derive(Debug)]
+pub struct GitHubAuthClient {
+    pub client: SetClient,
+}
+
+impl GitHubAuthClient {
+    pub fn from_env() -> Option<Self> {
+        let client_id = env::var("GITHUB_OAUTH_CLIENT_ID").ok()?;
+        let client_secret = env::var("GITHUB_OAUTH_CLIENT_SECRET").expect("client secret");
+        let auth_uri =
+            AuthUrl::new("https://github.com/login/oauth/authorize".to_string()).expect("auth_uri");
+        let token_uri = TokenUrl::new("https://github.com/login/oauth/access_token".to_string())
+            .expect("token_uri");
+
+        Some(Self {
+            client: BasicClient::new(ClientId::new(client_id))
+                .set_client_secret(ClientSecret::new(client_secret))
+                .set_auth_uri(auth_uri)
+                .set_token_uri(token_uri)
+                .set_redirect_uri(redirect_url("github")),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubUserInfo {
+    id: i64,
+    login: String,
+    name: Option<String>,
+    email: Option<String>,
+    avatar_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+impl IdentityProvider for GitHubAuthClient {
+    fn name(&self) -> &str {
+        "github"
+    }
+
+    fn client(&self) -> &SetClient {
+        &self.client
+    }
+
+    fn scopes(&self) -> Vec<Scope> {
+        vec![
+            Scope::new("read:user".to_string()),
+            Scope::new("user:email".to_string()),
+        ]
+    }
+
+    fn user_info<'a>(&'a self, access_token: &'a str) -> BoxFuture<'a, Result<ProviderUser, ()>> {
+        Box::pin(async move {
+            let user_info: GitHubUserInfo =
+                get_json("https://api.github.com/user", Some(access_token)).await?;
+            // the profile email is optional and carries no verification state,
+            // so prefer the primary address from the emails endpoint
+            let emails: Vec<GitHubEmail> =
+                get_json("https://api.github.com/user/emails", Some(access_token))
+                    .await
+                    .unwrap_or_default();
+            let (email, email_verified) = match emails.into_iter().find(|e| e.primary) {
+                Some(primary) => (Some(primary.email), primary.verified),
+                None => (user_info.email, false),
+            };
+            Ok(ProviderUser {
+                subject: user_info.id.to_string(),
+                email,
+                email_verified,
+                name: Some(user_info.name.unwrap_or(user_info.login)),
+                picture: user_info.avatar_url,
+            })
+        })
+    }
+}
@@ -0,0 +1,457 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use argon2::{
+    Argon2,
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
+};
+use axum::{
+    Form, Router,
+    extract::{ConnectInfo, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use lib_glonk::{
+    store::{SqliteStore, Store},
+    types::RequestObject,
+};
+use oauth2::CsrfToken;
+use serde::Deserialize;
+use tracing::{error, info};
+
+use super::{safe_return_to, start_session};
+use crate::{
+    app::AuthState,
+    auth::AuthenticatedUser,
+    error::AuthrError,
+    types::{
+        ApiToken, ApiTokenByOwnerId, Credential, CredentialByOwnerId, CredentialByUsername,
+        PasswordReset, PasswordResetPending, RequestApiToken, RequestCredential,
+        RequestPasswordReset, RequestUser, User,
+    },
+};
+
+const MIN_PASSWORD_LEN: usize = 8;
+// argon2 happily hashes megabytes, don't let anyone make us
+const MAX_PASSWORD_LEN: usize = 1024;
+const RESET_CODE_TTL: time::Duration = time::Duration::hours(1);
+// compared against when the username is unknown
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+// Failed attempts allowed per window before further attempts are refused
+const MAX_ACCOUNT_FAILURES: u32 = 5;
+const MAX_IP_FAILURES: u32 = 20;
+const FAILURE_WINDOW: time::Duration = time::Duration::minutes(15);
+
+// Counts failed logins per account and per client address
+#[derive(Default)]
+pub struct LoginThrottle {
+    failures: Mutex<HashMap<String, (u32, time::OffsetDateTime)>>,
+}
+
+impl LoginThrottle {
+    fn keys(username: &str, addr: &SocketAddr) -> [(String, u32); 2] {
+        [
+            (
+                format!("user:{}", username.to_lowercase()),
+                MAX_ACCOUNT_FAILURES,
+            ),
+            (format!("ip:{}", addr.ip()), MAX_IP_FAILURES),
+        ]
+    }
+
+    fn is_blocked(&self, username: &str, addr: &SocketAddr) -> bool {
+        let now = time::OffsetDateTime::now_utc();
+        match self.failures.lock() {
+            Ok(mut failures) => {
+                failures.retain(|_, (_, start)| *start + FAILURE_WINDOW > now);
+                Self::keys(username, addr)
+                    .iter()
+                    .any(|(key, max)| matches!(failures.get(key), Some((count, _)) if count >= max))
+            }
+            Err(e) => {
+                error!("{:?}", e);
+                true
+            }
+        }
+    }
+
+    fn record_failure(&self, username: &str, addr: &SocketAddr) {
+        let now = time::OffsetDateTime::now_utc();
+        if let Ok(mut failures) = self.failures.lock() {
+            for (key, _) in Self::keys(username, addr) {
+                failures.entry(key).or_insert((0, now)).0 += 1;
+            }
+        }
+    }
+
+    fn record_success(&self, username: &str) {
+        if let Ok(mut failures) = self.failures.lock() {
+            failures.remove(&format!("user:{}", username.to_lowercase()));
+        }
+    }
+}
+
+// routes
+pub fn routes(state: Arc<AuthState>) -> Router {
+    Router::new()
+        .route("/local/register", post(register))
+        .route("/local/login", post(login))
+        .route("/local/reset", post(reset))
+        .with_state(state)
+}
+
+fn hash_secret(secret: &str) -> Result<String, AuthrError> {
+    let salt = SaltString::generate(&mut OsRng);
+    match Argon2::default().hash_password(secret.as_bytes(), &salt) {
+        Ok(hash) => Ok(hash.to_string()),
+        Err(e) => {
+            error!("{:?}", e);
+            Err(AuthrError::NotAuthorized)
+        }
+    }
+}
+
+fn verify_secret(secret: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(secret.as_bytes(), &parsed)
+            .is_ok(),
+        Err(e) => {
+            error!("{:?}", e);
+            false
+        }
+    }
+}
+
+// hashing is deliberately slow, keep it off the async workers
+async fn hash_blocking(secret: String) -> Result<String, AuthrError> {
+    match tokio::task::spawn_blocking(move || hash_secret(&secret)).await {
+        Ok(hash) => hash,
+        Err(e) => {
+            error!("{:?}", e);
+            Err(AuthrError::NotAuthorized)
+        }
+    }
+}
+
+async fn verify_blocking(secret: String, hash: String) -> bool {
+    tokio::task::spawn_blocking(move || verify_secret(&secret, &hash))
+        .await
+        .unwrap_or(false)
+}
+
+fn valid_username(username: &str) -> bool {
+    (3..=64).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+fn valid_password(password: &str) -> bool {
+    (MIN_PASSWORD_LEN..=MAX_PASSWORD_LEN).contains(&password.chars().count())
+}
+
+fn credential_by_username(store: &SqliteStore, username: &str) -> Option<Credential> {
+    store
+        .get_queries::<Credential>(vec![Box::new(CredentialByUsername::new(
+            username.to_lowercase(),
+        ))])
+        .pop()
+}
+
+fn update_password_hash(
+    store: &SqliteStore,
+    credential: &Credential,
+    password_hash: String,
+) -> Result<Credential, AuthrError> {
+    let request = RequestCredential {
+        id: Some(credential.id),
+        owner_id: Some(credential.owner_id),
+        username: None,
+        password_hash: Some(password_hash),
+    };
+    store
+        .update::<_, Credential>(request)
+        .map_err(|_| AuthrError::NotAuthorized)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterForm {
+    username: String,
+    password: String,
+    name: Option<String>,
+    email: Option<String>,
+}
+
+pub async fn register(
+    State(state): State<Arc<AuthState>>,
+    Form(form): Form<RegisterForm>,
+) -> Response {
+    let username = form.username.to_lowercase();
+    if !valid_username(&username) {
+        return (StatusCode::BAD_REQUEST, "Invalid username").into_response();
+    }
+    if !valid_password(&form.password) {
+        return (StatusCode::BAD_REQUEST, "Invalid password").into_response();
+    }
+    let user = RequestUser {
+        id: None,
+        guid: Some(format!("local/{}", username)),
+        name: Some(form.name.unwrap_or_else(|| username.clone())),
+        email: Some(form.email.unwrap_or_default()),
+        picture: Some(String::new()),
+    };
+    // the same rules as an update to the user later
+    if let Err(e) = user.validate_fields() {
+        return AuthrError::from(e).into_response();
+    }
+    if credential_by_username(&state.store, &username).is_some() {
+        return (StatusCode::CONFLICT, "Username taken").into_response();
+    }
+    let password_hash = match hash_blocking(form.password).await {
+        Ok(hash) => hash,
+        Err(e) => return e.into_response(),
+    };
+
+    let user = match state.store.create::<_, User>(user) {
+        Ok(user) => user,
+        Err(e) => {
+            error!("Could not create user: {:?}", e);
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+    let credential = RequestCredential {
+        id: None,
+        owner_id: Some(user.id),
+        username: Some(username),
+        password_hash: Some(password_hash),
+    };
+    if let Err(e) = state.store.create::<_, Credential>(credential) {
+        error!("Could not create credential: {:?}", e);
+        let _ = state.store.delete::<User>(user.id, None);
+        return (StatusCode::CONFLICT, "Username taken").into_response();
+    }
+    info!("Registered {:?}", user);
+    start_session(&state, user, "/web")
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+    return_to: Option<String>,
+}
+
+pub async fn login(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AuthState>>,
+    Form(form): Form<LoginForm>,
+) -> Response {
+    if state.login_throttle.is_blocked(&form.username, &addr) {
+        return AuthrError::RateLimited.into_response();
+    }
+    let return_to = match safe_return_to(form.return_to.as_deref()) {
+        Ok(return_to) => return_to,
+        Err(()) => return (StatusCode::BAD_REQUEST, "Invalid return_to").into_response(),
+    };
+    let credential = credential_by_username(&state.store, &form.username);
+    // verify against a throwaway hash for unknown users so the response time
+    // does not reveal which usernames exist
+    let hash = match credential {
+        Some(ref credential) => credential.password_hash.clone(),
+        None => DUMMY_HASH
+            .get_or_init(|| hash_secret("").unwrap_or_default())
+            .clone(),
+    };
+    let verified = verify_blocking(form.password, hash).await;
+    let credential = match credential {
+        Some(credential) if verified => credential,
+        Some(_) | None => {
+            state.login_throttle.record_failure(&form.username, &addr);
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+    state.login_throttle.record_success(&form.username);
+
+    match state.store.get::<User>(credential.owner_id) {
+        Some(user) => start_session(&state, user, &return_to),
+        None => AuthrError::NotAuthorized.into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordForm {
+    current_password: String,
+    new_password: String,
+}
+
+pub async fn change_password(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    user: AuthenticatedUser,
+    State(state): State<Arc<AuthState>>,
+    Form(form): Form<ChangePasswordForm>,
+) -> Response {
+    let owner_id = user.id;
+    let credential = match state
+        .store
+        .get_queries::<Credential>(vec![Box::new(CredentialByOwnerId::new(owner_id))])
+        .pop()
+    {
+        Some(credential) => credential,
+        None => return AuthrError::NotFound.into_response(),
+    };
+    if state.login_throttle.is_blocked(&credential.username, &addr) {
+        return AuthrError::RateLimited.into_response();
+    }
+    if !valid_password(&form.new_password) {
+        return (StatusCode::BAD_REQUEST, "Invalid password").into_response();
+    }
+    if !verify_blocking(form.current_password, credential.password_hash.clone()).await {
+        state
+            .login_throttle
+            .record_failure(&credential.username, &addr);
+        return AuthrError::NotAuthorized.into_response();
+    }
+    let password_hash = match hash_blocking(form.new_password).await {
+        Ok(hash) => hash,
+        Err(e) => return e.into_response(),
+    };
+    match update_password_hash(&state.store, &credential, password_hash) {
+        Ok(_) => {
+            sign_out_everywhere(&state, owner_id, user.session_id.as_deref());
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(e) => e.into_response(),
+    }
+}
+
+// Nothing signed in under the old password outlasts it, neither the user's
+// sessions, but for `keep`, nor their API tokens.
+fn sign_out_everywhere(state: &AuthState, owner_id: i64, keep: Option<&str>) {
+    match state.sessions.lock() {
+        Ok(mut sessions) => {
+            sessions.retain(|id, session| session.user.id != owner_id || Some(id.as_str()) == keep)
+        }
+        Err(e) => error!("{:?}", e),
+    }
+    let tokens: Vec<ApiToken> = state
+        .store
+        .get_queries(vec![Box::new(ApiTokenByOwnerId::new(owner_id))]);
+    for token in tokens.into_iter().filter(|token| !token.revoked) {
+        let revoked = RequestApiToken {
+            id: Some(token.id),
+            owner_id: Some(owner_id),
+            name: None,
+            token_hash: None,
+            scopes: None,
+            expires_at: None,
+            created_at: None,
+            revoked: Some(true),
+        };
+        if let Err(e) = state.store.update::<_, ApiToken>(revoked) {
+            error!("Could not revoke token {}: {:?}", token.id, e);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetForm {
+    username: String,
+    code: String,
+    new_password: String,
+}
+
+pub async fn reset(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AuthState>>,
+    Form(form): Form<ResetForm>,
+) -> Response {
+    if state.login_throttle.is_blocked(&form.username, &addr) {
+        return AuthrError::RateLimited.into_response();
+    }
+    if !valid_password(&form.new_password) {
+        return (StatusCode::BAD_REQUEST, "Invalid password").into_response();
+    }
+    let credential = match credential_by_username(&state.store, &form.username) {
+        Some(credential) => credential,
+        None => {
+            state.login_throttle.record_failure(&form.username, &addr);
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+    let pending =
+        state
+            .store
+            .get_queries::<PasswordReset>(vec![Box::new(PasswordResetPending::new(
+                credential.owner_id,
+            ))]);
+    let mut redeemed = None;
+    for reset in pending.into_iter().filter(|r| r.expires_at > now) {
+        if verify_blocking(form.code.clone(), reset.code_hash.clone()).await {
+            redeemed = Some(reset);
+            break;
+        }
+    }
+    let redeemed = match redeemed {
+        Some(redeemed) => redeemed,
+        None => {
+            state.login_throttle.record_failure(&form.username, &addr);
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+
+    let used = RequestPasswordReset {
+        id: Some(redeemed.id),
+        owner_id: Some(redeemed.owner_id),
+        code_hash: None,
+        expires_at: None,
+        used: Some(true),
+    };
+    if let Err(e) = state.store.update::<_, PasswordReset>(used) {
+        error!("Could not redeem reset code: {:?}", e);
+        return AuthrError::NotAuthorized.into_response();
+    }
+    let password_hash = match hash_blocking(form.new_password).await {
+        Ok(hash) => hash,
+        Err(e) => return e.into_response(),
+    };
+    if let Err(e) = update_password_hash(&state.store, &credential, password_hash) {
+        return e.into_response();
+    }
+    sign_out_everywhere(&state, credential.owner_id, None);
+    state.login_throttle.record_success(&form.username);
+
+    match state.store.get::<User>(credential.owner_id) {
+        Some(user) => start_session(&state, user, "/web"),
+        None => AuthrError::NotAuthorized.into_response(),
+    }
+}
+
+// Issue a one time reset code for `username`, handed to the user out of band
+// by whoever administers the deployment.
+pub fn issue_reset_code(store: &SqliteStore, username: &str) -> Result<String, AuthrError> {
+    let credential = credential_by_username(store, username).ok_or(AuthrError::NotFound)?;
+    let code = CsrfToken::new_random_len(9).into_secret();
+    let expires_at = time::OffsetDateTime::now_utc() + RESET_CODE_TTL;
+    let reset = RequestPasswordReset {
+        id: None,
+        owner_id: Some(credential.owner_id),
+        code_hash: Some(hash_secret(&code)?),
+        expires_at: Some(expires_at.unix_timestamp()),
+        used: Some(false),
+    };
+    match store.create::<_, PasswordReset>(reset) {
+        Ok(_) => Ok(code),
+        Err(e) => {
+            error!("Could not create reset code: {:?}", e);
+            Err(AuthrError::NotFound)
+        }
+    }
+}
@@ -1,74 +1,282 @@
-use crate::app::AuthState;
+use crate::{
+    app::{AuthState, AuthrState, Session},
+    error::AuthrError,
+    jobs::Job,
+    ratelimit::rate_limit,
+    types::{Role, RoleByUserId, User},
+};
 use axum::{
     Router,
-    extract::{Request, State},
-    http::{HeaderName, HeaderValue, StatusCode},
-    middleware::Next,
-    response::{IntoResponse, Response},
+    extract::{FromRequestParts, Request, State},
+    http::{
+        StatusCode,
+        header::{AUTHORIZATION, LOCATION, SET_COOKIE},
+        request::Parts,
+    },
+    middleware::{self, Next},
+    response::{AppendHeaders, IntoResponse, Response},
+};
+use axum_extra::extract::{
+    CookieJar,
+    cookie::{Cookie, SameSite},
 };
-use axum_extra::extract::CookieJar;
-use std::{cmp::Ordering, str::FromStr, sync::Arc};
+use futures_util::future::BoxFuture;
+use lib_glonk::store::{SqliteStore, Store};
+use oauth2::{
+    PkceCodeChallenge,
+    url::{Position, Url},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 use tracing::error;
 
+pub mod api_tokens;
+pub mod csrf;
+pub mod github_auth;
 pub mod google_auth;
+pub mod local_auth;
+pub mod oidc_auth;
+pub mod provider;
+
+pub use provider::IdentityProvider;
 
 pub fn routes(state: Arc<AuthState>) -> Router {
-    Router::new().nest_service("/google/", google_auth::routes(state))
+    provider::routes(state.clone())
+        .merge(local_auth::routes(state.clone()))
+        .layer(middleware::from_fn_with_state(state, rate_limit))
 }
 
-// auth middleware
-pub async fn request_authorizer(
-    State(state): State<Arc<AuthState>>,
-    jar: CookieJar,
-    mut req: Request,
-    next: Next,
-) -> Response {
-    let user = match jar.get("session_id") {
-        Some(cookie) => {
-            let session_id = cookie.value_trimmed();
-            match state.sessions.lock() {
-                Ok(sessions) => {
-                    if let Some((user, exp)) = sessions.get(session_id) {
-                        if exp.cmp(&time::OffsetDateTime::now_utc()) != Ordering::Greater {
-                            return (StatusCode::FORBIDDEN, "Not Authorized".to_string())
-                                .into_response();
-                        }
-                        user.clone()
-                    } else {
-                        return (StatusCode::FORBIDDEN, "Not Authorized".to_string())
-                            .into_response();
-                    }
+// Start a session for an authenticated user and send them on to `location`
+pub(crate) fn start_session(state: &AuthState, user: User, location: &str) -> Response {
+    // Generate a PKCE challenge for a new session_id & set cookie
+    let (_pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+    let cookie_exp_duration = time::Duration::minutes(10);
+    let csrf_token = csrf::new_token();
+    match state.sessions.lock() {
+        Ok(mut sessions) => {
+            let now = time::OffsetDateTime::now_utc();
+            let expires = now.checked_add(cookie_exp_duration);
+            match expires {
+                Some(expires) => {
+                    let roles = roles_for(&state.store, user.id);
+                    sessions.insert(
+                        pkce_verifier.secret().clone(),
+                        Session {
+                            user,
+                            roles,
+                            csrf_token: csrf_token.clone(),
+                            expires,
+                        },
+                    );
                 }
-                Err(e) => {
-                    error!("{:?}", e);
-                    return (StatusCode::FORBIDDEN, "Not Authorized".to_string()).into_response();
+                None => {
+                    error!("Could not add {:?} and {:?}", now, cookie_exp_duration);
+                    return AuthrError::NotAuthorized.into_response();
                 }
             }
         }
-        None => {
-            return (StatusCode::FORBIDDEN, "Not Authorized".to_string()).into_response();
-        }
-    };
-
-    let owner_id = format!("{}", user.id);
-    let header_val = match HeaderValue::from_str(owner_id.as_str()) {
-        Ok(v) => v,
         Err(e) => {
             error!("{:?}", e);
-            return (StatusCode::FORBIDDEN, "Not Authorized".to_string()).into_response();
+            return AuthrError::NotAuthorized.into_response();
         }
     };
 
-    let header_name = match HeaderName::from_str("Owner-Id") {
-        Ok(v) => v,
+    let pkce_str = pkce_verifier.into_secret();
+    let cookie = Cookie::build(("session_id", pkce_str.as_str()))
+        .path("/")
+        .max_age(cookie_exp_duration)
+        .http_only(true)
+        // lax so the session survives the redirect back from a provider
+        .same_site(SameSite::Lax)
+        .build();
+    let csrf_cookie = csrf::cookie(&csrf_token, cookie_exp_duration);
+
+    // see other so a login form POST is followed by a GET
+    (
+        StatusCode::SEE_OTHER,
+        AppendHeaders([
+            (SET_COOKIE, cookie.to_string().as_str()),
+            (SET_COOKIE, csrf_cookie.to_string().as_str()),
+            (LOCATION, location),
+        ]),
+    )
+        .into_response()
+}
+
+// Where to send a user once they are signed in. Only paths on this origin
+// are allowed, anything else would hand a fresh session to another site.
+pub(crate) fn safe_return_to(return_to: Option<&str>) -> Result<String, ()> {
+    let path = match return_to {
+        Some(path) => path,
+        None => return Ok("/web".to_string()),
+    };
+    if !path.starts_with('/') || path.starts_with("//") || path.contains('\\') {
+        return Err(());
+    }
+    let base = Url::parse("http://return-to.invalid/").map_err(|_| ())?;
+    match base.join(path) {
+        Ok(url) if url.origin() == base.origin() => Ok(url[Position::BeforePath..].to_string()),
+        Ok(_) | Err(_) => Err(()),
+    }
+}
+
+// every provider with credentials present in the environment
+pub async fn providers_from_env() -> Vec<Arc<dyn IdentityProvider>> {
+    let mut providers: Vec<Arc<dyn IdentityProvider>> = vec![];
+    if let Some(google) = google_auth::GoogleAuthClient::from_env() {
+        providers.push(Arc::new(google));
+    }
+    if let Some(github) = github_auth::GitHubAuthClient::from_env() {
+        providers.push(Arc::new(github));
+    }
+    if let Some(oidc) = oidc_auth::OidcAuthClient::from_env().await {
+        providers.push(Arc::new(oidc));
+    }
+    providers
+}
+
+// The principal a request was authenticated as. Only `request_authorizer`
+// inserts this, as a request extension, so clients cannot supply it.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub id: i64,
+    pub roles: Vec<String>,
+    // None when authenticated with an API token
+    pub session_id: Option<String>,
+    // Some when authenticated with an API token
+    pub api_token_id: Option<i64>,
+}
+
+impl AuthenticatedUser {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+}
+
+impl<S> FromRequestParts<S> for AuthenticatedUser
+where
+    S: Send + Sync,
+{
+    type Rejection = AuthrError;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<AuthenticatedUser>() {
+            Some(user) => Ok(user.clone()),
+            None => {
+                error!("AuthenticatedUser extracted on a route without request_authorizer");
+                Err(AuthrError::NotAuthorized)
+            }
+        }
+    }
+}
+
+// Expired sessions are refused but otherwise kept until they're replaced,
+// so a job clears them out now and then, with abandoned logins.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SweepSessions;
+
+impl Job for SweepSessions {
+    const KIND: &'static str = "sweep_sessions";
+
+    fn run(self, state: Arc<AuthrState>) -> BoxFuture<'static, Result<(), String>> {
+        Box::pin(async move {
+            let now = time::OffsetDateTime::now_utc();
+            state
+                .auth
+                .sessions
+                .lock()
+                .map_err(|e| e.to_string())?
+                .retain(|_, session| session.expires > now);
+            state
+                .auth
+                .oauth_sessions
+                .lock()
+                .map_err(|e| e.to_string())?
+                .retain(|_, pending| pending.expires > now);
+            Ok(())
+        })
+    }
+}
+
+pub(crate) fn roles_for(store: &SqliteStore, user_id: i64) -> Vec<String> {
+    store
+        .get_queries::<Role>(vec![Box::new(RoleByUserId::new(user_id))])
+        .into_iter()
+        .map(|r| r.role)
+        .collect()
+}
+
+// the user the `session_id` cookie in `jar` is signed in as, if any
+pub(crate) fn session_user(state: &AuthState, jar: &CookieJar) -> Option<i64> {
+    let session_id = jar.get("session_id")?.value_trimmed().to_string();
+    match state.sessions.lock() {
+        Ok(sessions) => sessions
+            .get(&session_id)
+            .filter(|session| session.expires > time::OffsetDateTime::now_utc())
+            .map(|session| session.user.id),
         Err(e) => {
             error!("{:?}", e);
-            return (StatusCode::FORBIDDEN, "Not Authorized".to_string()).into_response();
+            None
         }
-    };
-    req.headers_mut().insert(header_name, header_val);
+    }
+}
 
-    let response = next.run(req).await;
+// auth middleware
+pub async fn request_authorizer(
+    State(state): State<Arc<AuthState>>,
+    jar: CookieJar,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    // API tokens authenticate data requests without a session
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_string());
+    let authenticated = if let Some(token) = bearer {
+        match api_tokens::authenticate_bearer(&state, &token) {
+            Some((api_token, user)) if api_token.allows(req.method(), req.uri().path()) => {
+                AuthenticatedUser {
+                    id: user.id,
+                    roles: roles_for(&state.store, user.id),
+                    session_id: None,
+                    api_token_id: Some(api_token.id),
+                }
+            }
+            Some(_) | None => {
+                return AuthrError::NotAuthorized.into_response();
+            }
+        }
+    } else {
+        let session_id = match jar.get("session_id") {
+            Some(cookie) => cookie.value_trimmed().to_string(),
+            None => {
+                return AuthrError::NotAuthorized.into_response();
+            }
+        };
+        match state.sessions.lock() {
+            Ok(sessions) => match sessions.get(&session_id) {
+                Some(session) if session.expires > time::OffsetDateTime::now_utc() => {
+                    AuthenticatedUser {
+                        id: session.user.id,
+                        roles: session.roles.clone(),
+                        session_id: Some(session_id),
+                        api_token_id: None,
+                    }
+                }
+                Some(_) | None => {
+                    return AuthrError::NotAuthorized.into_response();
+                }
+            },
+            Err(e) => {
+                error!("{:?}", e);
+                return AuthrError::NotAuthorized.into_response();
+            }
+        }
+    };
 
-    response
+    req.extensions_mut().insert(authenticated);
+    next.run(req).await
 }
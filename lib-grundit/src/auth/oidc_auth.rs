@@ -0,0 +1,132 @@
+use futures_util::future::BoxFuture;
+use oauth2::{AuthUrl, ClientId, ClientSecret, Scope, TokenUrl, basic::BasicClient};
+use serde::Deserialize;
+use std::env;
+use tracing::error;
+
+use super::provider::{IdentityProvider, ProviderUser, SetClient, get_json, redirect_url};
+
+// Subset of the OpenID Connect discovery document we rely on
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcDiscovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: String,
+}
+
+impl OidcDiscovery {
+    // fails unless the document names `issuer_url` as its issuer
+    pub async fn fetch(issuer_url: &str) -> Result<Self, ()> {
+        let issuer_url = issuer_url.trim_end_matches('/');
+        let url = format!("{}/.well-known/openid-configuration", issuer_url);
+        let discovery: Self = get_json(&url, None).await?;
+        if discovery.issuer.trim_end_matches('/') != issuer_url {
+            error!(
+                "discovery at {} is for issuer {}",
+                issuer_url, discovery.issuer
+            );
+            return Err(());
+        }
+        Ok(discovery)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OidcClaims {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+    picture: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct OidcAuthClient {
+    pub name: String,
+    pub client: SetClient,
+    pub discovery: OidcDiscovery,
+}
+
+impl OidcAuthClient {
+    pub async fn discover(
+        name: String,
+        issuer_url: &str,
+        client_id: String,
+        client_secret: String,
+    ) -> Result<Self, ()> {
+        let discovery = OidcDiscovery::fetch(issuer_url).await?;
+        let auth_uri = match AuthUrl::new(discovery.authorization_endpoint.clone()) {
+            Ok(u) => u,
+            Err(e) => {
+                error!("{:?}", e);
+                return Err(());
+            }
+        };
+        let token_uri = match TokenUrl::new(discovery.token_endpoint.clone()) {
+            Ok(u) => u,
+            Err(e) => {
+                error!("{:?}", e);
+                return Err(());
+            }
+        };
+        let redirect_uri = redirect_url(&name);
+
+        Ok(Self {
+            name,
+            client: BasicClient::new(ClientId::new(client_id))
+                .set_client_secret(ClientSecret::new(client_secret))
+                .set_auth_uri(auth_uri)
+                .set_token_uri(token_uri)
+                .set_redirect_uri(redirect_uri),
+            discovery,
+        })
+    }
+
+    pub async fn from_env() -> Option<Self> {
+        let issuer_url = env::var("OIDC_ISSUER_URL").ok()?;
+        let name = env::var("OIDC_PROVIDER_NAME").unwrap_or_else(|_| "oidc".to_string());
+        let client_id = env::var("OIDC_CLIENT_ID").expect("client id");
+        let client_secret = env::var("OIDC_CLIENT_SECRET").expect("client secret");
+        match Self::discover(name, &issuer_url, client_id, client_secret).await {
+            Ok(client) => Some(client),
+            Err(()) => {
+                error!("OpenID Connect discovery failed for {}", issuer_url);
+                None
+            }
+        }
+    }
+}
+
+impl IdentityProvider for OidcAuthClient {
+    fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    fn client(&self) -> &SetClient {
+        &self.client
+    }
+
+    fn scopes(&self) -> Vec<Scope> {
+        vec![
+            Scope::new("openid".to_string()),
+            Scope::new("email".to_string()),
+            Scope::new("profile".to_string()),
+        ]
+    }
+
+    fn user_info<'a>(&'a self, access_token: &'a str) -> BoxFuture<'a, Result<ProviderUser, ()>> {
+        Box::pin(async move {
+            let claims: OidcClaims =
+                get_json(&self.discovery.userinfo_endpoint, Some(access_token)).await?;
+            Ok(ProviderUser {
+                subject: claims.sub,
+                email: claims.email,
+                email_verified: claims.email_verified,
+                name: claims.name,
+                picture: claims.picture,
+            })
+        })
+    }
+}
@@ -0,0 +1,549 @@
+use std::{collections::HashMap, env, sync::Arc};
+
+use axum::{
+    Router,
+    extract::{Path, Query as UrlQuery, State},
+    http::{HeaderValue, StatusCode, header::SET_COOKIE},
+    response::{self, IntoResponse, Response},
+    routing::get,
+};
+use axum_extra::extract::{
+    CookieJar,
+    cookie::{Cookie, SameSite},
+};
+use futures_util::future::BoxFuture;
+use oauth2::{
+    AuthorizationCode, CsrfToken, EndpointNotSet, EndpointSet, PkceCodeChallenge, PkceCodeVerifier,
+    RedirectUrl, Scope, TokenResponse, reqwest, url::Url,
+};
+use oauth2::{
+    Client, StandardRevocableToken,
+    basic::{
+        BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
+        BasicTokenResponse,
+    },
+};
+
+use super::{csrf, safe_return_to, session_user, start_session};
+use crate::{
+    app::{AuthState, PendingLogin},
+    auth::AuthenticatedUser,
+    error::AuthrError,
+    types::{DataType, Identity, QueryTypes, RequestIdentity, RequestUser, User},
+};
+use lib_glonk::{
+    store::Store,
+    types::{DataObject, Query},
+};
+use serde::Deserialize;
+use tracing::{error, info};
+
+// there has to be a way to get rid of this
+// type SetClient<
+pub type SetClient<
+    HasAuthUrl = EndpointSet,
+    HasDeviceAuthUrl = EndpointNotSet,
+    HasIntrospectionUrl = EndpointNotSet,
+    HasRevocationUrl = EndpointNotSet,
+    HasTokenUrl = EndpointSet,
+> = Client<
+    BasicErrorResponse,
+    BasicTokenResponse,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+    HasAuthUrl,
+    HasDeviceAuthUrl,
+    HasIntrospectionUrl,
+    HasRevocationUrl,
+    HasTokenUrl,
+>;
+
+// The identity a provider vouches for, before it is mapped onto a RequestUser
+#[derive(Debug, Clone)]
+pub struct ProviderUser {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub name: Option<String>,
+    pub picture: Option<String>,
+}
+
+pub trait IdentityProvider: Send + Sync + std::fmt::Debug {
+    // name used in the `/auth/{provider}/...` routes and as the guid prefix
+    fn name(&self) -> &str;
+    fn client(&self) -> &SetClient;
+    fn scopes(&self) -> Vec<Scope>;
+    fn user_info<'a>(&'a self, access_token: &'a str) -> BoxFuture<'a, Result<ProviderUser, ()>>;
+
+    fn authorize_url(&self) -> (Url, CsrfToken, PkceCodeVerifier) {
+        // Generate a PKCE challenge.
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+        // Generate the full authorization URL.
+        let (auth_url, csrf_token) = self
+            .client()
+            .authorize_url(CsrfToken::new_random)
+            // Set the desired scopes.
+            .add_scopes(self.scopes())
+            // Set the PKCE code challenge.
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+
+        (auth_url, csrf_token, pkce_verifier)
+    }
+
+    fn exchange_code(
+        &self,
+        code: String,
+        pkce_verifier: String,
+    ) -> BoxFuture<'_, Result<String, ()>> {
+        Box::pin(async move {
+            // Now you can trade it for an access token.
+            let token_result = self
+                .client()
+                .exchange_code(AuthorizationCode::new(code))
+                // Set the PKCE code verifier.
+                .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier))
+                .request_async(&http_client())
+                .await;
+            match token_result {
+                Ok(token_result) => Ok(token_result.access_token().secret().clone()),
+                Err(e) => {
+                    error!("{} token exchange failed: {:?}", self.name(), e);
+                    Err(())
+                }
+            }
+        })
+    }
+
+    fn to_request_user(&self, user: ProviderUser) -> RequestUser {
+        RequestUser {
+            id: None,
+            guid: Some(format!("{}/{}", self.name(), user.subject)),
+            email: Some(user.email.unwrap_or_default()),
+            name: Some(user.name.unwrap_or_default()),
+            picture: Some(user.picture.unwrap_or_default()),
+        }
+    }
+}
+
+pub fn http_client() -> reqwest::Client {
+    reqwest::ClientBuilder::new()
+        // Following redirects opens the client up to SSRF vulnerabilities.
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("Client should build")
+}
+
+// Redirect url registered with the provider, overridable per deployment
+pub fn redirect_url(provider: &str) -> RedirectUrl {
+    let base =
+        env::var("AUTH_REDIRECT_BASE_URL").unwrap_or_else(|_| "http://localhost:8080".to_string());
+    RedirectUrl::new(format!(
+        "{}/auth/{}/callback",
+        base.trim_end_matches('/'),
+        provider
+    ))
+    .expect("redirect_uri")
+}
+
+// Fetch a json document from a provider endpoint, optionally with a bearer token
+pub(crate) async fn get_json<T: serde::de::DeserializeOwned>(
+    url: &str,
+    access_token: Option<&str>,
+) -> Result<T, ()> {
+    let mut request = http_client()
+        .get(url)
+        .header("Accept", "application/json")
+        // some providers (GitHub) refuse requests without a user agent
+        .header("User-Agent", "grundit");
+    if let Some(access_token) = access_token {
+        request = request.bearer_auth(access_token);
+    }
+    let data = match request.send().await {
+        Ok(data) if data.status().is_success() => data.text().await,
+        Ok(data) => {
+            error!("{} returned {}", url, data.status());
+            return Err(());
+        }
+        Err(e) => {
+            error!("{:?}", e);
+            return Err(());
+        }
+    };
+    match data {
+        Ok(data) => match serde_json::from_str::<T>(&data) {
+            Ok(data) => Ok(data),
+            Err(e) => {
+                error!("{:?}", e);
+                Err(())
+            }
+        },
+        Err(e) => {
+            error!("{:?}", e);
+            Err(())
+        }
+    }
+}
+
+// How long a user has to finish signing in at the provider
+const PENDING_LOGIN_TTL: time::Duration = time::Duration::minutes(10);
+// Unfinished logins kept at once, on top of the per client rate limit
+const MAX_PENDING_LOGINS: usize = 10_000;
+// holds the pending login's nonce in the browser that started it
+const LOGIN_COOKIE: &str = "oauth_login";
+
+// lax so it comes back with the provider's redirect, and only to /auth
+fn login_cookie(nonce: &str, max_age: time::Duration) -> Cookie<'_> {
+    Cookie::build((LOGIN_COOKIE, nonce))
+        .path("/auth")
+        .max_age(max_age)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .build()
+}
+
+// What to do with a provider email the provider has not verified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailPolicy {
+    // refuse the login
+    Reject,
+    // sign in, but don't record the email
+    Ignore,
+    // sign in with the email as is
+    Allow,
+}
+
+impl EmailPolicy {
+    pub fn from_env() -> Self {
+        match env::var("UNVERIFIED_EMAIL_POLICY").as_deref() {
+            Ok("reject") | Err(_) => Self::Reject,
+            Ok("ignore") => Self::Ignore,
+            Ok("allow") => Self::Allow,
+            Ok(other) => panic!("unknown UNVERIFIED_EMAIL_POLICY {}", other),
+        }
+    }
+
+    fn apply(self, user: ProviderUser) -> Result<ProviderUser, ()> {
+        if user.email_verified || user.email.is_none() {
+            return Ok(user);
+        }
+        match self {
+            Self::Reject => Err(()),
+            Self::Ignore => Ok(ProviderUser {
+                email: None,
+                ..user
+            }),
+            Self::Allow => Ok(user),
+        }
+    }
+}
+
+// routes
+pub fn routes(state: Arc<AuthState>) -> Router {
+    Router::new()
+        .route("/{provider}/login", get(login))
+        .route("/{provider}/callback", get(callback))
+        .with_state(state)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginParams {
+    return_to: Option<String>,
+}
+
+pub async fn login(
+    Path(provider_name): Path<String>,
+    UrlQuery(params): UrlQuery<LoginParams>,
+    State(state): State<Arc<AuthState>>,
+) -> impl IntoResponse {
+    start_login(state, provider_name, None, params.return_to)
+}
+
+// same as login, but the resulting identity is attached to the signed in user
+pub async fn link(
+    Path(provider_name): Path<String>,
+    UrlQuery(params): UrlQuery<LoginParams>,
+    user: AuthenticatedUser,
+    State(state): State<Arc<AuthState>>,
+) -> impl IntoResponse {
+    start_login(state, provider_name, Some(user.id), params.return_to)
+}
+
+fn start_login(
+    state: Arc<AuthState>,
+    provider_name: String,
+    link_user: Option<i64>,
+    return_to: Option<String>,
+) -> Response {
+    let provider = match state.providers.get(&provider_name) {
+        Some(provider) => provider,
+        None => {
+            return AuthrError::NotFound.into_response();
+        }
+    };
+    let return_to = match safe_return_to(return_to.as_deref()) {
+        Ok(return_to) => return_to,
+        Err(()) => return (StatusCode::BAD_REQUEST, "Invalid return_to").into_response(),
+    };
+
+    let (auth_url, csrf_token, pkce_verifier) = provider.authorize_url();
+    let nonce = csrf::new_token();
+
+    match state.oauth_sessions.lock() {
+        Ok(mut oauth_sessions) => {
+            // abandoned logins are dropped whenever a new one starts
+            let now = time::OffsetDateTime::now_utc();
+            oauth_sessions.retain(|_, pending| pending.expires > now);
+            if oauth_sessions.len() >= MAX_PENDING_LOGINS {
+                error!("{} logins pending, refusing new ones", oauth_sessions.len());
+                return AuthrError::RateLimited.into_response();
+            }
+            oauth_sessions.insert(
+                csrf_token.into_secret(),
+                PendingLogin {
+                    provider: provider_name,
+                    pkce_verifier: pkce_verifier.secret().clone(),
+                    link_user,
+                    return_to,
+                    nonce: nonce.clone(),
+                    expires: now + PENDING_LOGIN_TTL,
+                },
+            );
+        }
+        Err(e) => {
+            error!("{:?}", e);
+            return response::Redirect::temporary("/").into_response();
+        }
+    };
+
+    (
+        [(
+            SET_COOKIE,
+            login_cookie(&nonce, PENDING_LOGIN_TTL).to_string(),
+        )],
+        response::Redirect::temporary(auth_url.as_str()),
+    )
+        .into_response()
+}
+
+pub async fn callback(
+    Path(provider_name): Path<String>,
+    UrlQuery(params): UrlQuery<HashMap<String, String>>,
+    jar: CookieJar,
+    State(state): State<Arc<AuthState>>,
+) -> impl IntoResponse {
+    let provider = match state.providers.get(&provider_name) {
+        Some(provider) => provider.clone(),
+        None => {
+            return AuthrError::NotFound.into_response();
+        }
+    };
+
+    // the user declined, or the provider could not authenticate them
+    if let Some(error) = params.get("error") {
+        info!("{} login failed: {}", provider_name, error);
+        return AuthrError::NotAuthorized.into_response();
+    }
+
+    let csrf_token_header = params.get("state");
+    let token = match csrf_token_header {
+        Some(token) => token,
+        None => {
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+
+    let code_header = params.get("code");
+    let code = match code_header {
+        Some(code) => code.to_string(),
+        None => {
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+
+    let pending = match state.oauth_sessions.lock() {
+        Ok(mut sessions) => sessions.remove(token.as_str()),
+        Err(e) => {
+            error!("{:?}", e);
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+
+    // the state must have been issued by a recent login for this same
+    // provider, started in this browser
+    let nonce = jar.get(LOGIN_COOKIE).map(|cookie| cookie.value_trimmed());
+    let pending = match pending {
+        Some(pending)
+            if pending.provider == provider_name
+                && pending.expires > time::OffsetDateTime::now_utc()
+                && nonce.is_some_and(|nonce| {
+                    csrf::constant_time_eq(nonce.as_bytes(), pending.nonce.as_bytes())
+                }) =>
+        {
+            pending
+        }
+        Some(_) | None => {
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+    // and a link finished by the same user who asked for it
+    if let Some(link_user) = pending.link_user
+        && session_user(&state, &jar) != Some(link_user)
+    {
+        info!(
+            "{} link for user {} finished by someone else",
+            provider_name, link_user
+        );
+        return AuthrError::NotAuthorized.into_response();
+    }
+
+    let access_token = match provider.exchange_code(code, pending.pkce_verifier).await {
+        Ok(t) => t,
+        Err(_) => {
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+
+    let user_info = match provider.user_info(&access_token).await {
+        Ok(u) => u,
+        Err(_) => {
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+
+    let user_info = match state.email_policy.apply(user_info) {
+        Ok(u) => u,
+        Err(()) => {
+            info!("{} login refused, email is not verified", provider_name);
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+
+    let retrieved = match retrieve_or_create_user(
+        provider.as_ref(),
+        user_info,
+        pending.link_user,
+        state.clone(),
+    )
+    .await
+    {
+        Some(r) => r,
+        None => {
+            return AuthrError::NotAuthorized.into_response();
+        }
+    };
+
+    let mut res = start_session(&state, retrieved, &pending.return_to);
+    let spent = login_cookie("", time::Duration::ZERO).to_string();
+    if let Ok(spent) = HeaderValue::from_str(&spent) {
+        res.headers_mut().append(SET_COOKIE, spent);
+    }
+    res
+}
+
+fn find_by<T: DataObject>(
+    state: &AuthState,
+    data_type: DataType,
+    query: &str,
+    val: &str,
+) -> Vec<T> {
+    let queries = vec![QueryTypes::try_from((
+        &data_type,
+        (&String::from(query), &String::from(val)),
+    ))]
+    .into_iter()
+    .filter_map(|qtr| qtr.ok())
+    .map(|qt| qt.into())
+    .collect::<Vec<Box<dyn Query>>>();
+    state.store.clone().get_queries::<T>(queries)
+}
+
+fn create_identity(
+    state: &AuthState,
+    provider: &dyn IdentityProvider,
+    guid: String,
+    email: Option<String>,
+    owner_id: i64,
+) -> Option<Identity> {
+    let identity = RequestIdentity {
+        id: None,
+        owner_id: Some(owner_id),
+        guid: Some(guid),
+        provider: Some(provider.name().to_string()),
+        email: Some(email.unwrap_or_default()),
+    };
+    match state.store.clone().create(identity) {
+        Ok(identity) => {
+            info!("Linked {:?}", identity);
+            Some(identity)
+        }
+        Err(e) => {
+            error!("Could not create identity: {:?}", e);
+            None
+        }
+    }
+}
+
+// Resolve a provider identity to its user, creating both on first sign in.
+// When `link_user` is set the identity is attached to that user instead.
+async fn retrieve_or_create_user(
+    provider: &dyn IdentityProvider,
+    user_info: ProviderUser,
+    link_user: Option<i64>,
+    state: Arc<AuthState>,
+) -> Option<User> {
+    let email = user_info.email.clone();
+    let user = provider.to_request_user(user_info);
+    let guid = user.guid.clone().unwrap();
+
+    let identities: Vec<Identity> = find_by(&state, DataType::Identity, "byGuid", &guid);
+    if let Some(identity) = identities.first() {
+        return match link_user {
+            Some(link_user) if link_user != identity.owner_id => {
+                error!(
+                    "{} is already linked to user {}, refusing to link to {}",
+                    guid, identity.owner_id, link_user
+                );
+                None
+            }
+            Some(_) | None => state.store.clone().get::<User>(identity.owner_id),
+        };
+    }
+
+    if let Some(link_user) = link_user {
+        let user = state.store.clone().get::<User>(link_user)?;
+        create_identity(&state, provider, guid, email, user.id)?;
+        return Some(user);
+    }
+
+    // users created before identities existed only carry their guid
+    let mut retrieved: Vec<User> = find_by(&state, DataType::User, "byGuid", &guid);
+    retrieved.sort_by_key(|u| u.id);
+    if retrieved.len() > 1 {
+        error!(
+            "Found {} users with guid {}, using the oldest",
+            retrieved.len(),
+            guid
+        );
+    }
+    let user = match retrieved.into_iter().next() {
+        Some(user) => user,
+        None => {
+            info!("Creating new user {:?}", user);
+            match state.store.clone().create::<_, User>(user) {
+                Ok(user) => {
+                    info!("Created {:?}", user);
+                    user
+                }
+                Err(e) => {
+                    error!("Could not create user: {:?}", e);
+                    return None;
+                }
+            }
+        }
+    };
+    create_identity(&state, provider, guid, email, user.id)?;
+    Some(user)
+}
@@ -0,0 +1,117 @@
+// Where attachment bytes live, keyed by the hex sha256 of their contents.
+//
+// `LocalBlobStore` keeps them on disk under BLOB_DIR (default `blobs`) as
+// `{digest[..2]}/{digest}`. Anything else, an object store say, only has to
+// implement `BlobStore`.
+use std::{
+    env, io,
+    io::SeekFrom,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axum::body::Bytes;
+use futures_util::{StreamExt, future::BoxFuture, stream::BoxStream};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+
+pub type BlobStream = BoxStream<'static, io::Result<Bytes>>;
+
+pub trait BlobStore: Send + Sync {
+    // stores the bytes and returns their digest, storing them twice is a no-op
+    fn put(&self, bytes: Bytes) -> BoxFuture<'_, io::Result<String>>;
+    // `len` bytes of the blob starting at `start`
+    fn get<'a>(
+        &'a self,
+        digest: &'a str,
+        start: u64,
+        len: u64,
+    ) -> BoxFuture<'a, io::Result<BlobStream>>;
+    // deleting a missing blob is not an error
+    fn delete<'a>(&'a self, digest: &'a str) -> BoxFuture<'a, io::Result<()>>;
+}
+
+pub fn digest(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+pub struct LocalBlobStore {
+    root: PathBuf,
+    // numbers the files being written, so two uploads never share one
+    next_tmp: AtomicU64,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            next_tmp: AtomicU64::new(0),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(env::var("BLOB_DIR").unwrap_or_else(|_| "blobs".to_string()))
+    }
+
+    // digests come from the database, but never let one walk out of the root
+    fn path(&self, digest: &str) -> io::Result<PathBuf> {
+        if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not a blob digest: {}", digest),
+            ));
+        }
+        Ok(self.root.join(&digest[..2]).join(digest))
+    }
+}
+
+impl BlobStore for LocalBlobStore {
+    fn put(&self, bytes: Bytes) -> BoxFuture<'_, io::Result<String>> {
+        Box::pin(async move {
+            let digest = digest(&bytes);
+            let path = self.path(&digest)?;
+            if fs::try_exists(&path).await? {
+                return Ok(digest);
+            }
+            fs::create_dir_all(&self.root.join(&digest[..2])).await?;
+            // written to the side and renamed, so a blob is never seen half written
+            let tmp = self.root.join(format!(
+                "{}.{}.tmp",
+                digest,
+                self.next_tmp.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::write(&tmp, &bytes).await?;
+            fs::rename(&tmp, &path).await?;
+            Ok(digest)
+        })
+    }
+
+    fn get<'a>(
+        &'a self,
+        digest: &'a str,
+        start: u64,
+        len: u64,
+    ) -> BoxFuture<'a, io::Result<BlobStream>> {
+        Box::pin(async move {
+            let mut file = File::open(self.path(digest)?).await?;
+            file.seek(SeekFrom::Start(start)).await?;
+            Ok(ReaderStream::new(file.take(len)).boxed())
+        })
+    }
+
+    fn delete<'a>(&'a self, digest: &'a str) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            match fs::remove_file(self.path(digest)?).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        })
+    }
+}
@@ -0,0 +1,187 @@
+// When recurring jobs come round, in UTC.
+//
+//   */15 * * * *    five cron fields: minute hour day month weekday
+//   @daily          or @hourly, @weekly, @monthly, @yearly
+//   @every 30s      a fixed interval in s, m, h or d
+//
+// Fields take `*`, numbers, `a-b` ranges, `,` lists and `/n` steps, with
+// Sunday as 0 or 7. As in cron, when both the day and the weekday are
+// restricted a day matching either will do.
+use std::str::FromStr;
+
+use time::{Duration, Month, OffsetDateTime, Time};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    // seconds
+    Every(i64),
+    Cron(Cron),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    // bit n set when n matches
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // whether day and weekday were `*`
+    any_day: bool,
+    any_weekday: bool,
+}
+
+// how far ahead to look before giving up on a schedule like `0 0 30 2 *`
+const HORIZON_DAYS: i64 = 5 * 366;
+
+impl Schedule {
+    // the first time after `after`, in unix seconds, if there is one
+    pub fn next_after(&self, after: i64) -> Option<i64> {
+        match self {
+            Schedule::Every(secs) => after.checked_add(*secs),
+            Schedule::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+impl Cron {
+    fn next_after(&self, after: i64) -> Option<i64> {
+        let start = OffsetDateTime::from_unix_timestamp(after).ok()?;
+        let end = start + Duration::days(HORIZON_DAYS);
+        // the next whole minute
+        let mut t =
+            start.replace_second(0).ok()?.replace_nanosecond(0).ok()? + Duration::minutes(1);
+        while t < end {
+            if !has(self.months, u8::from(t.month()) as u32) {
+                t = next_month(t)?;
+            } else if !self.day_matches(t) {
+                t = t.replace_time(Time::MIDNIGHT) + Duration::days(1);
+            } else if !has(self.hours, t.hour() as u32) {
+                t = t.replace_minute(0).ok()? + Duration::hours(1);
+            } else if !has(self.minutes, t.minute() as u32) {
+                t += Duration::minutes(1);
+            } else {
+                return Some(t.unix_timestamp());
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, t: OffsetDateTime) -> bool {
+        let day = has(self.days, t.day() as u32);
+        let weekday = has(self.weekdays, t.weekday().number_days_from_sunday() as u32);
+        if self.any_day || self.any_weekday {
+            day && weekday
+        } else {
+            day || weekday
+        }
+    }
+}
+
+fn has(set: u64, n: u32) -> bool {
+    set & (1 << n) != 0
+}
+
+fn next_month(t: OffsetDateTime) -> Option<OffsetDateTime> {
+    let (year, month) = match t.month() {
+        Month::December => (t.year() + 1, Month::January),
+        month => (t.year(), month.next()),
+    };
+    t.replace_time(Time::MIDNIGHT)
+        .replace_day(1)
+        .ok()?
+        .replace_year(year)
+        .ok()?
+        .replace_month(month)
+        .ok()
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(every) = s.strip_prefix("@every ") {
+            return every_secs(every.trim()).map(Schedule::Every);
+        }
+        let expression = match s {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            s => s,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err("must be five fields, @every or a shorthand like @daily".to_string());
+        };
+        let mut weekday_set = field(weekdays, 0, 7, "weekday")?;
+        // 7 is Sunday too
+        if has(weekday_set, 7) {
+            weekday_set = (weekday_set & !(1 << 7)) | 1;
+        }
+        Ok(Schedule::Cron(Cron {
+            minutes: field(minutes, 0, 59, "minute")?,
+            hours: field(hours, 0, 23, "hour")?,
+            days: field(days, 1, 31, "day")?,
+            months: field(months, 1, 12, "month")?,
+            weekdays: weekday_set,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        }))
+    }
+}
+
+fn every_secs(every: &str) -> Result<i64, String> {
+    let split = every
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(every.len());
+    let (count, unit) = every.split_at(split);
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err("@every needs a unit of s, m, h or d".to_string()),
+    };
+    match count.parse::<i64>() {
+        Ok(count) if count > 0 => Ok(count * unit),
+        _ => Err(format!("invalid interval: {}", every)),
+    }
+}
+
+fn field(field: &str, min: u32, max: u32, name: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid {}: {}", name, field);
+    let number = |n: &str| match n.parse::<u32>() {
+        Ok(n) if (min..=max).contains(&n) => Ok(n),
+        _ => Err(invalid()),
+    };
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(invalid()),
+            },
+            None => (part, None),
+        };
+        let (from, to) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((from, to)) => (number(from)?, number(to)?),
+            // `5/10` runs from 5 to the end
+            None if step.is_some() => (number(range)?, max),
+            None => {
+                let n = number(range)?;
+                (n, n)
+            }
+        };
+        if from > to {
+            return Err(invalid());
+        }
+        for n in (from..=to).step_by(step.unwrap_or(1) as usize) {
+            set |= 1 << n;
+        }
+    }
+    Ok(set)
+}
@@ -1,5 +1,8 @@
+use axum::Json;
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
+use lib_glonk::{types::ValidationError, validation::Violation};
+use serde_json::json;
 use std::error::Error;
 use std::fmt;
 
@@ -8,6 +11,29 @@ use std::fmt;
 pub enum AuthrError {
     NotFound,
     NotAuthorized,
+    RateLimited,
+    Invalid(Vec<Violation>),
+}
+
+impl From<ValidationError> for AuthrError {
+    fn from(e: ValidationError) -> Self {
+        let violation = |field: &str, rule: &str, message: &str| {
+            AuthrError::Invalid(vec![Violation {
+                field: field.to_string(),
+                rule: rule.to_string(),
+                message: message.to_string(),
+            }])
+        };
+        match e {
+            ValidationError::InvalidOwnerId(_) => AuthrError::NotAuthorized,
+            ValidationError::MissingIdOnUpdate => violation("id", "required", "is required"),
+            ValidationError::MissingRequiredOnCreate(field) => {
+                violation(&field, "required", "is required")
+            }
+            ValidationError::IdProvidedOnCreate => violation("id", "absent", "must not be set"),
+            ValidationError::Violations(violations) => AuthrError::Invalid(violations),
+        }
+    }
 }
 
 impl IntoResponse for AuthrError {
@@ -15,6 +41,14 @@ impl IntoResponse for AuthrError {
         match self {
             AuthrError::NotFound => (StatusCode::NOT_FOUND, "Not Found"),
             AuthrError::NotAuthorized => (StatusCode::FORBIDDEN, "Not Authorized"),
+            AuthrError::RateLimited => (StatusCode::TOO_MANY_REQUESTS, "Too Many Requests"),
+            AuthrError::Invalid(violations) => {
+                return (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(json!({ "errors": violations })),
+                )
+                    .into_response();
+            }
         }
         .into_response()
     }
@@ -29,6 +63,12 @@ impl fmt::Display for AuthrError {
             AuthrError::NotAuthorized => {
                 write!(fmt, "Not Authorized")
             }
+            AuthrError::RateLimited => {
+                write!(fmt, "Too Many Requests")
+            }
+            AuthrError::Invalid(ref violations) => {
+                write!(fmt, "{} invalid field(s)", violations.len())
+            }
         }
     }
 }
@@ -38,6 +78,8 @@ impl Error for AuthrError {
         match *self {
             AuthrError::NotFound => "Not Found error",
             AuthrError::NotAuthorized => "Not Authorized error",
+            AuthrError::RateLimited => "Rate Limited error",
+            AuthrError::Invalid(_) => "Invalid request error",
         }
     }
 
@@ -45,6 +87,8 @@ impl Error for AuthrError {
         match *self {
             AuthrError::NotFound => None,
             AuthrError::NotAuthorized => None,
+            AuthrError::RateLimited => None,
+            AuthrError::Invalid(_) => None,
         }
     }
 }
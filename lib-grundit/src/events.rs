@@ -0,0 +1,145 @@
+// Changes as server-sent events, for clients that can't hold a WebSocket.
+//
+//   GET /data/{type}/events?{query}
+//
+// Streams `created`, `updated` and `deleted` events for one type, filtered
+// by the same queries and scoping as `GET /data/{type}`. Event ids are the
+// store's change log `seq`, so a client reconnecting with `Last-Event-ID`
+// is replayed everything after it the log still keeps. Without one the
+// stream starts at the newest change.
+//
+// Replayed rows are read as they are now, and rows gone by then are
+// skipped. Deletes can't be checked against the queries, so they carry
+// only the id and come for every row of the type, unless the type is
+// scoped to its owner, when they don't come at all.
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{Path, Query as UrlQuery, State},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::{Stream, stream};
+use lib_glonk::store::{Change, ChangeKind, Store};
+use serde_json::json;
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+use tracing::error;
+
+use crate::{
+    app::{self, DataState},
+    auth::AuthenticatedUser,
+    feed,
+    types::{ChangeRecord, ChangesAfter, DataType, LatestChange},
+};
+
+// log entries read at a time
+const PAGE_SIZE: i64 = 100;
+
+pub async fn events(
+    Path(data_type): Path<DataType>,
+    user: AuthenticatedUser,
+    UrlQuery(query): UrlQuery<HashMap<String, String>>,
+    headers: HeaderMap,
+    State(state): State<Arc<DataState>>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // before reading the log, so nothing written in between is missed
+    let changes = state.changes.subscribe();
+    let after = match headers
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse::<i64>().ok())
+    {
+        Some(seq) => seq,
+        None => state
+            .store
+            .get_queries::<ChangeRecord>(vec![Box::new(LatestChange)])
+            .first()
+            .map(|record| record.seq)
+            .unwrap_or(0),
+    };
+    let events = EventStream {
+        table: feed::table_name(data_type),
+        state,
+        data_type,
+        user_id: user.id,
+        query,
+        after,
+        changes,
+        pending: VecDeque::new(),
+    };
+    Sse::new(stream::unfold(events, next)).keep_alive(KeepAlive::default())
+}
+
+struct EventStream {
+    state: Arc<DataState>,
+    data_type: DataType,
+    table: String,
+    user_id: i64,
+    query: HashMap<String, String>,
+    // the last log entry looked at, sent or not
+    after: i64,
+    changes: Receiver<Change>,
+    pending: VecDeque<Event>,
+}
+
+async fn next(mut events: EventStream) -> Option<(Result<Event, Infallible>, EventStream)> {
+    loop {
+        if let Some(event) = events.pending.pop_front() {
+            return Some((Ok(event), events));
+        }
+        let records = events
+            .state
+            .store
+            .get_queries::<ChangeRecord>(vec![Box::new(ChangesAfter::new(
+                events.table.clone(),
+                events.after,
+                PAGE_SIZE,
+            ))]);
+        if records.is_empty() {
+            // nothing new in the log, wait until there might be
+            match events.changes.recv().await {
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+        for record in records {
+            events.after = record.seq;
+            if let Some(event) = events.event(&record) {
+                events.pending.push_back(event);
+            }
+        }
+    }
+}
+
+impl EventStream {
+    fn event(&self, record: &ChangeRecord) -> Option<Event> {
+        let data = match record.kind {
+            ChangeKind::Deleted if app::deletes_public(self.data_type) => {
+                json!({ "id": record.row_id })
+            }
+            ChangeKind::Deleted => return None,
+            ChangeKind::Created | ChangeKind::Updated => feed::read_back(
+                &self.state,
+                self.data_type,
+                self.user_id,
+                &self.query,
+                record.row_id,
+            )?,
+        };
+        match Event::default()
+            .id(record.seq.to_string())
+            .event(record.kind.as_str())
+            .json_data(data)
+        {
+            Ok(event) => Some(event),
+            Err(e) => {
+                error!("{:?}", e);
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,276 @@
+// Live changes over a WebSocket.
+//
+//   GET /data/subscribe
+//
+// Clients send `FeedRequest`s and get `FeedEvent`s back. Every change the
+// store makes is checked against each subscription by reading the row back
+// through the subscription's queries and the same scoping as
+// `GET /data/{type}`, so a socket is only told what its user could have
+// listed. Deleted rows can't be read back, so a subscription remembers the
+// ids it has sent and passes on deletes of those alone.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{
+        State,
+        ws::{Message, WebSocket, WebSocketUpgrade},
+    },
+    response::Response,
+};
+use lib_glonk::{
+    store::{Change, ChangeKind},
+    types::{Criteria, DataObject, EqualsCriteria, Query},
+};
+use sqlite::Value;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, error};
+
+use crate::{
+    app::{self, DataState},
+    auth::AuthenticatedUser,
+    types::{
+        Attachment, Comment, DataType, FeedEvent, FeedRequest, Geofence, Identity, Note, NoteTag,
+        Notification, Punch, QueryTypes, Tag, User, Webhook, WebhookDelivery,
+    },
+};
+
+// changes not yet read by the slowest socket, past this it gets snapshots
+const CHANNEL_CAPACITY: usize = 1024;
+const MAX_SUBSCRIPTIONS: usize = 32;
+
+pub(crate) fn channel() -> broadcast::Sender<Change> {
+    broadcast::channel(CHANNEL_CAPACITY).0
+}
+
+pub async fn subscribe(
+    ws: WebSocketUpgrade,
+    user: AuthenticatedUser,
+    State(state): State<Arc<DataState>>,
+) -> Response {
+    ws.on_upgrade(move |socket| serve(socket, state, user.id))
+}
+
+async fn serve(mut socket: WebSocket, state: Arc<DataState>, user_id: i64) {
+    let mut changes = state.changes.subscribe();
+    let mut subscriptions: HashMap<String, Subscription> = HashMap::new();
+    loop {
+        let events = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    request(&state, user_id, &mut subscriptions, text.as_str())
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // pings are answered for us
+                Some(Ok(_)) => vec![],
+            },
+            change = changes.recv() => match change {
+                Ok(change) => subscriptions
+                    .iter_mut()
+                    .filter_map(|(id, sub)| sub.event(&state, user_id, id, &change))
+                    .collect(),
+                // whatever was missed, start everything over
+                Err(RecvError::Lagged(missed)) => {
+                    debug!("feed for user {} missed {} changes", user_id, missed);
+                    subscriptions
+                        .iter_mut()
+                        .map(|(id, sub)| sub.snapshot(&state, user_id, id))
+                        .collect()
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        for event in events {
+            let text = match serde_json::to_string(&event) {
+                Ok(text) => text,
+                Err(e) => {
+                    error!("{:?}", e);
+                    continue;
+                }
+            };
+            if socket.send(Message::Text(text.into())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn request(
+    state: &DataState,
+    user_id: i64,
+    subscriptions: &mut HashMap<String, Subscription>,
+    text: &str,
+) -> Vec<FeedEvent> {
+    let request = match serde_json::from_str::<FeedRequest>(text) {
+        Ok(request) => request,
+        Err(e) => {
+            return vec![FeedEvent::Error {
+                subscription: String::new(),
+                message: e.to_string(),
+            }];
+        }
+    };
+    match request {
+        FeedRequest::Subscribe {
+            id,
+            data_type,
+            query,
+        } => {
+            let data_type =
+                match serde_json::from_value::<DataType>(serde_json::Value::String(data_type)) {
+                    Ok(data_type) => data_type,
+                    Err(e) => {
+                        return vec![FeedEvent::Error {
+                            subscription: id,
+                            message: e.to_string(),
+                        }];
+                    }
+                };
+            if subscriptions.len() >= MAX_SUBSCRIPTIONS && !subscriptions.contains_key(&id) {
+                return vec![FeedEvent::Error {
+                    subscription: id,
+                    message: format!("at most {} subscriptions", MAX_SUBSCRIPTIONS),
+                }];
+            }
+            let mut sub = Subscription {
+                data_type,
+                query,
+                ids: HashSet::new(),
+            };
+            let snapshot = sub.snapshot(state, user_id, &id);
+            subscriptions.insert(id, sub);
+            vec![snapshot]
+        }
+        FeedRequest::Unsubscribe { id } => {
+            subscriptions.remove(&id);
+            vec![]
+        }
+    }
+}
+
+struct Subscription {
+    data_type: DataType,
+    query: HashMap<String, String>,
+    // what the client has been sent and not told is gone
+    ids: HashSet<i64>,
+}
+
+impl Subscription {
+    fn snapshot(&mut self, state: &DataState, user_id: i64, id: &str) -> FeedEvent {
+        let data = app::list(
+            state,
+            self.data_type,
+            user_id,
+            queries(self.data_type, &self.query),
+        );
+        self.ids = data.iter().filter_map(|row| row["id"].as_i64()).collect();
+        FeedEvent::Snapshot {
+            subscription: id.to_string(),
+            data,
+        }
+    }
+
+    fn event(
+        &mut self,
+        state: &DataState,
+        user_id: i64,
+        id: &str,
+        change: &Change,
+    ) -> Option<FeedEvent> {
+        if change.table != table_name(self.data_type) {
+            return None;
+        }
+        let row = match change.kind {
+            ChangeKind::Deleted => None,
+            ChangeKind::Created | ChangeKind::Updated => {
+                read_back(state, self.data_type, user_id, &self.query, change.id)
+            }
+        };
+        let subscription = id.to_string();
+        match row {
+            Some(data) if self.ids.insert(change.id) => {
+                Some(FeedEvent::Created { subscription, data })
+            }
+            Some(data) => Some(FeedEvent::Updated { subscription, data }),
+            None if self.ids.remove(&change.id) => Some(FeedEvent::Deleted {
+                subscription,
+                id: change.id,
+            }),
+            None => None,
+        }
+    }
+}
+
+// `GET /data/{type}` queries, rebuilt each time as the store takes them by
+// value
+pub(crate) fn queries(data_type: DataType, query: &HashMap<String, String>) -> Vec<Box<dyn Query>> {
+    query
+        .iter()
+        .filter_map(|(k, v)| QueryTypes::try_from((&data_type, (k, v))).ok())
+        .map(|q| q.into())
+        .collect()
+}
+
+// a changed row, if the user would see it listed with these queries
+pub(crate) fn read_back(
+    state: &DataState,
+    data_type: DataType,
+    user_id: i64,
+    query: &HashMap<String, String>,
+    id: i64,
+) -> Option<serde_json::Value> {
+    let mut queries = queries(data_type, query);
+    queries.push(Box::new(ById::new(id)));
+    app::list(state, data_type, user_id, queries)
+        .into_iter()
+        .next()
+}
+
+pub(crate) fn table_name(data_type: DataType) -> String {
+    match data_type {
+        DataType::User => User::table_name(),
+        DataType::Note => Note::table_name(),
+        DataType::Comment => Comment::table_name(),
+        DataType::Punch => Punch::table_name(),
+        DataType::Identity => Identity::table_name(),
+        DataType::Geofence => Geofence::table_name(),
+        DataType::Tag => Tag::table_name(),
+        DataType::NoteTag => NoteTag::table_name(),
+        DataType::Attachment => Attachment::table_name(),
+        DataType::Notification => Notification::table_name(),
+        DataType::Webhook => Webhook::table_name(),
+        DataType::WebhookDelivery => WebhookDelivery::table_name(),
+    }
+}
+
+// the other way around, for changes from every table at once
+pub(crate) fn data_type(table: &str) -> Option<DataType> {
+    DataType::ALL
+        .into_iter()
+        .find(|data_type| table_name(*data_type) == table)
+}
+
+// narrows a subscription's queries to the changed row
+#[derive(Debug)]
+struct ById {
+    inner: EqualsCriteria,
+}
+
+impl ById {
+    fn new(id: i64) -> Self {
+        Self {
+            inner: EqualsCriteria {
+                field: String::from("id"),
+                val: Value::Integer(id),
+            },
+        }
+    }
+}
+
+impl Query for ById {
+    fn build(&self) -> (String, Vec<Value>) {
+        self.inner.build()
+    }
+}
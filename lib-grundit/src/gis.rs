@@ -0,0 +1,437 @@
+// Punches in and out of the usual GIS formats.
+//
+//   GET  /data/punch/export?format=geojson|gpx|kml[&capturedAfter=..][&capturedBefore=..]
+//   POST /data/punch/import     a GPX or GeoJSON file as the body
+//
+// Exports cover the caller's own punches in capture order. They are streamed
+// a page at a time, so a long history never sits in memory all at once.
+//
+// Imports read GPX track, route and way points, or GeoJSON Point features,
+// and run every point through the same validation as a single create. Either
+// every point is valid and they're all stored, or nothing is and the errors
+// come back against `points[i]`. Directions come from the GPX `type` or the
+// GeoJSON `direction` property, and default to `in`.
+use std::{convert::Infallible, env, sync::Arc};
+
+use axum::{
+    Json,
+    body::Body,
+    extract::{Query as UrlQuery, State},
+    http::{
+        StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    },
+    response::{IntoResponse, Response},
+};
+use futures_util::stream;
+use lib_glonk::{store::Store, types::Query, types::RequestObject, validation::Violation};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use tracing::{debug, error};
+
+use crate::{
+    app::DataState,
+    auth::AuthenticatedUser,
+    error::AuthrError,
+    types::{
+        Direction, Geofence, Punch, PunchByOwnerId, PunchCapturedAfter, PunchCapturedBefore,
+        PunchPage, RequestPunch, classify,
+    },
+};
+
+const PAGE_SIZE: i64 = 500;
+const MAX_IMPORT_POINTS: usize = 10_000;
+const DEFAULT_MAX_IMPORT_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    GeoJson,
+    Gpx,
+    Kml,
+}
+
+impl Format {
+    fn content_type(&self) -> &'static str {
+        match self {
+            Format::GeoJson => "application/geo+json",
+            Format::Gpx => "application/gpx+xml",
+            Format::Kml => "application/vnd.google-earth.kml+xml",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::GeoJson => "geojson",
+            Format::Gpx => "gpx",
+            Format::Kml => "kml",
+        }
+    }
+
+    fn header(&self) -> String {
+        match self {
+            Format::GeoJson => r#"{"type":"FeatureCollection","features":["#.to_string(),
+            Format::Gpx => concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<gpx version=\"1.1\" creator=\"grundit\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+                "<trk><name>punches</name><trkseg>\n"
+            )
+            .to_string(),
+            Format::Kml => concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<kml xmlns=\"http://www.opengis.net/kml/2.2\"><Document><name>punches</name>\n"
+            )
+            .to_string(),
+        }
+    }
+
+    // `index` is the punch's position in the whole export
+    fn item(&self, punch: &Punch, index: usize) -> String {
+        let time = timestamp(punch.captured_at);
+        match self {
+            Format::GeoJson => {
+                let mut coordinates = vec![punch.longitude, punch.latitude];
+                coordinates.extend(punch.altitude);
+                let feature = json!({
+                    "type": "Feature",
+                    "id": punch.id,
+                    "geometry": { "type": "Point", "coordinates": coordinates },
+                    "properties": {
+                        "direction": punch.direction,
+                        "time": time,
+                        "captured_at": punch.captured_at,
+                        "received_at": punch.received_at,
+                        "accuracy": punch.accuracy,
+                        "geofence_id": punch.geofence_id,
+                        "flagged": punch.flagged,
+                    },
+                });
+                let separator = if index == 0 { "" } else { "," };
+                format!("{}{}", separator, feature)
+            }
+            Format::Gpx => {
+                let ele = punch
+                    .altitude
+                    .map(|altitude| format!("<ele>{}</ele>", altitude))
+                    .unwrap_or_default();
+                format!(
+                    "<trkpt lat=\"{}\" lon=\"{}\">{}<time>{}</time><name>{}</name><type>{}</type></trkpt>\n",
+                    punch.latitude,
+                    punch.longitude,
+                    ele,
+                    time,
+                    punch.id,
+                    punch.direction.as_str()
+                )
+            }
+            Format::Kml => {
+                let altitude = punch
+                    .altitude
+                    .map(|altitude| format!(",{}", altitude))
+                    .unwrap_or_default();
+                format!(
+                    "<Placemark id=\"punch-{}\"><name>{}</name><TimeStamp><when>{}</when></TimeStamp><Point><coordinates>{},{}{}</coordinates></Point></Placemark>\n",
+                    punch.id,
+                    punch.direction.as_str(),
+                    time,
+                    punch.longitude,
+                    punch.latitude,
+                    altitude
+                )
+            }
+        }
+    }
+
+    fn footer(&self) -> String {
+        match self {
+            Format::GeoJson => "]}".to_string(),
+            Format::Gpx => "</trkseg></trk>\n</gpx>\n".to_string(),
+            Format::Kml => "</Document></kml>\n".to_string(),
+        }
+    }
+}
+
+fn timestamp(time: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(time)
+        .ok()
+        .and_then(|t| t.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportParams {
+    format: Option<String>,
+    captured_after: Option<i64>,
+    captured_before: Option<i64>,
+}
+
+enum Step {
+    Header,
+    // the (captured_at, id) the previous page ended on
+    Page(Option<(i64, i64)>),
+    Footer,
+    Done,
+}
+
+pub async fn export(
+    user: AuthenticatedUser,
+    State(state): State<Arc<DataState>>,
+    UrlQuery(params): UrlQuery<ExportParams>,
+) -> Response {
+    let format = match params.format.as_deref() {
+        Some("geojson") | None => Format::GeoJson,
+        Some("gpx") => Format::Gpx,
+        Some("kml") => Format::Kml,
+        Some(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "format must be geojson, gpx or kml",
+            )
+                .into_response();
+        }
+    };
+
+    let owner_id = user.id;
+    let (after, before) = (params.captured_after, params.captured_before);
+    let chunks = stream::unfold((Step::Header, 0), move |(step, written)| {
+        let state = state.clone();
+        async move {
+            let (chunk, next) = match step {
+                Step::Header => (format.header(), (Step::Page(None), written)),
+                Step::Page(last) => {
+                    let mut queries: Vec<Box<dyn Query>> = vec![
+                        Box::new(PunchByOwnerId::new(owner_id)),
+                        Box::new(PunchPage::new(last, PAGE_SIZE)),
+                    ];
+                    if let Some(after) = after {
+                        queries.push(Box::new(PunchCapturedAfter::new(after)));
+                    }
+                    if let Some(before) = before {
+                        queries.push(Box::new(PunchCapturedBefore::new(before)));
+                    }
+                    let punches: Vec<Punch> = state.store.get_queries(queries);
+                    let chunk: String = punches
+                        .iter()
+                        .enumerate()
+                        .map(|(i, punch)| format.item(punch, written + i))
+                        .collect();
+                    let step = match punches.last() {
+                        Some(punch) if punches.len() as i64 == PAGE_SIZE => {
+                            Step::Page(Some((punch.captured_at, punch.id)))
+                        }
+                        _ => Step::Footer,
+                    };
+                    (chunk, (step, written + punches.len()))
+                }
+                Step::Footer => (format.footer(), (Step::Done, written)),
+                Step::Done => return None,
+            };
+            Some((Ok::<_, Infallible>(chunk), next))
+        }
+    });
+
+    (
+        [
+            (CONTENT_TYPE, format.content_type().to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"punches.{}\"", format.extension()),
+            ),
+        ],
+        Body::from_stream(chunks),
+    )
+        .into_response()
+}
+
+fn violation(field: &str, rule: &str, message: &str) -> Violation {
+    Violation {
+        field: field.to_string(),
+        rule: rule.to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn parse_direction(val: Option<&str>) -> Result<Option<Direction>, Violation> {
+    match val.map(str::trim) {
+        None | Some("") => Ok(Some(Direction::In)),
+        Some(val) => val
+            .parse()
+            .map(Some)
+            .map_err(|_| violation("direction", "direction", "must be in, out or break")),
+    }
+}
+
+fn parse_time(val: Option<&str>) -> Result<Option<i64>, Violation> {
+    match val.map(str::trim) {
+        None => Ok(None),
+        Some(val) => OffsetDateTime::parse(val, &Rfc3339)
+            .map(|t| Some(t.unix_timestamp()))
+            .map_err(|_| violation("captured_at", "time", "must be an RFC 3339 time")),
+    }
+}
+
+fn request(
+    direction: Option<Direction>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    altitude: Option<f64>,
+    accuracy: Option<f64>,
+    captured_at: Option<i64>,
+) -> RequestPunch {
+    RequestPunch {
+        id: None,
+        owner_id: None,
+        direction,
+        latitude,
+        longitude,
+        accuracy,
+        altitude,
+        captured_at,
+        received_at: None,
+        geofence_id: None,
+        flagged: None,
+    }
+}
+
+// GPX track, route and way points
+fn gpx_points(body: &str) -> Result<Vec<Result<RequestPunch, Violation>>, String> {
+    let doc = roxmltree::Document::parse(body).map_err(|e| e.to_string())?;
+    let points = doc
+        .descendants()
+        .filter(|node| matches!(node.tag_name().name(), "trkpt" | "rtept" | "wpt"))
+        .map(|node| {
+            let attribute = |name| node.attribute(name).and_then(|v| v.trim().parse().ok());
+            let child = |name| {
+                node.children()
+                    .find(|child| child.tag_name().name() == name)
+                    .and_then(|child| child.text())
+            };
+            Ok(request(
+                parse_direction(child("type"))?,
+                attribute("lat"),
+                attribute("lon"),
+                child("ele").and_then(|v| v.trim().parse().ok()),
+                None,
+                parse_time(child("time"))?,
+            ))
+        })
+        .collect();
+    Ok(points)
+}
+
+// GeoJSON Point features, on their own or in a FeatureCollection
+fn geojson_points(body: &str) -> Result<Vec<Result<RequestPunch, Violation>>, String> {
+    let doc: Value = serde_json::from_str(body).map_err(|e| e.to_string())?;
+    let features = match doc["type"].as_str() {
+        Some("FeatureCollection") => doc["features"].as_array().cloned().unwrap_or_default(),
+        Some("Feature") => vec![doc],
+        _ => return Err("expected a Feature or FeatureCollection".to_string()),
+    };
+    let points = features
+        .iter()
+        .map(|feature| {
+            let geometry = &feature["geometry"];
+            if geometry["type"] != "Point" {
+                return Err(violation("geometry", "point", "must be a Point"));
+            }
+            let coordinate = |i: usize| geometry["coordinates"][i].as_f64();
+            let properties = &feature["properties"];
+            let captured_at = match properties["captured_at"].as_i64() {
+                Some(captured_at) => Some(captured_at),
+                None => parse_time(properties["time"].as_str())?,
+            };
+            Ok(request(
+                parse_direction(properties["direction"].as_str())?,
+                coordinate(1),
+                coordinate(0),
+                coordinate(2).or(properties["altitude"].as_f64()),
+                properties["accuracy"].as_f64(),
+                captured_at,
+            ))
+        })
+        .collect();
+    Ok(points)
+}
+
+pub async fn import(
+    user: AuthenticatedUser,
+    State(state): State<Arc<DataState>>,
+    body: String,
+) -> Response {
+    let points = if body.trim_start().starts_with('<') {
+        gpx_points(&body)
+    } else {
+        geojson_points(&body)
+    };
+    let points = match points {
+        Ok(points) if points.is_empty() => {
+            return (StatusCode::BAD_REQUEST, "No points to import").into_response();
+        }
+        Ok(points) if points.len() > MAX_IMPORT_POINTS => {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("At most {} points per import", MAX_IMPORT_POINTS),
+            )
+                .into_response();
+        }
+        Ok(points) => points,
+        Err(e) => {
+            debug!("{}", e);
+            return (StatusCode::BAD_REQUEST, "Expected a GPX or GeoJSON file").into_response();
+        }
+    };
+
+    let owner_id = Some(user.id);
+    let received_at = OffsetDateTime::now_utc().unix_timestamp();
+    let fences: Vec<Geofence> = state.store.get_queries(vec![]);
+    let mut payloads = vec![];
+    let mut violations = vec![];
+    for (i, point) in points.into_iter().enumerate() {
+        let checked = point.map_err(|v| vec![v]).and_then(|mut payload| {
+            payload.owner_id = owner_id;
+            payload.received_at = Some(received_at);
+            if let (Some(lat), Some(lon)) = (payload.latitude, payload.longitude) {
+                let (geofence_id, flagged) = classify(&fences, user.id, lat, lon);
+                payload.geofence_id = Some(geofence_id);
+                payload.flagged = Some(flagged);
+            }
+            let valid = payload
+                .validate_create(owner_id)
+                .and_then(|_| payload.validate_fields());
+            match valid.map_err(AuthrError::from) {
+                Ok(()) => Ok(payload),
+                Err(AuthrError::Invalid(violations)) => Err(violations),
+                Err(_) => Err(vec![violation("owner_id", "owner", "must be the caller")]),
+            }
+        });
+        match checked {
+            Ok(payload) => payloads.push(payload),
+            Err(found) => violations.extend(found.into_iter().map(|mut v| {
+                v.field = format!("points[{}].{}", i, v.field);
+                v
+            })),
+        }
+    }
+    if !violations.is_empty() {
+        return AuthrError::Invalid(violations).into_response();
+    }
+
+    match state.store.create_all::<_, Punch>(payloads) {
+        Ok(imported) => Json(json!({ "imported": imported.len() })).into_response(),
+        Err(e) => {
+            error!("import: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Nothing was imported").into_response()
+        }
+    }
+}
+
+// MAX_IMPORT_BYTES caps import bodies, they run larger than other requests
+pub(crate) fn max_import_bytes() -> usize {
+    match env::var("MAX_IMPORT_BYTES") {
+        Ok(val) => val
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid MAX_IMPORT_BYTES: {}", val)),
+        Err(_) => DEFAULT_MAX_IMPORT_BYTES,
+    }
+}
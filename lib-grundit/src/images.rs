@@ -0,0 +1,451 @@
+// Image attachments.
+//
+//   GET /data/attachment/{id}/thumb/{size}    size is small, medium or large
+//
+// Thumbnails are made by a job once a JPEG or PNG is uploaded, with its EXIF
+// orientation applied, and re-encoded as JPEG so they carry no
+// metadata at all. Until they're ready the route answers 503 with a
+// Retry-After.
+//
+// The GPS block of a JPEG's EXIF, or a PNG's eXIf chunk, is blanked on upload
+// unless KEEP_EXIF_GPS is set. Only those bytes change, and a PNG's checksum,
+// so the original is otherwise untouched and keeps its orientation.
+use std::{env, io::Cursor, sync::Arc};
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{CONTENT_TYPE, ETAG, IF_NONE_MATCH, RETRY_AFTER},
+    },
+    response::{IntoResponse, Response},
+};
+use futures_util::{TryStreamExt, future::BoxFuture};
+use image::{DynamicImage, ImageDecoder, ImageReader, Limits, codecs::jpeg::JpegEncoder};
+use lib_glonk::store::Store;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error};
+
+use crate::{
+    app::{AuthrState, DataState},
+    error::AuthrError,
+    jobs::Job,
+    types::{
+        Attachment, RequestAttachment, RequestThumbnail, Thumbnail, ThumbnailByAttachmentId,
+        ThumbnailBySize, Thumbnails,
+    },
+};
+
+// bigger images are left without thumbnails
+const MAX_DIMENSION: u32 = 12_000;
+const JPEG_QUALITY: u8 = 82;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbSize {
+    Small,
+    Medium,
+    Large,
+}
+
+impl ThumbSize {
+    const ALL: [ThumbSize; 3] = [ThumbSize::Small, ThumbSize::Medium, ThumbSize::Large];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThumbSize::Small => "small",
+            ThumbSize::Medium => "medium",
+            ThumbSize::Large => "large",
+        }
+    }
+
+    // longest side in pixels
+    fn pixels(&self) -> u32 {
+        match self {
+            ThumbSize::Small => 160,
+            ThumbSize::Medium => 640,
+            ThumbSize::Large => 1280,
+        }
+    }
+}
+
+impl std::str::FromStr for ThumbSize {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "small" => Ok(ThumbSize::Small),
+            "medium" => Ok(ThumbSize::Medium),
+            "large" => Ok(ThumbSize::Large),
+            _ => Err(()),
+        }
+    }
+}
+
+pub(crate) fn is_image(content_type: &str) -> bool {
+    matches!(content_type, "image/jpeg" | "image/png")
+}
+
+fn keep_gps() -> bool {
+    env::var("KEEP_EXIF_GPS").is_ok_and(|val| !val.is_empty() && val != "0")
+}
+
+// blanks the GPS block of a JPEG's EXIF, when there is one and it's wanted
+pub(crate) fn strip_gps(bytes: Bytes, content_type: &str) -> Bytes {
+    if keep_gps() {
+        return bytes;
+    }
+    let strip = match content_type {
+        "image/jpeg" => strip_jpeg_gps,
+        "image/png" => strip_png_gps,
+        _ => return bytes,
+    };
+    let mut image = bytes.to_vec();
+    if strip(&mut image) {
+        debug!("stripped EXIF GPS");
+        Bytes::from(image)
+    } else {
+        bytes
+    }
+}
+
+// Walks the segments before the image data for an EXIF APP1 and clears its
+// GPS IFD in place. Returns whether anything was cleared.
+fn strip_jpeg_gps(jpeg: &mut [u8]) -> bool {
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return false;
+    }
+    let mut stripped = false;
+    let mut at = 2;
+    while at + 4 <= jpeg.len() && jpeg[at] == 0xFF {
+        let marker = jpeg[at + 1];
+        // start of scan or end of image, no more metadata
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        let len = u16::from_be_bytes([jpeg[at + 2], jpeg[at + 3]]) as usize;
+        // the length counts its own two bytes, anything less is corrupt
+        if len < 2 {
+            break;
+        }
+        let end = (at + 2 + len).min(jpeg.len());
+        let segment = &mut jpeg[at + 4..end];
+        if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+            stripped |= strip_tiff_gps(&mut segment[6..]);
+        }
+        at = end;
+    }
+    stripped
+}
+
+// The same for the eXIf chunk of a PNG, whose checksum then needs redoing.
+fn strip_png_gps(png: &mut [u8]) -> bool {
+    if !png.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return false;
+    }
+    let mut stripped = false;
+    let mut at = 8;
+    while at + 12 <= png.len() {
+        let len = u32::from_be_bytes(png[at..at + 4].try_into().unwrap()) as usize;
+        let Some(end) = at.checked_add(12 + len).filter(|&end| end <= png.len()) else {
+            break;
+        };
+        // eXIf may come after the image data, so look as far as the end
+        let kind = &png[at + 4..at + 8];
+        if kind == b"IEND" {
+            break;
+        }
+        if kind == b"eXIf" && strip_tiff_gps(&mut png[at + 8..end - 4]) {
+            let crc = crc32(&png[at + 4..end - 4]);
+            png[end - 4..end].copy_from_slice(&crc.to_be_bytes());
+            stripped = true;
+        }
+        at = end;
+    }
+    stripped
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn strip_tiff_gps(tiff: &mut [u8]) -> bool {
+    let little = match tiff.get(..2) {
+        Some(b"II") => true,
+        Some(b"MM") => false,
+        _ => return false,
+    };
+    let u16_at = |tiff: &[u8], at: usize| -> Option<usize> {
+        let b: [u8; 2] = tiff.get(at..at + 2)?.try_into().ok()?;
+        Some(if little {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        } as usize)
+    };
+    let u32_at = |tiff: &[u8], at: usize| -> Option<usize> {
+        let b: [u8; 4] = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(if little {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        } as usize)
+    };
+
+    let Some(ifd0) = u32_at(tiff, 4) else {
+        return false;
+    };
+    let gps = (0..u16_at(tiff, ifd0).unwrap_or(0))
+        .map(|i| ifd0 + 2 + i * 12)
+        .find(|&entry| u16_at(tiff, entry) == Some(0x8825))
+        .and_then(|entry| u32_at(tiff, entry + 8));
+    let Some(gps) = gps else {
+        return false;
+    };
+    let Some(count) = u16_at(tiff, gps) else {
+        return false;
+    };
+    for i in 0..count {
+        let entry = gps + 2 + i * 12;
+        let (Some(kind), Some(n)) = (u16_at(tiff, entry + 2), u32_at(tiff, entry + 4)) else {
+            break;
+        };
+        // values over four bytes live elsewhere, the entry holds their offset
+        let len = n.saturating_mul(match kind {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => 0,
+        });
+        if len > 4
+            && let Some(offset) = u32_at(tiff, entry + 8)
+            && let Some(values) = tiff.get_mut(offset..offset.saturating_add(len))
+        {
+            values.fill(0);
+        }
+        if let Some(entry) = tiff.get_mut(entry..entry + 12) {
+            entry.fill(0);
+        }
+    }
+    // an empty GPS IFD is still a valid one
+    tiff[gps..gps + 2].fill(0);
+    true
+}
+
+struct Thumb {
+    size: ThumbSize,
+    width: u32,
+    height: u32,
+    jpeg: Vec<u8>,
+}
+
+// one for every size, never scaled up
+fn thumbnails(bytes: &[u8]) -> image::ImageResult<Vec<Thumb>> {
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    let mut thumbs = vec![];
+    for size in ThumbSize::ALL {
+        let pixels = size.pixels();
+        let thumb = if image.width().max(image.height()) > pixels {
+            image.thumbnail(pixels, pixels)
+        } else {
+            image.clone()
+        };
+        let mut jpeg = vec![];
+        thumb
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY))?;
+        thumbs.push(Thumb {
+            size,
+            width: thumb.width(),
+            height: thumb.height(),
+            jpeg,
+        });
+    }
+    Ok(thumbs)
+}
+
+// makes the thumbnails for a new image attachment off the request
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct MakeThumbnails {
+    pub(crate) attachment_id: i64,
+}
+
+impl Job for MakeThumbnails {
+    const KIND: &'static str = "make_thumbnails";
+
+    fn run(self, state: Arc<AuthrState>) -> BoxFuture<'static, Result<(), String>> {
+        Box::pin(async move {
+            process(&state.data, self.attachment_id).await;
+            Ok(())
+        })
+    }
+}
+
+async fn process(state: &DataState, attachment_id: i64) {
+    // nothing to do if it was deleted, or an earlier run got as far as
+    // marking it
+    let attachment = match state.store.get::<Attachment>(attachment_id) {
+        Some(attachment) if attachment.thumbnails == Thumbnails::Pending => attachment,
+        Some(_) | None => return,
+    };
+    let thumbnails = match read(state, &attachment).await {
+        Ok(bytes) => tokio::task::spawn_blocking(move || thumbnails(&bytes))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|made| made.map_err(|e| e.to_string())),
+        Err(e) => Err(e),
+    };
+    let state_after = match thumbnails {
+        Ok(thumbnails) => store(state, &attachment, thumbnails).await,
+        Err(e) => {
+            debug!("no thumbnails for attachment {}: {}", attachment.id, e);
+            Thumbnails::Failed
+        }
+    };
+    let update = RequestAttachment {
+        id: Some(attachment.id),
+        owner_id: Some(attachment.owner_id),
+        note_id: None,
+        name: None,
+        content_type: None,
+        size: None,
+        digest: None,
+        created_at: None,
+        thumbnails: Some(state_after),
+    };
+    // gone already if it was deleted in the meantime
+    let _ = state.store.update::<_, Attachment>(update);
+}
+
+async fn read(state: &DataState, attachment: &Attachment) -> Result<Vec<u8>, String> {
+    let stream = state
+        .blobs
+        .get(&attachment.digest, 0, attachment.size as u64)
+        .await
+        .map_err(|e| e.to_string())?;
+    let chunks: Vec<Bytes> = stream.try_collect().await.map_err(|e| e.to_string())?;
+    Ok(chunks.concat())
+}
+
+async fn store(state: &DataState, attachment: &Attachment, thumbnails: Vec<Thumb>) -> Thumbnails {
+    // under the lock, so a delete of the attachment either comes first and is
+    // seen here, or comes after and takes these thumbnails with it
+    let _guard = state.blob_lock.lock().await;
+    if state.store.get::<Attachment>(attachment.id).is_none() {
+        return Thumbnails::Failed;
+    }
+    for thumb in thumbnails {
+        // left by a run that was cut short
+        let made: Vec<Thumbnail> = state.store.get_queries(vec![
+            Box::new(ThumbnailByAttachmentId::new(attachment.id)),
+            Box::new(ThumbnailBySize::new(thumb.size.as_str().to_string())),
+        ]);
+        if !made.is_empty() {
+            continue;
+        }
+        let digest = match state.blobs.put(Bytes::from(thumb.jpeg)).await {
+            Ok(digest) => digest,
+            Err(e) => {
+                error!("storing thumbnail: {}", e);
+                return Thumbnails::Failed;
+            }
+        };
+        let thumbnail = RequestThumbnail {
+            id: None,
+            owner_id: Some(attachment.owner_id),
+            attachment_id: Some(attachment.id),
+            size: Some(thumb.size.as_str().to_string()),
+            width: Some(thumb.width as i64),
+            height: Some(thumb.height as i64),
+            digest: Some(digest),
+        };
+        if state.store.create::<_, Thumbnail>(thumbnail).is_err() {
+            return Thumbnails::Failed;
+        }
+    }
+    Thumbnails::Ready
+}
+
+pub async fn thumb(
+    Path((id, size)): Path<(i64, String)>,
+    State(state): State<Arc<DataState>>,
+    headers: HeaderMap,
+) -> Response {
+    let Ok(size) = size.parse::<ThumbSize>() else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "size must be small, medium or large",
+        )
+            .into_response();
+    };
+    let attachment: Attachment = match state.store.get(id) {
+        Some(attachment) => attachment,
+        None => return AuthrError::NotFound.into_response(),
+    };
+    match attachment.thumbnails {
+        Thumbnails::Ready => {}
+        Thumbnails::Pending => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(RETRY_AFTER, "1")],
+                "Thumbnail not ready",
+            )
+                .into_response();
+        }
+        Thumbnails::Unsupported | Thumbnails::Failed => {
+            return AuthrError::NotFound.into_response();
+        }
+    }
+    let thumbnail: Option<Thumbnail> = state
+        .store
+        .get_queries(vec![
+            Box::new(ThumbnailByAttachmentId::new(id)),
+            Box::new(ThumbnailBySize::new(size.as_str().to_string())),
+        ])
+        .pop();
+    let Some(thumbnail) = thumbnail else {
+        return AuthrError::NotFound.into_response();
+    };
+
+    // content addressed, so the digest is as good a tag as any
+    let etag = format!("\"{}\"", thumbnail.digest);
+    if headers
+        .get(IF_NONE_MATCH)
+        .is_some_and(|val| val.as_bytes() == etag.as_bytes())
+    {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+    }
+    let jpeg = match state.blobs.get(&thumbnail.digest, 0, u64::MAX).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!("reading thumbnail {}: {}", thumbnail.id, e);
+            return AuthrError::NotFound.into_response();
+        }
+    };
+    (
+        [(CONTENT_TYPE, "image/jpeg".to_string()), (ETAG, etag)],
+        axum::body::Body::from_stream(jpeg),
+    )
+        .into_response()
+}
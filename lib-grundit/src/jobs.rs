@@ -0,0 +1,444 @@
+// Work deferred from requests, kept in the store so it survives restarts.
+//
+//   GET /data/jobs?status=queued|running|failed|dead    admins only
+//
+// A job is a `Job` saved as JSON under its `KIND`, which the `Registry` maps
+// back to the type to run it. Up to JOB_CONCURRENCY (default 4) run at once.
+// One that fails is tried again after JOB_RETRY_BASE_SECS (default 10),
+// doubling each time up to an hour, until it has had `MAX_ATTEMPTS` and is
+// dead, left for an admin to look at. Jobs that run once are deleted when
+// they succeed.
+//
+// Recurring jobs are registered with a `cron::Schedule` and kept as a row
+// per kind, which goes back in the queue for its next time after each run,
+// or once it's out of attempts.
+//
+// Jobs run at least once. Any left running by a crash are queued again on
+// start, and on shutdown the worker takes no more and waits up to
+// JOB_DRAIN_SECS (default 30) for the running ones to finish.
+use std::{collections::HashMap, env, panic::AssertUnwindSafe, sync::Arc, time::Duration};
+
+use axum::{
+    Json,
+    extract::{Query as UrlQuery, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures_util::{
+    FutureExt,
+    future::{self, BoxFuture},
+};
+use lib_glonk::{store::Store, types::Query};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use time::OffsetDateTime;
+use tokio::{
+    sync::{Notify, Semaphore},
+    task::JoinHandle,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info};
+
+use crate::{
+    app::{AuthrState, DataState},
+    auth::AuthenticatedUser,
+    cron::Schedule,
+    error::AuthrError,
+    types::{JobByStatus, JobRecord, JobRecurring, JobStatus, JobsDue, NextJob, RequestJob},
+};
+
+const DEFAULT_CONCURRENCY: usize = 4;
+const DEFAULT_RETRY_BASE_SECS: i64 = 10;
+const MAX_RETRY_SECS: i64 = 60 * 60;
+const DEFAULT_DRAIN_SECS: u64 = 30;
+// longest the worker sleeps with nothing due
+const IDLE: Duration = Duration::from_secs(60);
+// kept of an error on the row
+const MAX_ERROR_LEN: usize = 500;
+
+pub trait Job: Serialize + DeserializeOwned + Send + 'static {
+    // names the job in the queue, so it mustn't change once jobs are saved
+    const KIND: &'static str;
+    const MAX_ATTEMPTS: i64 = 5;
+
+    fn run(self, state: Arc<AuthrState>) -> BoxFuture<'static, Result<(), String>>;
+}
+
+type Runner =
+    Box<dyn Fn(Arc<AuthrState>, &str) -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+// the jobs the worker knows how to run
+#[derive(Default)]
+pub struct Registry {
+    runners: HashMap<&'static str, Runner>,
+    recurring: Vec<Recurring>,
+}
+
+struct Recurring {
+    kind: &'static str,
+    schedule: String,
+    payload: String,
+    max_attempts: i64,
+}
+
+impl Registry {
+    pub fn register<J: Job>(&mut self) -> &mut Self {
+        self.runners.insert(
+            J::KIND,
+            Box::new(|state, payload| match serde_json::from_str::<J>(payload) {
+                Ok(job) => job.run(state),
+                Err(e) => future::ready(Err(e.to_string())).boxed(),
+            }),
+        );
+        self
+    }
+
+    // a bad schedule panics, as it's fixed when the server starts
+    pub fn recurring<J: Job>(&mut self, schedule: &str, job: J) -> &mut Self {
+        if let Err(e) = schedule.parse::<Schedule>() {
+            panic!("invalid schedule for {}: {}", J::KIND, e);
+        }
+        self.recurring.retain(|recurring| recurring.kind != J::KIND);
+        self.recurring.push(Recurring {
+            kind: J::KIND,
+            schedule: schedule.to_string(),
+            payload: serde_json::to_string(&job).expect("jobs serialize"),
+            max_attempts: J::MAX_ATTEMPTS,
+        });
+        self.register::<J>()
+    }
+}
+
+// what requests need to queue jobs
+#[derive(Default)]
+pub struct Queue {
+    wake: Notify,
+    shutdown: CancellationToken,
+}
+
+impl Queue {
+    pub(crate) fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+pub fn enqueue<J: Job>(state: &DataState, job: &J) -> Result<i64, String> {
+    enqueue_at(state, job, now())
+}
+
+// runs no sooner than `run_at`, in unix seconds
+pub fn enqueue_at<J: Job>(state: &DataState, job: &J, run_at: i64) -> Result<i64, String> {
+    let now = now();
+    let request = RequestJob {
+        id: None,
+        kind: Some(J::KIND.to_string()),
+        payload: Some(serde_json::to_string(job).map_err(|e| e.to_string())?),
+        status: Some(JobStatus::Queued),
+        attempts: Some(0),
+        max_attempts: Some(J::MAX_ATTEMPTS),
+        run_at: Some(run_at),
+        schedule: Some(String::new()),
+        last_error: Some(String::new()),
+        created_at: Some(now),
+        updated_at: Some(now),
+    };
+    let job = state
+        .store
+        .create::<_, JobRecord>(request)
+        .map_err(|e| format!("{:?}", e))?;
+    state.jobs.wake.notify_one();
+    Ok(job.id)
+}
+
+pub(crate) fn start(state: Arc<AuthrState>) -> JoinHandle<()> {
+    tokio::spawn(work(state))
+}
+
+async fn work(state: Arc<AuthrState>) {
+    let data = state.data.clone();
+    recover(&data);
+    schedule(&state);
+    let concurrency = concurrency();
+    let permits = Arc::new(Semaphore::new(concurrency));
+    while !data.jobs.shutdown.is_cancelled() {
+        let free = permits.available_permits() as i64;
+        let due: Vec<JobRecord> = match free {
+            0 => vec![],
+            free => data
+                .store
+                .get_queries(vec![Box::new(JobsDue::new(now(), free))]),
+        };
+        for job in due {
+            let Ok(permit) = permits.clone().try_acquire_owned() else {
+                break;
+            };
+            let Some(job) = claim(&data, &job) else {
+                continue;
+            };
+            let state = state.clone();
+            tokio::spawn(async move {
+                let result = run(&state, &job).await;
+                finish(&state.data, &job, result);
+                drop(permit);
+                state.data.jobs.wake.notify_one();
+            });
+        }
+        // woken by a job finishing when they're all taken
+        let wait = match permits.available_permits() {
+            0 => IDLE,
+            _ => until_next(&data),
+        };
+        tokio::select! {
+            _ = data.jobs.wake.notified() => {}
+            _ = data.jobs.shutdown.cancelled() => {}
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+    let drain = tokio::time::timeout(drain(), permits.acquire_many(concurrency as u32));
+    if drain.await.is_err() {
+        info!(
+            "{} jobs still running at shutdown, they'll run again on start",
+            concurrency - permits.available_permits()
+        );
+    }
+}
+
+// jobs a crash left running
+fn recover(state: &DataState) {
+    let running: Vec<JobRecord> = state
+        .store
+        .get_queries(vec![Box::new(JobByStatus::new(JobStatus::Running))]);
+    for job in running {
+        debug!("queueing job {} again, it was running at shutdown", job.id);
+        update(
+            state,
+            &job,
+            JobStatus::Queued,
+            job.attempts,
+            job.run_at,
+            None,
+        );
+    }
+}
+
+// keeps a row for each recurring job, and no others
+fn schedule(state: &AuthrState) {
+    let now = now();
+    let rows: Vec<JobRecord> = state.data.store.get_queries(vec![Box::new(JobRecurring)]);
+    for row in &rows {
+        if !state.jobs.recurring.iter().any(|r| r.kind == row.kind) {
+            let _ = state.data.store.delete::<JobRecord>(row.id, Some(row.id));
+        }
+    }
+    for recurring in &state.jobs.recurring {
+        let next = recurring
+            .schedule
+            .parse::<Schedule>()
+            .ok()
+            .and_then(|schedule| schedule.next_after(now))
+            .unwrap_or(now);
+        let existing = rows.iter().find(|row| row.kind == recurring.kind);
+        let request = match existing {
+            Some(row)
+                if row.schedule == recurring.schedule
+                    && row.payload == recurring.payload
+                    && row.max_attempts == recurring.max_attempts =>
+            {
+                continue;
+            }
+            // changed since it was saved, so it starts over
+            Some(row) => RequestJob {
+                id: Some(row.id),
+                kind: None,
+                payload: Some(recurring.payload.clone()),
+                status: Some(JobStatus::Queued),
+                attempts: Some(0),
+                max_attempts: Some(recurring.max_attempts),
+                run_at: Some(next),
+                schedule: Some(recurring.schedule.clone()),
+                last_error: None,
+                created_at: None,
+                updated_at: Some(now),
+            },
+            None => RequestJob {
+                id: None,
+                kind: Some(recurring.kind.to_string()),
+                payload: Some(recurring.payload.clone()),
+                status: Some(JobStatus::Queued),
+                attempts: Some(0),
+                max_attempts: Some(recurring.max_attempts),
+                run_at: Some(next),
+                schedule: Some(recurring.schedule.clone()),
+                last_error: Some(String::new()),
+                created_at: Some(now),
+                updated_at: Some(now),
+            },
+        };
+        let saved = match request.id {
+            Some(_) => state.data.store.update::<_, JobRecord>(request),
+            None => state.data.store.create::<_, JobRecord>(request),
+        };
+        if let Err(e) = saved {
+            error!("could not schedule {}: {:?}", recurring.kind, e);
+        }
+    }
+}
+
+fn claim(state: &DataState, job: &JobRecord) -> Option<JobRecord> {
+    update(
+        state,
+        job,
+        JobStatus::Running,
+        job.attempts,
+        job.run_at,
+        None,
+    )
+}
+
+async fn run(state: &Arc<AuthrState>, job: &JobRecord) -> Result<(), String> {
+    let Some(runner) = state.jobs.runners.get(job.kind.as_str()) else {
+        return Err(format!("nothing registered to run {}", job.kind));
+    };
+    // a panic is a failure like any other
+    match AssertUnwindSafe(runner(state.clone(), &job.payload))
+        .catch_unwind()
+        .await
+    {
+        Ok(result) => result,
+        Err(_) => Err("panicked".to_string()),
+    }
+}
+
+fn finish(state: &DataState, job: &JobRecord, result: Result<(), String>) {
+    let now = now();
+    let attempts = job.attempts + 1;
+    let next = job
+        .schedule
+        .parse::<Schedule>()
+        .ok()
+        .and_then(|schedule| schedule.next_after(now));
+    match (result, next) {
+        (Ok(()), None) => {
+            let _ = state.store.delete::<JobRecord>(job.id, Some(job.id));
+        }
+        (Ok(()), Some(next)) => {
+            update(state, job, JobStatus::Queued, 0, next, Some(String::new()));
+        }
+        (Err(e), next) => {
+            debug!("job {} ({}) failed: {}", job.id, job.kind, e);
+            let e: String = e.chars().take(MAX_ERROR_LEN).collect();
+            match next {
+                _ if attempts < job.max_attempts => {
+                    let retry_at = now + backoff(attempts);
+                    update(state, job, JobStatus::Failed, attempts, retry_at, Some(e));
+                }
+                Some(next) => {
+                    update(state, job, JobStatus::Queued, 0, next, Some(e));
+                }
+                None => {
+                    error!("job {} ({}) is dead: {}", job.id, job.kind, e);
+                    update(state, job, JobStatus::Dead, attempts, job.run_at, Some(e));
+                }
+            }
+        }
+    }
+}
+
+fn update(
+    state: &DataState,
+    job: &JobRecord,
+    status: JobStatus,
+    attempts: i64,
+    run_at: i64,
+    last_error: Option<String>,
+) -> Option<JobRecord> {
+    let update = RequestJob {
+        id: Some(job.id),
+        kind: None,
+        payload: None,
+        status: Some(status),
+        attempts: Some(attempts),
+        max_attempts: None,
+        run_at: Some(run_at),
+        schedule: None,
+        last_error,
+        created_at: None,
+        updated_at: Some(now()),
+    };
+    state.store.update::<_, JobRecord>(update).ok()
+}
+
+fn until_next(state: &DataState) -> Duration {
+    let next: Vec<JobRecord> = state.store.get_queries(vec![Box::new(NextJob)]);
+    match next.first() {
+        Some(job) => Duration::from_secs((job.run_at - now()).max(0) as u64).min(IDLE),
+        None => IDLE,
+    }
+}
+
+// seconds before trying again after this many attempts
+fn backoff(attempts: i64) -> i64 {
+    let doublings = (attempts - 1).clamp(0, 20) as u32;
+    retry_base_secs()
+        .saturating_mul(1 << doublings)
+        .min(MAX_RETRY_SECS)
+}
+
+fn concurrency() -> usize {
+    match env::var("JOB_CONCURRENCY") {
+        Ok(val) => match val.parse() {
+            Ok(n) if n > 0 => n,
+            _ => panic!("invalid JOB_CONCURRENCY: {}", val),
+        },
+        Err(_) => DEFAULT_CONCURRENCY,
+    }
+}
+
+fn retry_base_secs() -> i64 {
+    match env::var("JOB_RETRY_BASE_SECS") {
+        Ok(val) => val
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid JOB_RETRY_BASE_SECS: {}", val)),
+        Err(_) => DEFAULT_RETRY_BASE_SECS,
+    }
+}
+
+fn drain() -> Duration {
+    match env::var("JOB_DRAIN_SECS") {
+        Ok(val) => Duration::from_secs(
+            val.parse()
+                .unwrap_or_else(|_| panic!("invalid JOB_DRAIN_SECS: {}", val)),
+        ),
+        Err(_) => Duration::from_secs(DEFAULT_DRAIN_SECS),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListParams {
+    status: Option<String>,
+}
+
+pub async fn list(
+    user: AuthenticatedUser,
+    UrlQuery(params): UrlQuery<ListParams>,
+    State(state): State<Arc<DataState>>,
+) -> Response {
+    if !user.has_role("admin") {
+        return AuthrError::NotAuthorized.into_response();
+    }
+    let queries: Vec<Box<dyn Query>> = match params.status.as_deref().map(str::parse) {
+        Some(Ok(status)) => vec![Box::new(JobByStatus::new(status))],
+        Some(Err(())) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                "status must be queued, running, failed or dead",
+            )
+                .into_response();
+        }
+        None => vec![],
+    };
+    Json(state.store.get_queries::<JobRecord>(queries)).into_response()
+}
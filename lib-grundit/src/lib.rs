@@ -2,12 +2,40 @@
 #[cfg(feature = "full")]
 pub mod app;
 #[cfg(feature = "full")]
+pub mod attachments;
+#[cfg(feature = "full")]
 pub mod auth;
 #[cfg(feature = "full")]
+pub mod blob;
+#[cfg(feature = "full")]
 pub mod config;
 #[cfg(feature = "full")]
+pub mod cron;
+#[cfg(feature = "full")]
 pub mod error;
+#[cfg(feature = "full")]
+pub mod events;
+#[cfg(feature = "full")]
+pub mod feed;
+#[cfg(feature = "full")]
+pub mod gis;
+#[cfg(feature = "full")]
+pub mod images;
+#[cfg(feature = "full")]
+pub mod jobs;
+#[cfg(feature = "full")]
+pub mod markdown;
+#[cfg(feature = "full")]
+pub mod notifications;
+#[cfg(feature = "full")]
+pub mod ratelimit;
+#[cfg(feature = "full")]
+pub mod sync;
+#[cfg(feature = "full")]
+pub mod timesheet;
 pub mod types;
+#[cfg(feature = "full")]
+pub mod webhooks;
 
 #[cfg(feature = "full")]
 pub use app::{AuthrState, run};
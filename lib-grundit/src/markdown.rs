@@ -0,0 +1,145 @@
+// Notes are written in CommonMark and served as sanitized HTML.
+//
+//   GET /data/note/{id}/rendered
+//
+// Raw HTML in a note goes through an allowlist, links only keep http(s) and
+// mailto urls and always get `rel="nofollow noopener noreferrer"`, and
+// headings get ids from their text so they can be linked to. Rendered notes
+// are cached by id and version, and the version doubles as the ETag.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{
+        HeaderMap, StatusCode,
+        header::{CONTENT_SECURITY_POLICY, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+    },
+    response::{IntoResponse, Response},
+};
+use lib_glonk::store::Store;
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag, TagEnd, html};
+
+use crate::{app::DataState, error::AuthrError, types::Note};
+
+// the cache is emptied when it grows past this
+const MAX_CACHED: usize = 1_000;
+
+static SANITIZER: LazyLock<ammonia::Builder<'static>> = LazyLock::new(|| {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .url_schemes(HashSet::from(["http", "https", "mailto"]))
+        .link_rel(Some("nofollow noopener noreferrer"));
+    for heading in ["h1", "h2", "h3", "h4", "h5", "h6"] {
+        builder.add_tag_attributes(heading, &["id"]);
+    }
+    builder
+});
+
+// `Hello, World!` -> `hello-world`
+fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.trim().chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if (c.is_whitespace() || c == '-' || c == '_') && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    match slug.trim_matches('-') {
+        "" => "section".to_string(),
+        slug => slug.to_string(),
+    }
+}
+
+pub fn render(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let mut events: Vec<Event> = Parser::new_ext(markdown, options).collect();
+
+    // heading ids, numbered when the same text comes up again
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for i in 0..events.len() {
+        if !matches!(events[i], Event::Start(Tag::Heading { .. })) {
+            continue;
+        }
+        let text: String = events[i + 1..]
+            .iter()
+            .take_while(|event| !matches!(event, Event::End(TagEnd::Heading(_))))
+            .filter_map(|event| match event {
+                Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                _ => None,
+            })
+            .collect();
+        let base = slug(&text);
+        let count = seen.entry(base.clone()).or_default();
+        let id = match *count {
+            0 => base,
+            n => format!("{}-{}", base, n),
+        };
+        *count += 1;
+        if let Event::Start(Tag::Heading { id: heading_id, .. }) = &mut events[i] {
+            *heading_id = Some(CowStr::from(id));
+        }
+    }
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events.into_iter());
+    SANITIZER.clean(&unsafe_html).to_string()
+}
+
+#[derive(Default)]
+pub(crate) struct RenderCache {
+    // note id -> (version, html)
+    notes: Mutex<HashMap<i64, (i64, Arc<str>)>>,
+}
+
+impl RenderCache {
+    fn render(&self, note: &Note) -> Arc<str> {
+        if let Ok(notes) = self.notes.lock()
+            && let Some((_, html)) = notes.get(&note.id).filter(|(v, _)| *v == note.version)
+        {
+            return html.clone();
+        }
+        let html: Arc<str> = render(&note.contents).into();
+        if let Ok(mut notes) = self.notes.lock() {
+            if notes.len() >= MAX_CACHED {
+                notes.clear();
+            }
+            notes.insert(note.id, (note.version, html.clone()));
+        }
+        html
+    }
+}
+
+pub async fn rendered(
+    Path(id): Path<i64>,
+    State(state): State<Arc<DataState>>,
+    headers: HeaderMap,
+) -> Response {
+    let note: Note = match state.store.get(id) {
+        Some(note) => note,
+        None => return AuthrError::NotFound.into_response(),
+    };
+    let etag = format!("\"note-{}-v{}\"", note.id, note.version);
+    if headers
+        .get(IF_NONE_MATCH)
+        .is_some_and(|val| val.as_bytes() == etag.as_bytes())
+    {
+        return (StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response();
+    }
+    (
+        [
+            (CONTENT_TYPE, "text/html; charset=utf-8".to_string()),
+            // in case it's opened directly rather than placed in a page
+            (
+                CONTENT_SECURITY_POLICY,
+                "default-src 'none'; img-src http: https:".to_string(),
+            ),
+            (ETAG, etag),
+        ],
+        state.rendered.render(&note).to_string(),
+    )
+        .into_response()
+}
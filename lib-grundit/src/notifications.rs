@@ -0,0 +1,150 @@
+// In-app notifications, made when comments are posted.
+//
+//   GET  /data/notification?unread=true
+//   POST /data/notification/{id}/read
+//   POST /data/notification/read-all
+//
+// A note's owner hears about every comment on it, and anyone named as
+// `@name` in a comment hears about that, matched against user names without
+// regard to case. Nobody is told about their own comments, and a mention
+// stands in for the plain comment notification when both would apply.
+use std::sync::Arc;
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+};
+use lib_glonk::store::Store;
+use serde_json::json;
+use time::OffsetDateTime;
+use tracing::error;
+
+use crate::{
+    app::DataState,
+    auth::AuthenticatedUser,
+    error::AuthrError,
+    types::{
+        Comment, Note, Notification, NotificationByOwnerId, NotificationByRead, NotificationKind,
+        RequestNotification, User, UserByName,
+    },
+};
+
+// anything past this in one comment is ignored
+const MAX_MENTIONS: usize = 20;
+
+// `@name`s in order, once each. Names run on letters, digits, `_`, `-` and
+// `.` without a trailing `.`, and an `@` inside a word, an email address
+// say, isn't a mention.
+fn mentions(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut names: Vec<String> = vec![];
+    for (i, c) in chars.iter().enumerate() {
+        if *c != '@' || (i > 0 && (chars[i - 1].is_alphanumeric() || chars[i - 1] == '_')) {
+            continue;
+        }
+        let name: String = chars[i + 1..]
+            .iter()
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+            .collect();
+        let name = name.trim_end_matches('.');
+        if !name.is_empty()
+            && !names
+                .iter()
+                .any(|n| n.to_lowercase() == name.to_lowercase())
+        {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+pub(crate) fn comment_created(state: &DataState, comment: &Comment) {
+    let note: Note = match state.store.get(comment.note_id) {
+        Some(note) => note,
+        None => return,
+    };
+    let mut recipients: Vec<(i64, NotificationKind)> = vec![];
+    for name in mentions(&comment.contents).into_iter().take(MAX_MENTIONS) {
+        let users: Vec<User> = state
+            .store
+            .get_queries(vec![Box::new(UserByName::new(name))]);
+        for user in users {
+            if user.id != comment.owner_id && !recipients.iter().any(|(id, _)| *id == user.id) {
+                recipients.push((user.id, NotificationKind::Mention));
+            }
+        }
+    }
+    if note.owner_id != comment.owner_id && !recipients.iter().any(|(id, _)| *id == note.owner_id) {
+        recipients.push((note.owner_id, NotificationKind::Comment));
+    }
+
+    let created_at = OffsetDateTime::now_utc().unix_timestamp();
+    for (owner_id, kind) in recipients {
+        let notification = RequestNotification {
+            id: None,
+            owner_id: Some(owner_id),
+            kind: Some(kind),
+            actor_id: Some(comment.owner_id),
+            note_id: Some(comment.note_id),
+            comment_id: Some(comment.id),
+            read: Some(false),
+            created_at: Some(created_at),
+        };
+        if state.store.create::<_, Notification>(notification).is_err() {
+            error!(
+                "could not notify user {} of comment {}",
+                owner_id, comment.id
+            );
+        }
+    }
+}
+
+fn mark(state: &DataState, notification: &Notification) -> Result<Notification, AuthrError> {
+    let update = RequestNotification {
+        id: Some(notification.id),
+        owner_id: Some(notification.owner_id),
+        kind: None,
+        actor_id: None,
+        note_id: None,
+        comment_id: None,
+        read: Some(true),
+        created_at: None,
+    };
+    state
+        .store
+        .update::<_, Notification>(update)
+        .map_err(|_| AuthrError::NotFound)
+}
+
+pub async fn mark_read(
+    Path(id): Path<i64>,
+    user: AuthenticatedUser,
+    State(state): State<Arc<DataState>>,
+) -> Response {
+    // someone else's is as good as missing
+    let notification = state
+        .store
+        .get::<Notification>(id)
+        .filter(|notification| notification.owner_id == user.id);
+    match notification.map(|notification| mark(&state, &notification)) {
+        Some(Ok(notification)) => Json(notification).into_response(),
+        Some(Err(e)) => e.into_response(),
+        None => AuthrError::NotFound.into_response(),
+    }
+}
+
+pub async fn mark_all_read(
+    user: AuthenticatedUser,
+    State(state): State<Arc<DataState>>,
+) -> Response {
+    let unread: Vec<Notification> = state.store.get_queries(vec![
+        Box::new(NotificationByOwnerId::new(user.id)),
+        Box::new(NotificationByRead::new(false)),
+    ]);
+    let marked = unread
+        .iter()
+        .filter(|notification| mark(&state, notification).is_ok())
+        .count();
+    Json(json!({ "marked": marked })).into_response()
+}
@@ -0,0 +1,356 @@
+// Token bucket rate limiting.
+//
+// Requests are keyed by API token, then signed in user, then client address,
+// and each route group has its own bucket size and refill period, configured
+// as `{requests}/{seconds}`:
+//
+//   RATE_LIMIT_AUTH=20/60 RATE_LIMIT_DATA_READ=300/60 RATE_LIMIT_DATA_WRITE=60/60
+//
+// Buckets live in memory unless RATE_LIMIT_STORE=sqlite, in which case they
+// are kept in RATE_LIMIT_DB (default `ratelimit.db`) so several instances
+// pointed at the same file share their limits. Static files under `/web`
+// aren't limited.
+use std::{
+    collections::HashMap,
+    env,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue, Method, header::RETRY_AFTER},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlite::{Connection, State as SqliteState};
+use tracing::{debug, error};
+
+use crate::{app::AuthState, auth::AuthenticatedUser, error::AuthrError};
+
+// buckets that have refilled are dropped once the map grows past this
+const MAX_MEMORY_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteGroup {
+    Auth,
+    DataRead,
+    DataWrite,
+}
+
+impl RouteGroup {
+    // None for static files, a page pulls in several at once and they're
+    // behind sign in already
+    fn for_request(method: &Method, path: &str) -> Option<Self> {
+        if path == "/web" || path.starts_with("/web/") {
+            return None;
+        }
+        if !path.starts_with("/data/") {
+            return Some(Self::Auth);
+        }
+        match *method {
+            Method::GET | Method::HEAD => Some(Self::DataRead),
+            _ => Some(Self::DataWrite),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Auth => "auth",
+            Self::DataRead => "data_read",
+            Self::DataWrite => "data_write",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    // bucket size, the most requests allowed in a burst
+    pub capacity: u32,
+    // seconds for an empty bucket to refill
+    pub period: f64,
+}
+
+impl Limit {
+    fn from_env(var: &str, default: Limit) -> Self {
+        match env::var(var) {
+            Ok(val) => Self::parse(&val).unwrap_or_else(|| panic!("invalid {}: {}", var, val)),
+            Err(_) => default,
+        }
+    }
+
+    fn parse(val: &str) -> Option<Self> {
+        let (capacity, period) = val.split_once('/')?;
+        let capacity = capacity.trim().parse::<u32>().ok()?;
+        let period = period.trim().parse::<f64>().ok()?;
+        if capacity == 0 || period <= 0.0 {
+            return None;
+        }
+        Some(Self { capacity, period })
+    }
+
+    fn refill_rate(&self) -> f64 {
+        self.capacity as f64 / self.period
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    // seconds until the bucket is full again
+    pub reset: u64,
+    // seconds until the next request would be allowed
+    pub retry_after: u64,
+}
+
+// Refill a bucket last seen at `updated` holding `tokens`, then try to take
+// one. Returns the tokens left and the decision.
+fn take_token(tokens: f64, updated: f64, now: f64, limit: Limit) -> (f64, Decision) {
+    let rate = limit.refill_rate();
+    let capacity = limit.capacity as f64;
+    let tokens = (tokens + (now - updated).max(0.0) * rate).min(capacity);
+    let (tokens, allowed) = if tokens >= 1.0 {
+        (tokens - 1.0, true)
+    } else {
+        (tokens, false)
+    };
+    let decision = Decision {
+        allowed,
+        limit: limit.capacity,
+        remaining: tokens.floor() as u32,
+        reset: ((capacity - tokens) / rate).ceil() as u64,
+        retry_after: if allowed {
+            0
+        } else {
+            ((1.0 - tokens) / rate).ceil() as u64
+        },
+    };
+    (tokens, decision)
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default()
+}
+
+pub trait RateLimitStore: Send + Sync {
+    fn take(&self, key: &str, limit: Limit) -> Decision;
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    // key -> (tokens, updated)
+    buckets: Mutex<HashMap<String, (f64, f64)>>,
+}
+
+impl RateLimitStore for MemoryStore {
+    fn take(&self, key: &str, limit: Limit) -> Decision {
+        let now = now();
+        let mut buckets = match self.buckets.lock() {
+            Ok(buckets) => buckets,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if buckets.len() > MAX_MEMORY_BUCKETS {
+            let rate = limit.refill_rate();
+            let capacity = limit.capacity as f64;
+            buckets.retain(|_, (tokens, updated)| *tokens + (now - *updated) * rate < capacity);
+        }
+        let (tokens, updated) = buckets
+            .get(key)
+            .copied()
+            .unwrap_or((limit.capacity as f64, now));
+        let (tokens, decision) = take_token(tokens, updated, now, limit);
+        buckets.insert(key.to_string(), (tokens, now));
+        decision
+    }
+}
+
+// Buckets shared between instances through a sqlite file. Uses its own
+// connection so limiting never waits on the data store's lock.
+pub struct SqliteRateLimitStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteRateLimitStore {
+    pub fn open(path: &str) -> Self {
+        let mut conn = sqlite::open(path).unwrap();
+        conn.set_busy_timeout(1000).unwrap();
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS rate_limits (
+                key text primary key,
+                tokens real not null,
+                updated real not null)",
+        )
+        .unwrap();
+        Self {
+            conn: Mutex::new(conn),
+        }
+    }
+
+    fn try_take(conn: &Connection, key: &str, limit: Limit) -> sqlite::Result<Decision> {
+        let now = now();
+        // immediate so two instances can't both read the same bucket
+        conn.execute("BEGIN IMMEDIATE")?;
+        let mut statement =
+            conn.prepare("SELECT tokens, updated FROM rate_limits WHERE key = ?")?;
+        statement.bind((1, key))?;
+        let (tokens, updated) = match statement.next()? {
+            SqliteState::Row => (
+                statement.read::<f64, _>("tokens")?,
+                statement.read::<f64, _>("updated")?,
+            ),
+            SqliteState::Done => (limit.capacity as f64, now),
+        };
+        drop(statement);
+        let (tokens, decision) = take_token(tokens, updated, now, limit);
+        let mut statement = conn.prepare(
+            "INSERT INTO rate_limits (key, tokens, updated) VALUES (?, ?, ?)
+             ON CONFLICT(key) DO UPDATE SET tokens = excluded.tokens, updated = excluded.updated",
+        )?;
+        statement.bind((1, key))?;
+        statement.bind((2, tokens))?;
+        statement.bind((3, now))?;
+        statement.next()?;
+        drop(statement);
+        conn.execute("COMMIT")?;
+        Ok(decision)
+    }
+}
+
+impl RateLimitStore for SqliteRateLimitStore {
+    fn take(&self, key: &str, limit: Limit) -> Decision {
+        let conn = match self.conn.lock() {
+            Ok(conn) => conn,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        match Self::try_take(&conn, key, limit) {
+            Ok(decision) => decision,
+            Err(e) => {
+                // fail open, a broken limiter shouldn't take the site down
+                error!("rate limit store: {:?}", e);
+                let _ = conn.execute("ROLLBACK");
+                Decision {
+                    allowed: true,
+                    limit: limit.capacity,
+                    remaining: limit.capacity,
+                    reset: 0,
+                    retry_after: 0,
+                }
+            }
+        }
+    }
+}
+
+pub struct RateLimiter {
+    store: Box<dyn RateLimitStore>,
+    limits: HashMap<RouteGroup, Limit>,
+}
+
+impl RateLimiter {
+    pub fn new(store: Box<dyn RateLimitStore>, limits: HashMap<RouteGroup, Limit>) -> Self {
+        Self { store, limits }
+    }
+
+    pub fn from_env() -> Self {
+        let store: Box<dyn RateLimitStore> = match env::var("RATE_LIMIT_STORE").as_deref() {
+            Ok("sqlite") => Box::new(SqliteRateLimitStore::open(
+                &env::var("RATE_LIMIT_DB").unwrap_or_else(|_| "ratelimit.db".to_string()),
+            )),
+            Ok("memory") | Err(_) => Box::new(MemoryStore::default()),
+            Ok(other) => panic!("unknown RATE_LIMIT_STORE {}", other),
+        };
+        let limits = HashMap::from([
+            (
+                RouteGroup::Auth,
+                Limit::from_env(
+                    "RATE_LIMIT_AUTH",
+                    Limit {
+                        capacity: 20,
+                        period: 60.0,
+                    },
+                ),
+            ),
+            (
+                RouteGroup::DataRead,
+                Limit::from_env(
+                    "RATE_LIMIT_DATA_READ",
+                    Limit {
+                        capacity: 300,
+                        period: 60.0,
+                    },
+                ),
+            ),
+            (
+                RouteGroup::DataWrite,
+                Limit::from_env(
+                    "RATE_LIMIT_DATA_WRITE",
+                    Limit {
+                        capacity: 60,
+                        period: 60.0,
+                    },
+                ),
+            ),
+        ]);
+        Self::new(store, limits)
+    }
+
+    fn check(&self, group: RouteGroup, key: &str) -> Option<Decision> {
+        let limit = self.limits.get(&group)?;
+        Some(
+            self.store
+                .take(&format!("{}:{}", group.name(), key), *limit),
+        )
+    }
+}
+
+fn client_key(req: &Request) -> String {
+    match req.extensions().get::<AuthenticatedUser>() {
+        Some(AuthenticatedUser {
+            api_token_id: Some(token_id),
+            ..
+        }) => format!("token:{}", token_id),
+        Some(user) => format!("user:{}", user.id),
+        None => match req.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+            None => "ip:unknown".to_string(),
+        },
+    }
+}
+
+fn set_headers(headers: &mut HeaderMap, decision: &Decision) {
+    let mut set = |name: &'static str, val: u64| {
+        headers.insert(name, HeaderValue::from(val));
+    };
+    set("ratelimit-limit", decision.limit as u64);
+    set("ratelimit-remaining", decision.remaining as u64);
+    set("ratelimit-reset", decision.reset);
+    if !decision.allowed {
+        headers.insert(RETRY_AFTER, HeaderValue::from(decision.retry_after));
+    }
+}
+
+// Place inside `request_authorizer` where there is one, so signed in users
+// and API tokens get their own buckets rather than sharing their address's.
+pub async fn rate_limit(State(state): State<Arc<AuthState>>, req: Request, next: Next) -> Response {
+    let Some(group) = RouteGroup::for_request(req.method(), req.uri().path()) else {
+        return next.run(req).await;
+    };
+    let key = client_key(&req);
+    let decision = match state.rate_limiter.check(group, &key) {
+        Some(decision) => decision,
+        None => return next.run(req).await,
+    };
+    let mut response = if decision.allowed {
+        next.run(req).await
+    } else {
+        debug!("{} over the {} limit", key, group.name());
+        AuthrError::RateLimited.into_response()
+    };
+    set_headers(response.headers_mut(), &decision);
+    response
+}
@@ -0,0 +1,354 @@
+// For clients that work offline and catch up later.
+//
+//   GET /data/changes?since={seq}&types=note,punch
+//
+// Everything in the change log after `since` the user could see, oldest
+// first, as a `ChangeSet`. As with the event streams, rows are read as they
+// are now through the same scoping as `GET /data/{type}`, rows gone by then
+// are skipped, and deletes carry only the id and don't come at all for types
+// scoped to their owner. `types` narrows it to some types.
+//
+// A create sent with an `Idempotency-Key` header is only made once for the
+// user and key: the first successful response is kept for a day and sent
+// again, with `Idempotent-Replayed: true`, for a repeat of the same request.
+// The same key with a different request is a 422.
+//
+// The change log keeps the newest CHANGE_LOG_KEEP entries (default 100,000),
+// pruned hourly, though never ones a webhook has still to look at. A `since`
+// from before what's kept gets every row there is of the types instead, with
+// `reset` set so the client drops whatever else it has.
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    Json,
+    body::{self, Body},
+    extract::{Query as UrlQuery, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header::CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use futures_util::future::BoxFuture;
+use lib_glonk::store::{ChangeKind, Store};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{debug, error};
+
+use crate::{
+    app::{self, AuthrState, DataState},
+    auth::AuthenticatedUser,
+    blob, feed,
+    jobs::Job,
+    types::{
+        AllChangesAfter, ChangeRecord, ChangeSet, DataType, IdempotencyKey, IdempotencyKeyByKey,
+        IdempotencyKeysBefore, LatestChange, OldestChange, RequestIdempotencyKey, SyncChange,
+        Webhook,
+    },
+};
+
+// log entries looked at a page
+const PAGE_SIZE: i64 = 200;
+const MAX_KEY_LEN: usize = 255;
+const KEY_TTL_SECS: i64 = 24 * 60 * 60;
+const DEFAULT_CHANGE_LOG_KEEP: i64 = 100_000;
+
+#[derive(Debug, Deserialize)]
+pub struct ChangesParams {
+    since: Option<i64>,
+    types: Option<String>,
+}
+
+pub async fn changes(
+    user: AuthenticatedUser,
+    UrlQuery(params): UrlQuery<ChangesParams>,
+    State(state): State<Arc<DataState>>,
+) -> Response {
+    let types = match params.types.as_deref().map(parse_types) {
+        Some(Ok(types)) => Some(types),
+        Some(Err(name)) => {
+            return (StatusCode::BAD_REQUEST, format!("unknown type: {}", name)).into_response();
+        }
+        None => None,
+    };
+    let since = params.since.unwrap_or(0);
+    let oldest: Vec<ChangeRecord> = state.store.get_queries(vec![Box::new(OldestChange)]);
+    if oldest.first().is_some_and(|oldest| oldest.seq > since + 1) {
+        return Json(snapshot(&state, user.id, types)).into_response();
+    }
+    let records: Vec<ChangeRecord> = state
+        .store
+        .get_queries(vec![Box::new(AllChangesAfter::new(since, PAGE_SIZE))]);
+    let no_query = HashMap::new();
+    let changes = records
+        .iter()
+        .filter_map(|record| {
+            let data_type = feed::data_type(&record.table_name)?;
+            if types
+                .as_ref()
+                .is_some_and(|types| !types.contains(&data_type))
+            {
+                return None;
+            }
+            let (seq, name) = (record.seq, data_type.as_str().to_string());
+            match record.kind {
+                ChangeKind::Deleted if app::deletes_public(data_type) => {
+                    Some(SyncChange::Deleted {
+                        seq,
+                        data_type: name,
+                        id: record.row_id,
+                    })
+                }
+                ChangeKind::Deleted => None,
+                ChangeKind::Created => {
+                    feed::read_back(&state, data_type, user.id, &no_query, record.row_id).map(
+                        |data| SyncChange::Created {
+                            seq,
+                            data_type: name,
+                            data,
+                        },
+                    )
+                }
+                ChangeKind::Updated => {
+                    feed::read_back(&state, data_type, user.id, &no_query, record.row_id).map(
+                        |data| SyncChange::Updated {
+                            seq,
+                            data_type: name,
+                            data,
+                        },
+                    )
+                }
+            }
+        })
+        .collect();
+    Json(ChangeSet {
+        changes,
+        next: records.last().map(|record| record.seq).unwrap_or(since),
+        more: records.len() as i64 == PAGE_SIZE,
+        reset: false,
+    })
+    .into_response()
+}
+
+// every row of `types` the user can see, for a client the pruned log can't
+// bring up to date
+fn snapshot(state: &DataState, user_id: i64, types: Option<Vec<DataType>>) -> ChangeSet {
+    // read first, so rows made while listing come again rather than not at all
+    let latest: Vec<ChangeRecord> = state.store.get_queries(vec![Box::new(LatestChange)]);
+    let seq = latest.first().map(|latest| latest.seq).unwrap_or_default();
+    let changes = types
+        .unwrap_or(DataType::ALL.to_vec())
+        .into_iter()
+        .flat_map(|data_type| {
+            app::list(state, data_type, user_id, vec![])
+                .into_iter()
+                .map(move |data| SyncChange::Created {
+                    seq,
+                    data_type: data_type.as_str().to_string(),
+                    data,
+                })
+        })
+        .collect();
+    ChangeSet {
+        changes,
+        next: seq,
+        more: false,
+        reset: true,
+    }
+}
+
+// `note,punch`, or the first name that isn't a type
+fn parse_types(types: &str) -> Result<Vec<DataType>, String> {
+    types
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            serde_json::from_value(serde_json::Value::String(name.to_string()))
+                .map_err(|_| name.to_string())
+        })
+        .collect()
+}
+
+// Runs `create` unless the key in `headers` has been seen for the same
+// request, when its first response is sent again.
+pub(crate) async fn create_once(
+    state: &DataState,
+    user_id: i64,
+    headers: &HeaderMap,
+    data_type: DataType,
+    request: &str,
+    create: impl Future<Output = Response>,
+) -> Response {
+    let key = match headers.get("idempotency-key").map(|key| key.to_str()) {
+        None => return create.await,
+        Some(Ok(key)) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+        Some(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("Idempotency-Key must be 1 to {} characters", MAX_KEY_LEN),
+            )
+                .into_response();
+        }
+    };
+    let digest = blob::digest(format!("{}\n{}", data_type.as_str(), request).as_bytes());
+    // so a repeat sent before the first has finished waits for it
+    let held = KeyLock::new(&state.idempotency_locks, user_id, &key);
+    let _guard = held.lock.lock().await;
+    let seen: Vec<IdempotencyKey> =
+        state
+            .store
+            .get_queries(vec![Box::new(IdempotencyKeyByKey::new(
+                user_id,
+                key.clone(),
+            ))]);
+    if let Some(seen) = seen.into_iter().next() {
+        if seen.request != digest {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used for a different request",
+            )
+                .into_response();
+        }
+        return replay(seen);
+    }
+
+    let res = create.await;
+    // failures aren't kept, the client can fix the request and send it again
+    if !res.status().is_success() {
+        return res;
+    }
+    let (parts, res_body) = res.into_parts();
+    let bytes = match body::to_bytes(res_body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("{:?}", e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let kept = RequestIdempotencyKey {
+        id: None,
+        owner_id: Some(user_id),
+        key: Some(key),
+        request: Some(digest),
+        status: Some(parts.status.as_u16() as i64),
+        body: Some(String::from_utf8_lossy(&bytes).into_owned()),
+        created_at: Some(OffsetDateTime::now_utc().unix_timestamp()),
+    };
+    if let Err(e) = state.store.create::<_, IdempotencyKey>(kept) {
+        error!("{:?}", e);
+    }
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+// one lock for each user's key in use
+pub(crate) type IdempotencyLocks = Mutex<HashMap<(i64, String), Arc<tokio::sync::Mutex<()>>>>;
+
+// a key's lock, removed from the map when the last request using it is done
+struct KeyLock<'a> {
+    locks: &'a IdempotencyLocks,
+    key: (i64, String),
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> KeyLock<'a> {
+    fn new(locks: &'a IdempotencyLocks, user_id: i64, key: &str) -> Self {
+        let key = (user_id, key.to_string());
+        let lock = locks
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        Self { locks, key, lock }
+    }
+}
+
+impl Drop for KeyLock<'_> {
+    fn drop(&mut self) {
+        let mut locks = self.locks.lock().unwrap();
+        // the map's and this one, so nobody else is waiting
+        if Arc::strong_count(&self.lock) == 2 {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+fn replay(seen: IdempotencyKey) -> Response {
+    let status = StatusCode::from_u16(seen.status as u16).unwrap_or(StatusCode::OK);
+    let mut res = (status, seen.body).into_response();
+    let headers = res.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert("idempotent-replayed", HeaderValue::from_static("true"));
+    res
+}
+
+// forgets keys older than a day
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SweepIdempotencyKeys;
+
+impl Job for SweepIdempotencyKeys {
+    const KIND: &'static str = "sweep_idempotency_keys";
+
+    fn run(self, state: Arc<AuthrState>) -> BoxFuture<'static, Result<(), String>> {
+        Box::pin(async move {
+            let store = &state.data.store;
+            let before = OffsetDateTime::now_utc().unix_timestamp() - KEY_TTL_SECS;
+            let old: Vec<IdempotencyKey> =
+                store.get_queries(vec![Box::new(IdempotencyKeysBefore::new(before))]);
+            for kept in old {
+                store
+                    .delete::<IdempotencyKey>(kept.id, None)
+                    .map_err(|e| format!("{:?}", e))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+// drops change log entries beyond the newest `keep`
+#[derive(Serialize, Deserialize)]
+pub struct PruneChangeLog {
+    pub keep: i64,
+}
+
+impl PruneChangeLog {
+    pub(crate) fn from_env() -> Self {
+        let keep = match env::var("CHANGE_LOG_KEEP") {
+            Ok(val) => val
+                .parse()
+                .ok()
+                .filter(|keep| *keep > 0)
+                .unwrap_or_else(|| panic!("invalid CHANGE_LOG_KEEP: {}", val)),
+            Err(_) => DEFAULT_CHANGE_LOG_KEEP,
+        };
+        Self { keep }
+    }
+}
+
+impl Job for PruneChangeLog {
+    const KIND: &'static str = "prune_change_log";
+
+    fn run(self, state: Arc<AuthrState>) -> BoxFuture<'static, Result<(), String>> {
+        Box::pin(async move {
+            let store = &state.data.store;
+            let latest: Vec<ChangeRecord> = store.get_queries(vec![Box::new(LatestChange)]);
+            let Some(latest) = latest.first() else {
+                return Ok(());
+            };
+            let hooks: Vec<Webhook> = store.get_queries(vec![]);
+            let through = hooks
+                .iter()
+                .map(|hook| hook.last_seq)
+                .fold(latest.seq - self.keep, i64::min);
+            if through > 0 {
+                let pruned = store
+                    .prune_change_log(through)
+                    .map_err(|e| format!("{:?}", e))?;
+                debug!("pruned {} change log entries", pruned);
+            }
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,248 @@
+// Daily timesheets from clock punches.
+//
+//   GET /data/timesheet?from=2026-10-01&to=2026-10-31[&owner_id=2|&all=true]
+//       [&utc_offset=+02:00][&format=csv]
+//
+// An `in` opens a worked interval and the next `out` or `break` closes it, so
+// a day with a break reads in, break, in, out. Intervals count towards the day
+// they started on, which keeps night shifts in one piece. Punches that don't
+// pair up are listed as unmatched, as are intervals longer than MAX_SHIFT.
+//
+// Everyone can see their own timesheet, other people's need the admin role.
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    Json,
+    extract::{Query as UrlQuery, State},
+    http::{
+        StatusCode,
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    },
+    response::{IntoResponse, Response},
+};
+use lib_glonk::{store::Store, types::Query};
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration, Month, OffsetDateTime, UtcOffset};
+
+use crate::{
+    app::DataState,
+    auth::AuthenticatedUser,
+    error::AuthrError,
+    types::{Direction, Punch, PunchByOwnerId, PunchCapturedAfter, PunchCapturedBefore},
+};
+
+const MAX_SHIFT: i64 = 24 * 60 * 60;
+const MAX_DAYS: i64 = 93;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Interval {
+    pub start_punch: i64,
+    pub end_punch: i64,
+    pub start: i64,
+    pub end: i64,
+    pub seconds: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Day {
+    pub owner_id: i64,
+    // YYYY-MM-DD at the requested offset
+    pub date: String,
+    pub worked_seconds: i64,
+    pub intervals: Vec<Interval>,
+    // ids of punches that could not be paired
+    pub unmatched: Vec<i64>,
+}
+
+fn date_of(time: i64, offset: UtcOffset) -> Date {
+    OffsetDateTime::from_unix_timestamp(time)
+        .map(|t| t.to_offset(offset).date())
+        .unwrap_or(Date::MIN)
+}
+
+fn entry(
+    days: &mut BTreeMap<(i64, Date), Day>,
+    owner_id: i64,
+    time: i64,
+    offset: UtcOffset,
+) -> &mut Day {
+    let date = date_of(time, offset);
+    days.entry((owner_id, date)).or_insert_with(|| Day {
+        owner_id,
+        date: date.to_string(),
+        worked_seconds: 0,
+        intervals: vec![],
+        unmatched: vec![],
+    })
+}
+
+// Pair punches into worked intervals, grouped by owner and day and sorted.
+pub fn days(punches: &[Punch], offset: UtcOffset) -> Vec<Day> {
+    let mut by_owner: BTreeMap<i64, Vec<&Punch>> = BTreeMap::new();
+    for punch in punches {
+        by_owner.entry(punch.owner_id).or_default().push(punch);
+    }
+
+    let mut days: BTreeMap<(i64, Date), Day> = BTreeMap::new();
+    for (owner_id, mut punches) in by_owner {
+        punches.sort_by_key(|p| (p.captured_at, p.id));
+        let mut open: Option<&Punch> = None;
+        for punch in punches {
+            match (open, punch.direction) {
+                (None, Direction::In) => open = Some(punch),
+                (None, Direction::Out | Direction::Break) => {
+                    entry(&mut days, owner_id, punch.captured_at, offset)
+                        .unmatched
+                        .push(punch.id)
+                }
+                (Some(start), Direction::In) => {
+                    entry(&mut days, owner_id, start.captured_at, offset)
+                        .unmatched
+                        .push(start.id);
+                    open = Some(punch);
+                }
+                (Some(start), Direction::Out | Direction::Break) => {
+                    let seconds = punch.captured_at - start.captured_at;
+                    if seconds > MAX_SHIFT {
+                        entry(&mut days, owner_id, start.captured_at, offset)
+                            .unmatched
+                            .push(start.id);
+                        entry(&mut days, owner_id, punch.captured_at, offset)
+                            .unmatched
+                            .push(punch.id);
+                    } else {
+                        let day = entry(&mut days, owner_id, start.captured_at, offset);
+                        day.worked_seconds += seconds;
+                        day.intervals.push(Interval {
+                            start_punch: start.id,
+                            end_punch: punch.id,
+                            start: start.captured_at,
+                            end: punch.captured_at,
+                            seconds,
+                        });
+                    }
+                    open = None;
+                }
+            }
+        }
+        // still clocked in
+        if let Some(start) = open {
+            entry(&mut days, owner_id, start.captured_at, offset)
+                .unmatched
+                .push(start.id);
+        }
+    }
+    days.into_values().collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TimesheetParams {
+    from: String,
+    to: String,
+    owner_id: Option<i64>,
+    all: Option<bool>,
+    utc_offset: Option<String>,
+    format: Option<String>,
+}
+
+fn parse_date(val: &str) -> Option<Date> {
+    let mut parts = val.splitn(3, '-').map(|p| p.parse::<i32>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    let month = Month::try_from(u8::try_from(month).ok()?).ok()?;
+    Date::from_calendar_date(year, month, u8::try_from(day).ok()?).ok()
+}
+
+// `Z` or `+HH:MM` / `-HH:MM`
+fn parse_offset(val: &str) -> Option<UtcOffset> {
+    if val == "Z" {
+        return Some(UtcOffset::UTC);
+    }
+    let sign = match val.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let (hours, minutes) = val.get(1..)?.split_once(':')?;
+    let (hours, minutes) = (hours.parse::<i8>().ok()?, minutes.parse::<i8>().ok()?);
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+fn to_csv(days: &[Day]) -> String {
+    let mut csv = String::from("owner_id,date,worked_seconds,worked_hours,intervals,unmatched\n");
+    for day in days {
+        let unmatched: Vec<String> = day.unmatched.iter().map(|id| id.to_string()).collect();
+        csv.push_str(&format!(
+            "{},{},{},{:.2},{},{}\n",
+            day.owner_id,
+            day.date,
+            day.worked_seconds,
+            day.worked_seconds as f64 / 3600.0,
+            day.intervals.len(),
+            unmatched.join(" ")
+        ));
+    }
+    csv
+}
+
+pub async fn timesheet(
+    user: AuthenticatedUser,
+    State(state): State<Arc<DataState>>,
+    UrlQuery(params): UrlQuery<TimesheetParams>,
+) -> Response {
+    let (from, to) = match (parse_date(&params.from), parse_date(&params.to)) {
+        (Some(from), Some(to)) if from <= to && (to - from).whole_days() < MAX_DAYS => (from, to),
+        _ => {
+            return (
+                StatusCode::BAD_REQUEST,
+                format!("from and to must be dates at most {} days apart", MAX_DAYS),
+            )
+                .into_response();
+        }
+    };
+    let offset = match params.utc_offset.as_deref().map(parse_offset) {
+        Some(Some(offset)) => offset,
+        Some(None) => return (StatusCode::BAD_REQUEST, "Invalid utc_offset").into_response(),
+        None => UtcOffset::UTC,
+    };
+    let owner_id = match (params.all, params.owner_id) {
+        (Some(true), _) => None,
+        (_, Some(owner_id)) => Some(owner_id),
+        _ => Some(user.id),
+    };
+    if owner_id != Some(user.id) && !user.has_role("admin") {
+        return AuthrError::NotAuthorized.into_response();
+    }
+
+    // a day either side, for intervals crossing the edges of the range
+    let start = from.midnight().assume_offset(offset) - Duration::days(1);
+    let end = to.midnight().assume_offset(offset) + Duration::days(2);
+    let mut queries: Vec<Box<dyn Query>> = vec![
+        Box::new(PunchCapturedAfter::new(start.unix_timestamp())),
+        Box::new(PunchCapturedBefore::new(end.unix_timestamp())),
+    ];
+    if let Some(owner_id) = owner_id {
+        queries.push(Box::new(PunchByOwnerId::new(owner_id)));
+    }
+    let punches: Vec<Punch> = state.store.get_queries(queries);
+    let (from, to) = (from.to_string(), to.to_string());
+    let days: Vec<Day> = days(&punches, offset)
+        .into_iter()
+        .filter(|day| day.date >= from && day.date <= to)
+        .collect();
+
+    match params.format.as_deref() {
+        Some("csv") => (
+            [
+                (CONTENT_TYPE, "text/csv".to_string()),
+                (
+                    CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"timesheet-{}-{}.csv\"", from, to),
+                ),
+            ],
+            to_csv(&days),
+        )
+            .into_response(),
+        Some("json") | None => Json(days).into_response(),
+        Some(_) => (StatusCode::BAD_REQUEST, "format must be json or csv").into_response(),
+    }
+}
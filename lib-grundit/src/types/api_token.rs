@@ -0,0 +1,321 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub owner_id: i64,
+    pub name: String,
+    // only the sha256 of the token is kept, and it never leaves the server
+    #[serde(skip)]
+    pub token_hash: String,
+    // comma separated `{type}:{read|write}` or `*:{read|write}`, at least one
+    pub scopes: String,
+    pub expires_at: Option<i64>,
+    pub created_at: i64,
+    pub revoked: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestApiToken {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip)]
+    pub token_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scopes: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked: Option<bool>,
+}
+
+#[cfg(feature = "full")]
+pub use ext::*;
+
+#[cfg(feature = "full")]
+mod ext {
+    use super::{ApiToken, RequestApiToken};
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use lib_glonk::validation::{Rule, Validator};
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+
+    impl Bindable for ApiToken {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.name.as_str().bind(statement, 3)?;
+            self.token_hash.as_str().bind(statement, 4)?;
+            self.scopes.as_str().bind(statement, 5)?;
+            self.expires_at.bind(statement, 6)?;
+            self.created_at.bind(statement, 7)?;
+            (self.revoked as i64).bind(statement, 8)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for ApiToken {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    name: statement.read::<String, _>("name").unwrap(),
+                    token_hash: statement.read::<String, _>("token_hash").unwrap(),
+                    scopes: statement.read::<String, _>("scopes").unwrap(),
+                    expires_at: statement.read::<Option<i64>, _>("expires_at").unwrap(),
+                    created_at: statement.read::<i64, _>("created_at").unwrap(),
+                    revoked: statement.read::<i64, _>("revoked").unwrap() != 0,
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "api_tokens".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,name,token_hash,scopes,expires_at,created_at,revoked".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestApiToken {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(name) = self.name {
+                name.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(token_hash) = self.token_hash {
+                token_hash.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(scopes) = self.scopes {
+                scopes.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(expires_at) = self.expires_at {
+                expires_at.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(created_at) = self.created_at {
+                created_at.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(revoked) = self.revoked {
+                (revoked as i64).bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestApiToken {
+        fn validate_create(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            if self.name.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "name",
+                )));
+            }
+            if self.token_hash.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "token_hash",
+                )));
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.name.is_some() {
+                cols.push("name");
+            }
+            if self.token_hash.is_some() {
+                cols.push("token_hash");
+            }
+            if self.scopes.is_some() {
+                cols.push("scopes");
+            }
+            if self.expires_at.is_some() {
+                cols.push("expires_at");
+            }
+            if self.created_at.is_some() {
+                cols.push("created_at");
+            }
+            if self.revoked.is_some() {
+                cols.push("revoked");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.name.is_some() {
+                ct += 1;
+            }
+            if self.token_hash.is_some() {
+                ct += 1;
+            }
+            if self.scopes.is_some() {
+                ct += 1;
+            }
+            if self.expires_at.is_some() {
+                ct += 1;
+            }
+            if self.created_at.is_some() {
+                ct += 1;
+            }
+            if self.revoked.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn field_rules(&self, v: &mut Validator) {
+            v.text(
+                "name",
+                self.name.as_deref(),
+                &[Rule::NotBlank, Rule::MaxLen(100)],
+            );
+            if let (Some(expires_at), Some(created_at)) = (self.expires_at, self.created_at) {
+                v.check(
+                    "expires_at",
+                    "after",
+                    expires_at > created_at,
+                    "must be after created_at",
+                );
+            }
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    // Query types
+    #[derive(Debug)]
+    pub struct ApiTokenByHash {
+        inner: EqualsCriteria,
+    }
+
+    impl ApiTokenByHash {
+        pub fn new(val: String) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("token_hash"),
+                    val: Value::String(val),
+                },
+            }
+        }
+    }
+
+    impl Query for ApiTokenByHash {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct ApiTokenByOwnerId {
+        inner: EqualsCriteria,
+    }
+
+    impl ApiTokenByOwnerId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("owner_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for ApiTokenByOwnerId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// where an attachment's thumbnails are at, images get them in the background
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Thumbnails {
+    // not an image we can read
+    Unsupported,
+    Pending,
+    Ready,
+    Failed,
+}
+
+impl Thumbnails {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Thumbnails::Unsupported => "unsupported",
+            Thumbnails::Pending => "pending",
+            Thumbnails::Ready => "ready",
+            Thumbnails::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for Thumbnails {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unsupported" => Ok(Thumbnails::Unsupported),
+            "pending" => Ok(Thumbnails::Pending),
+            "ready" => Ok(Thumbnails::Ready),
+            "failed" => Ok(Thumbnails::Failed),
+            _ => Err(()),
+        }
+    }
+}
+
+// A file on a note. The bytes live in the blob store under their sha256, so
+// the same file attached twice is only stored once.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Attachment {
+    pub id: i64,
+    pub owner_id: i64,
+    pub note_id: i64,
+    // the uploaded file name
+    pub name: String,
+    pub content_type: String,
+    // in bytes
+    pub size: i64,
+    // hex sha256 of the contents, the key in the blob store
+    pub digest: String,
+    // unix seconds
+    pub created_at: i64,
+    pub thumbnails: Thumbnails,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestAttachment {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thumbnails: Option<Thumbnails>,
+}
+
+#[cfg(feature = "full")]
+pub use ext::*;
+
+#[cfg(feature = "full")]
+mod ext {
+    use super::{Attachment, RequestAttachment, Thumbnails};
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use lib_glonk::validation::{Rule, Validator};
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+    use tracing::error;
+
+    impl Bindable for Attachment {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.note_id.bind(statement, 3)?;
+            self.name.as_str().bind(statement, 4)?;
+            self.content_type.as_str().bind(statement, 5)?;
+            self.size.bind(statement, 6)?;
+            self.digest.as_str().bind(statement, 7)?;
+            self.created_at.bind(statement, 8)?;
+            self.thumbnails.as_str().bind(statement, 9)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for Attachment {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    note_id: statement.read::<i64, _>("note_id").unwrap(),
+                    name: statement.read::<String, _>("name").unwrap(),
+                    content_type: statement.read::<String, _>("content_type").unwrap(),
+                    size: statement.read::<i64, _>("size").unwrap(),
+                    digest: statement.read::<String, _>("digest").unwrap(),
+                    created_at: statement.read::<i64, _>("created_at").unwrap(),
+                    thumbnails: statement
+                        .read::<String, _>("thumbnails")
+                        .unwrap()
+                        .parse()
+                        .unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "attachments".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,note_id,name,content_type,size,digest,created_at,thumbnails".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestAttachment {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(note_id) = self.note_id {
+                note_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(name) = self.name {
+                name.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(content_type) = self.content_type {
+                content_type.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(size) = self.size {
+                size.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(digest) = self.digest {
+                digest.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(created_at) = self.created_at {
+                created_at.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(thumbnails) = self.thumbnails {
+                thumbnails.as_str().bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestAttachment {
+        fn validate_create(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            for (field, present) in [
+                ("note_id", self.note_id.is_some()),
+                ("name", self.name.is_some()),
+                ("content_type", self.content_type.is_some()),
+                ("size", self.size.is_some()),
+                ("digest", self.digest.is_some()),
+                ("created_at", self.created_at.is_some()),
+                ("thumbnails", self.thumbnails.is_some()),
+            ] {
+                if !present {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        field,
+                    )));
+                }
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.note_id.is_some() {
+                cols.push("note_id");
+            }
+            if self.name.is_some() {
+                cols.push("name");
+            }
+            if self.content_type.is_some() {
+                cols.push("content_type");
+            }
+            if self.size.is_some() {
+                cols.push("size");
+            }
+            if self.digest.is_some() {
+                cols.push("digest");
+            }
+            if self.created_at.is_some() {
+                cols.push("created_at");
+            }
+            if self.thumbnails.is_some() {
+                cols.push("thumbnails");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.note_id.is_some() {
+                ct += 1;
+            }
+            if self.name.is_some() {
+                ct += 1;
+            }
+            if self.content_type.is_some() {
+                ct += 1;
+            }
+            if self.size.is_some() {
+                ct += 1;
+            }
+            if self.digest.is_some() {
+                ct += 1;
+            }
+            if self.created_at.is_some() {
+                ct += 1;
+            }
+            if self.thumbnails.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn field_rules(&self, v: &mut Validator) {
+            v.text(
+                "name",
+                self.name.as_deref(),
+                &[Rule::NotBlank, Rule::MaxLen(255)],
+            )
+            .text(
+                "content_type",
+                self.content_type.as_deref(),
+                &[Rule::NotBlank, Rule::MaxLen(255)],
+            );
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    // Query types
+    #[derive(Debug)]
+    pub enum AttachmentQuery {
+        ByNoteId(AttachmentByNoteId),
+        ByOwnerId(AttachmentByOwnerId),
+    }
+
+    impl Query for AttachmentQuery {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            match self {
+                AttachmentQuery::ByNoteId(inner) => inner.build(),
+                AttachmentQuery::ByOwnerId(inner) => inner.build(),
+            }
+        }
+    }
+
+    impl TryFrom<(&String, &String)> for AttachmentQuery {
+        type Error = ();
+
+        fn try_from((q, v): (&String, &String)) -> Result<Self, Self::Error> {
+            let q = q.as_str();
+            let id = match v.parse::<i64>() {
+                Ok(id) => id,
+                Err(e) => {
+                    error!("{:?}", e);
+                    return Err(());
+                }
+            };
+            match q {
+                "byNoteId" => Ok(Self::ByNoteId(AttachmentByNoteId::new(id))),
+                "byOwnerId" => Ok(Self::ByOwnerId(AttachmentByOwnerId::new(id))),
+                _ => {
+                    error!("Unrecognized query for Attachment: {:?}", (q, v));
+                    Err(())
+                }
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct AttachmentByNoteId {
+        inner: EqualsCriteria,
+    }
+
+    impl AttachmentByNoteId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("note_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for AttachmentByNoteId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct AttachmentByOwnerId {
+        inner: EqualsCriteria,
+    }
+
+    impl AttachmentByOwnerId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("owner_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for AttachmentByOwnerId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    // every attachment sharing a blob
+    #[derive(Debug)]
+    pub struct AttachmentByDigest {
+        inner: EqualsCriteria,
+    }
+
+    impl AttachmentByDigest {
+        pub fn new(val: String) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("digest"),
+                    val: Value::String(val),
+                },
+            }
+        }
+    }
+
+    impl Query for AttachmentByDigest {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct AttachmentByThumbnails {
+        inner: EqualsCriteria,
+    }
+
+    impl AttachmentByThumbnails {
+        pub fn new(val: Thumbnails) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("thumbnails"),
+                    val: Value::String(val.as_str().to_string()),
+                },
+            }
+        }
+    }
+
+    impl Query for AttachmentByThumbnails {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+}
@@ -0,0 +1,167 @@
+// Entries in the store's change log, only compiled with the `full` feature
+// as nothing but the event streams, webhooks and sync read them.
+use lib_glonk::store::ChangeKind;
+
+// one create, update or delete made through the store, in order of `seq`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeRecord {
+    pub seq: i64,
+    pub table_name: String,
+    pub row_id: i64,
+    pub kind: ChangeKind,
+}
+
+pub use ext::*;
+
+mod ext {
+    use super::ChangeRecord;
+    use lib_glonk::types::{Criteria, DataObject, EqualsCriteria, Query};
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+
+    // written by the store itself, this only satisfies `DataObject`
+    impl Bindable for ChangeRecord {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.seq.bind(statement, 1)?;
+            self.table_name.as_str().bind(statement, 2)?;
+            self.row_id.bind(statement, 3)?;
+            self.kind.as_str().bind(statement, 4)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for ChangeRecord {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    seq: statement.read::<i64, _>("seq").unwrap(),
+                    table_name: statement.read::<String, _>("table_name").unwrap(),
+                    row_id: statement.read::<i64, _>("row_id").unwrap(),
+                    kind: statement
+                        .read::<String, _>("kind")
+                        .unwrap()
+                        .parse()
+                        .unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "changes".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "seq,table_name,row_id,kind".to_string()
+        }
+
+        fn id_col() -> String {
+            "seq".to_string()
+        }
+
+        // nobody owns these
+        fn owner_id_col() -> String {
+            "seq".to_string()
+        }
+    }
+
+    // a table's changes after `seq`, oldest first, a page at a time
+    #[derive(Debug)]
+    pub struct ChangesAfter {
+        table_name: EqualsCriteria,
+        seq: i64,
+        size: i64,
+    }
+
+    impl ChangesAfter {
+        pub fn new(table_name: String, seq: i64, size: i64) -> Self {
+            Self {
+                table_name: EqualsCriteria {
+                    field: String::from("table_name"),
+                    val: Value::String(table_name),
+                },
+                seq,
+                size,
+            }
+        }
+    }
+
+    impl Query for ChangesAfter {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            let (clause, mut vals) = self.table_name.build();
+            vals.push(Value::Integer(self.seq));
+            (format!("{} and seq > ?", clause), vals)
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("seq".to_string(), vec![]))
+        }
+
+        fn limit(&self) -> Option<i64> {
+            Some(self.size)
+        }
+    }
+
+    // every table's changes after `seq`, for webhooks and sync
+    #[derive(Debug)]
+    pub struct AllChangesAfter {
+        seq: i64,
+        size: i64,
+    }
+
+    impl AllChangesAfter {
+        pub fn new(seq: i64, size: i64) -> Self {
+            Self { seq, size }
+        }
+    }
+
+    impl Query for AllChangesAfter {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            ("seq > ?".to_string(), vec![Value::Integer(self.seq)])
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("seq".to_string(), vec![]))
+        }
+
+        fn limit(&self) -> Option<i64> {
+            Some(self.size)
+        }
+    }
+
+    // the oldest entry still kept, everything before it has been pruned
+    #[derive(Debug)]
+    pub struct OldestChange;
+
+    impl Query for OldestChange {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            ("1".to_string(), vec![])
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("seq".to_string(), vec![]))
+        }
+
+        fn limit(&self) -> Option<i64> {
+            Some(1)
+        }
+    }
+
+    // the newest entry, where a stream without `Last-Event-ID` starts
+    #[derive(Debug)]
+    pub struct LatestChange;
+
+    impl Query for LatestChange {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            ("1".to_string(), vec![])
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("seq desc".to_string(), vec![]))
+        }
+
+        fn limit(&self) -> Option<i64> {
+            Some(1)
+        }
+    }
+}
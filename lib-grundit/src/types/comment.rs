@@ -1,23 +1,65 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Comment {
     pub id: i64,
     pub owner_id: i64,
     pub note_id: i64,
+    // set on replies, to a comment on the same note
+    pub parent_comment_id: Option<i64>,
     pub contents: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RequestComment {
     #[serde(skip_serializing_if = "Option::is_none")]
-    id: Option<i64>,
+    pub id: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    owner_id: Option<i64>,
+    pub owner_id: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    note_id: Option<i64>,
+    pub note_id: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    contents: Option<String>,
+    pub parent_comment_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contents: Option<String>,
+}
+
+// a comment with its replies nested under it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommentThread {
+    #[serde(flatten)]
+    pub comment: Comment,
+    pub replies: Vec<CommentThread>,
+}
+
+// Nests comments under their parents, keeping the order they came in at every
+// level. Replies to a comment that's since been deleted move to the top.
+pub fn threads(comments: &[Comment]) -> Vec<CommentThread> {
+    let ids: HashSet<i64> = comments.iter().map(|c| c.id).collect();
+    let mut replies: HashMap<Option<i64>, Vec<&Comment>> = HashMap::new();
+    for comment in comments {
+        let parent = comment.parent_comment_id.filter(|id| ids.contains(id));
+        replies.entry(parent).or_default().push(comment);
+    }
+    fn nest(
+        parent: Option<i64>,
+        replies: &HashMap<Option<i64>, Vec<&Comment>>,
+    ) -> Vec<CommentThread> {
+        replies
+            .get(&parent)
+            .map(|comments| {
+                comments
+                    .iter()
+                    .map(|comment| CommentThread {
+                        comment: (*comment).clone(),
+                        replies: nest(Some(comment.id), replies),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    nest(None, &replies)
 }
 
 #[cfg(feature = "full")]
@@ -30,7 +72,9 @@ mod ext {
         ContainsCriteria, Criteria, DataObject, EqualsCriteria, Query, RequestObject,
         ValidationError,
     };
+    use lib_glonk::validation::{Rule, Validator};
     use sqlite::{Bindable, BindableWithIndex, State, Value};
+    use std::str::FromStr;
     use tracing::error;
 
     impl Bindable for Comment {
@@ -38,7 +82,8 @@ mod ext {
             self.id.clone().bind(statement, 1)?;
             self.owner_id.clone().bind(statement, 2)?;
             self.note_id.clone().bind(statement, 3)?;
-            self.contents.clone().as_str().bind(statement, 4)?;
+            self.parent_comment_id.bind(statement, 4)?;
+            self.contents.clone().as_str().bind(statement, 5)?;
             Ok(())
         }
     }
@@ -50,6 +95,9 @@ mod ext {
                     id: statement.read::<i64, _>("id").unwrap(),
                     owner_id: statement.read::<i64, _>("owner_id").unwrap(),
                     note_id: statement.read::<i64, _>("note_id").unwrap(),
+                    parent_comment_id: statement
+                        .read::<Option<i64>, _>("parent_comment_id")
+                        .unwrap(),
                     contents: statement.read::<String, _>("contents").unwrap(),
                 });
             }
@@ -61,7 +109,7 @@ mod ext {
         }
 
         fn sql_cols() -> String {
-            "id,owner_id,note_id,contents".to_string()
+            "id,owner_id,note_id,parent_comment_id,contents".to_string()
         }
 
         fn id_col() -> String {
@@ -88,6 +136,10 @@ mod ext {
                 note_id.clone().bind(statement, idx)?;
                 idx += 1;
             }
+            if let Some(parent_comment_id) = self.parent_comment_id {
+                parent_comment_id.bind(statement, idx)?;
+                idx += 1;
+            }
             if let Some(contents) = self.contents {
                 contents.clone().as_str().bind(statement, idx)?;
             }
@@ -177,6 +229,9 @@ mod ext {
             if let Some(_) = self.note_id {
                 cols.push("note_id");
             }
+            if self.parent_comment_id.is_some() {
+                cols.push("parent_comment_id");
+            }
             if let Some(_) = self.contents {
                 cols.push("contents");
             }
@@ -194,12 +249,23 @@ mod ext {
             if let Some(_) = self.note_id {
                 ct += 1;
             }
+            if self.parent_comment_id.is_some() {
+                ct += 1;
+            }
             if let Some(_) = self.contents {
                 ct += 1;
             }
             vec!["?"; ct].join(",")
         }
 
+        fn field_rules(&self, v: &mut Validator) {
+            v.text(
+                "contents",
+                self.contents.as_deref(),
+                &[Rule::NotBlank, Rule::MaxLen(5_000)],
+            );
+        }
+
         fn id(&self) -> Option<i64> {
             self.id
         }
@@ -210,11 +276,16 @@ mod ext {
     }
 
     // Query types
+    //
+    //   byParentId=id        direct replies to a comment
+    //   sort=oldest|newest|mostReplies
     #[derive(Debug)]
     pub enum CommentQuery {
         ByContentsContains(CommentContentsContains),
         ByOwnerId(CommentByOwnerId),
         ByNoteId(CommentByNoteId),
+        ByParentId(CommentByParentId),
+        Sort(CommentSort),
     }
 
     impl Query for CommentQuery {
@@ -223,6 +294,15 @@ mod ext {
                 CommentQuery::ByContentsContains(inner) => inner.build(),
                 CommentQuery::ByOwnerId(inner) => inner.build(),
                 CommentQuery::ByNoteId(inner) => inner.build(),
+                CommentQuery::ByParentId(inner) => inner.build(),
+                CommentQuery::Sort(inner) => inner.build(),
+            }
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            match self {
+                CommentQuery::Sort(inner) => inner.order_by(),
+                _ => None,
             }
         }
     }
@@ -256,6 +336,23 @@ mod ext {
                     };
                     Ok(Self::ByNoteId(CommentByNoteId::new(id)))
                 }
+                "byParentId" => {
+                    let id = match v.parse::<i64>() {
+                        Ok(id) => id,
+                        Err(e) => {
+                            error!("{:?}", e);
+                            return Err(());
+                        }
+                    };
+                    Ok(Self::ByParentId(CommentByParentId::new(id)))
+                }
+                "sort" => match v.parse::<CommentOrder>() {
+                    Ok(order) => Ok(Self::Sort(CommentSort::new(order))),
+                    Err(_) => {
+                        error!("Invalid sort for Comment: {:?}", v);
+                        Err(())
+                    }
+                },
                 _ => {
                     error!("Unrecognized query for Comment: {:?}", (q, v));
                     Err(())
@@ -270,7 +367,7 @@ mod ext {
     }
 
     impl CommentByNoteId {
-        fn new(val: i64) -> Self {
+        pub fn new(val: i64) -> Self {
             Self {
                 inner: EqualsCriteria {
                     field: String::from("note_id"),
@@ -286,6 +383,78 @@ mod ext {
         }
     }
 
+    #[derive(Debug)]
+    pub struct CommentByParentId {
+        inner: EqualsCriteria,
+    }
+
+    impl CommentByParentId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("parent_comment_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for CommentByParentId {
+        fn build(&self) -> (String, Vec<Value>) {
+            self.inner.build()
+        }
+    }
+
+    // ids go up with time, so they double as the posting order
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum CommentOrder {
+        Oldest,
+        Newest,
+        MostReplies,
+    }
+
+    impl FromStr for CommentOrder {
+        type Err = ();
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "oldest" => Ok(CommentOrder::Oldest),
+                "newest" => Ok(CommentOrder::Newest),
+                "mostReplies" => Ok(CommentOrder::MostReplies),
+                _ => Err(()),
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct CommentSort {
+        order: CommentOrder,
+    }
+
+    impl CommentSort {
+        pub fn new(order: CommentOrder) -> Self {
+            Self { order }
+        }
+    }
+
+    impl Query for CommentSort {
+        fn build(&self) -> (String, Vec<Value>) {
+            ("1 = 1".to_string(), vec![])
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<Value>)> {
+            let order_by = match self.order {
+                CommentOrder::Oldest => "id",
+                CommentOrder::Newest => "id desc",
+                CommentOrder::MostReplies => {
+                    "(select count(*) from comments replies \
+                     where replies.parent_comment_id = comments.id) desc, id"
+                }
+            };
+            Some((order_by.to_string(), vec![]))
+        }
+    }
+
     #[derive(Debug)]
     pub struct CommentByOwnerId {
         inner: EqualsCriteria,
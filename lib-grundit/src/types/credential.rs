@@ -0,0 +1,406 @@
+// Credentials never leave the server, so unlike the other types these are
+// only compiled with the `full` feature.
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Credential {
+    pub id: i64,
+    pub owner_id: i64,
+    pub username: String,
+    pub password_hash: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestCredential {
+    pub id: Option<i64>,
+    pub owner_id: Option<i64>,
+    pub username: Option<String>,
+    pub password_hash: Option<String>,
+}
+
+// One time code issued by an admin to reset a forgotten password
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PasswordReset {
+    pub id: i64,
+    pub owner_id: i64,
+    pub code_hash: String,
+    pub expires_at: i64,
+    pub used: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestPasswordReset {
+    pub id: Option<i64>,
+    pub owner_id: Option<i64>,
+    pub code_hash: Option<String>,
+    pub expires_at: Option<i64>,
+    pub used: Option<bool>,
+}
+
+pub use ext::*;
+
+mod ext {
+    use super::{Credential, PasswordReset, RequestCredential, RequestPasswordReset};
+    use lib_glonk::types::{
+        AndCriteria, Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+
+    impl Bindable for Credential {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.username.as_str().bind(statement, 3)?;
+            self.password_hash.as_str().bind(statement, 4)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for Credential {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    username: statement.read::<String, _>("username").unwrap(),
+                    password_hash: statement.read::<String, _>("password_hash").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "credentials".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,username,password_hash".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestCredential {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(username) = self.username {
+                username.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(password_hash) = self.password_hash {
+                password_hash.as_str().bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestCredential {
+        fn validate_create(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            if self.owner_id.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "owner_id",
+                )));
+            }
+            if self.username.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "username",
+                )));
+            }
+            if self.password_hash.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "password_hash",
+                )));
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.username.is_some() {
+                cols.push("username");
+            }
+            if self.password_hash.is_some() {
+                cols.push("password_hash");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.username.is_some() {
+                ct += 1;
+            }
+            if self.password_hash.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    impl Bindable for PasswordReset {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.code_hash.as_str().bind(statement, 3)?;
+            self.expires_at.bind(statement, 4)?;
+            (self.used as i64).bind(statement, 5)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for PasswordReset {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    code_hash: statement.read::<String, _>("code_hash").unwrap(),
+                    expires_at: statement.read::<i64, _>("expires_at").unwrap(),
+                    used: statement.read::<i64, _>("used").unwrap() != 0,
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "password_resets".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,code_hash,expires_at,used".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestPasswordReset {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(code_hash) = self.code_hash {
+                code_hash.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(expires_at) = self.expires_at {
+                expires_at.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(used) = self.used {
+                (used as i64).bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestPasswordReset {
+        fn validate_create(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            if self.owner_id.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "owner_id",
+                )));
+            }
+            if self.code_hash.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "code_hash",
+                )));
+            }
+            if self.expires_at.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "expires_at",
+                )));
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.code_hash.is_some() {
+                cols.push("code_hash");
+            }
+            if self.expires_at.is_some() {
+                cols.push("expires_at");
+            }
+            if self.used.is_some() {
+                cols.push("used");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.code_hash.is_some() {
+                ct += 1;
+            }
+            if self.expires_at.is_some() {
+                ct += 1;
+            }
+            if self.used.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    // Query types
+    #[derive(Debug)]
+    pub struct CredentialByUsername {
+        inner: EqualsCriteria,
+    }
+
+    impl CredentialByUsername {
+        pub fn new(val: String) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("username"),
+                    val: Value::String(val),
+                },
+            }
+        }
+    }
+
+    impl Query for CredentialByUsername {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct CredentialByOwnerId {
+        inner: EqualsCriteria,
+    }
+
+    impl CredentialByOwnerId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("owner_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for CredentialByOwnerId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    // outstanding (unused) reset codes for a user
+    #[derive(Debug)]
+    pub struct PasswordResetPending {
+        inner: AndCriteria<EqualsCriteria, EqualsCriteria>,
+    }
+
+    impl PasswordResetPending {
+        pub fn new(owner_id: i64) -> Self {
+            Self {
+                inner: AndCriteria {
+                    left: EqualsCriteria {
+                        field: String::from("owner_id"),
+                        val: Value::Integer(owner_id),
+                    },
+                    right: EqualsCriteria {
+                        field: String::from("used"),
+                        val: Value::Integer(0),
+                    },
+                },
+            }
+        }
+    }
+
+    impl Query for PasswordResetPending {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+}
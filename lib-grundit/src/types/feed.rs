@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Sent by clients on the `/data/subscribe` socket.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum FeedRequest {
+    // `data_type` and `query` read as in `GET /data/{data_type}?{query}`, the
+    // id is the client's own and comes back on every event
+    Subscribe {
+        id: String,
+        data_type: String,
+        #[serde(default)]
+        query: HashMap<String, String>,
+    },
+    Unsubscribe {
+        id: String,
+    },
+}
+
+// Sent by the server, `subscription` is the id given when subscribing.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum FeedEvent {
+    // everything the subscription matches, first and again after falling
+    // behind
+    Snapshot {
+        subscription: String,
+        data: Vec<Value>,
+    },
+    // newly matching, made or changed into a match
+    Created {
+        subscription: String,
+        data: Value,
+    },
+    Updated {
+        subscription: String,
+        data: Value,
+    },
+    // gone, or changed so it no longer matches
+    Deleted {
+        subscription: String,
+        id: i64,
+    },
+    Error {
+        subscription: String,
+        message: String,
+    },
+}
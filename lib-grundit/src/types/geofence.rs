@@ -0,0 +1,496 @@
+// Named areas punches are checked against. Only admins can write them.
+//
+// A circle is `latitude`, `longitude` and `radius` in metres, a polygon is a
+// JSON list of `[lat, lon]` corners in `polygon`. `user_ids` is a comma list
+// of the users the fence is meant for, empty meaning everyone.
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    Circle,
+    Polygon,
+}
+
+impl Shape {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Shape::Circle => "circle",
+            Shape::Polygon => "polygon",
+        }
+    }
+}
+
+impl std::str::FromStr for Shape {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "circle" => Ok(Shape::Circle),
+            "polygon" => Ok(Shape::Polygon),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Geofence {
+    pub id: i64,
+    pub owner_id: i64,
+    pub name: String,
+    pub shape: Shape,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub radius: Option<f64>,
+    pub polygon: Option<String>,
+    pub user_ids: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestGeofence {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shape: Option<Shape>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub radius: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub polygon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_ids: Option<String>,
+}
+
+#[cfg(feature = "full")]
+pub use ext::*;
+
+#[cfg(feature = "full")]
+mod ext {
+    use super::{Geofence, RequestGeofence, Shape};
+    use crate::types::punch::EARTH_RADIUS;
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use lib_glonk::validation::{Rule, Validator};
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+    use tracing::error;
+
+    const MAX_RADIUS: f64 = 100_000.0;
+    const MAX_CORNERS: usize = 1000;
+
+    // great circle distance in metres
+    pub fn distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+        let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (lon2 - lon1).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().asin()
+    }
+
+    fn parse_polygon(polygon: &str) -> Option<Vec<[f64; 2]>> {
+        serde_json::from_str(polygon).ok()
+    }
+
+    fn parse_user_ids(user_ids: &str) -> Option<Vec<i64>> {
+        if user_ids.is_empty() {
+            return Some(vec![]);
+        }
+        user_ids
+            .split(',')
+            .map(|id| id.trim().parse().ok())
+            .collect()
+    }
+
+    impl Geofence {
+        pub fn contains(&self, lat: f64, lon: f64) -> bool {
+            match self.shape {
+                Shape::Circle => match (self.latitude, self.longitude, self.radius) {
+                    (Some(c_lat), Some(c_lon), Some(radius)) => {
+                        distance(c_lat, c_lon, lat, lon) <= radius
+                    }
+                    _ => false,
+                },
+                // even-odd ray casting on plain lat/lon, fine at the size of a
+                // site but not across the antimeridian
+                Shape::Polygon => {
+                    let corners = match self.polygon.as_deref().and_then(parse_polygon) {
+                        Some(corners) => corners,
+                        None => return false,
+                    };
+                    let mut inside = false;
+                    let mut j = corners.len().wrapping_sub(1);
+                    for i in 0..corners.len() {
+                        let ([lat_i, lon_i], [lat_j, lon_j]) = (corners[i], corners[j]);
+                        if (lat_i > lat) != (lat_j > lat)
+                            && lon < (lon_j - lon_i) * (lat - lat_i) / (lat_j - lat_i) + lon_i
+                        {
+                            inside = !inside;
+                        }
+                        j = i;
+                    }
+                    inside
+                }
+            }
+        }
+
+        pub fn applies_to(&self, user_id: i64) -> bool {
+            match parse_user_ids(&self.user_ids) {
+                Some(ids) => ids.is_empty() || ids.contains(&user_id),
+                None => false,
+            }
+        }
+    }
+
+    // The fence a punch falls in, preferring ones meant for the user, and
+    // whether to flag it: the user has fences and the punch is in none of them.
+    pub fn classify(fences: &[Geofence], user_id: i64, lat: f64, lon: f64) -> (Option<i64>, bool) {
+        let (own, other): (Vec<&Geofence>, Vec<&Geofence>) =
+            fences.iter().partition(|f| f.applies_to(user_id));
+        match own.iter().find(|f| f.contains(lat, lon)) {
+            Some(fence) => (Some(fence.id), false),
+            None => (
+                other.iter().find(|f| f.contains(lat, lon)).map(|f| f.id),
+                !own.is_empty(),
+            ),
+        }
+    }
+
+    impl Bindable for Geofence {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.name.as_str().bind(statement, 3)?;
+            self.shape.as_str().bind(statement, 4)?;
+            self.latitude.bind(statement, 5)?;
+            self.longitude.bind(statement, 6)?;
+            self.radius.bind(statement, 7)?;
+            self.polygon.as_deref().bind(statement, 8)?;
+            self.user_ids.as_str().bind(statement, 9)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for Geofence {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    name: statement.read::<String, _>("name").unwrap(),
+                    shape: statement
+                        .read::<String, _>("shape")
+                        .unwrap()
+                        .parse()
+                        .unwrap(),
+                    latitude: statement.read::<Option<f64>, _>("latitude").unwrap(),
+                    longitude: statement.read::<Option<f64>, _>("longitude").unwrap(),
+                    radius: statement.read::<Option<f64>, _>("radius").unwrap(),
+                    polygon: statement.read::<Option<String>, _>("polygon").unwrap(),
+                    user_ids: statement.read::<String, _>("user_ids").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "geofences".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,name,shape,latitude,longitude,radius,polygon,user_ids".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestGeofence {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(name) = self.name {
+                name.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(shape) = self.shape {
+                shape.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(latitude) = self.latitude {
+                latitude.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(longitude) = self.longitude {
+                longitude.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(radius) = self.radius {
+                radius.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(polygon) = self.polygon {
+                polygon.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(user_ids) = self.user_ids {
+                user_ids.as_str().bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestGeofence {
+        fn validate_create(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            for (field, present) in [
+                ("name", self.name.is_some()),
+                ("shape", self.shape.is_some()),
+            ] {
+                if !present {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        field,
+                    )));
+                }
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.name.is_some() {
+                cols.push("name");
+            }
+            if self.shape.is_some() {
+                cols.push("shape");
+            }
+            if self.latitude.is_some() {
+                cols.push("latitude");
+            }
+            if self.longitude.is_some() {
+                cols.push("longitude");
+            }
+            if self.radius.is_some() {
+                cols.push("radius");
+            }
+            if self.polygon.is_some() {
+                cols.push("polygon");
+            }
+            if self.user_ids.is_some() {
+                cols.push("user_ids");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.name.is_some() {
+                ct += 1;
+            }
+            if self.shape.is_some() {
+                ct += 1;
+            }
+            if self.latitude.is_some() {
+                ct += 1;
+            }
+            if self.longitude.is_some() {
+                ct += 1;
+            }
+            if self.radius.is_some() {
+                ct += 1;
+            }
+            if self.polygon.is_some() {
+                ct += 1;
+            }
+            if self.user_ids.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn field_rules(&self, v: &mut Validator) {
+            v.text(
+                "name",
+                self.name.as_deref(),
+                &[Rule::NotBlank, Rule::MaxLen(100)],
+            )
+            .range("latitude", self.latitude, -90.0, 90.0)
+            .range("longitude", self.longitude, -180.0, 180.0)
+            .range("radius", self.radius, 1.0, MAX_RADIUS);
+            if let Some(polygon) = self.polygon.as_deref() {
+                let valid = parse_polygon(polygon).is_some_and(|corners| {
+                    (3..=MAX_CORNERS).contains(&corners.len())
+                        && corners.iter().all(|[lat, lon]| {
+                            (-90.0..=90.0).contains(lat) && (-180.0..=180.0).contains(lon)
+                        })
+                });
+                v.check(
+                    "polygon",
+                    "polygon",
+                    valid,
+                    "must be a list of 3 or more [lat, lon] corners",
+                );
+            }
+            if let Some(user_ids) = self.user_ids.as_deref() {
+                v.check(
+                    "user_ids",
+                    "ids",
+                    parse_user_ids(user_ids).is_some(),
+                    "must be a comma separated list of user ids",
+                );
+            }
+            // each shape needs its own fields, checked whenever the shape is set
+            match self.shape {
+                Some(Shape::Circle) => {
+                    v.check(
+                        "radius",
+                        "required",
+                        self.latitude.is_some()
+                            && self.longitude.is_some()
+                            && self.radius.is_some(),
+                        "circles need latitude, longitude and radius",
+                    );
+                }
+                Some(Shape::Polygon) => {
+                    v.check(
+                        "polygon",
+                        "required",
+                        self.polygon.is_some(),
+                        "polygons need a polygon",
+                    );
+                }
+                None => {}
+            }
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    // Query types
+    #[derive(Debug)]
+    pub enum GeofenceQuery {
+        ByName(GeofenceByName),
+    }
+
+    impl Query for GeofenceQuery {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            match self {
+                GeofenceQuery::ByName(inner) => inner.build(),
+            }
+        }
+    }
+
+    impl TryFrom<(&String, &String)> for GeofenceQuery {
+        type Error = ();
+
+        fn try_from((q, v): (&String, &String)) -> Result<Self, Self::Error> {
+            match q.as_str() {
+                "byName" => Ok(Self::ByName(GeofenceByName::new(v.to_string()))),
+                _ => {
+                    error!("Unrecognized query for Geofence: {:?}", (q, v));
+                    Err(())
+                }
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct GeofenceByName {
+        inner: EqualsCriteria,
+    }
+
+    impl GeofenceByName {
+        pub fn new(val: String) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("name"),
+                    val: Value::String(val),
+                },
+            }
+        }
+    }
+
+    impl Query for GeofenceByName {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+}
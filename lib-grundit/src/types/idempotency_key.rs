@@ -0,0 +1,268 @@
+// Responses kept for creates sent with an `Idempotency-Key`, see `sync`.
+// Only the server makes them, so like roles these are only compiled with the
+// `full` feature.
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct IdempotencyKey {
+    pub id: i64,
+    pub owner_id: i64,
+    // as the client sent it
+    pub key: String,
+    // digest of the type and body, so a key can't be reused for another
+    // request
+    pub request: String,
+    // the response the first time
+    pub status: i64,
+    pub body: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestIdempotencyKey {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+}
+
+pub use ext::*;
+
+mod ext {
+    use super::{IdempotencyKey, RequestIdempotencyKey};
+    use lib_glonk::types::{
+        AndCriteria, Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+
+    impl Bindable for IdempotencyKey {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.key.as_str().bind(statement, 3)?;
+            self.request.as_str().bind(statement, 4)?;
+            self.status.bind(statement, 5)?;
+            self.body.as_str().bind(statement, 6)?;
+            self.created_at.bind(statement, 7)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for IdempotencyKey {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    key: statement.read::<String, _>("key").unwrap(),
+                    request: statement.read::<String, _>("request").unwrap(),
+                    status: statement.read::<i64, _>("status").unwrap(),
+                    body: statement.read::<String, _>("body").unwrap(),
+                    created_at: statement.read::<i64, _>("created_at").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "idempotency_keys".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,key,request,status,body,created_at".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestIdempotencyKey {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(key) = self.key {
+                key.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(request) = self.request {
+                request.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(status) = self.status {
+                status.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(body) = self.body {
+                body.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(created_at) = self.created_at {
+                created_at.bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestIdempotencyKey {
+        fn validate_create(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            for (field, present) in [
+                ("owner_id", self.owner_id.is_some()),
+                ("key", self.key.is_some()),
+                ("request", self.request.is_some()),
+                ("status", self.status.is_some()),
+                ("body", self.body.is_some()),
+                ("created_at", self.created_at.is_some()),
+            ] {
+                if !present {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        field,
+                    )));
+                }
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.key.is_some() {
+                cols.push("key");
+            }
+            if self.request.is_some() {
+                cols.push("request");
+            }
+            if self.status.is_some() {
+                cols.push("status");
+            }
+            if self.body.is_some() {
+                cols.push("body");
+            }
+            if self.created_at.is_some() {
+                cols.push("created_at");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.key.is_some() {
+                ct += 1;
+            }
+            if self.request.is_some() {
+                ct += 1;
+            }
+            if self.status.is_some() {
+                ct += 1;
+            }
+            if self.body.is_some() {
+                ct += 1;
+            }
+            if self.created_at.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    // Query types
+    #[derive(Debug)]
+    pub struct IdempotencyKeyByKey {
+        inner: AndCriteria<EqualsCriteria, EqualsCriteria>,
+    }
+
+    impl IdempotencyKeyByKey {
+        pub fn new(owner_id: i64, key: String) -> Self {
+            Self {
+                inner: AndCriteria {
+                    left: EqualsCriteria {
+                        field: String::from("owner_id"),
+                        val: Value::Integer(owner_id),
+                    },
+                    right: EqualsCriteria {
+                        field: String::from("key"),
+                        val: Value::String(key),
+                    },
+                },
+            }
+        }
+    }
+
+    impl Query for IdempotencyKeyByKey {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    // kept since before `created_at`, for sweeping
+    #[derive(Debug)]
+    pub struct IdempotencyKeysBefore {
+        created_at: i64,
+    }
+
+    impl IdempotencyKeysBefore {
+        pub fn new(created_at: i64) -> Self {
+            Self { created_at }
+        }
+    }
+
+    impl Query for IdempotencyKeysBefore {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            (
+                "created_at < ?".to_string(),
+                vec![Value::Integer(self.created_at)],
+            )
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Identity {
+    pub id: i64,
+    pub owner_id: i64,
+    pub guid: String,
+    pub provider: String,
+    pub email: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestIdentity {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+#[cfg(feature = "full")]
+pub use ext::*;
+
+#[cfg(feature = "full")]
+mod ext {
+    use super::{Identity, RequestIdentity};
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+    use tracing::error;
+    impl Bindable for Identity {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.guid.as_str().bind(statement, 3)?;
+            self.provider.as_str().bind(statement, 4)?;
+            self.email.as_str().bind(statement, 5)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for Identity {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    guid: statement.read::<String, _>("guid").unwrap(),
+                    provider: statement.read::<String, _>("provider").unwrap(),
+                    email: statement.read::<String, _>("email").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "identities".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,guid,provider,email".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestIdentity {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(guid) = self.guid {
+                guid.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(provider) = self.provider {
+                provider.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(email) = self.email {
+                email.as_str().bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestIdentity {
+        fn validate_create(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            if self.guid.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "guid",
+                )));
+            }
+            if self.provider.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "provider",
+                )));
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.guid.is_some() {
+                cols.push("guid");
+            }
+            if self.provider.is_some() {
+                cols.push("provider");
+            }
+            if self.email.is_some() {
+                cols.push("email");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.guid.is_some() {
+                ct += 1;
+            }
+            if self.provider.is_some() {
+                ct += 1;
+            }
+            if self.email.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    // Query types
+    #[derive(Debug)]
+    pub enum IdentityQuery {
+        ByGuid(IdentityByGuid),
+        ByOwnerId(IdentityByOwnerId),
+    }
+
+    impl Query for IdentityQuery {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            match self {
+                IdentityQuery::ByGuid(inner) => inner.build(),
+                IdentityQuery::ByOwnerId(inner) => inner.build(),
+            }
+        }
+    }
+
+    impl TryFrom<(&String, &String)> for IdentityQuery {
+        type Error = ();
+
+        fn try_from((q, v): (&String, &String)) -> Result<Self, Self::Error> {
+            let q = q.as_str();
+            match q {
+                "byGuid" => Ok(Self::ByGuid(IdentityByGuid::new(v.to_string()))),
+                "byOwnerId" => {
+                    let id = match v.parse::<i64>() {
+                        Ok(id) => id,
+                        Err(e) => {
+                            error!("{:?}", e);
+                            return Err(());
+                        }
+                    };
+                    Ok(Self::ByOwnerId(IdentityByOwnerId::new(id)))
+                }
+                _ => {
+                    error!("Unrecognized query for Identity: {:?}", (q, v));
+                    Err(())
+                }
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct IdentityByGuid {
+        inner: EqualsCriteria,
+    }
+
+    impl IdentityByGuid {
+        pub fn new(val: String) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("guid"),
+                    val: Value::String(val),
+                },
+            }
+        }
+    }
+
+    impl Query for IdentityByGuid {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct IdentityByOwnerId {
+        inner: EqualsCriteria,
+    }
+
+    impl IdentityByOwnerId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("owner_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for IdentityByOwnerId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+}
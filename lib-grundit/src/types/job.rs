@@ -0,0 +1,417 @@
+// Rows of the job queue, see `jobs`. Only the server makes them, so like
+// roles these are only compiled with the `full` feature.
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    // waiting for `run_at`
+    Queued,
+    Running,
+    // waiting for `run_at` to try again
+    Failed,
+    // out of attempts, left for someone to look at
+    Dead,
+}
+
+impl JobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Failed => "failed",
+            JobStatus::Dead => "dead",
+        }
+    }
+}
+
+impl FromStr for JobStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(JobStatus::Queued),
+            "running" => Ok(JobStatus::Running),
+            "failed" => Ok(JobStatus::Failed),
+            "dead" => Ok(JobStatus::Dead),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct JobRecord {
+    pub id: i64,
+    // which `Job` runs it
+    pub kind: String,
+    // the job itself, as JSON
+    pub payload: String,
+    pub status: JobStatus,
+    pub attempts: i64,
+    pub max_attempts: i64,
+    // unix seconds, as are the other times
+    pub run_at: i64,
+    // when it comes round again, empty for jobs that run once
+    pub schedule: String,
+    pub last_error: String,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestJob {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<JobStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub run_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<i64>,
+}
+
+pub use ext::*;
+
+mod ext {
+    use super::{JobRecord, JobStatus, RequestJob};
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+
+    impl Bindable for JobRecord {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.kind.as_str().bind(statement, 2)?;
+            self.payload.as_str().bind(statement, 3)?;
+            self.status.as_str().bind(statement, 4)?;
+            self.attempts.bind(statement, 5)?;
+            self.max_attempts.bind(statement, 6)?;
+            self.run_at.bind(statement, 7)?;
+            self.schedule.as_str().bind(statement, 8)?;
+            self.last_error.as_str().bind(statement, 9)?;
+            self.created_at.bind(statement, 10)?;
+            self.updated_at.bind(statement, 11)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for JobRecord {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    kind: statement.read::<String, _>("kind").unwrap(),
+                    payload: statement.read::<String, _>("payload").unwrap(),
+                    status: statement
+                        .read::<String, _>("status")
+                        .unwrap()
+                        .parse()
+                        .unwrap(),
+                    attempts: statement.read::<i64, _>("attempts").unwrap(),
+                    max_attempts: statement.read::<i64, _>("max_attempts").unwrap(),
+                    run_at: statement.read::<i64, _>("run_at").unwrap(),
+                    schedule: statement.read::<String, _>("schedule").unwrap(),
+                    last_error: statement.read::<String, _>("last_error").unwrap(),
+                    created_at: statement.read::<i64, _>("created_at").unwrap(),
+                    updated_at: statement.read::<i64, _>("updated_at").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "jobs".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,kind,payload,status,attempts,max_attempts,run_at,schedule,last_error,created_at,updated_at".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        // nobody owns these
+        fn owner_id_col() -> String {
+            "id".to_string()
+        }
+    }
+
+    impl Bindable for RequestJob {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(kind) = self.kind {
+                kind.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(payload) = self.payload {
+                payload.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(status) = self.status {
+                status.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(attempts) = self.attempts {
+                attempts.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(max_attempts) = self.max_attempts {
+                max_attempts.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(run_at) = self.run_at {
+                run_at.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(schedule) = self.schedule {
+                schedule.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(last_error) = self.last_error {
+                last_error.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(created_at) = self.created_at {
+                created_at.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(updated_at) = self.updated_at {
+                updated_at.bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestJob {
+        fn validate_create(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            for (field, present) in [
+                ("kind", self.kind.is_some()),
+                ("payload", self.payload.is_some()),
+                ("status", self.status.is_some()),
+                ("max_attempts", self.max_attempts.is_some()),
+                ("run_at", self.run_at.is_some()),
+                ("created_at", self.created_at.is_some()),
+                ("updated_at", self.updated_at.is_some()),
+            ] {
+                if !present {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        field,
+                    )));
+                }
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.kind.is_some() {
+                cols.push("kind");
+            }
+            if self.payload.is_some() {
+                cols.push("payload");
+            }
+            if self.status.is_some() {
+                cols.push("status");
+            }
+            if self.attempts.is_some() {
+                cols.push("attempts");
+            }
+            if self.max_attempts.is_some() {
+                cols.push("max_attempts");
+            }
+            if self.run_at.is_some() {
+                cols.push("run_at");
+            }
+            if self.schedule.is_some() {
+                cols.push("schedule");
+            }
+            if self.last_error.is_some() {
+                cols.push("last_error");
+            }
+            if self.created_at.is_some() {
+                cols.push("created_at");
+            }
+            if self.updated_at.is_some() {
+                cols.push("updated_at");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.kind.is_some() {
+                ct += 1;
+            }
+            if self.payload.is_some() {
+                ct += 1;
+            }
+            if self.status.is_some() {
+                ct += 1;
+            }
+            if self.attempts.is_some() {
+                ct += 1;
+            }
+            if self.max_attempts.is_some() {
+                ct += 1;
+            }
+            if self.run_at.is_some() {
+                ct += 1;
+            }
+            if self.schedule.is_some() {
+                ct += 1;
+            }
+            if self.last_error.is_some() {
+                ct += 1;
+            }
+            if self.created_at.is_some() {
+                ct += 1;
+            }
+            if self.updated_at.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        // as `owner_id_col` is the id
+        fn owner_id(&self) -> Option<i64> {
+            self.id
+        }
+    }
+
+    // Query types
+    #[derive(Debug)]
+    pub struct JobByStatus {
+        inner: EqualsCriteria,
+    }
+
+    impl JobByStatus {
+        pub fn new(status: JobStatus) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("status"),
+                    val: Value::String(status.as_str().to_string()),
+                },
+            }
+        }
+    }
+
+    impl Query for JobByStatus {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("run_at, id".to_string(), vec![]))
+        }
+    }
+
+    // the rows kept for recurring jobs
+    #[derive(Debug)]
+    pub struct JobRecurring;
+
+    impl Query for JobRecurring {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            ("schedule != ''".to_string(), vec![])
+        }
+    }
+
+    // queued or failed jobs whose time has come, soonest first
+    #[derive(Debug)]
+    pub struct JobsDue {
+        now: i64,
+        size: i64,
+    }
+
+    impl JobsDue {
+        pub fn new(now: i64, size: i64) -> Self {
+            Self { now, size }
+        }
+    }
+
+    impl Query for JobsDue {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            (
+                "status in (?, ?) and run_at <= ?".to_string(),
+                vec![
+                    Value::String(JobStatus::Queued.as_str().to_string()),
+                    Value::String(JobStatus::Failed.as_str().to_string()),
+                    Value::Integer(self.now),
+                ],
+            )
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("run_at, id".to_string(), vec![]))
+        }
+
+        fn limit(&self) -> Option<i64> {
+            Some(self.size)
+        }
+    }
+
+    // the next job to come due, for how long the worker may sleep
+    #[derive(Debug)]
+    pub struct NextJob;
+
+    impl Query for NextJob {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            (
+                "status in (?, ?)".to_string(),
+                vec![
+                    Value::String(JobStatus::Queued.as_str().to_string()),
+                    Value::String(JobStatus::Failed.as_str().to_string()),
+                ],
+            )
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("run_at".to_string(), vec![]))
+        }
+
+        fn limit(&self) -> Option<i64> {
+            Some(1)
+        }
+    }
+}
@@ -1,22 +1,81 @@
+mod api_token;
+mod attachment;
+#[cfg(feature = "full")]
+mod change;
 mod comment;
+#[cfg(feature = "full")]
+mod credential;
+mod feed;
+mod geofence;
+#[cfg(feature = "full")]
+mod idempotency_key;
+mod identity;
+#[cfg(feature = "full")]
+mod job;
 mod note;
+mod note_tag;
+mod notification;
 mod punch;
+#[cfg(feature = "full")]
+mod role;
+mod sync;
+mod tag;
+mod thumbnail;
 mod user;
+mod webhook;
+mod webhook_delivery;
 
-pub use comment::Comment;
-pub use note::Note;
-pub use punch::{Punch, RequestPunch};
+pub use api_token::{ApiToken, RequestApiToken};
+pub use attachment::{Attachment, RequestAttachment, Thumbnails};
+pub use comment::{Comment, CommentThread, threads};
+pub use feed::{FeedEvent, FeedRequest};
+pub use geofence::{Geofence, RequestGeofence, Shape};
+pub use identity::{Identity, RequestIdentity};
+pub use note::{Note, RequestNote};
+pub use note_tag::{NoteTag, RequestNoteTag};
+pub use notification::{Notification, NotificationKind, RequestNotification};
+pub use punch::{Direction, Punch, RequestPunch};
+pub use sync::{ChangeSet, SyncChange};
+pub use tag::{RequestTag, Tag, TagCount};
+pub use thumbnail::{RequestThumbnail, Thumbnail};
 pub use user::User;
+pub use webhook::{RequestWebhook, Webhook};
+pub use webhook_delivery::{DeliveryStatus, RequestWebhookDelivery, WebhookDelivery};
 
 #[cfg(feature = "full")]
 pub use ext::*;
 
 #[cfg(feature = "full")]
 mod ext {
-    pub use super::comment::{CommentQuery, RequestComment};
-    pub use super::note::{NoteQuery, RequestNote};
-    pub use super::punch::PunchQuery;
-    pub use super::user::{RequestUser, UserByGuid, UserQuery};
+    pub use super::api_token::{ApiTokenByHash, ApiTokenByOwnerId};
+    pub use super::attachment::{
+        AttachmentByDigest, AttachmentByNoteId, AttachmentByOwnerId, AttachmentByThumbnails,
+        AttachmentQuery,
+    };
+    pub use super::change::*;
+    pub use super::comment::{
+        CommentByNoteId, CommentByParentId, CommentOrder, CommentQuery, CommentSort, RequestComment,
+    };
+    pub use super::credential::*;
+    pub use super::geofence::{GeofenceByName, GeofenceQuery, classify, distance};
+    pub use super::idempotency_key::*;
+    pub use super::identity::{IdentityByOwnerId, IdentityQuery};
+    pub use super::job::*;
+    pub use super::note::{NoteByTags, NoteQuery};
+    pub use super::note_tag::{NoteTagByNoteId, NoteTagByOwnerId, NoteTagByTagId, NoteTagQuery};
+    pub use super::notification::{NotificationByOwnerId, NotificationByRead, NotificationQuery};
+    pub use super::punch::{
+        PunchByOwnerId, PunchCapturedAfter, PunchCapturedBefore, PunchPage, PunchQuery,
+    };
+    pub use super::role::*;
+    pub use super::tag::{TagByName, TagByOwnerId, TagCloud, TagQuery};
+    pub use super::thumbnail::{ThumbnailByAttachmentId, ThumbnailByDigest, ThumbnailBySize};
+    pub use super::user::{RequestUser, UserByGuid, UserByName, UserQuery};
+    pub use super::webhook::{WebhookByActive, WebhookByOwnerId, WebhookQuery};
+    pub use super::webhook_delivery::{
+        DeliveriesDue, DeliveryByOwnerId, DeliveryByStatus, DeliveryByWebhookId, NextDelivery,
+        WebhookDeliveryQuery,
+    };
 
     use axum::{
         extract::{
@@ -32,7 +91,7 @@ mod ext {
     use tracing::debug;
 
     // Application specific
-    #[derive(Debug, Deserialize)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
     pub enum DataType {
         #[serde(rename = "user")]
         User,
@@ -42,6 +101,57 @@ mod ext {
         Comment,
         #[serde(rename = "punch")]
         Punch,
+        #[serde(rename = "identity")]
+        Identity,
+        #[serde(rename = "geofence")]
+        Geofence,
+        #[serde(rename = "tag")]
+        Tag,
+        #[serde(rename = "note_tag")]
+        NoteTag,
+        #[serde(rename = "attachment")]
+        Attachment,
+        #[serde(rename = "notification")]
+        Notification,
+        #[serde(rename = "webhook")]
+        Webhook,
+        #[serde(rename = "webhook_delivery")]
+        WebhookDelivery,
+    }
+
+    impl DataType {
+        pub const ALL: [DataType; 12] = [
+            DataType::User,
+            DataType::Note,
+            DataType::Comment,
+            DataType::Punch,
+            DataType::Identity,
+            DataType::Geofence,
+            DataType::Tag,
+            DataType::NoteTag,
+            DataType::Attachment,
+            DataType::Notification,
+            DataType::Webhook,
+            DataType::WebhookDelivery,
+        ];
+
+        // as it's named in routes
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                DataType::User => "user",
+                DataType::Note => "note",
+                DataType::Comment => "comment",
+                DataType::Punch => "punch",
+                DataType::Identity => "identity",
+                DataType::Geofence => "geofence",
+                DataType::Tag => "tag",
+                DataType::NoteTag => "note_tag",
+                DataType::Attachment => "attachment",
+                DataType::Notification => "notification",
+                DataType::Webhook => "webhook",
+                DataType::WebhookDelivery => "webhook_delivery",
+            }
+        }
     }
 
     #[derive(Debug)]
@@ -50,6 +160,14 @@ mod ext {
         NoteQuery(NoteQuery),
         CommentQuery(CommentQuery),
         PunchQuery(PunchQuery),
+        IdentityQuery(IdentityQuery),
+        GeofenceQuery(GeofenceQuery),
+        TagQuery(TagQuery),
+        NoteTagQuery(NoteTagQuery),
+        AttachmentQuery(AttachmentQuery),
+        NotificationQuery(NotificationQuery),
+        WebhookQuery(WebhookQuery),
+        WebhookDeliveryQuery(WebhookDeliveryQuery),
     }
 
     impl Query for QueryTypes {
@@ -59,6 +177,30 @@ mod ext {
                 Self::NoteQuery(inner) => inner.build(),
                 Self::CommentQuery(inner) => inner.build(),
                 Self::PunchQuery(inner) => inner.build(),
+                Self::IdentityQuery(inner) => inner.build(),
+                Self::GeofenceQuery(inner) => inner.build(),
+                Self::TagQuery(inner) => inner.build(),
+                Self::NoteTagQuery(inner) => inner.build(),
+                Self::AttachmentQuery(inner) => inner.build(),
+                Self::NotificationQuery(inner) => inner.build(),
+                Self::WebhookQuery(inner) => inner.build(),
+                Self::WebhookDeliveryQuery(inner) => inner.build(),
+            }
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            match self {
+                Self::CommentQuery(inner) => inner.order_by(),
+                Self::PunchQuery(inner) => inner.order_by(),
+                Self::NotificationQuery(inner) => inner.order_by(),
+                _ => None,
+            }
+        }
+
+        fn limit(&self) -> Option<i64> {
+            match self {
+                Self::PunchQuery(inner) => inner.limit(),
+                _ => None,
             }
         }
     }
@@ -86,6 +228,38 @@ mod ext {
                     let nq = PunchQuery::try_from((query, val))?;
                     Ok(QueryTypes::PunchQuery(nq))
                 }
+                DataType::Identity => {
+                    let iq = IdentityQuery::try_from((query, val))?;
+                    Ok(QueryTypes::IdentityQuery(iq))
+                }
+                DataType::Geofence => {
+                    let gq = GeofenceQuery::try_from((query, val))?;
+                    Ok(QueryTypes::GeofenceQuery(gq))
+                }
+                DataType::Tag => {
+                    let tq = TagQuery::try_from((query, val))?;
+                    Ok(QueryTypes::TagQuery(tq))
+                }
+                DataType::NoteTag => {
+                    let ntq = NoteTagQuery::try_from((query, val))?;
+                    Ok(QueryTypes::NoteTagQuery(ntq))
+                }
+                DataType::Attachment => {
+                    let aq = AttachmentQuery::try_from((query, val))?;
+                    Ok(QueryTypes::AttachmentQuery(aq))
+                }
+                DataType::Notification => {
+                    let nq = NotificationQuery::try_from((query, val))?;
+                    Ok(QueryTypes::NotificationQuery(nq))
+                }
+                DataType::Webhook => {
+                    let wq = WebhookQuery::try_from((query, val))?;
+                    Ok(QueryTypes::WebhookQuery(wq))
+                }
+                DataType::WebhookDelivery => {
+                    let dq = WebhookDeliveryQuery::try_from((query, val))?;
+                    Ok(QueryTypes::WebhookDeliveryQuery(dq))
+                }
             }
         }
     }
@@ -4,7 +4,10 @@ use serde::{Deserialize, Serialize};
 pub struct Note {
     pub id: i64,
     pub owner_id: i64,
+    // CommonMark, see /data/note/{id}/rendered
     pub contents: String,
+    // goes up by one each time the contents change
+    pub version: i64,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -15,6 +18,10 @@ pub struct RequestNote {
     pub owner_id: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub contents: Option<String>,
+    // on update, the version the change was made to, and if the note has
+    // moved on since it's refused with a 409. Otherwise set by the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
 }
 
 #[cfg(feature = "full")]
@@ -24,9 +31,10 @@ pub use ext::*;
 mod ext {
     use super::{Note, RequestNote};
     use lib_glonk::types::{
-        ContainsCriteria, Criteria, DataObject, EqualsCriteria, Query, RequestObject,
-        ValidationError,
+        AndCriteria, ContainsCriteria, Criteria, DataObject, EqualsCriteria, OrCriteria, Query,
+        RequestObject, ValidationError,
     };
+    use lib_glonk::validation::{Rule, Validator};
     use sqlite::{Bindable, BindableWithIndex, State, Value};
     use tracing::error;
     impl Bindable for Note {
@@ -34,6 +42,7 @@ mod ext {
             self.id.clone().bind(statement, 1)?;
             self.owner_id.clone().bind(statement, 2)?;
             self.contents.clone().as_str().bind(statement, 3)?;
+            self.version.bind(statement, 4)?;
             Ok(())
         }
     }
@@ -46,6 +55,7 @@ mod ext {
                     id: statement.read::<i64, _>("id").unwrap(),
                     owner_id: statement.read::<i64, _>("owner_id").unwrap(),
                     contents: statement.read::<String, _>("contents").unwrap(),
+                    version: statement.read::<i64, _>("version").unwrap(),
                 });
             }
             return res;
@@ -56,7 +66,7 @@ mod ext {
         }
 
         fn sql_cols() -> String {
-            "id,owner_id,contents".to_string()
+            "id,owner_id,contents,version".to_string()
         }
 
         fn id_col() -> String {
@@ -81,6 +91,10 @@ mod ext {
             }
             if let Some(contents) = self.contents {
                 contents.clone().as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(version) = self.version {
+                version.bind(statement, idx)?;
             }
             Ok(())
         }
@@ -155,6 +169,9 @@ mod ext {
             if let Some(_) = self.contents {
                 cols.push("contents");
             }
+            if self.version.is_some() {
+                cols.push("version");
+            }
             cols.join(",")
         }
 
@@ -169,9 +186,20 @@ mod ext {
             if let Some(_) = self.contents {
                 ct += 1;
             }
+            if self.version.is_some() {
+                ct += 1;
+            }
             vec!["?"; ct].join(",")
         }
 
+        fn field_rules(&self, v: &mut Validator) {
+            v.text(
+                "contents",
+                self.contents.as_deref(),
+                &[Rule::MaxLen(10_000)],
+            );
+        }
+
         fn id(&self) -> Option<i64> {
             self.id
         }
@@ -182,10 +210,15 @@ mod ext {
     }
 
     // Query types
+    //
+    //   byTag=name
+    //   byAllTags=a,b        tagged with every one of them
+    //   byAnyTags=a,b        tagged with at least one
     #[derive(Debug)]
     pub enum NoteQuery {
         ByContentsContains(NoteContentsContains),
         ByOwnerId(NoteByOwnerId),
+        ByTags(NoteByTags),
     }
 
     impl Query for NoteQuery {
@@ -193,6 +226,7 @@ mod ext {
             match self {
                 NoteQuery::ByContentsContains(inner) => inner.build(),
                 NoteQuery::ByOwnerId(inner) => inner.build(),
+                NoteQuery::ByTags(inner) => inner.build(),
             }
         }
     }
@@ -216,6 +250,23 @@ mod ext {
                     };
                     Ok(Self::ByOwnerId(NoteByOwnerId::new(id)))
                 }
+                "byTag" => Ok(Self::ByTags(NoteByTags::all(vec![v.to_string()]))),
+                "byAllTags" | "byAnyTags" => {
+                    let names: Vec<String> = v
+                        .split(',')
+                        .map(|name| name.trim().to_string())
+                        .filter(|name| !name.is_empty())
+                        .collect();
+                    if names.is_empty() {
+                        error!("No tags for Note: {:?}", (q, v));
+                        return Err(());
+                    }
+                    if q == "byAllTags" {
+                        Ok(Self::ByTags(NoteByTags::all(names)))
+                    } else {
+                        Ok(Self::ByTags(NoteByTags::any(names)))
+                    }
+                }
                 _ => {
                     error!("Unrecognized query for Note: {:?}", (q, v));
                     Err(())
@@ -224,6 +275,58 @@ mod ext {
         }
     }
 
+    // the note carries its owner's tag called `name`
+    #[derive(Debug)]
+    struct TaggedCriteria {
+        name: String,
+    }
+
+    impl Criteria for TaggedCriteria {
+        fn build(&self) -> (String, Vec<Value>) {
+            (
+                "id in (select note_tags.note_id from note_tags \
+                 join tags on tags.id = note_tags.tag_id \
+                 where tags.name = ? and tags.owner_id = notes.owner_id)"
+                    .to_string(),
+                vec![Value::String(self.name.clone())],
+            )
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct NoteByTags {
+        inner: Box<dyn Criteria>,
+    }
+
+    impl NoteByTags {
+        fn tagged(names: Vec<String>) -> impl Iterator<Item = Box<dyn Criteria>> {
+            names
+                .into_iter()
+                .map(|name| Box::new(TaggedCriteria { name }) as Box<dyn Criteria>)
+        }
+
+        // `names` must not be empty
+        pub fn all(names: Vec<String>) -> Self {
+            let inner = Self::tagged(names)
+                .reduce(|left, right| Box::new(AndCriteria { left, right }))
+                .unwrap();
+            Self { inner }
+        }
+
+        pub fn any(names: Vec<String>) -> Self {
+            let inner = Self::tagged(names)
+                .reduce(|left, right| Box::new(OrCriteria { left, right }))
+                .unwrap();
+            Self { inner }
+        }
+    }
+
+    impl Query for NoteByTags {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
     #[derive(Debug)]
     pub struct NoteByOwnerId {
         inner: EqualsCriteria,
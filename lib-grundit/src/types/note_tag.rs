@@ -0,0 +1,303 @@
+use serde::{Deserialize, Serialize};
+
+// A tag on a note, both belonging to the same user.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct NoteTag {
+    pub id: i64,
+    pub owner_id: i64,
+    pub note_id: i64,
+    pub tag_id: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestNoteTag {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag_id: Option<i64>,
+}
+
+#[cfg(feature = "full")]
+pub use ext::*;
+
+#[cfg(feature = "full")]
+mod ext {
+    use super::{NoteTag, RequestNoteTag};
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+    use tracing::error;
+
+    impl Bindable for NoteTag {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.note_id.bind(statement, 3)?;
+            self.tag_id.bind(statement, 4)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for NoteTag {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    note_id: statement.read::<i64, _>("note_id").unwrap(),
+                    tag_id: statement.read::<i64, _>("tag_id").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "note_tags".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,note_id,tag_id".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestNoteTag {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(note_id) = self.note_id {
+                note_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(tag_id) = self.tag_id {
+                tag_id.bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestNoteTag {
+        fn validate_create(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            for (field, present) in [
+                ("note_id", self.note_id.is_some()),
+                ("tag_id", self.tag_id.is_some()),
+            ] {
+                if !present {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        field,
+                    )));
+                }
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.note_id.is_some() {
+                cols.push("note_id");
+            }
+            if self.tag_id.is_some() {
+                cols.push("tag_id");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.note_id.is_some() {
+                ct += 1;
+            }
+            if self.tag_id.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    // Query types
+    #[derive(Debug)]
+    pub enum NoteTagQuery {
+        ByNoteId(NoteTagByNoteId),
+        ByTagId(NoteTagByTagId),
+    }
+
+    impl Query for NoteTagQuery {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            match self {
+                NoteTagQuery::ByNoteId(inner) => inner.build(),
+                NoteTagQuery::ByTagId(inner) => inner.build(),
+            }
+        }
+    }
+
+    impl TryFrom<(&String, &String)> for NoteTagQuery {
+        type Error = ();
+
+        fn try_from((q, v): (&String, &String)) -> Result<Self, Self::Error> {
+            let q = q.as_str();
+            let id = match v.parse::<i64>() {
+                Ok(id) => id,
+                Err(e) => {
+                    error!("{:?}", e);
+                    return Err(());
+                }
+            };
+            match q {
+                "byNoteId" => Ok(Self::ByNoteId(NoteTagByNoteId::new(id))),
+                "byTagId" => Ok(Self::ByTagId(NoteTagByTagId::new(id))),
+                _ => {
+                    error!("Unrecognized query for NoteTag: {:?}", (q, v));
+                    Err(())
+                }
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct NoteTagByNoteId {
+        inner: EqualsCriteria,
+    }
+
+    impl NoteTagByNoteId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("note_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for NoteTagByNoteId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct NoteTagByTagId {
+        inner: EqualsCriteria,
+    }
+
+    impl NoteTagByTagId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("tag_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for NoteTagByTagId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct NoteTagByOwnerId {
+        inner: EqualsCriteria,
+    }
+
+    impl NoteTagByOwnerId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("owner_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for NoteTagByOwnerId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+}
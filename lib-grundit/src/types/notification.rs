@@ -0,0 +1,377 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    // someone commented on your note
+    Comment,
+    // someone wrote `@you` in a comment
+    Mention,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::Comment => "comment",
+            NotificationKind::Mention => "mention",
+        }
+    }
+}
+
+impl FromStr for NotificationKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "comment" => Ok(NotificationKind::Comment),
+            "mention" => Ok(NotificationKind::Mention),
+            _ => Err(()),
+        }
+    }
+}
+
+// Something for `owner_id` to look at, made by the server when comments are
+// posted. Only `read` ever changes.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Notification {
+    pub id: i64,
+    pub owner_id: i64,
+    pub kind: NotificationKind,
+    // who commented
+    pub actor_id: i64,
+    pub note_id: i64,
+    pub comment_id: i64,
+    pub read: bool,
+    // unix seconds
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestNotification {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<NotificationKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+}
+
+#[cfg(feature = "full")]
+pub use ext::*;
+
+#[cfg(feature = "full")]
+mod ext {
+    use super::{Notification, RequestNotification};
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+    use tracing::error;
+
+    impl Bindable for Notification {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.kind.as_str().bind(statement, 3)?;
+            self.actor_id.bind(statement, 4)?;
+            self.note_id.bind(statement, 5)?;
+            self.comment_id.bind(statement, 6)?;
+            (self.read as i64).bind(statement, 7)?;
+            self.created_at.bind(statement, 8)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for Notification {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    kind: statement
+                        .read::<String, _>("kind")
+                        .unwrap()
+                        .parse()
+                        .unwrap(),
+                    actor_id: statement.read::<i64, _>("actor_id").unwrap(),
+                    note_id: statement.read::<i64, _>("note_id").unwrap(),
+                    comment_id: statement.read::<i64, _>("comment_id").unwrap(),
+                    read: statement.read::<i64, _>("read").unwrap() != 0,
+                    created_at: statement.read::<i64, _>("created_at").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "notifications".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,kind,actor_id,note_id,comment_id,read,created_at".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestNotification {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(kind) = self.kind {
+                kind.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(actor_id) = self.actor_id {
+                actor_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(note_id) = self.note_id {
+                note_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(comment_id) = self.comment_id {
+                comment_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(read) = self.read {
+                (read as i64).bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(created_at) = self.created_at {
+                created_at.bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    // only ever made by the server, for someone other than the caller
+    impl RequestObject for RequestNotification {
+        fn validate_create(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            for (field, present) in [
+                ("owner_id", self.owner_id.is_some()),
+                ("kind", self.kind.is_some()),
+                ("actor_id", self.actor_id.is_some()),
+                ("note_id", self.note_id.is_some()),
+                ("comment_id", self.comment_id.is_some()),
+                ("created_at", self.created_at.is_some()),
+            ] {
+                if !present {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        field,
+                    )));
+                }
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.kind.is_some() {
+                cols.push("kind");
+            }
+            if self.actor_id.is_some() {
+                cols.push("actor_id");
+            }
+            if self.note_id.is_some() {
+                cols.push("note_id");
+            }
+            if self.comment_id.is_some() {
+                cols.push("comment_id");
+            }
+            if self.read.is_some() {
+                cols.push("read");
+            }
+            if self.created_at.is_some() {
+                cols.push("created_at");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.kind.is_some() {
+                ct += 1;
+            }
+            if self.actor_id.is_some() {
+                ct += 1;
+            }
+            if self.note_id.is_some() {
+                ct += 1;
+            }
+            if self.comment_id.is_some() {
+                ct += 1;
+            }
+            if self.read.is_some() {
+                ct += 1;
+            }
+            if self.created_at.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    // Query types
+    //
+    //   unread=true|false
+    //
+    // Everyone only ever sees their own, see `data_get_queries`.
+    #[derive(Debug)]
+    pub enum NotificationQuery {
+        Unread(NotificationByRead),
+    }
+
+    impl Query for NotificationQuery {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            match self {
+                NotificationQuery::Unread(inner) => inner.build(),
+            }
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            match self {
+                NotificationQuery::Unread(inner) => inner.order_by(),
+            }
+        }
+    }
+
+    impl TryFrom<(&String, &String)> for NotificationQuery {
+        type Error = ();
+
+        fn try_from((q, v): (&String, &String)) -> Result<Self, Self::Error> {
+            match (q.as_str(), v.as_str()) {
+                ("unread", "true") => Ok(Self::Unread(NotificationByRead::new(false))),
+                ("unread", "false") => Ok(Self::Unread(NotificationByRead::new(true))),
+                _ => {
+                    error!("Unrecognized query for Notification: {:?}", (q, v));
+                    Err(())
+                }
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct NotificationByOwnerId {
+        inner: EqualsCriteria,
+    }
+
+    impl NotificationByOwnerId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("owner_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for NotificationByOwnerId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+
+        // newest first
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("created_at desc, id desc".to_string(), vec![]))
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct NotificationByRead {
+        inner: EqualsCriteria,
+    }
+
+    impl NotificationByRead {
+        pub fn new(read: bool) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("read"),
+                    val: Value::Integer(read as i64),
+                },
+            }
+        }
+    }
+
+    impl Query for NotificationByRead {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("created_at desc, id desc".to_string(), vec![]))
+        }
+    }
+}
@@ -1,10 +1,56 @@
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    In,
+    Out,
+    // starts a break, the next `in` ends it
+    Break,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Out => "out",
+            Direction::Break => "break",
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "in" => Ok(Direction::In),
+            "out" => Ok(Direction::Out),
+            "break" => Ok(Direction::Break),
+            _ => Err(()),
+        }
+    }
+}
+
+// Times are unix seconds. `captured_at` comes from the client's position fix,
+// `received_at` is set by the server, as are `geofence_id` and `flagged` from
+// the fences the punch falls in.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Punch {
     pub id: i64,
     pub owner_id: i64,
-    pub geo: String,
+    pub direction: Direction,
+    pub latitude: f64,
+    pub longitude: f64,
+    // metres
+    pub accuracy: Option<f64>,
+    pub altitude: Option<f64>,
+    pub captured_at: i64,
+    pub received_at: i64,
+    pub geofence_id: Option<i64>,
+    // outside all of the user's geofences
+    pub flagged: bool,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -14,7 +60,24 @@ pub struct RequestPunch {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub owner_id: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub geo: Option<String>,
+    pub direction: Option<Direction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accuracy: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub captured_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received_at: Option<i64>,
+    // set by the server, `Some(None)` clears the fence
+    #[serde(skip)]
+    pub geofence_id: Option<Option<i64>>,
+    #[serde(skip)]
+    pub flagged: Option<bool>,
 }
 
 #[cfg(feature = "full")]
@@ -22,17 +85,31 @@ pub use ext::*;
 
 #[cfg(feature = "full")]
 mod ext {
-    use super::{Punch, RequestPunch};
+    use super::{Direction, Punch, RequestPunch};
     use lib_glonk::types::{
-        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+        Criteria, DataObject, EqualsCriteria, GreaterOrEqualCriteria, LessThanCriteria, Query,
+        RequestObject, ValidationError,
     };
+    use lib_glonk::validation::Validator;
     use sqlite::{Bindable, BindableWithIndex, State, Value};
     use tracing::error;
+
+    // how far a device clock may run ahead of the server's
+    const CLOCK_SKEW: i64 = 5 * 60;
+
     impl Bindable for Punch {
         fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
-            self.id.clone().bind(statement, 1)?;
-            self.owner_id.clone().bind(statement, 2)?;
-            self.geo.clone().bind(statement, 2)?;
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.direction.as_str().bind(statement, 3)?;
+            self.latitude.bind(statement, 4)?;
+            self.longitude.bind(statement, 5)?;
+            self.accuracy.bind(statement, 6)?;
+            self.altitude.bind(statement, 7)?;
+            self.captured_at.bind(statement, 8)?;
+            self.received_at.bind(statement, 9)?;
+            self.geofence_id.bind(statement, 10)?;
+            (self.flagged as i64).bind(statement, 11)?;
             Ok(())
         }
     }
@@ -44,7 +121,19 @@ mod ext {
                 res.push(Self {
                     id: statement.read::<i64, _>("id").unwrap(),
                     owner_id: statement.read::<i64, _>("owner_id").unwrap(),
-                    geo: statement.read::<String, _>("geo").unwrap(),
+                    direction: statement
+                        .read::<String, _>("direction")
+                        .unwrap()
+                        .parse()
+                        .unwrap(),
+                    latitude: statement.read::<f64, _>("latitude").unwrap(),
+                    longitude: statement.read::<f64, _>("longitude").unwrap(),
+                    accuracy: statement.read::<Option<f64>, _>("accuracy").unwrap(),
+                    altitude: statement.read::<Option<f64>, _>("altitude").unwrap(),
+                    captured_at: statement.read::<i64, _>("captured_at").unwrap(),
+                    received_at: statement.read::<i64, _>("received_at").unwrap(),
+                    geofence_id: statement.read::<Option<i64>, _>("geofence_id").unwrap(),
+                    flagged: statement.read::<i64, _>("flagged").unwrap() != 0,
                 });
             }
             return res;
@@ -55,7 +144,8 @@ mod ext {
         }
 
         fn sql_cols() -> String {
-            "id,owner_id,geo".to_string()
+            "id,owner_id,direction,latitude,longitude,accuracy,altitude,captured_at,received_at,geofence_id,flagged"
+                .to_string()
         }
 
         fn id_col() -> String {
@@ -71,15 +161,47 @@ mod ext {
         fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
             let mut idx = 1;
             if let Some(id) = self.id {
-                id.clone().bind(statement, idx)?;
+                id.bind(statement, idx)?;
                 idx += 1;
             }
             if let Some(owner_id) = self.owner_id {
-                owner_id.clone().bind(statement, idx)?;
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(direction) = self.direction {
+                direction.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(latitude) = self.latitude {
+                latitude.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(longitude) = self.longitude {
+                longitude.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(accuracy) = self.accuracy {
+                accuracy.bind(statement, idx)?;
                 idx += 1;
             }
-            if let Some(geo) = self.geo {
-                geo.clone().as_str().bind(statement, idx)?;
+            if let Some(altitude) = self.altitude {
+                altitude.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(captured_at) = self.captured_at {
+                captured_at.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(received_at) = self.received_at {
+                received_at.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(geofence_id) = self.geofence_id {
+                geofence_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(flagged) = self.flagged {
+                (flagged as i64).bind(statement, idx)?;
             }
             Ok(())
         }
@@ -103,11 +225,16 @@ mod ext {
                     )));
                 }
             }
-            match self.geo {
-                Some(_) => {}
-                None => {
+            for (field, present) in [
+                ("direction", self.direction.is_some()),
+                ("latitude", self.latitude.is_some()),
+                ("longitude", self.longitude.is_some()),
+                ("captured_at", self.captured_at.is_some()),
+                ("received_at", self.received_at.is_some()),
+            ] {
+                if !present {
                     return Err(ValidationError::MissingRequiredOnCreate(String::from(
-                        "geo",
+                        field,
                     )));
                 }
             }
@@ -145,32 +272,95 @@ mod ext {
 
         fn sql_cols(&self) -> String {
             let mut cols = vec![];
-            if let Some(_) = self.id {
+            if self.id.is_some() {
                 cols.push("id");
             }
-            if let Some(_) = self.owner_id {
+            if self.owner_id.is_some() {
                 cols.push("owner_id");
             }
-            if let Some(_) = self.geo {
-                cols.push("geo");
+            if self.direction.is_some() {
+                cols.push("direction");
+            }
+            if self.latitude.is_some() {
+                cols.push("latitude");
+            }
+            if self.longitude.is_some() {
+                cols.push("longitude");
+            }
+            if self.accuracy.is_some() {
+                cols.push("accuracy");
+            }
+            if self.altitude.is_some() {
+                cols.push("altitude");
+            }
+            if self.captured_at.is_some() {
+                cols.push("captured_at");
+            }
+            if self.received_at.is_some() {
+                cols.push("received_at");
+            }
+            if self.geofence_id.is_some() {
+                cols.push("geofence_id");
+            }
+            if self.flagged.is_some() {
+                cols.push("flagged");
             }
             cols.join(",")
         }
 
         fn sql_placeholders(&self) -> String {
             let mut ct = 0;
-            if let Some(_) = self.id {
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.direction.is_some() {
+                ct += 1;
+            }
+            if self.latitude.is_some() {
+                ct += 1;
+            }
+            if self.longitude.is_some() {
+                ct += 1;
+            }
+            if self.accuracy.is_some() {
                 ct += 1;
             }
-            if let Some(_) = self.owner_id {
+            if self.altitude.is_some() {
                 ct += 1;
             }
-            if let Some(_) = self.geo {
+            if self.captured_at.is_some() {
+                ct += 1;
+            }
+            if self.received_at.is_some() {
+                ct += 1;
+            }
+            if self.geofence_id.is_some() {
+                ct += 1;
+            }
+            if self.flagged.is_some() {
                 ct += 1;
             }
             vec!["?"; ct].join(",")
         }
 
+        fn field_rules(&self, v: &mut Validator) {
+            v.range("latitude", self.latitude, -90.0, 90.0)
+                .range("longitude", self.longitude, -180.0, 180.0)
+                .range("accuracy", self.accuracy, 0.0, f64::MAX)
+                .range("altitude", self.altitude, -f64::MAX, f64::MAX);
+            if let (Some(captured_at), Some(received_at)) = (self.captured_at, self.received_at) {
+                v.check(
+                    "captured_at",
+                    "before",
+                    captured_at <= received_at + CLOCK_SKEW,
+                    "must not be after received_at",
+                );
+            }
+        }
+
         fn id(&self) -> Option<i64> {
             self.id
         }
@@ -181,17 +371,68 @@ mod ext {
     }
 
     // Query types
+    //
+    //   byOwnerId=1
+    //   byDirection=in|out|break
+    //   capturedAfter=1700000000         unix seconds, inclusive
+    //   capturedBefore=1700086400        unix seconds, exclusive
+    //   bbox=south,west,north,east       degrees, west > east crosses the antimeridian
+    //   withinRadius=lat,lon,meters
+    //   nearest=lat,lon,count            closest first, at most MAX_NEAREST
+    //
+    // The geographic ones go through the `punch_locations` R*Tree first.
+    // `nearest` only looks as far out as it takes to find `count` punches of
+    // anyone's, so alongside other queries it can come back with fewer.
     #[derive(Debug)]
     pub enum PunchQuery {
         ByOwnerId(PunchByOwnerId),
+        ByDirection(PunchByDirection),
+        CapturedAfter(PunchCapturedAfter),
+        CapturedBefore(PunchCapturedBefore),
+        WithinBox(PunchWithinBox),
+        WithinRadius(PunchWithinRadius),
+        Nearest(PunchNearest),
     }
 
     impl Query for PunchQuery {
         fn build(&self) -> (String, Vec<sqlite::Value>) {
             match self {
                 PunchQuery::ByOwnerId(inner) => inner.build(),
+                PunchQuery::ByDirection(inner) => inner.build(),
+                PunchQuery::CapturedAfter(inner) => inner.build(),
+                PunchQuery::CapturedBefore(inner) => inner.build(),
+                PunchQuery::WithinBox(inner) => inner.build(),
+                PunchQuery::WithinRadius(inner) => inner.build(),
+                PunchQuery::Nearest(inner) => inner.build(),
             }
         }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            match self {
+                PunchQuery::Nearest(inner) => inner.order_by(),
+                _ => None,
+            }
+        }
+
+        fn limit(&self) -> Option<i64> {
+            match self {
+                PunchQuery::Nearest(inner) => inner.limit(),
+                _ => None,
+            }
+        }
+    }
+
+    // comma separated numbers, exactly `N` of them
+    fn parse_floats<const N: usize>(v: &str) -> Option<[f64; N]> {
+        let vals = v
+            .split(',')
+            .map(|f| f.trim().parse::<f64>().ok().filter(|f| f.is_finite()))
+            .collect::<Option<Vec<f64>>>()?;
+        vals.try_into().ok()
+    }
+
+    fn valid_point(lat: f64, lon: f64) -> bool {
+        (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)
     }
 
     impl TryFrom<(&String, &String)> for PunchQuery {
@@ -210,8 +451,62 @@ mod ext {
                     };
                     Ok(Self::ByOwnerId(PunchByOwnerId::new(id)))
                 }
+                "byDirection" => match v.parse::<Direction>() {
+                    Ok(direction) => Ok(Self::ByDirection(PunchByDirection::new(direction))),
+                    Err(_) => {
+                        error!("Invalid direction for Punch: {:?}", v);
+                        Err(())
+                    }
+                },
+                "capturedAfter" | "capturedBefore" => {
+                    let time = match v.parse::<i64>() {
+                        Ok(time) => time,
+                        Err(e) => {
+                            error!("{:?}", e);
+                            return Err(());
+                        }
+                    };
+                    if q == "capturedAfter" {
+                        Ok(Self::CapturedAfter(PunchCapturedAfter::new(time)))
+                    } else {
+                        Ok(Self::CapturedBefore(PunchCapturedBefore::new(time)))
+                    }
+                }
+                "bbox" => match parse_floats::<4>(v) {
+                    Some([south, west, north, east])
+                        if valid_point(south, west)
+                            && valid_point(north, east)
+                            && south <= north =>
+                    {
+                        Ok(Self::WithinBox(PunchWithinBox::new(
+                            south, west, north, east,
+                        )))
+                    }
+                    _ => {
+                        error!("Invalid bbox for Punch: {:?}", v);
+                        Err(())
+                    }
+                },
+                "withinRadius" => match parse_floats::<3>(v) {
+                    Some([lat, lon, meters]) if valid_point(lat, lon) && meters >= 0.0 => {
+                        Ok(Self::WithinRadius(PunchWithinRadius::new(lat, lon, meters)))
+                    }
+                    _ => {
+                        error!("Invalid withinRadius for Punch: {:?}", v);
+                        Err(())
+                    }
+                },
+                "nearest" => match parse_floats::<3>(v) {
+                    Some([lat, lon, count]) if valid_point(lat, lon) && count >= 1.0 => {
+                        Ok(Self::Nearest(PunchNearest::new(lat, lon, count as i64)))
+                    }
+                    _ => {
+                        error!("Invalid nearest for Punch: {:?}", v);
+                        Err(())
+                    }
+                },
                 _ => {
-                    error!("Unrecognized query for Note: {:?}", (q, v));
+                    error!("Unrecognized query for Punch: {:?}", (q, v));
                     Err(())
                 }
             }
@@ -239,4 +534,292 @@ mod ext {
             self.inner.build()
         }
     }
+
+    #[derive(Debug)]
+    pub struct PunchByDirection {
+        inner: EqualsCriteria,
+    }
+
+    impl PunchByDirection {
+        pub fn new(val: Direction) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("direction"),
+                    val: Value::String(val.as_str().to_string()),
+                },
+            }
+        }
+    }
+
+    impl Query for PunchByDirection {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct PunchCapturedAfter {
+        inner: GreaterOrEqualCriteria,
+    }
+
+    impl PunchCapturedAfter {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: GreaterOrEqualCriteria {
+                    field: String::from("captured_at"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for PunchCapturedAfter {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct PunchCapturedBefore {
+        inner: LessThanCriteria,
+    }
+
+    impl PunchCapturedBefore {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: LessThanCriteria {
+                    field: String::from("captured_at"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for PunchCapturedBefore {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    // A page of punches in capture order, for walking a long history without
+    // loading it all. `after` is the (captured_at, id) the last page ended on.
+    #[derive(Debug)]
+    pub struct PunchPage {
+        after: Option<(i64, i64)>,
+        size: i64,
+    }
+
+    impl PunchPage {
+        pub fn new(after: Option<(i64, i64)>, size: i64) -> Self {
+            Self { after, size }
+        }
+    }
+
+    impl Query for PunchPage {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            match self.after {
+                Some((captured_at, id)) => (
+                    "(captured_at, id) > (?, ?)".to_string(),
+                    vec![Value::Integer(captured_at), Value::Integer(id)],
+                ),
+                None => ("1 = 1".to_string(), vec![]),
+            }
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("captured_at, id".to_string(), vec![]))
+        }
+
+        fn limit(&self) -> Option<i64> {
+            Some(self.size)
+        }
+    }
+
+    // mean earth radius in metres
+    pub(crate) const EARTH_RADIUS: f64 = 6_371_008.8;
+    const MAX_NEAREST: i64 = 100;
+    // tried in turn for somewhere holding enough punches for `nearest`
+    const NEAREST_RADII: [f64; 5] = [100.0, 1_000.0, 10_000.0, 100_000.0, 1_000_000.0];
+
+    // haversine distance in metres from the bound `(lat, lat, lon)` to a punch
+    fn distance() -> String {
+        format!(
+            "(2 * {} * asin(sqrt(pow(sin(radians(latitude - ?) / 2), 2) \
+             + cos(radians(?)) * cos(radians(latitude)) * pow(sin(radians(longitude - ?) / 2), 2))))",
+            EARTH_RADIUS
+        )
+    }
+
+    fn distance_vals(lat: f64, lon: f64) -> Vec<Value> {
+        vec![Value::Float(lat), Value::Float(lat), Value::Float(lon)]
+    }
+
+    #[derive(Debug)]
+    pub struct PunchWithinBox {
+        south: f64,
+        west: f64,
+        north: f64,
+        east: f64,
+    }
+
+    impl PunchWithinBox {
+        pub fn new(south: f64, west: f64, north: f64, east: f64) -> Self {
+            Self {
+                south,
+                west,
+                north,
+                east,
+            }
+        }
+    }
+
+    impl PunchWithinBox {
+        // The index stores 32 bit floats rounded outwards, so a punch is a tiny
+        // box rather than a point and is matched on overlap, not containment.
+        fn overlaps(&self) -> (String, Vec<sqlite::Value>) {
+            let lon = if self.west <= self.east {
+                "max_lon >= ? and min_lon <= ?"
+            } else {
+                "(max_lon >= ? or min_lon <= ?)"
+            };
+            (
+                format!("max_lat >= ? and min_lat <= ? and {}", lon),
+                vec![
+                    Value::Float(self.south),
+                    Value::Float(self.north),
+                    Value::Float(self.west),
+                    Value::Float(self.east),
+                ],
+            )
+        }
+    }
+
+    impl Query for PunchWithinBox {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            let (overlaps, vals) = self.overlaps();
+            (
+                format!("id in (select id from punch_locations where {})", overlaps),
+                vals,
+            )
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct PunchWithinRadius {
+        lat: f64,
+        lon: f64,
+        meters: f64,
+    }
+
+    impl PunchWithinRadius {
+        pub fn new(lat: f64, lon: f64, meters: f64) -> Self {
+            Self { lat, lon, meters }
+        }
+
+        // the box around the circle, used to narrow things down in the R*Tree
+        fn bounds(&self) -> PunchWithinBox {
+            let dlat = (self.meters / EARTH_RADIUS).to_degrees();
+            let (south, north) = (self.lat - dlat, self.lat + dlat);
+            if south <= -90.0 || north >= 90.0 {
+                // reaches a pole, every longitude is in range
+                return PunchWithinBox::new(south.max(-90.0), -180.0, north.min(90.0), 180.0);
+            }
+            let dlon = (dlat / self.lat.to_radians().cos()).min(180.0);
+            let wrap = |lon: f64| {
+                if lon < -180.0 {
+                    lon + 360.0
+                } else if lon > 180.0 {
+                    lon - 360.0
+                } else {
+                    lon
+                }
+            };
+            if dlon >= 180.0 {
+                PunchWithinBox::new(south, -180.0, north, 180.0)
+            } else {
+                PunchWithinBox::new(south, wrap(self.lon - dlon), north, wrap(self.lon + dlon))
+            }
+        }
+    }
+
+    impl Query for PunchWithinRadius {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            let (bounds, mut vals) = self.bounds().build();
+            vals.extend(distance_vals(self.lat, self.lon));
+            vals.push(Value::Float(self.meters));
+            (format!("({} and {} <= ?)", bounds, distance()), vals)
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct PunchNearest {
+        lat: f64,
+        lon: f64,
+        count: i64,
+    }
+
+    impl PunchNearest {
+        pub fn new(lat: f64, lon: f64, count: i64) -> Self {
+            Self {
+                lat,
+                lon,
+                count: count.min(MAX_NEAREST),
+            }
+        }
+    }
+
+    impl PunchNearest {
+        // whether the circle of `meters` holds at least `count` punches
+        fn holds(&self, meters: f64) -> (String, Vec<sqlite::Value>) {
+            let (within, mut vals) = PunchWithinRadius::new(self.lat, self.lon, meters).build();
+            vals.push(Value::Integer(self.count));
+            (
+                format!("(select count(*) from punches where {}) >= ?", within),
+                vals,
+            )
+        }
+    }
+
+    impl Query for PunchNearest {
+        // Candidates come from the R*Tree, around the smallest of NEAREST_RADII
+        // that holds `count` punches, as anything outside it is further away
+        // than those. Everything is a candidate when none of them do.
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            let mut selects = vec![];
+            let mut vals = vec![];
+            let mut smaller: Option<(String, Vec<sqlite::Value>)> = None;
+            for meters in NEAREST_RADII {
+                let (holds, holds_vals) = self.holds(meters);
+                let (overlaps, overlaps_vals) = PunchWithinRadius::new(self.lat, self.lon, meters)
+                    .bounds()
+                    .overlaps();
+                let mut select = format!("select id from punch_locations where {}", holds);
+                vals.extend(holds_vals.clone());
+                if let Some((smaller, smaller_vals)) = &smaller {
+                    select.push_str(&format!(" and not {}", smaller));
+                    vals.extend(smaller_vals.clone());
+                }
+                select.push_str(&format!(" and {}", overlaps));
+                vals.extend(overlaps_vals);
+                selects.push(select);
+                smaller = Some((holds, holds_vals));
+            }
+            if let Some((largest, largest_vals)) = smaller {
+                selects.push(format!(
+                    "select id from punch_locations where not {}",
+                    largest
+                ));
+                vals.extend(largest_vals);
+            }
+            (format!("id in ({})", selects.join(" union all ")), vals)
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some((distance(), distance_vals(self.lat, self.lon)))
+        }
+
+        fn limit(&self) -> Option<i64> {
+            Some(self.count)
+        }
+    }
 }
@@ -0,0 +1,169 @@
+// Roles are granted by an administrator and never accepted from clients, so
+// like credentials these are only compiled with the `full` feature.
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Role {
+    pub id: i64,
+    pub user_id: i64,
+    pub role: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestRole {
+    pub id: Option<i64>,
+    pub user_id: Option<i64>,
+    pub role: Option<String>,
+}
+
+pub use ext::*;
+
+mod ext {
+    use super::{RequestRole, Role};
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+
+    impl Bindable for Role {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.user_id.bind(statement, 2)?;
+            self.role.as_str().bind(statement, 3)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for Role {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    user_id: statement.read::<i64, _>("user_id").unwrap(),
+                    role: statement.read::<String, _>("role").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "roles".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,user_id,role".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "user_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestRole {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(user_id) = self.user_id {
+                user_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(role) = self.role {
+                role.as_str().bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestRole {
+        fn validate_create(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            if self.user_id.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "user_id",
+                )));
+            }
+            if self.role.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "role",
+                )));
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.user_id.is_some() {
+                cols.push("user_id");
+            }
+            if self.role.is_some() {
+                cols.push("role");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.user_id.is_some() {
+                ct += 1;
+            }
+            if self.role.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.user_id
+        }
+    }
+
+    // Query types
+    #[derive(Debug)]
+    pub struct RoleByUserId {
+        inner: EqualsCriteria,
+    }
+
+    impl RoleByUserId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("user_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for RoleByUserId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// A page of `GET /data/changes?since={seq}`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ChangeSet {
+    pub changes: Vec<SyncChange>,
+    // `since` for the next page
+    pub next: i64,
+    // whether there's more to fetch straight away
+    pub more: bool,
+    // `since` was older than the change log goes back, so `changes` is every
+    // row there is, and anything else kept locally is gone
+    #[serde(default)]
+    pub reset: bool,
+}
+
+// One entry of the change log, `seq` orders them.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SyncChange {
+    // `data` is the row as it is now, not as it was made
+    Created {
+        seq: i64,
+        data_type: String,
+        data: Value,
+    },
+    Updated {
+        seq: i64,
+        data_type: String,
+        data: Value,
+    },
+    Deleted {
+        seq: i64,
+        data_type: String,
+        id: i64,
+    },
+}
@@ -0,0 +1,359 @@
+use serde::{Deserialize, Serialize};
+
+// Tags belong to one user and are put on that user's notes through
+// `NoteTag`. Notes point at the tag's id, so a rename is a single row.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Tag {
+    pub id: i64,
+    pub owner_id: i64,
+    // unique per owner
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestTag {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+// a tag and how many notes carry it, from the `tag_counts` view
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TagCount {
+    pub id: i64,
+    pub owner_id: i64,
+    pub name: String,
+    pub count: i64,
+}
+
+#[cfg(feature = "full")]
+pub use ext::*;
+
+#[cfg(feature = "full")]
+mod ext {
+    use super::{RequestTag, Tag, TagCount};
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use lib_glonk::validation::{Rule, Validator};
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+    use tracing::error;
+
+    impl Bindable for Tag {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.name.as_str().bind(statement, 3)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for Tag {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    name: statement.read::<String, _>("name").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "tags".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,name".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestTag {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(name) = self.name {
+                name.as_str().bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestTag {
+        fn validate_create(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            if self.name.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "name",
+                )));
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.name.is_some() {
+                cols.push("name");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.name.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        // commas separate names in the note queries
+        fn field_rules(&self, v: &mut Validator) {
+            v.text(
+                "name",
+                self.name.as_deref(),
+                &[Rule::NotBlank, Rule::MaxLen(50)],
+            )
+            .check(
+                "name",
+                "no_commas",
+                !self.name.as_deref().unwrap_or_default().contains(','),
+                "must not contain commas",
+            );
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    // the view is read only, this only satisfies `DataObject`
+    impl Bindable for TagCount {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.name.as_str().bind(statement, 3)?;
+            self.count.bind(statement, 4)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for TagCount {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    name: statement.read::<String, _>("name").unwrap(),
+                    count: statement.read::<i64, _>("count").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "tag_counts".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,name,count".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    // Query types
+    #[derive(Debug)]
+    pub enum TagQuery {
+        ByOwnerId(TagByOwnerId),
+        ByName(TagByName),
+    }
+
+    impl Query for TagQuery {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            match self {
+                TagQuery::ByOwnerId(inner) => inner.build(),
+                TagQuery::ByName(inner) => inner.build(),
+            }
+        }
+    }
+
+    impl TryFrom<(&String, &String)> for TagQuery {
+        type Error = ();
+
+        fn try_from((q, v): (&String, &String)) -> Result<Self, Self::Error> {
+            let q = q.as_str();
+            match q {
+                "byOwnerId" => {
+                    let id = match v.parse::<i64>() {
+                        Ok(id) => id,
+                        Err(e) => {
+                            error!("{:?}", e);
+                            return Err(());
+                        }
+                    };
+                    Ok(Self::ByOwnerId(TagByOwnerId::new(id)))
+                }
+                "byName" => Ok(Self::ByName(TagByName::new(v.to_string()))),
+                _ => {
+                    error!("Unrecognized query for Tag: {:?}", (q, v));
+                    Err(())
+                }
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct TagByOwnerId {
+        inner: EqualsCriteria,
+    }
+
+    impl TagByOwnerId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("owner_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for TagByOwnerId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct TagByName {
+        inner: EqualsCriteria,
+    }
+
+    impl TagByName {
+        pub fn new(val: String) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("name"),
+                    val: Value::String(val),
+                },
+            }
+        }
+    }
+
+    impl Query for TagByName {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    // a user's tags on `tag_counts`, most used first
+    #[derive(Debug)]
+    pub struct TagCloud {
+        inner: EqualsCriteria,
+    }
+
+    impl TagCloud {
+        pub fn new(owner_id: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("owner_id"),
+                    val: Value::Integer(owner_id),
+                },
+            }
+        }
+    }
+
+    impl Query for TagCloud {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("count desc, name".to_string(), vec![]))
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use serde::{Deserialize, Serialize};
+
+// A scaled down copy of an image attachment, kept in the blob store like the
+// original. Made in the background, see `images`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Thumbnail {
+    pub id: i64,
+    pub owner_id: i64,
+    pub attachment_id: i64,
+    // small, medium or large
+    pub size: String,
+    pub width: i64,
+    pub height: i64,
+    pub digest: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestThumbnail {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attachment_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+#[cfg(feature = "full")]
+pub use ext::*;
+
+#[cfg(feature = "full")]
+mod ext {
+    use super::{RequestThumbnail, Thumbnail};
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+
+    impl Bindable for Thumbnail {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.attachment_id.bind(statement, 3)?;
+            self.size.as_str().bind(statement, 4)?;
+            self.width.bind(statement, 5)?;
+            self.height.bind(statement, 6)?;
+            self.digest.as_str().bind(statement, 7)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for Thumbnail {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    attachment_id: statement.read::<i64, _>("attachment_id").unwrap(),
+                    size: statement.read::<String, _>("size").unwrap(),
+                    width: statement.read::<i64, _>("width").unwrap(),
+                    height: statement.read::<i64, _>("height").unwrap(),
+                    digest: statement.read::<String, _>("digest").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "thumbnails".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,attachment_id,size,width,height,digest".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestThumbnail {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(attachment_id) = self.attachment_id {
+                attachment_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(size) = self.size {
+                size.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(width) = self.width {
+                width.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(height) = self.height {
+                height.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(digest) = self.digest {
+                digest.as_str().bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    // only ever made by the server, so there's little to check
+    impl RequestObject for RequestThumbnail {
+        fn validate_create(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            if self.owner_id.is_none() {
+                return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                    "owner_id",
+                )));
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.attachment_id.is_some() {
+                cols.push("attachment_id");
+            }
+            if self.size.is_some() {
+                cols.push("size");
+            }
+            if self.width.is_some() {
+                cols.push("width");
+            }
+            if self.height.is_some() {
+                cols.push("height");
+            }
+            if self.digest.is_some() {
+                cols.push("digest");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.attachment_id.is_some() {
+                ct += 1;
+            }
+            if self.size.is_some() {
+                ct += 1;
+            }
+            if self.width.is_some() {
+                ct += 1;
+            }
+            if self.height.is_some() {
+                ct += 1;
+            }
+            if self.digest.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct ThumbnailByAttachmentId {
+        inner: EqualsCriteria,
+    }
+
+    impl ThumbnailByAttachmentId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("attachment_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for ThumbnailByAttachmentId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct ThumbnailBySize {
+        inner: EqualsCriteria,
+    }
+
+    impl ThumbnailBySize {
+        pub fn new(val: String) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("size"),
+                    val: Value::String(val),
+                },
+            }
+        }
+    }
+
+    impl Query for ThumbnailBySize {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct ThumbnailByDigest {
+        inner: EqualsCriteria,
+    }
+
+    impl ThumbnailByDigest {
+        pub fn new(val: String) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("digest"),
+                    val: Value::String(val),
+                },
+            }
+        }
+    }
+
+    impl Query for ThumbnailByDigest {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+}
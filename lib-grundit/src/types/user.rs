@@ -32,6 +32,7 @@ mod ext {
     use lib_glonk::types::{
         Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
     };
+    use lib_glonk::validation::{Rule, Validator};
     use sqlite::{Bindable, BindableWithIndex, State, Statement};
     impl Bindable for User {
         fn bind(self, statement: &mut Statement) -> sqlite::Result<()> {
@@ -224,6 +225,20 @@ mod ext {
             vec!["?"; ct].join(",")
         }
 
+        fn field_rules(&self, v: &mut Validator) {
+            v.text("name", self.name.as_deref(), &[Rule::MaxLen(200)])
+                .text(
+                    "email",
+                    self.email.as_deref(),
+                    &[Rule::Email, Rule::MaxLen(254)],
+                )
+                .text(
+                    "picture",
+                    self.picture.as_deref(),
+                    &[Rule::Url, Rule::MaxLen(2048)],
+                );
+        }
+
         fn id(&self) -> Option<i64> {
             self.id
         }
@@ -280,4 +295,25 @@ mod ext {
             self.inner.build()
         }
     }
+
+    // how mentions find people, `@Alice` is the same as `@alice`
+    #[derive(Debug)]
+    pub struct UserByName {
+        name: String,
+    }
+
+    impl UserByName {
+        pub fn new(name: String) -> Self {
+            Self { name }
+        }
+    }
+
+    impl Query for UserByName {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            (
+                "name = ? COLLATE NOCASE".to_string(),
+                vec![sqlite::Value::String(self.name.clone())],
+            )
+        }
+    }
 }
@@ -0,0 +1,410 @@
+use serde::{Deserialize, Serialize};
+
+// Somewhere to POST changes to, see `webhooks`. Deliveries carry what the
+// owner could read themselves.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Webhook {
+    pub id: i64,
+    pub owner_id: i64,
+    pub url: String,
+    // comma separated `DataType` names and operations (`created`, `updated`,
+    // `deleted`), empty for all of them
+    pub data_types: String,
+    pub operations: String,
+    // signs deliveries, never sent back out
+    #[serde(skip_serializing, default)]
+    pub secret: String,
+    pub active: bool,
+    // the last change log entry looked at, set by the server
+    pub last_seq: i64,
+    // unix seconds
+    pub created_at: i64,
+}
+
+impl Webhook {
+    pub fn wants(&self, data_type: &str, operation: &str) -> bool {
+        listed(&self.data_types, data_type) && listed(&self.operations, operation)
+    }
+}
+
+fn listed(list: &str, item: &str) -> bool {
+    list.trim().is_empty() || list.split(',').any(|listed| listed.trim() == item)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestWebhook {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_types: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub operations: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active: Option<bool>,
+    // set by the server
+    #[serde(skip)]
+    pub last_seq: Option<i64>,
+    #[serde(skip)]
+    pub created_at: Option<i64>,
+}
+
+#[cfg(feature = "full")]
+pub use ext::*;
+
+#[cfg(feature = "full")]
+mod ext {
+    use super::{RequestWebhook, Webhook};
+    use crate::types::DataType;
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use lib_glonk::validation::{Rule, Validator};
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+    use tracing::error;
+
+    impl Bindable for Webhook {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.url.as_str().bind(statement, 3)?;
+            self.data_types.as_str().bind(statement, 4)?;
+            self.operations.as_str().bind(statement, 5)?;
+            self.secret.as_str().bind(statement, 6)?;
+            (self.active as i64).bind(statement, 7)?;
+            self.last_seq.bind(statement, 8)?;
+            self.created_at.bind(statement, 9)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for Webhook {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    url: statement.read::<String, _>("url").unwrap(),
+                    data_types: statement.read::<String, _>("data_types").unwrap(),
+                    operations: statement.read::<String, _>("operations").unwrap(),
+                    secret: statement.read::<String, _>("secret").unwrap(),
+                    active: statement.read::<i64, _>("active").unwrap() != 0,
+                    last_seq: statement.read::<i64, _>("last_seq").unwrap(),
+                    created_at: statement.read::<i64, _>("created_at").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "webhooks".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,url,data_types,operations,secret,active,last_seq,created_at".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestWebhook {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(url) = self.url {
+                url.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(data_types) = self.data_types {
+                data_types.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(operations) = self.operations {
+                operations.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(secret) = self.secret {
+                secret.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(active) = self.active {
+                (active as i64).bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(last_seq) = self.last_seq {
+                last_seq.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(created_at) = self.created_at {
+                created_at.bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    // a filter list's entries, each of which must pass `known`
+    fn list_ok(list: Option<&str>, known: impl Fn(&str) -> bool) -> bool {
+        list.unwrap_or_default()
+            .split(',')
+            .map(|item| item.trim())
+            .filter(|item| !item.is_empty())
+            .all(known)
+    }
+
+    impl RequestObject for RequestWebhook {
+        fn validate_create(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            for (field, present) in [
+                ("url", self.url.is_some()),
+                ("secret", self.secret.is_some()),
+            ] {
+                if !present {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        field,
+                    )));
+                }
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.url.is_some() {
+                cols.push("url");
+            }
+            if self.data_types.is_some() {
+                cols.push("data_types");
+            }
+            if self.operations.is_some() {
+                cols.push("operations");
+            }
+            if self.secret.is_some() {
+                cols.push("secret");
+            }
+            if self.active.is_some() {
+                cols.push("active");
+            }
+            if self.last_seq.is_some() {
+                cols.push("last_seq");
+            }
+            if self.created_at.is_some() {
+                cols.push("created_at");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.url.is_some() {
+                ct += 1;
+            }
+            if self.data_types.is_some() {
+                ct += 1;
+            }
+            if self.operations.is_some() {
+                ct += 1;
+            }
+            if self.secret.is_some() {
+                ct += 1;
+            }
+            if self.active.is_some() {
+                ct += 1;
+            }
+            if self.last_seq.is_some() {
+                ct += 1;
+            }
+            if self.created_at.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn field_rules(&self, v: &mut Validator) {
+            v.text(
+                "url",
+                self.url.as_deref(),
+                &[Rule::NotBlank, Rule::MaxLen(2048), Rule::Url],
+            )
+            .text(
+                "secret",
+                self.secret.as_deref(),
+                &[Rule::MinLen(16), Rule::MaxLen(256)],
+            )
+            .check(
+                "data_types",
+                "data_types",
+                // webhooks and their deliveries aren't themselves delivered
+                list_ok(self.data_types.as_deref(), |name| {
+                    serde_json::from_value::<DataType>(serde_json::Value::String(name.into()))
+                        .is_ok_and(|data_type| {
+                            !matches!(data_type, DataType::Webhook | DataType::WebhookDelivery)
+                        })
+                }),
+                "must be data type names separated by commas",
+            )
+            .check(
+                "operations",
+                "operations",
+                list_ok(self.operations.as_deref(), |op| {
+                    matches!(op, "created" | "updated" | "deleted")
+                }),
+                "must be created, updated or deleted, separated by commas",
+            );
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    // Query types
+    //
+    // Everyone only ever sees their own, see `app::list`.
+    #[derive(Debug)]
+    pub enum WebhookQuery {
+        Active(WebhookByActive),
+    }
+
+    impl Query for WebhookQuery {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            match self {
+                WebhookQuery::Active(inner) => inner.build(),
+            }
+        }
+    }
+
+    impl TryFrom<(&String, &String)> for WebhookQuery {
+        type Error = ();
+
+        fn try_from((q, v): (&String, &String)) -> Result<Self, Self::Error> {
+            match (q.as_str(), v.as_str()) {
+                ("active", "true") => Ok(Self::Active(WebhookByActive::new(true))),
+                ("active", "false") => Ok(Self::Active(WebhookByActive::new(false))),
+                _ => {
+                    error!("Unrecognized query for Webhook: {:?}", (q, v));
+                    Err(())
+                }
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct WebhookByOwnerId {
+        inner: EqualsCriteria,
+    }
+
+    impl WebhookByOwnerId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("owner_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for WebhookByOwnerId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct WebhookByActive {
+        inner: EqualsCriteria,
+    }
+
+    impl WebhookByActive {
+        pub fn new(active: bool) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("active"),
+                    val: Value::Integer(active as i64),
+                },
+            }
+        }
+    }
+
+    impl Query for WebhookByActive {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    // waiting for `next_attempt_at`
+    Pending,
+    Delivered,
+    // out of attempts
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+impl FromStr for DeliveryStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(DeliveryStatus::Pending),
+            "delivered" => Ok(DeliveryStatus::Delivered),
+            "failed" => Ok(DeliveryStatus::Failed),
+            _ => Err(()),
+        }
+    }
+}
+
+// One change for one webhook. Pending ones are the outbox, and all of them
+// together are the delivery log. Made and updated by the server only.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub owner_id: i64,
+    pub webhook_id: i64,
+    // the change log entry it's for
+    pub seq: i64,
+    // `{data_type}.{operation}`
+    pub event: String,
+    // the exact body posted and signed
+    pub payload: String,
+    pub status: DeliveryStatus,
+    pub attempts: i64,
+    // unix seconds, as are the other times
+    pub next_attempt_at: i64,
+    // of the last attempt, 0 when there was no response
+    pub response_status: i64,
+    pub error: String,
+    pub created_at: i64,
+    // 0 until delivered
+    pub delivered_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestWebhookDelivery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<DeliveryStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attempts: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_attempt_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_status: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivered_at: Option<i64>,
+}
+
+#[cfg(feature = "full")]
+pub use ext::*;
+
+#[cfg(feature = "full")]
+mod ext {
+    use super::{DeliveryStatus, RequestWebhookDelivery, WebhookDelivery};
+    use lib_glonk::types::{
+        Criteria, DataObject, EqualsCriteria, Query, RequestObject, ValidationError,
+    };
+    use sqlite::{Bindable, BindableWithIndex, State, Value};
+    use tracing::error;
+
+    impl Bindable for WebhookDelivery {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            self.id.bind(statement, 1)?;
+            self.owner_id.bind(statement, 2)?;
+            self.webhook_id.bind(statement, 3)?;
+            self.seq.bind(statement, 4)?;
+            self.event.as_str().bind(statement, 5)?;
+            self.payload.as_str().bind(statement, 6)?;
+            self.status.as_str().bind(statement, 7)?;
+            self.attempts.bind(statement, 8)?;
+            self.next_attempt_at.bind(statement, 9)?;
+            self.response_status.bind(statement, 10)?;
+            self.error.as_str().bind(statement, 11)?;
+            self.created_at.bind(statement, 12)?;
+            self.delivered_at.bind(statement, 13)?;
+            Ok(())
+        }
+    }
+
+    impl DataObject for WebhookDelivery {
+        fn from_rows(statement: &mut sqlite::Statement) -> Vec<Self> {
+            let mut res = vec![];
+            while let Ok(State::Row) = statement.next() {
+                res.push(Self {
+                    id: statement.read::<i64, _>("id").unwrap(),
+                    owner_id: statement.read::<i64, _>("owner_id").unwrap(),
+                    webhook_id: statement.read::<i64, _>("webhook_id").unwrap(),
+                    seq: statement.read::<i64, _>("seq").unwrap(),
+                    event: statement.read::<String, _>("event").unwrap(),
+                    payload: statement.read::<String, _>("payload").unwrap(),
+                    status: statement
+                        .read::<String, _>("status")
+                        .unwrap()
+                        .parse()
+                        .unwrap(),
+                    attempts: statement.read::<i64, _>("attempts").unwrap(),
+                    next_attempt_at: statement.read::<i64, _>("next_attempt_at").unwrap(),
+                    response_status: statement.read::<i64, _>("response_status").unwrap(),
+                    error: statement.read::<String, _>("error").unwrap(),
+                    created_at: statement.read::<i64, _>("created_at").unwrap(),
+                    delivered_at: statement.read::<i64, _>("delivered_at").unwrap(),
+                });
+            }
+            res
+        }
+
+        fn table_name() -> String {
+            "webhook_deliveries".to_string()
+        }
+
+        fn sql_cols() -> String {
+            "id,owner_id,webhook_id,seq,event,payload,status,attempts,next_attempt_at,response_status,error,created_at,delivered_at".to_string()
+        }
+
+        fn id_col() -> String {
+            "id".to_string()
+        }
+
+        fn owner_id_col() -> String {
+            "owner_id".to_string()
+        }
+    }
+
+    impl Bindable for RequestWebhookDelivery {
+        fn bind(self, statement: &mut sqlite::Statement) -> sqlite::Result<()> {
+            let mut idx = 1;
+            if let Some(id) = self.id {
+                id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(owner_id) = self.owner_id {
+                owner_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(webhook_id) = self.webhook_id {
+                webhook_id.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(seq) = self.seq {
+                seq.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(event) = self.event {
+                event.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(payload) = self.payload {
+                payload.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(status) = self.status {
+                status.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(attempts) = self.attempts {
+                attempts.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(next_attempt_at) = self.next_attempt_at {
+                next_attempt_at.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(response_status) = self.response_status {
+                response_status.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(error) = self.error {
+                error.as_str().bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(created_at) = self.created_at {
+                created_at.bind(statement, idx)?;
+                idx += 1;
+            }
+            if let Some(delivered_at) = self.delivered_at {
+                delivered_at.bind(statement, idx)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl RequestObject for RequestWebhookDelivery {
+        fn validate_create(&self, _owner_id: Option<i64>) -> Result<(), ValidationError> {
+            for (field, present) in [
+                ("owner_id", self.owner_id.is_some()),
+                ("webhook_id", self.webhook_id.is_some()),
+                ("seq", self.seq.is_some()),
+                ("event", self.event.is_some()),
+                ("payload", self.payload.is_some()),
+                ("status", self.status.is_some()),
+                ("next_attempt_at", self.next_attempt_at.is_some()),
+                ("created_at", self.created_at.is_some()),
+            ] {
+                if !present {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        field,
+                    )));
+                }
+            }
+            if self.id.is_some() {
+                return Err(ValidationError::IdProvidedOnCreate);
+            }
+            Ok(())
+        }
+
+        fn validate_update(&self, owner_id: Option<i64>) -> Result<(), ValidationError> {
+            match self.owner_id {
+                Some(request_data_owner_id) => match owner_id {
+                    Some(owner_id) if owner_id != request_data_owner_id => {
+                        return Err(ValidationError::InvalidOwnerId(format!(
+                            "request header owner_id ({}) does not match data owner_id ({})",
+                            request_data_owner_id, owner_id
+                        )));
+                    }
+                    Some(_) | None => {}
+                },
+                None => {
+                    return Err(ValidationError::MissingRequiredOnCreate(String::from(
+                        "owner_id",
+                    )));
+                }
+            }
+            match self.id {
+                Some(_) => Ok(()),
+                None => Err(ValidationError::MissingIdOnUpdate),
+            }
+        }
+
+        fn sql_cols(&self) -> String {
+            let mut cols = vec![];
+            if self.id.is_some() {
+                cols.push("id");
+            }
+            if self.owner_id.is_some() {
+                cols.push("owner_id");
+            }
+            if self.webhook_id.is_some() {
+                cols.push("webhook_id");
+            }
+            if self.seq.is_some() {
+                cols.push("seq");
+            }
+            if self.event.is_some() {
+                cols.push("event");
+            }
+            if self.payload.is_some() {
+                cols.push("payload");
+            }
+            if self.status.is_some() {
+                cols.push("status");
+            }
+            if self.attempts.is_some() {
+                cols.push("attempts");
+            }
+            if self.next_attempt_at.is_some() {
+                cols.push("next_attempt_at");
+            }
+            if self.response_status.is_some() {
+                cols.push("response_status");
+            }
+            if self.error.is_some() {
+                cols.push("error");
+            }
+            if self.created_at.is_some() {
+                cols.push("created_at");
+            }
+            if self.delivered_at.is_some() {
+                cols.push("delivered_at");
+            }
+            cols.join(",")
+        }
+
+        fn sql_placeholders(&self) -> String {
+            let mut ct = 0;
+            if self.id.is_some() {
+                ct += 1;
+            }
+            if self.owner_id.is_some() {
+                ct += 1;
+            }
+            if self.webhook_id.is_some() {
+                ct += 1;
+            }
+            if self.seq.is_some() {
+                ct += 1;
+            }
+            if self.event.is_some() {
+                ct += 1;
+            }
+            if self.payload.is_some() {
+                ct += 1;
+            }
+            if self.status.is_some() {
+                ct += 1;
+            }
+            if self.attempts.is_some() {
+                ct += 1;
+            }
+            if self.next_attempt_at.is_some() {
+                ct += 1;
+            }
+            if self.response_status.is_some() {
+                ct += 1;
+            }
+            if self.error.is_some() {
+                ct += 1;
+            }
+            if self.created_at.is_some() {
+                ct += 1;
+            }
+            if self.delivered_at.is_some() {
+                ct += 1;
+            }
+            vec!["?"; ct].join(",")
+        }
+
+        fn id(&self) -> Option<i64> {
+            self.id
+        }
+
+        fn owner_id(&self) -> Option<i64> {
+            self.owner_id
+        }
+    }
+
+    // Query types
+    //
+    //   byWebhookId=3, status=pending|delivered|failed
+    //
+    // Everyone only ever sees their own, see `app::list`.
+    #[derive(Debug)]
+    pub enum WebhookDeliveryQuery {
+        ByWebhookId(DeliveryByWebhookId),
+        ByStatus(DeliveryByStatus),
+    }
+
+    impl Query for WebhookDeliveryQuery {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            match self {
+                WebhookDeliveryQuery::ByWebhookId(inner) => inner.build(),
+                WebhookDeliveryQuery::ByStatus(inner) => inner.build(),
+            }
+        }
+    }
+
+    impl TryFrom<(&String, &String)> for WebhookDeliveryQuery {
+        type Error = ();
+
+        fn try_from((q, v): (&String, &String)) -> Result<Self, Self::Error> {
+            match q.as_str() {
+                "byWebhookId" => match v.parse::<i64>() {
+                    Ok(id) => Ok(Self::ByWebhookId(DeliveryByWebhookId::new(id))),
+                    Err(e) => {
+                        error!("{:?}", e);
+                        Err(())
+                    }
+                },
+                "status" => match v.parse::<DeliveryStatus>() {
+                    Ok(status) => Ok(Self::ByStatus(DeliveryByStatus::new(status))),
+                    Err(_) => {
+                        error!("Unrecognized delivery status: {:?}", v);
+                        Err(())
+                    }
+                },
+                _ => {
+                    error!("Unrecognized query for WebhookDelivery: {:?}", (q, v));
+                    Err(())
+                }
+            }
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct DeliveryByOwnerId {
+        inner: EqualsCriteria,
+    }
+
+    impl DeliveryByOwnerId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("owner_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for DeliveryByOwnerId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+
+        // newest first
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("id desc".to_string(), vec![]))
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct DeliveryByWebhookId {
+        inner: EqualsCriteria,
+    }
+
+    impl DeliveryByWebhookId {
+        pub fn new(val: i64) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("webhook_id"),
+                    val: Value::Integer(val),
+                },
+            }
+        }
+    }
+
+    impl Query for DeliveryByWebhookId {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct DeliveryByStatus {
+        inner: EqualsCriteria,
+    }
+
+    impl DeliveryByStatus {
+        pub fn new(status: DeliveryStatus) -> Self {
+            Self {
+                inner: EqualsCriteria {
+                    field: String::from("status"),
+                    val: Value::String(status.as_str().to_string()),
+                },
+            }
+        }
+    }
+
+    impl Query for DeliveryByStatus {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            self.inner.build()
+        }
+    }
+
+    // pending deliveries whose time has come, in change order
+    #[derive(Debug)]
+    pub struct DeliveriesDue {
+        now: i64,
+        size: i64,
+    }
+
+    impl DeliveriesDue {
+        pub fn new(now: i64, size: i64) -> Self {
+            Self { now, size }
+        }
+    }
+
+    impl Query for DeliveriesDue {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            (
+                "status = ? and next_attempt_at <= ?".to_string(),
+                vec![
+                    Value::String(DeliveryStatus::Pending.as_str().to_string()),
+                    Value::Integer(self.now),
+                ],
+            )
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("next_attempt_at, seq".to_string(), vec![]))
+        }
+
+        fn limit(&self) -> Option<i64> {
+            Some(self.size)
+        }
+    }
+
+    // the soonest pending delivery, for how long the dispatcher may sleep
+    #[derive(Debug)]
+    pub struct NextDelivery;
+
+    impl Query for NextDelivery {
+        fn build(&self) -> (String, Vec<sqlite::Value>) {
+            (
+                "status = ?".to_string(),
+                vec![Value::String(DeliveryStatus::Pending.as_str().to_string())],
+            )
+        }
+
+        fn order_by(&self) -> Option<(String, Vec<sqlite::Value>)> {
+            Some(("next_attempt_at".to_string(), vec![]))
+        }
+
+        fn limit(&self) -> Option<i64> {
+            Some(1)
+        }
+    }
+}
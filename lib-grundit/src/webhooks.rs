@@ -0,0 +1,432 @@
+// Webhooks, POSTed the changes their owners ask for.
+//
+//   GET/POST/PUT/DELETE /data/webhook
+//   GET /data/webhook_delivery?byWebhookId={id}
+//
+// A task in the background reads the store's change log past each
+// webhook's `last_seq` into a pending delivery per change it wants, the
+// outbox, and then posts whatever is due. Bodies are the change as the
+// owner could read it
+//
+//   {"seq": 12, "event": "comment.created", "data_type": "comment",
+//    "operation": "created", "data": {...}}
+//
+// signed with the webhook's secret over `{timestamp}.{body}`, with the
+// timestamp in `X-Grundit-Timestamp`, as
+//
+//   X-Grundit-Signature: sha256={hex hmac-sha256}
+//
+// Anything but a 2xx is tried again after WEBHOOK_RETRY_BASE_SECS (default
+// 30), doubling each time up to an hour, and after MAX_ATTEMPTS the delivery
+// is marked failed. Only admins' webhooks may point at loopback, private or
+// link-local addresses.
+use std::{
+    collections::HashMap,
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures_util::future::join_all;
+use hmac::{Hmac, Mac};
+use lib_glonk::{
+    store::{Change, ChangeKind, Store},
+    types::DataObject,
+};
+use oauth2::{reqwest, url::Url};
+use serde_json::json;
+use sha2::Sha256;
+use time::OffsetDateTime;
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+use tracing::{debug, error};
+
+use crate::{
+    app::{self, DataState},
+    auth, feed,
+    types::{
+        AllChangesAfter, ChangeRecord, DeliveriesDue, DeliveryByWebhookId, DeliveryStatus,
+        NextDelivery, RequestWebhook, RequestWebhookDelivery, Webhook, WebhookDelivery,
+    },
+};
+
+// log entries, and due deliveries, read at a time
+const PAGE_SIZE: i64 = 100;
+const MAX_ATTEMPTS: i64 = 8;
+const DEFAULT_RETRY_BASE_SECS: i64 = 30;
+const MAX_RETRY_SECS: i64 = 60 * 60;
+const TIMEOUT: Duration = Duration::from_secs(10);
+// longest the task sleeps without a change
+const IDLE: Duration = Duration::from_secs(60);
+// kept of an error in the log
+const MAX_ERROR_LEN: usize = 500;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub(crate) fn start(state: Arc<DataState>) {
+    tokio::spawn(async move {
+        let mut changes = state.changes.subscribe();
+        loop {
+            enqueue(&state);
+            deliver(&state).await;
+            let wait = until_next(&state);
+            tokio::select! {
+                more = outside_change(&mut changes) => if !more {
+                    return;
+                },
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    });
+}
+
+// webhooks' own bookkeeping is never delivered, and mustn't wake the task
+// it came from
+fn internal(table: &str) -> bool {
+    table == Webhook::table_name() || table == WebhookDelivery::table_name()
+}
+
+// false once the store is gone
+async fn outside_change(changes: &mut Receiver<Change>) -> bool {
+    loop {
+        match changes.recv().await {
+            Ok(change) if internal(&change.table) => continue,
+            Ok(_) | Err(RecvError::Lagged(_)) => return true,
+            Err(RecvError::Closed) => return false,
+        }
+    }
+}
+
+fn until_next(state: &DataState) -> Duration {
+    let next: Vec<WebhookDelivery> = state.store.get_queries(vec![Box::new(NextDelivery)]);
+    match next.first() {
+        Some(delivery) => {
+            let secs = delivery.next_attempt_at - now();
+            Duration::from_secs(secs.max(0) as u64).min(IDLE)
+        }
+        None => IDLE,
+    }
+}
+
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+// Turns new changes into pending deliveries. Paused webhooks are moved past
+// them all the same, and miss what happens while they're paused.
+fn enqueue(state: &DataState) {
+    let hooks: Vec<Webhook> = state.store.get_queries(vec![]);
+    for hook in hooks {
+        let mut last_seq = hook.last_seq;
+        loop {
+            let records: Vec<ChangeRecord> = state
+                .store
+                .get_queries(vec![Box::new(AllChangesAfter::new(last_seq, PAGE_SIZE))]);
+            let Some(last) = records.last() else {
+                break;
+            };
+            last_seq = last.seq;
+            if hook.active {
+                records
+                    .iter()
+                    .filter(|record| !internal(&record.table_name))
+                    .for_each(|record| queue(state, &hook, record));
+            }
+        }
+        if last_seq != hook.last_seq {
+            let update = RequestWebhook {
+                id: Some(hook.id),
+                owner_id: Some(hook.owner_id),
+                url: None,
+                data_types: None,
+                operations: None,
+                secret: None,
+                active: None,
+                last_seq: Some(last_seq),
+                created_at: None,
+            };
+            // gone already if it was deleted in the meantime
+            let _ = state.store.update::<_, Webhook>(update);
+        }
+    }
+}
+
+fn queue(state: &DataState, hook: &Webhook, record: &ChangeRecord) {
+    let Some(data_type) = feed::data_type(&record.table_name) else {
+        return;
+    };
+    let type_name = data_type.as_str();
+    let operation = record.kind.as_str();
+    if !hook.wants(type_name, operation) {
+        return;
+    }
+    // as the owner would read it, if they may
+    let data = match record.kind {
+        ChangeKind::Deleted if app::deletes_public(data_type) => json!({ "id": record.row_id }),
+        ChangeKind::Deleted => return,
+        ChangeKind::Created | ChangeKind::Updated => {
+            match feed::read_back(
+                state,
+                data_type,
+                hook.owner_id,
+                &HashMap::new(),
+                record.row_id,
+            ) {
+                Some(data) => data,
+                None => return,
+            }
+        }
+    };
+    let event = format!("{}.{}", type_name, operation);
+    let payload = json!({
+        "seq": record.seq,
+        "event": event,
+        "data_type": type_name,
+        "operation": operation,
+        "data": data,
+    });
+    let created_at = now();
+    let delivery = RequestWebhookDelivery {
+        id: None,
+        owner_id: Some(hook.owner_id),
+        webhook_id: Some(hook.id),
+        seq: Some(record.seq),
+        event: Some(event),
+        payload: Some(payload.to_string()),
+        status: Some(DeliveryStatus::Pending),
+        attempts: Some(0),
+        next_attempt_at: Some(created_at),
+        response_status: Some(0),
+        error: Some(String::new()),
+        created_at: Some(created_at),
+        delivered_at: Some(0),
+    };
+    if state.store.create::<_, WebhookDelivery>(delivery).is_err() {
+        error!(
+            "could not queue change {} for webhook {}",
+            record.seq, hook.id
+        );
+    }
+}
+
+// Each webhook's deliveries go in order, but webhooks don't wait on each
+// other, so one slow endpoint holds up only its own.
+async fn deliver(state: &DataState) {
+    let due: Vec<WebhookDelivery> = state
+        .store
+        .get_queries(vec![Box::new(DeliveriesDue::new(now(), PAGE_SIZE))]);
+    let mut by_hook: HashMap<i64, Vec<WebhookDelivery>> = HashMap::new();
+    for delivery in due {
+        by_hook
+            .entry(delivery.webhook_id)
+            .or_default()
+            .push(delivery);
+    }
+    join_all(
+        by_hook
+            .into_values()
+            .map(|due| deliver_in_order(state, due)),
+    )
+    .await;
+}
+
+async fn deliver_in_order(state: &DataState, due: Vec<WebhookDelivery>) {
+    for delivery in due {
+        // left behind by a deleted webhook, so there is nowhere to send it
+        let Some(hook) = state.store.get::<Webhook>(delivery.webhook_id) else {
+            record(state, &delivery, 0, Err("webhook deleted".into()), false);
+            continue;
+        };
+        let (response_status, result) = post(state, &hook, &delivery).await;
+        record(state, &delivery, response_status, result, true);
+    }
+}
+
+pub(crate) fn delete_deliveries(state: &DataState, webhook_id: i64) {
+    let deliveries: Vec<WebhookDelivery> = state
+        .store
+        .get_queries(vec![Box::new(DeliveryByWebhookId::new(webhook_id))]);
+    for delivery in deliveries {
+        if state
+            .store
+            .delete::<WebhookDelivery>(delivery.id, None)
+            .is_err()
+        {
+            error!(
+                "could not delete delivery {} of webhook {}",
+                delivery.id, webhook_id
+            );
+        }
+    }
+}
+
+// the response status, 0 without one, and how it went
+async fn post(
+    state: &DataState,
+    hook: &Webhook,
+    delivery: &WebhookDelivery,
+) -> (i64, Result<(), String>) {
+    let client = match client(state, hook).await {
+        Ok(client) => client,
+        Err(e) => return (0, Err(e)),
+    };
+    let timestamp = now().to_string();
+    let response = client
+        .post(&hook.url)
+        .header("content-type", "application/json")
+        .header("x-grundit-event", &delivery.event)
+        .header("x-grundit-delivery", delivery.id.to_string())
+        .header("x-grundit-timestamp", &timestamp)
+        .header(
+            "x-grundit-signature",
+            format!(
+                "sha256={}",
+                sign(&hook.secret, &timestamp, &delivery.payload)
+            ),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+    match response {
+        Ok(response) if response.status().is_success() => {
+            (response.status().as_u16() as i64, Ok(()))
+        }
+        Ok(response) => (
+            response.status().as_u16() as i64,
+            Err(format!("responded {}", response.status())),
+        ),
+        Err(e) => (0, Err(e.to_string())),
+    }
+}
+
+fn sign(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// A client held to an address checked here, so the name can't resolve
+// somewhere else by the time it connects. Redirects aren't followed, they'd
+// skip the check.
+async fn client(state: &DataState, hook: &Webhook) -> Result<reqwest::Client, String> {
+    let url = Url::parse(&hook.url).map_err(|e| e.to_string())?;
+    let host = url.host_str().ok_or("no host in url")?;
+    let port = url.port_or_known_default().ok_or("no port for url")?;
+    // `[::1]` comes back bracketed
+    let name = host.trim_start_matches('[').trim_end_matches(']');
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name, port))
+        .await
+        .map_err(|e| e.to_string())?
+        .collect();
+    let admin = auth::roles_for(&state.store, hook.owner_id)
+        .iter()
+        .any(|role| role == "admin");
+    let addr = addrs
+        .into_iter()
+        .find(|addr| admin || public(addr.ip()))
+        .ok_or_else(|| format!("{} is not a public address", host))?;
+    debug!("delivering webhook {} to {}", hook.id, addr);
+    reqwest::ClientBuilder::new()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(TIMEOUT)
+        .resolve(host, addr)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+fn public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                // shared address space, carrier-grade nat
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // unique local
+                    || (first & 0xfe00) == 0xfc00
+                    // link-local
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+fn record(
+    state: &DataState,
+    delivery: &WebhookDelivery,
+    response_status: i64,
+    result: Result<(), String>,
+    retry: bool,
+) {
+    let attempts = delivery.attempts + 1;
+    let now = now();
+    let (status, next_attempt_at, delivered_at, error) = match result {
+        Ok(()) => (
+            DeliveryStatus::Delivered,
+            delivery.next_attempt_at,
+            now,
+            String::new(),
+        ),
+        Err(e) => {
+            debug!("webhook delivery {} failed: {}", delivery.id, e);
+            let e: String = e.chars().take(MAX_ERROR_LEN).collect();
+            if !retry || attempts >= MAX_ATTEMPTS {
+                (DeliveryStatus::Failed, delivery.next_attempt_at, 0, e)
+            } else {
+                (DeliveryStatus::Pending, now + backoff(attempts), 0, e)
+            }
+        }
+    };
+    let update = RequestWebhookDelivery {
+        id: Some(delivery.id),
+        owner_id: Some(delivery.owner_id),
+        webhook_id: None,
+        seq: None,
+        event: None,
+        payload: None,
+        status: Some(status),
+        attempts: Some(attempts),
+        next_attempt_at: Some(next_attempt_at),
+        response_status: Some(response_status),
+        error: Some(error),
+        created_at: None,
+        delivered_at: Some(delivered_at),
+    };
+    let _ = state.store.update::<_, WebhookDelivery>(update);
+}
+
+// seconds before trying again after this many attempts
+fn backoff(attempts: i64) -> i64 {
+    let doublings = (attempts - 1).clamp(0, 20) as u32;
+    retry_base_secs()
+        .saturating_mul(1 << doublings)
+        .min(MAX_RETRY_SECS)
+}
+
+fn retry_base_secs() -> i64 {
+    match env::var("WEBHOOK_RETRY_BASE_SECS") {
+        Ok(val) => val
+            .parse()
+            .unwrap_or_else(|_| panic!("invalid WEBHOOK_RETRY_BASE_SECS: {}", val)),
+        Err(_) => DEFAULT_RETRY_BASE_SECS,
+    }
+}
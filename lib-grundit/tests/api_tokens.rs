@@ -0,0 +1,131 @@
+// Personal API tokens: made and revoked from a session, used as bearer
+// tokens for the data routes their scopes cover and nothing else.
+mod common;
+
+use common::{client, register, start};
+use oauth2::reqwest::{RequestBuilder, StatusCode, header};
+use serde_json::{Value, json};
+
+fn with_session(req: RequestBuilder, (cookies, csrf_token): &(String, String)) -> RequestBuilder {
+    req.header(header::COOKIE, cookies)
+        .header("X-CSRF-Token", csrf_token)
+}
+
+fn with_token(req: RequestBuilder, token: &str) -> RequestBuilder {
+    req.header(header::AUTHORIZATION, format!("Bearer {}", token))
+}
+
+async fn create(base: &str, session: &(String, String), request: Value) -> (StatusCode, Value) {
+    let res = with_session(client().post(format!("{}/auth/tokens", base)), session)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(request.to_string())
+        .send()
+        .await
+        .unwrap();
+    let status = res.status();
+    let body = res.text().await.unwrap();
+    (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn api_tokens() {
+    let base = start("api-tokens", vec![]).await;
+    let alice = register(&base, "alice").await;
+    let bob = register(&base, "bob").await;
+
+    // an empty list used to mean everything
+    for request in [
+        json!({ "name": "all" }),
+        json!({ "name": "all", "scopes": [] }),
+        json!({ "name": "bad", "scopes": ["note:delete"] }),
+        json!({ "name": "bad", "scopes": ["nope:read"] }),
+        json!({ "name": "old", "scopes": ["note:read"], "expires_in_days": 0 }),
+        json!({ "name": " ", "scopes": ["note:read"] }),
+    ] {
+        let (status, _) = create(&base, &alice, request.clone()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", request);
+    }
+
+    let (status, read) = create(
+        &base,
+        &alice,
+        json!({ "name": "reader", "scopes": ["note:read"] }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let reader = read["token"].as_str().unwrap().to_string();
+    let (_, write) = create(
+        &base,
+        &alice,
+        json!({ "name": "writer", "scopes": ["*:write"], "expires_in_days": 30 }),
+    )
+    .await;
+    let writer = write["token"].as_str().unwrap().to_string();
+
+    let note = r#"{"owner_id":1,"contents":"by token"}"#;
+    let res = with_token(client().post(format!("{}/data/note", base)), &writer)
+        .body(note)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = with_token(client().get(format!("{}/data/note", base)), &reader)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let notes: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(notes[0]["contents"], "by token");
+
+    // only what the scopes say
+    for (token, method, path) in [
+        (&reader, "post", "/data/note"),
+        (&reader, "get", "/data/punch"),
+        (&writer, "get", "/data/note"),
+        // tokens don't manage tokens
+        (&reader, "get", "/auth/tokens"),
+        (&writer, "post", "/auth/tokens"),
+    ] {
+        let url = format!("{}{}", base, path);
+        let req = match method {
+            "get" => client().get(url),
+            _ => client().post(url),
+        };
+        let res = with_token(req, token).body(note).send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN, "{} {}", method, path);
+    }
+    let res = with_token(client().get(format!("{}/data/note", base)), "grundit_nope")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let res = with_session(client().get(format!("{}/auth/tokens", base)), &alice)
+        .send()
+        .await
+        .unwrap();
+    let listed: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(listed.as_array().unwrap().len(), 2);
+
+    // revoked by their owner only
+    let id = read["api_token"]["id"].as_i64().unwrap();
+    let revoke = |session| {
+        with_session(
+            client().delete(format!("{}/auth/tokens/{}", base, id)),
+            session,
+        )
+        .send()
+    };
+    assert_eq!(revoke(&bob).await.unwrap().status(), StatusCode::NOT_FOUND);
+    let res = with_token(client().get(format!("{}/data/note", base)), &reader)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(revoke(&alice).await.unwrap().status(), StatusCode::OK);
+    let res = with_token(client().get(format!("{}/data/note", base)), &reader)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,195 @@
+// Uploading files to notes, reading them back in ranges, and their blobs
+// going away with the last attachment that uses them.
+mod common;
+
+use std::{env, fs, path::PathBuf, process};
+
+use common::{client, register, start};
+use oauth2::reqwest::{Response, StatusCode, header};
+use serde_json::Value;
+
+const BOUNDARY: &str = "grundit-test-boundary";
+
+// (file name, content type, contents) as `file` parts
+fn multipart(files: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+    let mut body = vec![];
+    for (name, content_type, contents) in files {
+        body.extend(format!("--{}\r\n", BOUNDARY).bytes());
+        body.extend(
+            format!(
+                "Content-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n",
+                name
+            )
+            .bytes(),
+        );
+        if let Some(content_type) = content_type {
+            body.extend(format!("Content-Type: {}\r\n", content_type).bytes());
+        }
+        body.extend(b"\r\n");
+        body.extend(*contents);
+        body.extend(b"\r\n");
+    }
+    body.extend(format!("--{}--\r\n", BOUNDARY).bytes());
+    body
+}
+
+async fn upload(
+    base: &str,
+    (cookies, csrf_token): &(String, String),
+    note_id: i64,
+    files: &[(&str, Option<&str>, &[u8])],
+) -> Response {
+    client()
+        .post(format!("{}/data/note/{}/attachments", base, note_id))
+        .header(header::COOKIE, cookies)
+        .header("X-CSRF-Token", csrf_token)
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        )
+        .body(multipart(files))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn download(
+    base: &str,
+    (cookies, _): &(String, String),
+    id: i64,
+    range: Option<&str>,
+) -> Response {
+    let mut req = client()
+        .get(format!("{}/data/attachment/{}/content", base, id))
+        .header(header::COOKIE, cookies);
+    if let Some(range) = range {
+        req = req.header(header::RANGE, range);
+    }
+    req.send().await.unwrap()
+}
+
+async fn delete(base: &str, (cookies, csrf_token): &(String, String), path: &str) -> StatusCode {
+    client()
+        .delete(format!("{}{}", base, path))
+        .header(header::COOKIE, cookies)
+        .header("X-CSRF-Token", csrf_token)
+        .send()
+        .await
+        .unwrap()
+        .status()
+}
+
+fn blob_count(dir: &PathBuf) -> usize {
+    fs::read_dir(dir)
+        .unwrap()
+        .flat_map(|sub| fs::read_dir(sub.unwrap().path()).unwrap())
+        .count()
+}
+
+#[tokio::test]
+async fn attachments() {
+    let blobs = env::temp_dir().join(format!("grundit-blobs-{}", process::id()));
+    let _ = fs::remove_dir_all(&blobs);
+    // the only test in this binary, nothing else reads the environment yet
+    unsafe {
+        env::set_var("BLOB_DIR", &blobs);
+        env::set_var("ATTACHMENT_QUOTA_BYTES", "1000");
+    }
+    let base = start("attachments", vec![]).await;
+    let alice = register(&base, "alice").await;
+    let bob = register(&base, "bob").await;
+    for _ in 0..2 {
+        let res = client()
+            .post(format!("{}/data/note", base))
+            .header(header::COOKIE, &alice.0)
+            .header("X-CSRF-Token", &alice.1)
+            .body(r#"{"owner_id":1,"contents":"files"}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    let data: Vec<u8> = (0..100).collect();
+    let res = upload(
+        &base,
+        &alice,
+        1,
+        &[
+            ("hello.txt", None, b"hello, world"),
+            ("data.bin", Some("application/x-grundit"), &data),
+        ],
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let created: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(created[0]["content_type"], "text/plain");
+    assert_eq!(created[0]["size"], 12);
+    assert_eq!(created[1]["content_type"], "application/x-grundit");
+    let (hello, bin) = (
+        created[0]["id"].as_i64().unwrap(),
+        created[1]["id"].as_i64().unwrap(),
+    );
+
+    let res = client()
+        .get(format!("{}/data/attachment?byNoteId=1", base))
+        .header(header::COOKIE, &alice.0)
+        .send()
+        .await
+        .unwrap();
+    let listed: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(listed.as_array().unwrap().len(), 2);
+
+    let res = download(&base, &alice, hello, None).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "text/plain");
+    assert_eq!(res.headers()[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(res.text().await.unwrap(), "hello, world");
+    let res = download(&base, &alice, bin, Some("bytes=10-19")).await;
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes 10-19/100");
+    assert_eq!(res.bytes().await.unwrap().as_ref(), &data[10..20]);
+    let res = download(&base, &alice, bin, Some("bytes=-5")).await;
+    assert_eq!(res.bytes().await.unwrap().as_ref(), &data[95..]);
+    let res = download(&base, &alice, bin, Some("bytes=100-")).await;
+    assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+    // only onto your own notes, and within your quota
+    let res = upload(&base, &bob, 1, &[("x.txt", None, b"x")]).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = upload(&base, &alice, 2, &[("big.bin", None, &[0; 900])]).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let res: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(res["errors"][0]["rule"], "quota");
+
+    // the same file twice is one blob
+    let res = upload(&base, &alice, 2, &[("again.txt", None, b"hello, world")]).await;
+    let again: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    let again = again[0]["id"].as_i64().unwrap();
+    assert_eq!(blob_count(&blobs), 2);
+
+    // nobody else can delete the note, or its attachments with it
+    assert_eq!(
+        delete(&base, &bob, "/data/note/1").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        download(&base, &alice, hello, None).await.status(),
+        StatusCode::OK
+    );
+    assert_eq!(blob_count(&blobs), 2);
+
+    // the note takes its attachments along, the shared blob stays
+    assert_eq!(delete(&base, &alice, "/data/note/1").await, StatusCode::OK);
+    assert_eq!(
+        download(&base, &alice, hello, None).await.status(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(blob_count(&blobs), 1);
+    let path = format!("/data/attachment/{}", again);
+    assert_eq!(delete(&base, &bob, &path).await, StatusCode::FORBIDDEN);
+    assert_eq!(delete(&base, &alice, &path).await, StatusCode::OK);
+    assert_eq!(blob_count(&blobs), 0);
+
+    let _ = fs::remove_dir_all(&blobs);
+}
@@ -0,0 +1,133 @@
+// Replies to comments, and reading a note's comments back as a tree.
+mod common;
+
+use common::{get, json, register, send, start};
+use oauth2::reqwest::StatusCode;
+use serde_json::Value;
+
+// posts a comment on `note_id`, returning its id or the violated rules
+async fn reply(
+    base: &str,
+    session: &(String, String),
+    note_id: i64,
+    parent: Option<i64>,
+) -> Result<i64, Vec<String>> {
+    let parent = parent.map(|id| format!(r#","parent_comment_id":{}"#, id));
+    let body = format!(
+        r#"{{"owner_id":1,"note_id":{},"contents":"hi"{}}}"#,
+        note_id,
+        parent.unwrap_or_default()
+    );
+    let res = send(base, session, "post", "/data/comment", &body).await;
+    let status = res.status();
+    let res: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    match status {
+        StatusCode::OK => Ok(res["id"].as_i64().unwrap()),
+        _ => Err(res["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["rule"].as_str().unwrap().to_string())
+            .collect()),
+    }
+}
+
+// (id, replies) all the way down
+fn shape(threads: &Value) -> Vec<(i64, Value)> {
+    threads
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|t| (t["id"].as_i64().unwrap(), t["replies"].clone()))
+        .collect()
+}
+
+#[tokio::test]
+async fn threads() {
+    let base = start("comment-threads", vec![]).await;
+    let session = register(&base, "talker").await;
+    for _ in 0..2 {
+        let res = send(
+            &base,
+            &session,
+            "post",
+            "/data/note",
+            r#"{"owner_id":1,"contents":"n"}"#,
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    let first = reply(&base, &session, 1, None).await.unwrap();
+    let second = reply(&base, &session, 1, None).await.unwrap();
+    let answer = reply(&base, &session, 1, Some(first)).await.unwrap();
+    let follow_up = reply(&base, &session, 1, Some(answer)).await.unwrap();
+    let other = reply(&base, &session, 1, Some(second)).await.unwrap();
+    let another = reply(&base, &session, 1, Some(second)).await.unwrap();
+
+    assert_eq!(
+        reply(&base, &session, 2, Some(first)).await,
+        Err(vec!["same_note".to_string()])
+    );
+    assert_eq!(
+        reply(&base, &session, 1, Some(999)).await,
+        Err(vec!["exists".to_string()])
+    );
+
+    // six deep at most
+    let mut parent = follow_up;
+    for _ in 3..=6 {
+        parent = reply(&base, &session, 1, Some(parent)).await.unwrap();
+    }
+    assert_eq!(
+        reply(&base, &session, 1, Some(parent)).await,
+        Err(vec!["depth".to_string()])
+    );
+
+    // a reply stays where it was posted
+    let body = format!(
+        r#"{{"id":{},"owner_id":1,"parent_comment_id":{}}}"#,
+        answer, second
+    );
+    let res = send(&base, &session, "put", "/data/comment", &body).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = format!(r#"{{"id":{},"owner_id":1,"note_id":2}}"#, answer);
+    let res = send(&base, &session, "put", "/data/comment", &body).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = format!(r#"{{"id":{},"owner_id":1,"contents":"edited"}}"#, answer);
+    let res = send(&base, &session, "put", "/data/comment", &body).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let thread = |sort: &'static str| {
+        let (base, session) = (&base, &session);
+        async move {
+            let path = format!("/data/comment/thread/1{}", sort);
+            json(get(base, session, &path).await, StatusCode::OK).await
+        }
+    };
+
+    let oldest = thread("").await;
+    let top = shape(&oldest);
+    assert_eq!(
+        top.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+        vec![first, second]
+    );
+    let under_first = shape(&top[0].1);
+    assert_eq!(under_first[0].0, answer);
+    assert_eq!(shape(&under_first[0].1)[0].0, follow_up);
+    let under_second: Vec<i64> = shape(&top[1].1).iter().map(|(id, _)| *id).collect();
+    assert_eq!(under_second, vec![other, another]);
+
+    let newest = thread("?sort=newest").await;
+    let top: Vec<i64> = shape(&newest).iter().map(|(id, _)| *id).collect();
+    assert_eq!(top, vec![second, first]);
+    let under_second: Vec<i64> = shape(&shape(&newest)[0].1)
+        .iter()
+        .map(|(id, _)| *id)
+        .collect();
+    assert_eq!(under_second, vec![another, other]);
+
+    // the second has two direct replies to the first's one
+    let busiest = thread("?sort=mostReplies").await;
+    assert_eq!(shape(&busiest)[0].0, second);
+}
@@ -0,0 +1,337 @@
+// Shared setup for the integration tests: the real router on a random port,
+// backed by a scratch database.
+use std::{env, path::PathBuf, process, sync::Arc};
+
+use lib_glonk::store::SqliteStore;
+use lib_grundit::{AuthrState, auth::IdentityProvider, run};
+use oauth2::reqwest::{Client, RequestBuilder, Response, StatusCode, header, redirect::Policy};
+use serde_json::Value;
+use tokio::net::TcpListener;
+
+const SCHEMA: &str = "
+    CREATE TABLE users (
+        id integer primary key autoincrement,
+        guid text not null,
+        name text,
+        email text,
+        picture text);
+
+    CREATE TABLE notes (
+        id integer primary key autoincrement,
+        owner_id integer,
+        contents text,
+        version integer not null default 1);
+
+    CREATE TABLE comments (
+        id integer primary key autoincrement,
+        owner_id integer,
+        note_id integer,
+        parent_comment_id integer,
+        contents text);
+
+    CREATE TABLE tags (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        name text not null,
+        unique(owner_id, name));
+
+    CREATE TABLE note_tags (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        note_id integer not null,
+        tag_id integer not null,
+        unique(note_id, tag_id));
+
+    CREATE TRIGGER note_tags_tag_delete AFTER DELETE ON tags BEGIN
+        DELETE FROM note_tags where tag_id = old.id;
+    END;
+
+    CREATE TRIGGER note_tags_note_delete AFTER DELETE ON notes BEGIN
+        DELETE FROM note_tags where note_id = old.id;
+    END;
+
+    CREATE VIEW tag_counts AS
+        SELECT tags.id, tags.owner_id, tags.name, count(note_tags.id) as count
+        FROM tags LEFT JOIN note_tags ON note_tags.tag_id = tags.id
+        GROUP BY tags.id;
+
+    CREATE TABLE attachments (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        note_id integer not null,
+        name text not null,
+        content_type text not null,
+        size integer not null,
+        digest text not null,
+        created_at integer not null,
+        thumbnails text not null default 'unsupported');
+
+    CREATE TABLE thumbnails (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        attachment_id integer not null,
+        size text not null,
+        width integer not null,
+        height integer not null,
+        digest text not null,
+        unique(attachment_id, size));
+
+    CREATE TABLE notifications (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        kind text not null,
+        actor_id integer not null,
+        note_id integer not null,
+        comment_id integer not null,
+        read integer not null default 0,
+        created_at integer not null);
+
+    CREATE TABLE changes (
+        seq integer primary key autoincrement,
+        table_name text not null,
+        row_id integer not null,
+        kind text not null);
+
+    CREATE TABLE webhooks (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        url text not null,
+        data_types text not null default '',
+        operations text not null default '',
+        secret text not null,
+        active integer not null default 1,
+        last_seq integer not null default 0,
+        created_at integer not null);
+
+    CREATE TABLE webhook_deliveries (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        webhook_id integer not null,
+        seq integer not null,
+        event text not null,
+        payload text not null,
+        status text not null,
+        attempts integer not null default 0,
+        next_attempt_at integer not null,
+        response_status integer not null default 0,
+        error text not null default '',
+        created_at integer not null,
+        delivered_at integer not null default 0);
+
+    CREATE TRIGGER webhook_deliveries_webhook_delete AFTER DELETE ON webhooks BEGIN
+        DELETE FROM webhook_deliveries where webhook_id = old.id;
+    END;
+
+    CREATE TABLE jobs (
+        id integer primary key autoincrement,
+        kind text not null,
+        payload text not null,
+        status text not null,
+        attempts integer not null default 0,
+        max_attempts integer not null,
+        run_at integer not null,
+        schedule text not null default '',
+        last_error text not null default '',
+        created_at integer not null,
+        updated_at integer not null);
+
+    CREATE TABLE idempotency_keys (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        key text not null,
+        request text not null,
+        status integer not null,
+        body text not null,
+        created_at integer not null,
+        unique(owner_id, key));
+
+    CREATE TRIGGER notifications_comment_delete AFTER DELETE ON comments BEGIN
+        DELETE FROM notifications where comment_id = old.id;
+    END;
+
+    CREATE TRIGGER notifications_note_delete AFTER DELETE ON notes BEGIN
+        DELETE FROM notifications where note_id = old.id;
+    END;
+
+    CREATE TABLE geofences (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        name text not null,
+        shape text not null,
+        latitude real,
+        longitude real,
+        radius real,
+        polygon text,
+        user_ids text not null default '');
+
+    CREATE TABLE punches (
+        id integer primary key autoincrement,
+        owner_id integer,
+        direction text not null,
+        latitude real not null,
+        longitude real not null,
+        accuracy real,
+        altitude real,
+        captured_at integer not null,
+        received_at integer not null,
+        geofence_id integer,
+        flagged integer not null default 0);
+
+    CREATE VIRTUAL TABLE punch_locations USING rtree(
+        id, min_lat, max_lat, min_lon, max_lon);
+
+    CREATE TRIGGER punch_locations_insert AFTER INSERT ON punches BEGIN
+        INSERT INTO punch_locations VALUES (
+            new.id, new.latitude, new.latitude, new.longitude, new.longitude);
+    END;
+
+    CREATE TRIGGER punch_locations_update AFTER UPDATE OF latitude, longitude ON punches BEGIN
+        UPDATE punch_locations SET
+            min_lat = new.latitude, max_lat = new.latitude,
+            min_lon = new.longitude, max_lon = new.longitude
+            where id = new.id;
+    END;
+
+    CREATE TRIGGER punch_locations_delete AFTER DELETE ON punches BEGIN
+        DELETE FROM punch_locations where id = old.id;
+    END;
+
+    CREATE TABLE identities (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        guid text not null unique,
+        provider text not null,
+        email text);
+
+    CREATE TABLE credentials (
+        id integer primary key autoincrement,
+        owner_id integer not null unique,
+        username text not null unique,
+        password_hash text not null);
+
+    CREATE TABLE password_resets (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        code_hash text not null,
+        expires_at integer not null,
+        used integer not null default 0);
+
+    CREATE TABLE api_tokens (
+        id integer primary key autoincrement,
+        owner_id integer not null,
+        name text not null,
+        token_hash text not null unique,
+        scopes text not null default '',
+        expires_at integer,
+        created_at integer not null,
+        revoked integer not null default 0);
+
+    CREATE TABLE roles (
+        id integer primary key autoincrement,
+        user_id integer not null,
+        role text not null);
+";
+
+// the scratch database behind `start(name, ..)`
+pub fn db_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("grundit-{}-{}.db", name, process::id()))
+}
+
+// returns the base url of a fresh server
+#[allow(dead_code)] // the job tests register their own first
+pub async fn start(name: &str, providers: Vec<Arc<dyn IdentityProvider>>) -> String {
+    start_with(name, providers, |_| {}).await
+}
+
+// `start`, with a look at the state before it runs
+pub async fn start_with(
+    name: &str,
+    providers: Vec<Arc<dyn IdentityProvider>>,
+    configure: impl FnOnce(&mut AuthrState),
+) -> String {
+    let path = db_path(name);
+    let _ = std::fs::remove_file(&path);
+    sqlite::open(&path).unwrap().execute(SCHEMA).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let mut state = AuthrState::new(providers, SqliteStore::open(&path));
+    configure(&mut state);
+    tokio::spawn(run(listener, state));
+    base
+}
+
+pub fn client() -> Client {
+    Client::builder().redirect(Policy::none()).build().unwrap()
+}
+
+// registers a local account and returns its cookies and csrf token
+#[allow(dead_code)] // not every test signs in
+pub async fn register(base: &str, username: &str) -> (String, String) {
+    let res = client()
+        .post(format!("{}/auth/local/register", base))
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(format!("username={}&password=correct-horse", username))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    let cookies: Vec<String> = res
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|c| c.to_str().unwrap().split(';').next().unwrap().to_string())
+        .collect();
+    let csrf_token = cookies
+        .iter()
+        .find_map(|c| c.strip_prefix("csrf_token="))
+        .unwrap()
+        .to_string();
+    (cookies.join("; "), csrf_token)
+}
+
+// a request as the signed in `session`, `method` is get, put, delete or post
+#[allow(dead_code)]
+pub fn request(
+    base: &str,
+    (cookies, csrf_token): &(String, String),
+    method: &str,
+    path: &str,
+) -> RequestBuilder {
+    let url = format!("{}{}", base, path);
+    let req = match method {
+        "get" => client().get(url),
+        "put" => client().put(url),
+        "delete" => client().delete(url),
+        _ => client().post(url),
+    };
+    req.header(header::COOKIE, cookies)
+        .header("X-CSRF-Token", csrf_token)
+}
+
+#[allow(dead_code)]
+pub async fn send(
+    base: &str,
+    session: &(String, String),
+    method: &str,
+    path: &str,
+    body: &str,
+) -> Response {
+    request(base, session, method, path)
+        .body(body.to_string())
+        .send()
+        .await
+        .unwrap()
+}
+
+#[allow(dead_code)]
+pub async fn get(base: &str, session: &(String, String), path: &str) -> Response {
+    send(base, session, "get", path, "").await
+}
+
+// the body of a response with `status`
+#[allow(dead_code)]
+pub async fn json(res: Response, status: StatusCode) -> Value {
+    assert_eq!(res.status(), status);
+    serde_json::from_str(&res.text().await.unwrap()).unwrap()
+}
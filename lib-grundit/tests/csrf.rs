@@ -0,0 +1,112 @@
+// Cross site request forgery: unsafe requests need the session's token, and
+// browsers on another origin are turned away, websocket handshakes included.
+mod common;
+
+use common::{client, register, start};
+use oauth2::reqwest::{RequestBuilder, StatusCode, header};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{Error, client::IntoClientRequest},
+};
+
+// what the server takes as its own without PUBLIC_ORIGIN
+const OWN_ORIGIN: &str = "http://localhost:8080";
+const NOTE: &str = r#"{"owner_id":1,"contents":"csrf"}"#;
+
+fn post_note(base: &str, cookies: &str) -> RequestBuilder {
+    client()
+        .post(format!("{}/data/note", base))
+        .header(header::COOKIE, cookies)
+        .body(NOTE)
+}
+
+#[tokio::test]
+async fn token_required() {
+    let base = start("csrf-token", vec![]).await;
+    let (cookies, csrf_token) = register(&base, "alice").await;
+    let (_, other_token) = register(&base, "bob").await;
+
+    let res = post_note(&base, &cookies).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    for token in ["wrong", "", other_token.as_str()] {
+        let res = post_note(&base, &cookies)
+            .header("X-CSRF-Token", token)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN, "{:?}", token);
+    }
+    let res = post_note(&base, &cookies)
+        .header("X-CSRF-Token", &csrf_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // reads don't need it
+    let res = client()
+        .get(format!("{}/data/note", base))
+        .header(header::COOKIE, &cookies)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn foreign_origin_refused() {
+    let base = start("csrf-origin", vec![]).await;
+    let (cookies, csrf_token) = register(&base, "alice").await;
+
+    for (name, value, status) in [
+        (header::ORIGIN, "https://evil.test", StatusCode::FORBIDDEN),
+        (header::ORIGIN, "null", StatusCode::FORBIDDEN),
+        (
+            header::REFERER,
+            "https://evil.test/page",
+            StatusCode::FORBIDDEN,
+        ),
+        (header::ORIGIN, OWN_ORIGIN, StatusCode::OK),
+        (header::REFERER, "http://localhost:8080/web", StatusCode::OK),
+    ] {
+        let res = post_note(&base, &cookies)
+            .header("X-CSRF-Token", &csrf_token)
+            .header(&name, value)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), status, "{}: {}", name, value);
+    }
+
+    // the login forms too
+    let res = client()
+        .post(format!("{}/auth/local/login", base))
+        .header(header::ORIGIN, "https://evil.test")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body("username=alice&password=correct-horse")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn foreign_origin_websocket_refused() {
+    let base = start("csrf-websocket", vec![]).await;
+    let (cookies, _) = register(&base, "alice").await;
+    let url = format!("{}/data/subscribe", base.replace("http://", "ws://"));
+    let handshake = |origin: &str| {
+        let mut req = url.as_str().into_client_request().unwrap();
+        req.headers_mut()
+            .insert(header::COOKIE, cookies.parse().unwrap());
+        req.headers_mut()
+            .insert(header::ORIGIN, origin.parse().unwrap());
+        connect_async(req)
+    };
+
+    match handshake("https://evil.test").await {
+        Err(Error::Http(res)) => assert_eq!(res.status(), StatusCode::FORBIDDEN),
+        other => panic!("expected a refused handshake, got {:?}", other.map(|_| ())),
+    }
+    assert!(handshake(OWN_ORIGIN).await.is_ok());
+}
@@ -0,0 +1,187 @@
+// Server-sent change events: live events, filtering by query and owner, and
+// replay after `Last-Event-ID`.
+mod common;
+
+use common::{client, json, register, send, start};
+use oauth2::reqwest::{Response, StatusCode, header};
+use serde_json::Value;
+
+struct Events {
+    res: Response,
+    buf: String,
+}
+
+#[derive(Debug)]
+struct Event {
+    id: i64,
+    event: String,
+    data: Value,
+}
+
+async fn open(
+    base: &str,
+    (cookies, _): &(String, String),
+    path: &str,
+    last: Option<i64>,
+) -> Events {
+    let mut req = client()
+        .get(format!("{}{}", base, path))
+        .header(header::COOKIE, cookies);
+    if let Some(last) = last {
+        req = req.header("Last-Event-ID", last.to_string());
+    }
+    let res = req.send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "text/event-stream");
+    Events {
+        res,
+        buf: String::new(),
+    }
+}
+
+impl Events {
+    async fn next(&mut self) -> Event {
+        loop {
+            if let Some(end) = self.buf.find("\n\n") {
+                let block: String = self.buf.drain(..end + 2).collect();
+                let field = |name: &str| {
+                    block
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .map(|val| val.trim().to_string())
+                };
+                // keep-alive comments have no fields
+                if let Some(event) = field("event:") {
+                    return Event {
+                        id: field("id:").unwrap().parse().unwrap(),
+                        event,
+                        data: serde_json::from_str(&field("data:").unwrap()).unwrap(),
+                    };
+                }
+                continue;
+            }
+            let chunk = self.res.chunk().await.unwrap().unwrap();
+            self.buf.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+}
+
+#[tokio::test]
+async fn events() {
+    let base = start("events", vec![]).await;
+    let alice = register(&base, "alice").await;
+    let bob = register(&base, "bob").await;
+    json(
+        send(
+            &base,
+            &alice,
+            "post",
+            "/data/note",
+            r#"{"owner_id":1,"contents":"a"}"#,
+        )
+        .await,
+        StatusCode::OK,
+    )
+    .await;
+    json(
+        send(
+            &base,
+            &bob,
+            "post",
+            "/data/note",
+            r#"{"owner_id":2,"contents":"b"}"#,
+        )
+        .await,
+        StatusCode::OK,
+    )
+    .await;
+
+    let mut events = open(&base, &alice, "/data/comment/events?byNoteId=1", None).await;
+    // not on note 1, so skipped
+    let body = r#"{"owner_id":2,"note_id":2,"contents":"elsewhere"}"#;
+    json(
+        send(&base, &bob, "post", "/data/comment", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let body = r#"{"owner_id":2,"note_id":1,"contents":"first"}"#;
+    let first = json(
+        send(&base, &bob, "post", "/data/comment", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let created = events.next().await;
+    assert_eq!(created.event, "created");
+    assert_eq!(created.data, first);
+
+    let body = format!(
+        r#"{{"id":{},"owner_id":2,"contents":"edited"}}"#,
+        first["id"]
+    );
+    json(
+        send(&base, &bob, "put", "/data/comment", &body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let updated = events.next().await;
+    assert_eq!(updated.event, "updated");
+    assert_eq!(updated.data["contents"], "edited");
+    assert!(updated.id > created.id);
+    drop(events);
+
+    // missed while away, and replayed in order on reconnecting
+    let body = r#"{"owner_id":2,"note_id":1,"contents":"second"}"#;
+    let second = json(
+        send(&base, &bob, "post", "/data/comment", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    json(
+        send(
+            &base,
+            &bob,
+            "delete",
+            &format!("/data/comment/{}", first["id"]),
+            "",
+        )
+        .await,
+        StatusCode::OK,
+    )
+    .await;
+    let mut events = open(
+        &base,
+        &alice,
+        "/data/comment/events?byNoteId=1",
+        Some(updated.id),
+    )
+    .await;
+    let replayed = events.next().await;
+    assert_eq!(replayed.event, "created");
+    assert_eq!(replayed.data, second);
+    let deleted = events.next().await;
+    assert_eq!(deleted.event, "deleted");
+    assert_eq!(deleted.data["id"], first["id"]);
+    assert!(deleted.id > replayed.id);
+
+    // only alice's own notifications, from the start of the log. The first
+    // comment's went with it, so isn't replayed
+    let mut events = open(&base, &alice, "/data/notification/events", Some(0)).await;
+    let event = events.next().await;
+    assert_eq!(event.event, "created");
+    assert_eq!(event.data["owner_id"], 1);
+    assert_eq!(event.data["comment_id"], second["id"]);
+    let body = r#"{"owner_id":1,"note_id":2,"contents":"for bob"}"#;
+    json(
+        send(&base, &alice, "post", "/data/comment", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let body = r#"{"owner_id":2,"note_id":1,"contents":"third"}"#;
+    let third = json(
+        send(&base, &bob, "post", "/data/comment", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let event = events.next().await;
+    assert_eq!(event.data["comment_id"], third["id"]);
+}
@@ -0,0 +1,185 @@
+// The change feed: snapshots, live creates, updates and deletes, and that a
+// socket only hears about what its user could list.
+mod common;
+
+use common::{json, register, send, start};
+use futures_util::{SinkExt, StreamExt};
+use lib_grundit::types::{FeedEvent, FeedRequest};
+use oauth2::reqwest::{StatusCode, header};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    MaybeTlsStream, WebSocketStream, connect_async,
+    tungstenite::{Error, Message, client::IntoClientRequest},
+};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(base: &str, (cookies, _): &(String, String)) -> Socket {
+    let url = format!("{}/data/subscribe", base.replace("http://", "ws://"));
+    let mut req = url.into_client_request().unwrap();
+    req.headers_mut()
+        .insert(header::COOKIE, cookies.parse().unwrap());
+    connect_async(req).await.unwrap().0
+}
+
+async fn subscribe(socket: &mut Socket, id: &str, data_type: &str, query: &[(&str, &str)]) {
+    let req = FeedRequest::Subscribe {
+        id: id.to_string(),
+        data_type: data_type.to_string(),
+        query: query
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+    };
+    let text = serde_json::to_string(&req).unwrap();
+    socket.send(Message::Text(text.into())).await.unwrap();
+}
+
+async fn next(socket: &mut Socket) -> FeedEvent {
+    match socket.next().await.unwrap().unwrap() {
+        Message::Text(text) => serde_json::from_str(text.as_str()).unwrap(),
+        message => panic!("unexpected {:?}", message),
+    }
+}
+
+#[tokio::test]
+async fn feed() {
+    let base = start("feed", vec![]).await;
+    let alice = register(&base, "alice").await;
+    let bob = register(&base, "bob").await;
+    json(
+        send(
+            &base,
+            &alice,
+            "post",
+            "/data/note",
+            r#"{"owner_id":1,"contents":"a"}"#,
+        )
+        .await,
+        StatusCode::OK,
+    )
+    .await;
+    json(
+        send(
+            &base,
+            &bob,
+            "post",
+            "/data/note",
+            r#"{"owner_id":2,"contents":"b"}"#,
+        )
+        .await,
+        StatusCode::OK,
+    )
+    .await;
+    let first = r#"{"owner_id":1,"note_id":1,"contents":"first"}"#;
+    json(
+        send(&base, &alice, "post", "/data/comment", first).await,
+        StatusCode::OK,
+    )
+    .await;
+
+    let mut alice_socket = connect(&base, &alice).await;
+    subscribe(&mut alice_socket, "c", "comment", &[("byNoteId", "1")]).await;
+    match next(&mut alice_socket).await {
+        FeedEvent::Snapshot { subscription, data } => {
+            assert_eq!(subscription, "c");
+            assert_eq!(data.len(), 1);
+            assert_eq!(data[0]["contents"], "first");
+        }
+        event => panic!("unexpected {:?}", event),
+    }
+    subscribe(&mut alice_socket, "n", "notification", &[]).await;
+    assert!(matches!(
+        next(&mut alice_socket).await,
+        FeedEvent::Snapshot { data, .. } if data.is_empty()
+    ));
+    let mut bob_socket = connect(&base, &bob).await;
+    subscribe(&mut bob_socket, "n", "notification", &[]).await;
+    assert!(matches!(
+        next(&mut bob_socket).await,
+        FeedEvent::Snapshot { data, .. } if data.is_empty()
+    ));
+
+    // a comment on the other note isn't in alice's query, but bob is told
+    // about it, and not alice
+    let body = r#"{"owner_id":1,"note_id":2,"contents":"hi bob"}"#;
+    json(
+        send(&base, &alice, "post", "/data/comment", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    match next(&mut bob_socket).await {
+        FeedEvent::Created { subscription, data } => {
+            assert_eq!(subscription, "n");
+            assert_eq!(data["owner_id"], 2);
+            assert_eq!(data["comment_id"], 2);
+        }
+        event => panic!("unexpected {:?}", event),
+    }
+
+    let body = r#"{"owner_id":2,"note_id":1,"contents":"second"}"#;
+    let second = json(
+        send(&base, &bob, "post", "/data/comment", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let id = second["id"].as_i64().unwrap();
+    match next(&mut alice_socket).await {
+        FeedEvent::Created { subscription, data } => {
+            assert_eq!(subscription, "c");
+            assert_eq!(data, second);
+        }
+        event => panic!("unexpected {:?}", event),
+    }
+    // alice's own notification of bob's comment, after it
+    assert!(matches!(
+        next(&mut alice_socket).await,
+        FeedEvent::Created { subscription, data }
+            if subscription == "n" && data["comment_id"] == id
+    ));
+
+    let body = format!(r#"{{"id":{},"owner_id":2,"contents":"edited"}}"#, id);
+    json(
+        send(&base, &bob, "put", "/data/comment", &body).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert!(matches!(
+        next(&mut alice_socket).await,
+        FeedEvent::Updated { data, .. } if data["contents"] == "edited"
+    ));
+
+    // nothing more about notifications, just the comment going
+    let text = serde_json::to_string(&FeedRequest::Unsubscribe { id: "n".into() }).unwrap();
+    alice_socket.send(Message::Text(text.into())).await.unwrap();
+    json(
+        send(&base, &bob, "delete", &format!("/data/comment/{}", id), "").await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(
+        next(&mut alice_socket).await,
+        FeedEvent::Deleted {
+            subscription: "c".into(),
+            id
+        }
+    );
+
+    subscribe(&mut alice_socket, "x", "nope", &[]).await;
+    assert!(matches!(
+        next(&mut alice_socket).await,
+        FeedEvent::Error { subscription, .. } if subscription == "x"
+    ));
+
+    // other sites can't open one with our cookies
+    let url = format!("{}/data/subscribe", base.replace("http://", "ws://"));
+    let mut req = url.into_client_request().unwrap();
+    req.headers_mut()
+        .insert(header::COOKIE, alice.0.parse().unwrap());
+    req.headers_mut()
+        .insert(header::ORIGIN, "http://evil.example".parse().unwrap());
+    match connect_async(req).await {
+        Err(Error::Http(res)) => assert_eq!(res.status(), StatusCode::FORBIDDEN),
+        res => panic!("unexpected {:?}", res.map(|_| ())),
+    }
+}
@@ -0,0 +1,120 @@
+// Bounding box, radius and nearest queries on punches, through the R*Tree.
+mod common;
+
+use common::{client, register, start};
+use oauth2::reqwest::{StatusCode, header};
+use serde_json::Value;
+
+const SITE: (f64, f64) = (51.5, -0.12);
+
+// creates punches at each point and returns their ids
+async fn punch_at(
+    base: &str,
+    (cookies, csrf_token): &(String, String),
+    points: &[(f64, f64)],
+) -> Vec<i64> {
+    let mut ids = vec![];
+    for (lat, lon) in points {
+        let res = client()
+            .post(format!("{}/data/punch", base))
+            .header(header::COOKIE, cookies)
+            .header("X-CSRF-Token", csrf_token)
+            .body(format!(
+                r#"{{"owner_id":1,"direction":"in","latitude":{},"longitude":{},"captured_at":0}}"#,
+                lat, lon
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let punch: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        ids.push(punch["id"].as_i64().unwrap());
+    }
+    ids
+}
+
+async fn query(base: &str, (cookies, _): &(String, String), query: &str) -> Vec<i64> {
+    let res = client()
+        .get(format!("{}/data/punch?{}", base, query))
+        .header(header::COOKIE, cookies)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let punches: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    punches
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["id"].as_i64().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn radius_box_and_nearest() {
+    let base = start("geo-queries", vec![]).await;
+    let session = register(&base, "alice").await;
+    // at the site, about 500m north, about 5km north
+    let ids = punch_at(
+        &base,
+        &session,
+        &[SITE, (SITE.0 + 0.0045, SITE.1), (SITE.0 + 0.045, SITE.1)],
+    )
+    .await;
+
+    let mut near = query(&base, &session, "withinRadius=51.5,-0.12,1000").await;
+    near.sort();
+    assert_eq!(near, ids[..2]);
+    // exactly on the point
+    assert_eq!(
+        query(&base, &session, "withinRadius=51.5,-0.12,0").await,
+        ids[..1]
+    );
+
+    let mut boxed = query(&base, &session, "bbox=51.49,-0.13,51.51,-0.11").await;
+    boxed.sort();
+    assert_eq!(boxed, ids[..2]);
+
+    assert_eq!(
+        query(&base, &session, "nearest=51.6,-0.12,2").await,
+        vec![ids[2], ids[1]]
+    );
+    assert_eq!(
+        query(&base, &session, "nearest=51.5,-0.12,5&byOwnerId=1").await,
+        ids
+    );
+    // found close by before looking further out
+    assert_eq!(
+        query(&base, &session, "nearest=51.5,-0.12,1").await,
+        ids[..1]
+    );
+    assert_eq!(
+        query(&base, &session, "nearest=51.5,-0.12,2").await,
+        ids[..2]
+    );
+}
+
+#[tokio::test]
+async fn across_the_antimeridian() {
+    let base = start("geo-antimeridian", vec![]).await;
+    let session = register(&base, "alice").await;
+    let ids = punch_at(
+        &base,
+        &session,
+        &[(-17.0, 179.95), (-17.0, -179.95), (-17.0, 0.0)],
+    )
+    .await;
+
+    let mut boxed = query(&base, &session, "bbox=-18,179,-16,-179").await;
+    boxed.sort();
+    assert_eq!(boxed, ids[..2]);
+
+    let mut near = query(&base, &session, "withinRadius=-17,179.99,20000").await;
+    near.sort();
+    assert_eq!(near, ids[..2]);
+
+    assert_eq!(
+        query(&base, &session, "nearest=-17,179.99,2").await,
+        ids[..2]
+    );
+}
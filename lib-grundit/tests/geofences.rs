@@ -0,0 +1,88 @@
+// Admin managed geofences and how punches are classified against them.
+mod common;
+
+use common::{db_path, json, register, send, start};
+use oauth2::reqwest::StatusCode;
+use serde_json::Value;
+
+async fn punch(base: &str, session: &(String, String), owner_id: i64, lat: f64, lon: f64) -> Value {
+    let body = format!(
+        r#"{{"owner_id":{},"direction":"in","latitude":{},"longitude":{},"captured_at":0}}"#,
+        owner_id, lat, lon
+    );
+    json(
+        send(base, session, "post", "/data/punch", &body).await,
+        StatusCode::OK,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn punches_are_classified() {
+    let base = start("geofences", vec![]).await;
+    // the first account to register is the admin
+    sqlite::open(db_path("geofences"))
+        .unwrap()
+        .execute("INSERT INTO roles (user_id, role) VALUES (1, 'admin')")
+        .unwrap();
+    let admin = register(&base, "admin").await;
+    let worker = register(&base, "worker").await;
+
+    let body = r#"{"owner_id":2,"name":"depot","shape":"circle","latitude":51.5,"longitude":-0.12,"radius":200}"#;
+    assert_eq!(
+        send(&base, &worker, "post", "/data/geofence", body)
+            .await
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+
+    // a circle needs its centre and radius
+    let body = r#"{"owner_id":1,"name":"depot","shape":"circle","latitude":51.5}"#;
+    assert_eq!(
+        send(&base, &admin, "post", "/data/geofence", body)
+            .await
+            .status(),
+        StatusCode::UNPROCESSABLE_ENTITY
+    );
+
+    let body = r#"{"owner_id":1,"name":"depot","shape":"circle","latitude":51.5,"longitude":-0.12,"radius":200}"#;
+    let depot = json(
+        send(&base, &admin, "post", "/data/geofence", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let body = r#"{"owner_id":1,"name":"site","shape":"polygon","polygon":"[[48.85,2.29],[48.86,2.29],[48.86,2.30],[48.85,2.30]]","user_ids":"2"}"#;
+    let site = json(
+        send(&base, &admin, "post", "/data/geofence", body).await,
+        StatusCode::OK,
+    )
+    .await;
+
+    // everyone may punch at the depot
+    let at_depot = punch(&base, &worker, 2, 51.5005, -0.12).await;
+    assert_eq!(at_depot["geofence_id"], depot["id"]);
+    assert_eq!(at_depot["flagged"], false);
+
+    let on_site = punch(&base, &worker, 2, 48.855, 2.295).await;
+    assert_eq!(on_site["geofence_id"], site["id"]);
+    assert_eq!(on_site["flagged"], false);
+
+    // the site isn't one of the admin's fences
+    let admin_on_site = punch(&base, &admin, 1, 48.855, 2.295).await;
+    assert_eq!(admin_on_site["geofence_id"], site["id"]);
+    assert_eq!(admin_on_site["flagged"], true);
+
+    let elsewhere = punch(&base, &worker, 2, 40.0, -74.0).await;
+    assert_eq!(elsewhere["geofence_id"], Value::Null);
+    assert_eq!(elsewhere["flagged"], true);
+
+    // moving a punch reclassifies it
+    let body = format!(
+        r#"{{"id":{},"owner_id":2,"latitude":51.5,"longitude":-0.12}}"#,
+        elsewhere["id"]
+    );
+    let res = send(&base, &worker, "put", "/data/punch", &body).await;
+    let moved = json(res, StatusCode::OK).await;
+    assert_eq!(moved["geofence_id"], depot["id"]);
+    assert_eq!(moved["flagged"], false);
+}
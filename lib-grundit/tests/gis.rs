@@ -0,0 +1,147 @@
+// Exporting punches as GeoJSON, GPX and KML, and importing GPX and GeoJSON.
+mod common;
+
+use common::{client, db_path, register, start};
+use oauth2::reqwest::{Response, StatusCode, header};
+use serde_json::Value;
+
+// 2025-01-01T00:00:00Z
+const START: i64 = 1_735_689_600;
+
+async fn import(base: &str, (cookies, csrf_token): &(String, String), body: String) -> Response {
+    client()
+        .post(format!("{}/data/punch/import", base))
+        .header(header::COOKIE, cookies)
+        .header("X-CSRF-Token", csrf_token)
+        .body(body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn export(base: &str, (cookies, _): &(String, String), query: &str) -> String {
+    let res = client()
+        .get(format!("{}/data/punch/export?{}", base, query))
+        .header(header::COOKIE, cookies)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    res.text().await.unwrap()
+}
+
+#[tokio::test]
+async fn round_trip() {
+    let base = start("gis", vec![]).await;
+    let session = register(&base, "walker").await;
+
+    // more than one page of export, and over the usual body limit
+    let count = 1200;
+    let mut gpx = String::from(r#"<?xml version="1.0"?><gpx version="1.1"><trk><trkseg>"#);
+    for i in 0..count {
+        gpx.push_str(&format!(
+            r#"<trkpt lat="{}" lon="-0.12"><ele>12.5</ele><time>2025-01-01T{:02}:{:02}:{:02}Z</time><type>{}</type></trkpt>"#,
+            51.5 + i as f64 * 0.0001,
+            i / 3600,
+            i / 60 % 60,
+            i % 60,
+            if i % 2 == 0 { "in" } else { "out" },
+        ));
+    }
+    gpx.push_str("</trkseg></trk></gpx>");
+    assert!(gpx.len() > 64 * 1024);
+    let res = import(&base, &session, gpx).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(res["imported"], count);
+
+    let geojson: Value = serde_json::from_str(&export(&base, &session, "").await).unwrap();
+    let features = geojson["features"].as_array().unwrap();
+    assert_eq!(features.len(), count);
+    let times: Vec<i64> = features
+        .iter()
+        .map(|f| f["properties"]["captured_at"].as_i64().unwrap())
+        .collect();
+    assert_eq!(
+        times,
+        (0..count as i64).map(|i| START + i).collect::<Vec<_>>()
+    );
+    assert_eq!(features[1]["properties"]["direction"], "out");
+    assert_eq!(features[1]["properties"]["time"], "2025-01-01T00:00:01Z");
+    assert_eq!(features[1]["geometry"]["coordinates"][2], 12.5);
+
+    // the time range filters apply
+    let gpx = export(
+        &base,
+        &session,
+        &format!(
+            "format=gpx&capturedAfter={}&capturedBefore={}",
+            START + 10,
+            START + 20
+        ),
+    )
+    .await;
+    assert_eq!(gpx.matches("<trkpt").count(), 10);
+    assert!(gpx.contains("<time>2025-01-01T00:00:10Z</time>"));
+    let kml = export(&base, &session, "format=kml&capturedBefore=1735689605").await;
+    assert_eq!(kml.matches("<Placemark").count(), 5);
+
+    // someone else's export doesn't include these
+    let other = register(&base, "sitter").await;
+    let geojson: Value = serde_json::from_str(&export(&base, &other, "").await).unwrap();
+    assert_eq!(geojson["features"], Value::Array(vec![]));
+}
+
+#[tokio::test]
+async fn invalid_points_import_nothing() {
+    let base = start("gis-invalid", vec![]).await;
+    let session = register(&base, "walker").await;
+
+    let body = r#"{"type":"FeatureCollection","features":[
+        {"type":"Feature","geometry":{"type":"Point","coordinates":[-0.12,51.5]},"properties":{"time":"2025-01-01T09:00:00Z"}},
+        {"type":"Feature","geometry":{"type":"Point","coordinates":[-0.12,100.0]},"properties":{"captured_at":1735722000}},
+        {"type":"Feature","geometry":{"type":"Point","coordinates":[-0.12,51.5]},"properties":{"direction":"sideways","captured_at":1735722000}},
+        {"type":"Feature","geometry":{"type":"Point","coordinates":[-0.12,51.5]},"properties":{}}
+    ]}"#;
+    let res = import(&base, &session, body.to_string()).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let res: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    let fields: Vec<&str> = res["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        fields,
+        vec![
+            "points[1].latitude",
+            "points[2].direction",
+            "points[3].captured_at"
+        ]
+    );
+    let geojson: Value = serde_json::from_str(&export(&base, &session, "").await).unwrap();
+    assert_eq!(geojson["features"], Value::Array(vec![]));
+
+    let res = import(&base, &session, "not a file".to_string()).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    // valid points the database refuses part way through
+    sqlite::open(db_path("gis-invalid"))
+        .unwrap()
+        .execute(
+            "CREATE TRIGGER refuse BEFORE INSERT ON punches WHEN new.altitude = 13 BEGIN
+                SELECT RAISE(ABORT, 'refused');
+            END",
+        )
+        .unwrap();
+    let body = r#"{"type":"FeatureCollection","features":[
+        {"type":"Feature","geometry":{"type":"Point","coordinates":[-0.12,51.5,12]},"properties":{"captured_at":1735722000}},
+        {"type":"Feature","geometry":{"type":"Point","coordinates":[-0.12,51.5,13]},"properties":{"captured_at":1735722060}},
+        {"type":"Feature","geometry":{"type":"Point","coordinates":[-0.12,51.5,14]},"properties":{"captured_at":1735722120}}
+    ]}"#;
+    let res = import(&base, &session, body.to_string()).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let geojson: Value = serde_json::from_str(&export(&base, &session, "").await).unwrap();
+    assert_eq!(geojson["features"], Value::Array(vec![]));
+}
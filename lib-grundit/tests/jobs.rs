@@ -0,0 +1,219 @@
+// The job queue: retries with backoff, dead jobs, recurring and scheduled
+// jobs, the concurrency limit and the admin listing.
+mod common;
+
+use std::{
+    env,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use common::{db_path, get, json, register, start_with};
+use futures_util::future::BoxFuture;
+use lib_grundit::{
+    AuthrState,
+    jobs::{self, Job},
+};
+use oauth2::reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use time::OffsetDateTime;
+
+static FLAKY_RUNS: AtomicUsize = AtomicUsize::new(0);
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+static LATER_RUNS: AtomicUsize = AtomicUsize::new(0);
+static SLOW_RUNNING: AtomicUsize = AtomicUsize::new(0);
+static SLOW_MOST: AtomicUsize = AtomicUsize::new(0);
+static SLOW_DONE: AtomicUsize = AtomicUsize::new(0);
+
+// fails the first time only
+#[derive(Serialize, Deserialize)]
+struct Flaky;
+
+impl Job for Flaky {
+    const KIND: &'static str = "flaky";
+
+    fn run(self, _: Arc<AuthrState>) -> BoxFuture<'static, Result<(), String>> {
+        Box::pin(async {
+            match FLAKY_RUNS.fetch_add(1, Ordering::SeqCst) {
+                0 => Err("not yet".to_string()),
+                _ => Ok(()),
+            }
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Doomed {
+    reason: String,
+}
+
+impl Job for Doomed {
+    const KIND: &'static str = "doomed";
+    const MAX_ATTEMPTS: i64 = 2;
+
+    fn run(self, _: Arc<AuthrState>) -> BoxFuture<'static, Result<(), String>> {
+        Box::pin(async move { Err(self.reason) })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Tick;
+
+impl Job for Tick {
+    const KIND: &'static str = "tick";
+
+    fn run(self, _: Arc<AuthrState>) -> BoxFuture<'static, Result<(), String>> {
+        Box::pin(async {
+            TICKS.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Later;
+
+impl Job for Later {
+    const KIND: &'static str = "later";
+
+    fn run(self, _: Arc<AuthrState>) -> BoxFuture<'static, Result<(), String>> {
+        Box::pin(async {
+            LATER_RUNS.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Slow;
+
+impl Job for Slow {
+    const KIND: &'static str = "slow";
+
+    fn run(self, _: Arc<AuthrState>) -> BoxFuture<'static, Result<(), String>> {
+        Box::pin(async {
+            let running = SLOW_RUNNING.fetch_add(1, Ordering::SeqCst) + 1;
+            SLOW_MOST.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            SLOW_RUNNING.fetch_sub(1, Ordering::SeqCst);
+            SLOW_DONE.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })
+    }
+}
+
+async fn until(what: &str, done: impl Fn() -> bool) {
+    for _ in 0..100 {
+        if done() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("{} never happened", what);
+}
+
+#[tokio::test]
+async fn jobs() {
+    // the only test in this binary, so nothing else reads the environment
+    unsafe {
+        env::set_var("JOB_RETRY_BASE_SECS", "1");
+        env::set_var("JOB_CONCURRENCY", "2");
+    }
+    let mut data = None;
+    let base = start_with("jobs", vec![], |state| {
+        state
+            .jobs
+            .register::<Flaky>()
+            .register::<Doomed>()
+            .register::<Later>()
+            .register::<Slow>()
+            .recurring("@every 1s", Tick);
+        data = Some(state.data.clone());
+    })
+    .await;
+    let data = data.unwrap();
+    sqlite::open(db_path("jobs"))
+        .unwrap()
+        .execute("INSERT INTO roles (user_id, role) VALUES (1, 'admin')")
+        .unwrap();
+    let admin = register(&base, "admin").await;
+    let bob = register(&base, "bob").await;
+
+    let res = get(&base, &bob, "/data/jobs").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = get(&base, &admin, "/data/jobs?status=sleeping").await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    // tried again after a second, then gone
+    let flaky = jobs::enqueue(&data, &Flaky).unwrap();
+    until("a failed attempt", || {
+        FLAKY_RUNS.load(Ordering::SeqCst) == 1
+    })
+    .await;
+    let failed = get(&base, &admin, "/data/jobs?status=failed").await;
+    let failed = json(failed, StatusCode::OK).await;
+    let failed = &failed.as_array().unwrap()[0];
+    assert_eq!(failed["id"], flaky);
+    assert_eq!(failed["attempts"], 1);
+    assert_eq!(failed["last_error"], "not yet");
+    until("a retry", || FLAKY_RUNS.load(Ordering::SeqCst) == 2).await;
+
+    let doomed = Doomed {
+        reason: "no good".to_string(),
+    };
+    let doomed = jobs::enqueue(&data, &doomed).unwrap();
+    let later_at = OffsetDateTime::now_utc().unix_timestamp() + 2;
+    jobs::enqueue_at(&data, &Later, later_at).unwrap();
+    for _ in 0..4 {
+        jobs::enqueue(&data, &Slow).unwrap();
+    }
+
+    until("all the slow jobs", || {
+        SLOW_DONE.load(Ordering::SeqCst) == 4
+    })
+    .await;
+    assert_eq!(SLOW_MOST.load(Ordering::SeqCst), 2);
+    until("the scheduled job", || {
+        LATER_RUNS.load(Ordering::SeqCst) == 1
+    })
+    .await;
+    assert!(OffsetDateTime::now_utc().unix_timestamp() >= later_at);
+    until("a few ticks", || TICKS.load(Ordering::SeqCst) >= 2).await;
+
+    // out of attempts after two
+    let mut dead = Value::Null;
+    for _ in 0..50 {
+        dead = json(
+            get(&base, &admin, "/data/jobs?status=dead").await,
+            StatusCode::OK,
+        )
+        .await;
+        if !dead.as_array().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let dead = &dead.as_array().unwrap()[0];
+    assert_eq!(dead["id"], doomed);
+    assert_eq!(dead["kind"], "doomed");
+    assert_eq!(dead["attempts"], 2);
+    assert_eq!(dead["last_error"], "no good");
+
+    // finished one-off jobs are gone, the recurring ones wait for next time
+    let all = json(get(&base, &admin, "/data/jobs").await, StatusCode::OK).await;
+    let kinds: Vec<&str> = all
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|job| job["kind"].as_str().unwrap())
+        .collect();
+    assert!(kinds.contains(&"tick"));
+    assert!(kinds.contains(&"sweep_sessions"));
+    assert!(!kinds.contains(&"flaky"));
+    assert!(!kinds.contains(&"slow"));
+    assert!(!kinds.contains(&"later"));
+}
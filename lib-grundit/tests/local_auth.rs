@@ -0,0 +1,243 @@
+// Local accounts: registering, signing in, the login throttle, changing a
+// password and redeeming reset codes, either of which signs the user out
+// everywhere else.
+mod common;
+
+use std::collections::HashMap;
+
+use common::{client, db_path, register, start, start_with};
+use lib_glonk::store::SqliteStore;
+use lib_grundit::{
+    auth::local_auth::issue_reset_code,
+    ratelimit::{MemoryStore, RateLimiter},
+};
+use oauth2::reqwest::{Response, StatusCode, header};
+use serde_json::Value;
+
+async fn post_form(base: &str, path: &str, form: &str) -> Response {
+    client()
+        .post(format!("{}{}", base, path))
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(form.to_string())
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn login(base: &str, username: &str, password: &str) -> StatusCode {
+    let form = format!("username={}&password={}", username, password);
+    post_form(base, "/auth/local/login", &form).await.status()
+}
+
+// the session cookies a login sets
+async fn sign_in(base: &str, username: &str, password: &str) -> String {
+    let form = format!("username={}&password={}", username, password);
+    let res = post_form(base, "/auth/local/login", &form).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    res.headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|c| c.to_str().unwrap().split(';').next().unwrap())
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// an API token made from `session` that can read /data/whoami
+async fn api_token(base: &str, (cookies, csrf_token): &(String, String)) -> String {
+    let res = client()
+        .post(format!("{}/auth/tokens", base))
+        .header(header::COOKIE, cookies)
+        .header("X-CSRF-Token", csrf_token)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(r#"{"name":"script","scopes":["user:read"]}"#)
+        .send()
+        .await
+        .unwrap();
+    let created: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    created["token"].as_str().unwrap().to_string()
+}
+
+// whoami with a session cookie, or a token
+async fn whoami(base: &str, cookies: &str, token: Option<&str>) -> StatusCode {
+    let mut req = client()
+        .get(format!("{}/data/whoami", base))
+        .header(header::COOKIE, cookies);
+    if let Some(token) = token {
+        req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    req.send().await.unwrap().status()
+}
+
+#[tokio::test]
+async fn register_and_login() {
+    let base = start("local-register", vec![]).await;
+    register(&base, "alice").await;
+
+    for (form, status) in [
+        (
+            "username=alice&password=correct-horse",
+            StatusCode::CONFLICT,
+        ),
+        (
+            "username=a!&password=correct-horse",
+            StatusCode::BAD_REQUEST,
+        ),
+        ("username=bob&password=short", StatusCode::BAD_REQUEST),
+        (
+            "username=bob&password=correct-horse&email=not-an-email",
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ),
+    ] {
+        let res = post_form(&base, "/auth/local/register", form).await;
+        assert_eq!(res.status(), status, "{}", form);
+    }
+    // nothing was made for the refused email
+    assert_eq!(
+        login(&base, "bob", "correct-horse").await,
+        StatusCode::FORBIDDEN
+    );
+
+    let form = "username=bob&password=correct-horse&email=bob%40example.com";
+    let res = post_form(&base, "/auth/local/register", form).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+
+    // usernames don't care about case
+    assert_eq!(
+        login(&base, "ALICE", "correct-horse").await,
+        StatusCode::SEE_OTHER
+    );
+    assert_eq!(
+        login(&base, "alice", "wrong-horse").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        login(&base, "nobody", "correct-horse").await,
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn account_throttle() {
+    let base = start("local-account-throttle", vec![]).await;
+    register(&base, "alice").await;
+    register(&base, "bob").await;
+
+    for _ in 0..5 {
+        assert_eq!(
+            login(&base, "alice", "wrong-horse").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+    // even with the right password
+    assert_eq!(
+        login(&base, "alice", "correct-horse").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    // only for that account
+    assert_eq!(
+        login(&base, "bob", "correct-horse").await,
+        StatusCode::SEE_OTHER
+    );
+}
+
+#[tokio::test]
+async fn address_throttle() {
+    // the auth rate limit would step in first
+    let base = start_with("local-address-throttle", vec![], |state| {
+        state.set_rate_limiter(RateLimiter::new(
+            Box::new(MemoryStore::default()),
+            HashMap::new(),
+        ));
+    })
+    .await;
+    register(&base, "alice").await;
+
+    for i in 0..20 {
+        let username = format!("nobody{}", i);
+        assert_eq!(
+            login(&base, &username, "wrong-horse").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+    assert_eq!(
+        login(&base, "alice", "correct-horse").await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[tokio::test]
+async fn change_password() {
+    let base = start("local-change-password", vec![]).await;
+    let session = register(&base, "alice").await;
+    let other = sign_in(&base, "alice", "correct-horse").await;
+    let token = api_token(&base, &session).await;
+    let (cookies, csrf_token) = &session;
+    let change = |form: &'static str| {
+        client()
+            .post(format!("{}/auth/password", base))
+            .header(header::COOKIE, cookies)
+            .header("X-CSRF-Token", csrf_token)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form)
+            .send()
+    };
+
+    let res = change("current_password=wrong-horse&new_password=battery-staple")
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = change("current_password=correct-horse&new_password=short")
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let res = change("current_password=correct-horse&new_password=battery-staple")
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+
+    // signed out everywhere but here
+    assert_eq!(whoami(&base, cookies, None).await, StatusCode::OK);
+    assert_eq!(whoami(&base, &other, None).await, StatusCode::FORBIDDEN);
+    assert_eq!(whoami(&base, "", Some(&token)).await, StatusCode::FORBIDDEN);
+
+    assert_eq!(
+        login(&base, "alice", "correct-horse").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        login(&base, "alice", "battery-staple").await,
+        StatusCode::SEE_OTHER
+    );
+}
+
+#[tokio::test]
+async fn reset_code() {
+    let name = "local-reset";
+    let base = start(name, vec![]).await;
+    let session = register(&base, "alice").await;
+    let token = api_token(&base, &session).await;
+    let store = SqliteStore::open(db_path(name));
+    assert!(issue_reset_code(&store, "nobody").is_err());
+    let code = issue_reset_code(&store, "alice").unwrap();
+
+    let reset = |code: &str| format!("username=alice&code={}&new_password=battery-staple", code);
+    let res = post_form(&base, "/auth/local/reset", &reset("not-the-code")).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let res = post_form(&base, "/auth/local/reset", &reset(&code)).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    // and signed out everywhere
+    assert_eq!(whoami(&base, &session.0, None).await, StatusCode::FORBIDDEN);
+    assert_eq!(whoami(&base, "", Some(&token)).await, StatusCode::FORBIDDEN);
+    // once only
+    let res = post_form(&base, "/auth/local/reset", &reset(&code)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    assert_eq!(
+        login(&base, "alice", "correct-horse").await,
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        login(&base, "alice", "battery-staple").await,
+        StatusCode::SEE_OTHER
+    );
+}
@@ -0,0 +1,88 @@
+// Notes rendered from CommonMark to sanitized HTML, cached by version.
+mod common;
+
+use common::{client, register, start};
+use lib_grundit::markdown::render;
+use oauth2::reqwest::{Response, StatusCode, header};
+use serde_json::Value;
+
+#[test]
+fn sanitizes() {
+    let html = render(concat!(
+        "# Plan\n\n",
+        "## Plan\n\n",
+        "## `cargo` & *tests*\n\n",
+        "[docs](https://example.com) [bad](javascript:alert(1))\n\n",
+        "<script>alert(1)</script><img src=\"x.png\" onerror=\"alert(1)\">\n\n",
+        "| a |\n|---|\n| b |\n",
+    ));
+    assert!(html.contains(r#"<h1 id="plan">Plan</h1>"#), "{}", html);
+    assert!(html.contains(r#"<h2 id="plan-1">Plan</h2>"#), "{}", html);
+    assert!(html.contains(r#"<h2 id="cargo-tests">"#), "{}", html);
+    assert!(
+        html.contains(
+            r#"<a href="https://example.com" rel="nofollow noopener noreferrer">docs</a>"#
+        ),
+        "{}",
+        html
+    );
+    assert!(!html.contains("javascript"), "{}", html);
+    assert!(!html.contains("<script"), "{}", html);
+    assert!(!html.contains("onerror"), "{}", html);
+    assert!(html.contains("<td>b</td>"), "{}", html);
+}
+
+async fn rendered(base: &str, cookies: &str, etag: Option<&str>) -> Response {
+    let mut req = client()
+        .get(format!("{}/data/note/1/rendered", base))
+        .header(header::COOKIE, cookies);
+    if let Some(etag) = etag {
+        req = req.header(header::IF_NONE_MATCH, etag);
+    }
+    req.send().await.unwrap()
+}
+
+#[tokio::test]
+async fn versions() {
+    let base = start("markdown", vec![]).await;
+    let (cookies, csrf_token) = register(&base, "writer").await;
+    let send = |put: bool, body: &'static str| {
+        let url = format!("{}/data/note", base);
+        let req = if put {
+            client().put(url)
+        } else {
+            client().post(url)
+        };
+        req.header(header::COOKIE, &cookies)
+            .header("X-CSRF-Token", &csrf_token)
+            .body(body)
+            .send()
+    };
+
+    let res = send(false, r#"{"owner_id":1,"contents":"*first*"}"#)
+        .await
+        .unwrap();
+    let note: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(note["version"], 1);
+
+    let res = rendered(&base, &cookies, None).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+    assert_eq!(res.text().await.unwrap(), "<p><em>first</em></p>\n");
+    let res = rendered(&base, &cookies, Some(&etag)).await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+    let res = send(true, r#"{"id":1,"owner_id":1,"contents":"**second**"}"#)
+        .await
+        .unwrap();
+    let note: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(note["version"], 2);
+
+    // the old ETag no longer matches and the cache moves on
+    let res = rendered(&base, &cookies, Some(&etag)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.text().await.unwrap(),
+        "<p><strong>second</strong></p>\n"
+    );
+}
@@ -0,0 +1,168 @@
+// Notifications from comments: the note's owner and `@name` mentions, the
+// unread filter, and marking them read.
+mod common;
+
+use common::{get, json, register, send, start};
+use oauth2::reqwest::StatusCode;
+use serde_json::Value;
+
+async fn comment(base: &str, session: &(String, String), owner_id: i64, contents: &str) {
+    let body = format!(
+        r#"{{"owner_id":{},"note_id":1,"contents":"{}"}}"#,
+        owner_id, contents
+    );
+    let res = send(base, session, "post", "/data/comment", &body).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+fn kinds(notifications: &Value) -> Vec<(String, i64)> {
+    notifications
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|n| {
+            (
+                n["kind"].as_str().unwrap().to_string(),
+                n["actor_id"].as_i64().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn notifications() {
+    let base = start("notifications", vec![]).await;
+    let alice = register(&base, "alice").await;
+    let bob = register(&base, "bob").await;
+    let carol = register(&base, "carol").await;
+
+    let res = send(
+        &base,
+        &alice,
+        "post",
+        "/data/note",
+        r#"{"owner_id":1,"contents":"hi"}"#,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // nothing for your own comment, even naming yourself
+    comment(&base, &alice, 1, "@alice first").await;
+    assert!(
+        kinds(
+            &json(
+                get(&base, &alice, "/data/notification").await,
+                StatusCode::OK
+            )
+            .await
+        )
+        .is_empty()
+    );
+
+    // the owner hears about bob's comment, carol about being named in it
+    comment(&base, &bob, 2, "hey @CAROL, and @nobody@example").await;
+    assert_eq!(
+        kinds(
+            &json(
+                get(&base, &alice, "/data/notification").await,
+                StatusCode::OK
+            )
+            .await
+        ),
+        vec![("comment".to_string(), 2)]
+    );
+    assert_eq!(
+        kinds(
+            &json(
+                get(&base, &carol, "/data/notification").await,
+                StatusCode::OK
+            )
+            .await
+        ),
+        vec![("mention".to_string(), 2)]
+    );
+    assert!(
+        kinds(&json(get(&base, &bob, "/data/notification").await, StatusCode::OK).await).is_empty()
+    );
+
+    // a mention of the owner stands in for the comment one
+    comment(&base, &carol, 3, "@alice look").await;
+    let unread = json(
+        get(&base, &alice, "/data/notification?unread=true").await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(
+        kinds(&unread),
+        vec![("mention".to_string(), 3), ("comment".to_string(), 2)]
+    );
+
+    // only the owner sees or marks a notification
+    let id = unread[0]["id"].as_i64().unwrap();
+    let res = get(&base, &bob, &format!("/data/notification/{}", id)).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let path = format!("/data/notification/{}/read", id);
+    assert_eq!(
+        send(&base, &bob, "post", &path, "").await.status(),
+        StatusCode::NOT_FOUND
+    );
+    let res = send(&base, &alice, "post", &path, "").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let read: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(read["read"], Value::Bool(true));
+    assert_eq!(
+        kinds(
+            &json(
+                get(&base, &alice, "/data/notification?unread=true").await,
+                StatusCode::OK
+            )
+            .await
+        ),
+        vec![("comment".to_string(), 2)]
+    );
+
+    let res = send(&base, &alice, "post", "/data/notification/read-all", "").await;
+    let marked: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(marked["marked"], 1);
+    assert!(
+        kinds(
+            &json(
+                get(&base, &alice, "/data/notification?unread=true").await,
+                StatusCode::OK
+            )
+            .await
+        )
+        .is_empty()
+    );
+    assert_eq!(
+        kinds(
+            &json(
+                get(&base, &alice, "/data/notification?unread=false").await,
+                StatusCode::OK
+            )
+            .await
+        )
+        .len(),
+        2
+    );
+    assert_eq!(
+        kinds(
+            &json(
+                get(&base, &carol, "/data/notification?unread=true").await,
+                StatusCode::OK
+            )
+            .await
+        )
+        .len(),
+        1
+    );
+
+    // they can't be made by hand
+    let body = r#"{"owner_id":1,"kind":"comment","actor_id":2,"note_id":1,"comment_id":1}"#;
+    assert_eq!(
+        send(&base, &alice, "post", "/data/notification", body)
+            .await
+            .status(),
+        StatusCode::FORBIDDEN
+    );
+}
@@ -0,0 +1,355 @@
+// The provider callback against a stub token and userinfo server. Every
+// failure has to end in a refused login, never a panicked handler, and a
+// login or link can only be finished by the browser and user that began it.
+// Discovery has to be for the issuer that was configured.
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::StatusCode as StubStatus,
+    routing::{get, post},
+};
+use common::{client, register, start};
+use lib_grundit::auth::oidc_auth::OidcAuthClient;
+use oauth2::{
+    reqwest::{Response, StatusCode, header},
+    url::Url,
+};
+use serde_json::{Value, json};
+use tokio::net::TcpListener;
+
+#[derive(Default)]
+struct Stub {
+    fail_token: bool,
+    fail_userinfo: bool,
+    unverified_email: bool,
+}
+
+type StubState = Arc<Mutex<Stub>>;
+
+// starts the stub provider and a server that knows it as `stub`
+async fn start_with_stub(name: &str) -> (String, StubState) {
+    let stub = StubState::default();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+    let discovery = json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "userinfo_endpoint": format!("{}/userinfo", issuer),
+    });
+    let app = Router::new()
+        .route(
+            "/.well-known/openid-configuration",
+            get(move || async move { Json(discovery) }),
+        )
+        .route("/token", post(token))
+        .route("/userinfo", get(userinfo))
+        .with_state(stub.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let provider = OidcAuthClient::discover(
+        "stub".to_string(),
+        &issuer,
+        "client".to_string(),
+        "secret".to_string(),
+    )
+    .await
+    .unwrap();
+    (start(name, vec![Arc::new(provider)]).await, stub)
+}
+
+async fn token(State(stub): State<StubState>) -> (StubStatus, Json<Value>) {
+    if stub.lock().unwrap().fail_token {
+        return (
+            StubStatus::BAD_REQUEST,
+            Json(json!({ "error": "invalid_grant" })),
+        );
+    }
+    (
+        StubStatus::OK,
+        Json(json!({ "access_token": "stub-token", "token_type": "bearer" })),
+    )
+}
+
+async fn userinfo(State(stub): State<StubState>) -> (StubStatus, Json<Value>) {
+    let stub = stub.lock().unwrap();
+    if stub.fail_userinfo {
+        return (StubStatus::INTERNAL_SERVER_ERROR, Json(json!({})));
+    }
+    (
+        StubStatus::OK,
+        Json(json!({
+            "sub": "stub-user",
+            "email": "stub@example.com",
+            "email_verified": !stub.unverified_email,
+        })),
+    )
+}
+
+async fn fetch(base: &str, path: &str) -> Response {
+    client()
+        .get(format!("{}{}", base, path))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn location(res: &Response) -> String {
+    res.headers()[header::LOCATION]
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+// a login started in a browser
+struct Login {
+    // what the provider would hand back
+    state: String,
+    // the browser's, with the login's nonce
+    cookies: String,
+}
+
+// starts a login at `path` from a browser with `cookies`
+async fn start_login(base: &str, path: &str, cookies: &str) -> Login {
+    let res = client()
+        .get(format!("{}{}", base, path))
+        .header(header::COOKIE, cookies)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::TEMPORARY_REDIRECT);
+    let state = Url::parse(&location(&res))
+        .unwrap()
+        .query_pairs()
+        .find(|(k, _)| k == "state")
+        .map(|(_, v)| v.to_string())
+        .unwrap();
+    let nonce = res
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|c| c.to_str().unwrap().split(';').next().unwrap())
+        .find(|c| c.starts_with("oauth_login="))
+        .unwrap()
+        .to_string();
+    let cookies = match cookies {
+        "" => nonce,
+        cookies => format!("{}; {}", cookies, nonce),
+    };
+    Login { state, cookies }
+}
+
+async fn login_state(base: &str, query: &str) -> Login {
+    start_login(base, &format!("/auth/stub/login{}", query), "").await
+}
+
+// the provider's redirect back, followed by a browser with `cookies`
+async fn finish(base: &str, state: &str, cookies: &str) -> Response {
+    client()
+        .get(format!(
+            "{}/auth/stub/callback?code=stub-code&state={}",
+            base, state
+        ))
+        .header(header::COOKIE, cookies)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn callback(base: &str, login: &Login) -> Response {
+    finish(base, &login.state, &login.cookies).await
+}
+
+async fn whoami(base: &str, cookies: &str) -> Value {
+    let res = client()
+        .get(format!("{}/data/whoami", base))
+        .header(header::COOKIE, cookies)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    serde_json::from_str(&res.text().await.unwrap()).unwrap()
+}
+
+fn session_cookies(res: &Response) -> String {
+    res.headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|c| c.to_str().unwrap().split(';').next().unwrap())
+        .filter(|c| !c.starts_with("oauth_login="))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[tokio::test]
+async fn login_lands_on_return_to() {
+    let (base, _) = start_with_stub("callback-ok").await;
+    let state = login_state(&base, "?return_to=/web/notes%3Fid%3D1").await;
+
+    let res = callback(&base, &state).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(location(&res), "/web/notes?id=1");
+    assert!(
+        res.headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .any(|c| c.to_str().unwrap().starts_with("session_id="))
+    );
+}
+
+#[tokio::test]
+async fn foreign_return_to_is_refused() {
+    let (base, _) = start_with_stub("callback-return-to").await;
+    for return_to in ["//evil.test/", "https://evil.test/", "/\\evil.test", "web"] {
+        let res = fetch(&base, &format!("/auth/stub/login?return_to={}", return_to)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", return_to);
+    }
+}
+
+#[tokio::test]
+async fn state_is_single_use() {
+    let (base, _) = start_with_stub("callback-replay").await;
+    let state = login_state(&base, "").await;
+
+    assert_eq!(
+        callback(&base, &state).await.status(),
+        StatusCode::SEE_OTHER
+    );
+    assert_eq!(
+        callback(&base, &state).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        finish(&base, "never-issued", &state.cookies).await.status(),
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn provider_errors_refuse_login() {
+    let (base, stub) = start_with_stub("callback-errors").await;
+
+    let state = login_state(&base, "").await;
+    let res = fetch(
+        &base,
+        &format!(
+            "/auth/stub/callback?error=access_denied&state={}",
+            state.state
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    stub.lock().unwrap().fail_token = true;
+    let state = login_state(&base, "").await;
+    assert_eq!(
+        callback(&base, &state).await.status(),
+        StatusCode::FORBIDDEN
+    );
+
+    *stub.lock().unwrap() = Stub {
+        fail_userinfo: true,
+        ..Stub::default()
+    };
+    let state = login_state(&base, "").await;
+    assert_eq!(
+        callback(&base, &state).await.status(),
+        StatusCode::FORBIDDEN
+    );
+
+    // the server is still up and the flow works once the provider recovers
+    *stub.lock().unwrap() = Stub::default();
+    let state = login_state(&base, "").await;
+    assert_eq!(
+        callback(&base, &state).await.status(),
+        StatusCode::SEE_OTHER
+    );
+}
+
+#[tokio::test]
+async fn unverified_email_is_refused() {
+    let (base, stub) = start_with_stub("callback-unverified").await;
+    stub.lock().unwrap().unverified_email = true;
+
+    let state = login_state(&base, "").await;
+    assert_eq!(
+        callback(&base, &state).await.status(),
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn login_is_finished_by_the_browser_that_started_it() {
+    let (base, _) = start_with_stub("callback-browser").await;
+
+    let login = login_state(&base, "").await;
+    assert_eq!(
+        finish(&base, &login.state, "").await.status(),
+        StatusCode::FORBIDDEN
+    );
+    let login = login_state(&base, "").await;
+    let other = login_state(&base, "").await;
+    assert_eq!(
+        finish(&base, &login.state, &other.cookies).await.status(),
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn link_is_finished_by_the_user_linking() {
+    let (base, _) = start_with_stub("callback-link").await;
+    let (alice, _) = register(&base, "alice").await;
+    let (bob, _) = register(&base, "bob").await;
+    let alice_id = whoami(&base, &alice).await["id"].clone();
+
+    // alice's link, finished by bob without her browser's nonce
+    let link = start_login(&base, "/auth/link/stub", &alice).await;
+    assert_eq!(
+        finish(&base, &link.state, &bob).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    // or even with it, signed in as himself
+    let link = start_login(&base, "/auth/link/stub", &alice).await;
+    let cookies = link.cookies.replace(&alice, &bob);
+    assert_eq!(
+        finish(&base, &link.state, &cookies).await.status(),
+        StatusCode::FORBIDDEN
+    );
+
+    // alice finishing her own link signs her back in as herself
+    let link = start_login(&base, "/auth/link/stub", &alice).await;
+    let res = callback(&base, &link).await;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert_eq!(whoami(&base, &session_cookies(&res)).await["id"], alice_id);
+}
+
+#[tokio::test]
+async fn discovery_for_another_issuer_is_refused() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+    let discovery = json!({
+        "issuer": "https://elsewhere.example",
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "userinfo_endpoint": format!("{}/userinfo", issuer),
+    });
+    let app = Router::new().route(
+        "/.well-known/openid-configuration",
+        get(move || async move { Json(discovery) }),
+    );
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let discovered = OidcAuthClient::discover(
+        "stub".to_string(),
+        &issuer,
+        "client".to_string(),
+        "secret".to_string(),
+    )
+    .await;
+    assert!(discovered.is_err());
+}
@@ -0,0 +1,98 @@
+// Rate limits: a 429 with RateLimit headers once a group's bucket is empty,
+// static files left alone, and buckets shared through a sqlite file.
+mod common;
+
+use std::{collections::HashMap, env, process};
+
+use common::{client, register, start_with};
+use lib_grundit::ratelimit::{
+    Limit, MemoryStore, RateLimitStore, RateLimiter, RouteGroup, SqliteRateLimitStore,
+};
+use oauth2::reqwest::{Response, StatusCode, header};
+
+fn limit(capacity: u32) -> Limit {
+    Limit {
+        capacity,
+        period: 60.0,
+    }
+}
+
+fn header(res: &Response, name: &str) -> Option<u64> {
+    res.headers()
+        .get(name)
+        .map(|val| val.to_str().unwrap().parse().unwrap())
+}
+
+#[tokio::test]
+async fn limited() {
+    let base = start_with("ratelimit", vec![], |state| {
+        state.set_rate_limiter(RateLimiter::new(
+            Box::new(MemoryStore::default()),
+            HashMap::from([
+                (RouteGroup::Auth, limit(3)),
+                (RouteGroup::DataRead, limit(2)),
+            ]),
+        ));
+    })
+    .await;
+    let (cookies, _) = register(&base, "alice").await;
+    let get = |path: &str| {
+        client()
+            .get(format!("{}{}", base, path))
+            .header(header::COOKIE, &cookies)
+            .send()
+    };
+
+    for remaining in [1, 0] {
+        let res = get("/data/note").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(&res, "ratelimit-limit"), Some(2));
+        assert_eq!(header(&res, "ratelimit-remaining"), Some(remaining));
+        assert!(header(&res, "retry-after").is_none());
+    }
+    let res = get("/data/note").await.unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(header(&res, "ratelimit-remaining"), Some(0));
+    assert!(header(&res, "ratelimit-reset").unwrap() > 0);
+    assert!(header(&res, "retry-after").unwrap() > 0);
+
+    // loading a page doesn't touch the auth bucket
+    for _ in 0..10 {
+        let res = get("/web/index.html").await.unwrap();
+        assert_ne!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(header(&res, "ratelimit-limit").is_none());
+    }
+    let login = || {
+        client()
+            .post(format!("{}/auth/local/login", base))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body("username=alice&password=correct-horse")
+            .send()
+    };
+    assert_eq!(login().await.unwrap().status(), StatusCode::SEE_OTHER);
+    assert_eq!(login().await.unwrap().status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        login().await.unwrap().status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[test]
+fn sqlite_store_shared() {
+    let path = env::temp_dir().join(format!("grundit-ratelimit-{}.db", process::id()));
+    let _ = std::fs::remove_file(&path);
+    let path = path.to_str().unwrap();
+    // as two instances would have them
+    let first = SqliteRateLimitStore::open(path);
+    let second = SqliteRateLimitStore::open(path);
+
+    let taken = first.take("user:1", limit(2));
+    assert!(taken.allowed);
+    assert_eq!(taken.remaining, 1);
+    assert!(second.take("user:1", limit(2)).allowed);
+    let refused = first.take("user:1", limit(2));
+    assert!(!refused.allowed);
+    assert!(refused.retry_after > 0);
+    // other keys have their own
+    assert!(second.take("user:2", limit(2)).allowed);
+}
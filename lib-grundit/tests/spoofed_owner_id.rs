@@ -0,0 +1,64 @@
+// The signed in user comes from the session, never from a client header.
+mod common;
+
+use common::{client, register, start};
+use oauth2::reqwest::{StatusCode, header};
+use serde_json::Value;
+
+#[tokio::test]
+async fn owner_id_header_alone_is_not_authenticated() {
+    let base = start("anonymous", vec![]).await;
+    register(&base, "alice").await;
+
+    let res = client()
+        .get(format!("{}/data/whoami", base))
+        .header("Owner-Id", "1")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn owner_id_header_does_not_override_session() {
+    let base = start("override", vec![]).await;
+    register(&base, "alice").await;
+    let (mallory, csrf_token) = register(&base, "mallory").await;
+
+    let whoami: Value = client()
+        .get(format!("{}/data/whoami", base))
+        .header(header::COOKIE, &mallory)
+        .header("Owner-Id", "1")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .map(|body| serde_json::from_str(&body).unwrap())
+        .unwrap();
+    assert_eq!(whoami["guid"], "local/mallory");
+
+    // writing as alice is still rejected
+    let res = client()
+        .post(format!("{}/data/note", base))
+        .header(header::COOKIE, &mallory)
+        .header("Owner-Id", "1")
+        .header("X-CSRF-Token", &csrf_token)
+        .body(r#"{"owner_id":1,"contents":"not mine"}"#)
+        .send()
+        .await
+        .unwrap();
+    assert!(!res.status().is_success());
+
+    let notes: Value = client()
+        .get(format!("{}/data/note?byOwnerId=1", base))
+        .header(header::COOKIE, &mallory)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .map(|body| serde_json::from_str(&body).unwrap())
+        .unwrap();
+    assert_eq!(notes, Value::Array(vec![]));
+}
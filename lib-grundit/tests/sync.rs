@@ -0,0 +1,173 @@
+// Offline sync: creates made once per idempotency key, stale note versions
+// refused, catching up through /data/changes, and the change log pruned.
+mod common;
+
+use std::time::Duration;
+
+use common::{db_path, get, json, register, request, send, start, start_with};
+use futures_util::future::join_all;
+use lib_grundit::sync::PruneChangeLog;
+use oauth2::reqwest::{Response, StatusCode};
+use serde_json::{Value, json};
+
+// a create with an idempotency key, as the outbox sends them
+async fn create(
+    base: &str,
+    session: &(String, String),
+    path: &str,
+    key: &str,
+    body: &str,
+) -> Response {
+    request(base, session, "post", path)
+        .header("Idempotency-Key", key)
+        .body(body.to_string())
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn sync() {
+    let base = start("sync", vec![]).await;
+    let alice = register(&base, "alice").await;
+    let bob = register(&base, "bob").await;
+
+    // sent twice, made once
+    let body = r#"{"owner_id":1,"contents":"offline"}"#;
+    let res = create(&base, &alice, "/data/note", "k1", body).await;
+    assert!(res.headers().get("idempotent-replayed").is_none());
+    let note = json(res, StatusCode::OK).await;
+    let res = create(&base, &alice, "/data/note", "k1", body).await;
+    assert_eq!(res.headers()["idempotent-replayed"], "true");
+    assert_eq!(json(res, StatusCode::OK).await, note);
+    let notes = json(get(&base, &alice, "/data/note").await, StatusCode::OK).await;
+    assert_eq!(notes.as_array().unwrap().len(), 1);
+
+    // and sent together, still made once
+    let body = r#"{"owner_id":1,"contents":"together"}"#;
+    let sent = join_all([0, 1].map(|_| create(&base, &alice, "/data/note", "k3", body))).await;
+    let made: Vec<Value> = join_all(sent.into_iter().map(|res| json(res, StatusCode::OK))).await;
+    assert_eq!(made[0]["id"], made[1]["id"]);
+
+    // but not for something else
+    let other = r#"{"owner_id":1,"contents":"else"}"#;
+    let res = create(&base, &alice, "/data/note", "k1", other).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    // keys are per user
+    let body = r#"{"owner_id":2,"contents":"offline"}"#;
+    let res = create(&base, &bob, "/data/note", "k1", body).await;
+    assert_eq!(json(res, StatusCode::OK).await["owner_id"], 2);
+    // failures aren't kept
+    let res = create(&base, &alice, "/data/note", "k2", "{}").await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = r#"{"owner_id":1,"contents":"fixed"}"#;
+    let res = create(&base, &alice, "/data/note", "k2", body).await;
+    json(res, StatusCode::OK).await;
+
+    // edits to a version the note has moved on from
+    let id = note["id"].as_i64().unwrap();
+    let body = json!({ "id": id, "owner_id": 1, "contents": "first", "version": 1 });
+    let res = send(&base, &alice, "put", "/data/note", &body.to_string()).await;
+    assert_eq!(json(res, StatusCode::OK).await["version"], 2);
+    let body = json!({ "id": id, "owner_id": 1, "contents": "second", "version": 1 });
+    let res = send(&base, &alice, "put", "/data/note", &body.to_string()).await;
+    let current = json(res, StatusCode::CONFLICT).await;
+    assert_eq!(current["contents"], "first");
+    assert_eq!(current["version"], 2);
+    let body = json!({ "id": id, "owner_id": 1, "contents": "second", "version": 2 });
+    let res = send(&base, &alice, "put", "/data/note", &body.to_string()).await;
+    assert_eq!(json(res, StatusCode::OK).await["version"], 3);
+
+    // catching up
+    let set = json(
+        get(&base, &alice, "/data/changes?since=0").await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(set["more"], false);
+    let changes = set["changes"].as_array().unwrap();
+    let notes: Vec<&Value> = changes
+        .iter()
+        .filter(|c| c["data_type"] == "note")
+        .collect();
+    // rows come as they are now
+    assert_eq!(notes[0]["event"], "created");
+    assert_eq!(notes[0]["data"]["contents"], "second");
+    assert!(notes.iter().any(|c| c["event"] == "updated"));
+    assert!(changes.iter().all(|c| c["data_type"] != "idempotency_key"));
+
+    let next = set["next"].as_i64().unwrap();
+    send(&base, &alice, "delete", &format!("/data/note/{}", id), "").await;
+    let path = format!("/data/changes?since={}&types=note", next);
+    let set = json(get(&base, &alice, &path).await, StatusCode::OK).await;
+    let changes = set["changes"].as_array().unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0]["event"], "deleted");
+    assert_eq!(changes[0]["id"], id);
+    let next = set["next"].as_i64().unwrap();
+    assert!(changes[0]["seq"].as_i64().unwrap() <= next);
+    let path = format!("/data/changes?since={}&types=note", next);
+    let set = json(get(&base, &alice, &path).await, StatusCode::OK).await;
+    assert_eq!(set["changes"], json!([]));
+    assert!(set["next"].as_i64().unwrap() >= next);
+
+    let res = get(&base, &alice, "/data/changes?types=nope").await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn change_log() {
+    let base = start_with("sync-log", vec![], |state| {
+        state
+            .jobs
+            .recurring("@every 1s", PruneChangeLog { keep: 2 });
+    })
+    .await;
+    let alice = register(&base, "alice").await;
+    for contents in ["a", "b", "c"] {
+        let body = json!({ "owner_id": 1, "contents": contents }).to_string();
+        json(
+            send(&base, &alice, "post", "/data/note", &body).await,
+            StatusCode::OK,
+        )
+        .await;
+    }
+
+    // too far behind once it's pruned, so everything comes again
+    let mut set = Value::Null;
+    for _ in 0..50 {
+        let res = get(&base, &alice, "/data/changes?since=0&types=note").await;
+        set = json(res, StatusCode::OK).await;
+        if set["reset"] == true {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(set["reset"], true);
+    let contents: Vec<&Value> = set["changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| &c["data"]["contents"])
+        .collect();
+    assert_eq!(contents, vec!["a", "b", "c"]);
+    let path = format!("/data/changes?since={}&types=note", set["next"]);
+    let set = json(get(&base, &alice, &path).await, StatusCode::OK).await;
+    assert_eq!(set["reset"], false);
+    assert_eq!(set["changes"], json!([]));
+
+    // a change that can't be logged isn't made
+    sqlite::open(db_path("sync-log"))
+        .unwrap()
+        .execute(
+            "CREATE TRIGGER refuse BEFORE INSERT ON changes WHEN new.table_name = 'notes' BEGIN
+                SELECT RAISE(ABORT, 'refused');
+            END",
+        )
+        .unwrap();
+    let body = json!({ "owner_id": 1, "contents": "d" }).to_string();
+    let res = send(&base, &alice, "post", "/data/note", &body).await;
+    assert!(!res.status().is_success());
+    let notes = json(get(&base, &alice, "/data/note").await, StatusCode::OK).await;
+    assert_eq!(notes.as_array().unwrap().len(), 3);
+}
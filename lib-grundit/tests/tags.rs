@@ -0,0 +1,157 @@
+// Tags on notes, the note queries over them, and the tag cloud.
+mod common;
+
+use common::{get, json, register, send, start};
+use oauth2::reqwest::StatusCode;
+use serde_json::Value;
+
+async fn create(base: &str, session: &(String, String), path: &str, body: String) -> i64 {
+    let res = send(base, session, "post", path, &body).await;
+    json(res, StatusCode::OK).await["id"].as_i64().unwrap()
+}
+
+fn ids(notes: &Value) -> Vec<i64> {
+    let mut ids: Vec<i64> = notes
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|note| note["id"].as_i64().unwrap())
+        .collect();
+    ids.sort();
+    ids
+}
+
+#[tokio::test]
+async fn tags() {
+    let base = start("tags", vec![]).await;
+    let alice = register(&base, "alice").await;
+    let bob = register(&base, "bob").await;
+
+    let mut notes = vec![];
+    for contents in ["a", "b", "c"] {
+        let body = format!(r#"{{"owner_id":1,"contents":"{}"}}"#, contents);
+        notes.push(create(&base, &alice, "/data/note", body).await);
+    }
+    let work = create(
+        &base,
+        &alice,
+        "/data/tag",
+        r#"{"owner_id":1,"name":"work"}"#.into(),
+    )
+    .await;
+    let home = create(
+        &base,
+        &alice,
+        "/data/tag",
+        r#"{"owner_id":1,"name":"home"}"#.into(),
+    )
+    .await;
+    for (note, tag) in [
+        (notes[0], work),
+        (notes[1], work),
+        (notes[1], home),
+        (notes[2], home),
+    ] {
+        let body = format!(r#"{{"owner_id":1,"note_id":{},"tag_id":{}}}"#, note, tag);
+        create(&base, &alice, "/data/note_tag", body).await;
+    }
+
+    let notes_for =
+        |query: &'static str| async { json(get(&base, &alice, query).await, StatusCode::OK).await };
+    assert_eq!(
+        ids(&notes_for("/data/note?byTag=work").await),
+        vec![notes[0], notes[1]]
+    );
+    assert_eq!(
+        ids(&notes_for("/data/note?byAllTags=work,home").await),
+        vec![notes[1]]
+    );
+    assert_eq!(
+        ids(&notes_for("/data/note?byAnyTags=work,home").await),
+        notes
+    );
+
+    // renaming is picked up by every tagged note
+    let body = format!(r#"{{"id":{},"owner_id":1,"name":"office"}}"#, work);
+    let res = send(&base, &alice, "put", "/data/tag", &body).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(ids(&notes_for("/data/note?byTag=work").await).is_empty());
+    assert_eq!(
+        ids(&notes_for("/data/note?byTag=office").await),
+        vec![notes[0], notes[1]]
+    );
+
+    // names are unique per owner, not across owners
+    let res = send(
+        &base,
+        &alice,
+        "post",
+        "/data/tag",
+        r#"{"owner_id":1,"name":"home"}"#,
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let theirs = create(
+        &base,
+        &bob,
+        "/data/tag",
+        r#"{"owner_id":2,"name":"home"}"#.into(),
+    )
+    .await;
+    assert_eq!(
+        ids(&notes_for("/data/note?byTag=home").await),
+        vec![notes[1], notes[2]]
+    );
+
+    // each note carries a tag once, and only its owner's tags
+    let body = format!(
+        r#"{{"owner_id":1,"note_id":{},"tag_id":{}}}"#,
+        notes[0], work
+    );
+    let res = send(&base, &alice, "post", "/data/note_tag", &body).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = format!(
+        r#"{{"owner_id":1,"note_id":{},"tag_id":{}}}"#,
+        notes[0], theirs
+    );
+    let res = send(&base, &alice, "post", "/data/note_tag", &body).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = format!(
+        r#"{{"owner_id":2,"note_id":{},"tag_id":{}}}"#,
+        notes[0], theirs
+    );
+    let res = send(&base, &bob, "post", "/data/note_tag", &body).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let res = send(&base, &bob, "delete", &format!("/data/tag/{}", work), "").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // nor can they see them
+    assert_eq!(
+        ids(&json(get(&base, &bob, "/data/tag").await, StatusCode::OK).await),
+        vec![theirs]
+    );
+    assert_eq!(
+        json(get(&base, &bob, "/data/note_tag").await, StatusCode::OK).await,
+        Value::Array(vec![])
+    );
+    let res = get(&base, &bob, &format!("/data/tag/{}", work)).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let tagged = json(get(&base, &alice, "/data/note_tag").await, StatusCode::OK).await;
+    let tagged = tagged[0]["id"].as_i64().unwrap();
+    let res = get(&base, &bob, &format!("/data/note_tag/{}", tagged)).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let cloud = json(get(&base, &alice, "/data/tag/cloud").await, StatusCode::OK).await;
+    let cloud: Vec<(&str, i64)> = cloud
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tag| {
+            (
+                tag["name"].as_str().unwrap(),
+                tag["count"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(cloud, vec![("home", 2), ("office", 2)]);
+}
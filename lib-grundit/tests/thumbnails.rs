@@ -0,0 +1,187 @@
+// Thumbnails for image attachments, turned the way the camera says, and the
+// location taken out of uploaded photos.
+mod common;
+
+use std::{env, fs, io::Cursor, process, time::Duration};
+
+use common::{get, json, register, request, send, start};
+use image::{
+    DynamicImage, GenericImageView, ImageDecoder, ImageFormat, ImageReader, RgbImage,
+    codecs::jpeg::JpegEncoder, metadata::Orientation,
+};
+use oauth2::reqwest::{StatusCode, header};
+
+// recognisable bytes for the GPS latitude
+const LATITUDE: [u8; 8] = [0x51, 0x51, 0x51, 0x51, 0x01, 0x00, 0x00, 0x00];
+
+// little endian EXIF with an orientation of 6, turn 90 degrees clockwise, and
+// a GPS latitude
+fn exif() -> Vec<u8> {
+    let mut tiff: Vec<u8> = b"II\x2a\x00\x08\x00\x00\x00".to_vec();
+    // IFD0 at 8: orientation, then the GPS IFD at 38
+    tiff.extend([2, 0]);
+    tiff.extend([0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+    tiff.extend([0x25, 0x88, 4, 0, 1, 0, 0, 0, 38, 0, 0, 0]);
+    tiff.extend([0, 0, 0, 0]);
+    // GPS IFD at 38: latitude ref, then the latitude itself at 68
+    tiff.extend([2, 0]);
+    tiff.extend([1, 0, 2, 0, 2, 0, 0, 0, b'N', 0, 0, 0]);
+    tiff.extend([2, 0, 5, 0, 3, 0, 0, 0, 68, 0, 0, 0]);
+    tiff.extend([0, 0, 0, 0]);
+    for _ in 0..3 {
+        tiff.extend(LATITUDE);
+    }
+
+    tiff
+}
+
+fn app1() -> Vec<u8> {
+    let tiff = exif();
+    let mut app1 = vec![0xFF, 0xE1];
+    app1.extend(((2 + 6 + tiff.len()) as u16).to_be_bytes());
+    app1.extend(b"Exif\0\0");
+    app1.extend(tiff);
+    app1
+}
+
+// a 400x200 photo with `exif()` right after the start of image
+fn photo() -> Vec<u8> {
+    let image = RgbImage::from_fn(400, 200, |x, _| image::Rgb([(x % 256) as u8, 80, 160]));
+    let mut jpeg = vec![];
+    image
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, 90))
+        .unwrap();
+    let mut photo = jpeg[..2].to_vec();
+    photo.extend(app1());
+    photo.extend(&jpeg[2..]);
+    photo
+}
+
+// the same as a PNG, the eXIf chunk after the header with a checksum of
+// zeroes that stripping has to put right
+fn png() -> Vec<u8> {
+    let image = RgbImage::from_fn(40, 20, |x, _| image::Rgb([x as u8, 80, 160]));
+    let mut png = vec![];
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .unwrap();
+    // signature, then the IHDR chunk
+    let header = 8 + 12 + 13;
+    let tiff = exif();
+    let mut chunk = (tiff.len() as u32).to_be_bytes().to_vec();
+    chunk.extend(b"eXIf");
+    chunk.extend(tiff);
+    chunk.extend([0; 4]);
+    png.splice(header..header, chunk);
+    png
+}
+
+async fn upload(base: &str, session: &(String, String), name: &str, contents: &[u8]) -> i64 {
+    let mut body = format!(
+        "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n",
+        name
+    )
+    .into_bytes();
+    body.extend(contents);
+    body.extend(b"\r\n--b--\r\n");
+    let res = request(base, session, "post", "/data/note/1/attachments")
+        .header(header::CONTENT_TYPE, "multipart/form-data; boundary=b")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    json(res, StatusCode::OK).await[0]["id"].as_i64().unwrap()
+}
+
+#[tokio::test]
+async fn thumbnails() {
+    let blobs = env::temp_dir().join(format!("grundit-thumbs-{}", process::id()));
+    let _ = fs::remove_dir_all(&blobs);
+    // the only test in this binary, nothing else reads the environment yet
+    unsafe {
+        env::set_var("BLOB_DIR", &blobs);
+    }
+    let base = start("thumbnails", vec![]).await;
+    let alice = register(&base, "alice").await;
+    let body = r#"{"owner_id":1,"contents":"site visit"}"#;
+    let res = send(&base, &alice, "post", "/data/note", body).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let photo_id = upload(&base, &alice, "site.jpg", &photo()).await;
+    let text_id = upload(&base, &alice, "site.txt", b"no pictures").await;
+
+    // the original keeps its orientation but not where it was taken
+    let res = get(
+        &base,
+        &alice,
+        &format!("/data/attachment/{}/content", photo_id),
+    )
+    .await;
+    let original = res.bytes().await.unwrap();
+    assert!(!original.windows(LATITUDE.len()).any(|w| w == LATITUDE));
+    let mut decoder = ImageReader::new(Cursor::new(&original))
+        .with_guessed_format()
+        .unwrap()
+        .into_decoder()
+        .unwrap();
+    assert_eq!(decoder.orientation().unwrap(), Orientation::Rotate90);
+
+    // a PNG's too, and it still reads
+    let png_id = upload(&base, &alice, "site.png", &png()).await;
+    let res = get(
+        &base,
+        &alice,
+        &format!("/data/attachment/{}/content", png_id),
+    )
+    .await;
+    let original = res.bytes().await.unwrap();
+    assert!(!original.windows(LATITUDE.len()).any(|w| w == LATITUDE));
+    assert_eq!(
+        image::load_from_memory(&original).unwrap().dimensions(),
+        (40, 20)
+    );
+
+    // a segment too short to hold its own length is left as it is
+    let broken = [0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x00];
+    let broken_id = upload(&base, &alice, "broken.jpg", &broken).await;
+    let res = get(
+        &base,
+        &alice,
+        &format!("/data/attachment/{}/content", broken_id),
+    )
+    .await;
+    assert_eq!(res.bytes().await.unwrap(), &broken[..]);
+
+    let path = format!("/data/attachment/{}/thumb/small", photo_id);
+    let mut res = get(&base, &alice, &path).await;
+    for _ in 0..100 {
+        if res.status() != StatusCode::SERVICE_UNAVAILABLE {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        res = get(&base, &alice, &path).await;
+    }
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "image/jpeg");
+    let small = image::load_from_memory(&res.bytes().await.unwrap()).unwrap();
+    assert_eq!(small.dimensions(), (80, 160));
+
+    // smaller than the size asked for, so only turned
+    let path = format!("/data/attachment/{}/thumb/medium", photo_id);
+    let medium: DynamicImage =
+        image::load_from_memory(&get(&base, &alice, &path).await.bytes().await.unwrap()).unwrap();
+    assert_eq!(medium.dimensions(), (200, 400));
+
+    let path = format!("/data/attachment/{}/thumb/huge", photo_id);
+    assert_eq!(
+        get(&base, &alice, &path).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+    let path = format!("/data/attachment/{}/thumb/small", text_id);
+    assert_eq!(
+        get(&base, &alice, &path).await.status(),
+        StatusCode::NOT_FOUND
+    );
+
+    let _ = fs::remove_dir_all(&blobs);
+}
@@ -0,0 +1,155 @@
+// Pairing clock punches into daily timesheets.
+mod common;
+
+use common::{client, register, start};
+use lib_grundit::{
+    timesheet::{Interval, days},
+    types::{Direction, Punch},
+};
+use oauth2::reqwest::{StatusCode, header};
+use time::{Date, Month, UtcOffset};
+
+fn punch(id: i64, owner_id: i64, direction: Direction, captured_at: i64) -> Punch {
+    Punch {
+        id,
+        owner_id,
+        direction,
+        latitude: 0.0,
+        longitude: 0.0,
+        accuracy: None,
+        altitude: None,
+        captured_at,
+        received_at: captured_at,
+        geofence_id: None,
+        flagged: false,
+    }
+}
+
+const HOUR: i64 = 60 * 60;
+
+// unix time on a day in October 2026, UTC
+fn october(day: u8, hour: u8) -> i64 {
+    Date::from_calendar_date(2026, Month::October, day)
+        .unwrap()
+        .with_hms(hour, 0, 0)
+        .unwrap()
+        .assume_utc()
+        .unix_timestamp()
+}
+
+#[test]
+fn breaks_split_the_day() {
+    let nine = october(5, 9);
+    let punches = vec![
+        punch(4, 1, Direction::Out, nine + 8 * HOUR),
+        punch(1, 1, Direction::In, nine),
+        punch(2, 1, Direction::Break, nine + 3 * HOUR),
+        punch(3, 1, Direction::In, nine + 4 * HOUR),
+    ];
+    let days = days(&punches, UtcOffset::UTC);
+    assert_eq!(days.len(), 1);
+    assert_eq!(days[0].date, "2026-10-05");
+    assert_eq!(days[0].worked_seconds, 7 * HOUR);
+    assert_eq!(
+        days[0].intervals[1],
+        Interval {
+            start_punch: 3,
+            end_punch: 4,
+            start: nine + 4 * HOUR,
+            end: nine + 8 * HOUR,
+            seconds: 4 * HOUR,
+        }
+    );
+    assert!(days[0].unmatched.is_empty());
+}
+
+#[test]
+fn night_shifts_stay_on_the_day_they_started() {
+    let ten_pm = october(5, 22);
+    let punches = vec![
+        punch(1, 1, Direction::In, ten_pm),
+        punch(2, 1, Direction::Out, ten_pm + 8 * HOUR),
+    ];
+    let utc = days(&punches, UtcOffset::UTC);
+    assert_eq!(utc.len(), 1);
+    assert_eq!(utc[0].date, "2026-10-05");
+    assert_eq!(utc[0].worked_seconds, 8 * HOUR);
+
+    // two hours ahead the shift starts at midnight
+    let ahead = days(&punches, UtcOffset::from_hms(2, 0, 0).unwrap());
+    assert_eq!(ahead[0].date, "2026-10-06");
+}
+
+#[test]
+fn unmatched_punches_are_flagged() {
+    let nine = october(5, 9);
+    let punches = vec![
+        // out with nothing open
+        punch(1, 1, Direction::Out, nine),
+        // forgot to clock out, then clocked in again
+        punch(2, 1, Direction::In, nine + HOUR),
+        punch(3, 1, Direction::In, nine + 2 * HOUR),
+        punch(4, 1, Direction::Out, nine + 3 * HOUR),
+        // another user, still clocked in
+        punch(5, 2, Direction::In, nine),
+    ];
+    let days = days(&punches, UtcOffset::UTC);
+    assert_eq!(days.len(), 2);
+    assert_eq!(days[0].unmatched, vec![1, 2]);
+    assert_eq!(days[0].worked_seconds, HOUR);
+    assert_eq!(days[1].owner_id, 2);
+    assert_eq!(days[1].unmatched, vec![5]);
+}
+
+#[tokio::test]
+async fn csv_export_and_other_users() {
+    let base = start("timesheet", vec![]).await;
+    let (cookies, csrf_token) = register(&base, "alice").await;
+    let nine = october(5, 9);
+    for (direction, time) in [("in", nine), ("out", nine + 8 * HOUR)] {
+        let res = client()
+            .post(format!("{}/data/punch", base))
+            .header(header::COOKIE, &cookies)
+            .header("X-CSRF-Token", &csrf_token)
+            .body(format!(
+                r#"{{"owner_id":1,"direction":"{}","latitude":0,"longitude":0,"captured_at":{}}}"#,
+                direction, time
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    let res = client()
+        .get(format!(
+            "{}/data/timesheet?from=2026-10-01&to=2026-10-31&format=csv",
+            base
+        ))
+        .header(header::COOKIE, &cookies)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "text/csv");
+    assert_eq!(
+        res.text().await.unwrap(),
+        "owner_id,date,worked_seconds,worked_hours,intervals,unmatched\n\
+         1,2026-10-05,28800,8.00,1,\n"
+    );
+
+    // someone else's timesheet needs the admin role
+    let (mallory, _) = register(&base, "mallory").await;
+    for query in ["owner_id=1", "all=true"] {
+        let res = client()
+            .get(format!(
+                "{}/data/timesheet?from=2026-10-01&to=2026-10-31&{}",
+                base, query
+            ))
+            .header(header::COOKIE, &mallory)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,96 @@
+// Field rules and the body size limit on the data routes.
+mod common;
+
+use common::{register, send, start};
+use oauth2::reqwest::{Response, StatusCode};
+use serde_json::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+async fn errors(res: Response) -> Vec<(String, String)> {
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| {
+            (
+                e["field"].as_str().unwrap().to_string(),
+                e["rule"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn violations_are_reported_together() {
+    let base = start("validation-fields", vec![]).await;
+    let session = register(&base, "alice").await;
+
+    let body = r#"{"id":1,"email":"not an email","picture":"javascript:alert(1)"}"#;
+    let res = send(&base, &session, "put", "/data/user", body).await;
+    assert_eq!(
+        errors(res).await,
+        vec![
+            ("email".to_string(), "email".to_string()),
+            ("picture".to_string(), "url".to_string()),
+        ]
+    );
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let body = format!(
+        r#"{{"owner_id":1,"direction":"in","latitude":91,"longitude":-181,"captured_at":{}}}"#,
+        now + 24 * 60 * 60
+    );
+    let res = send(&base, &session, "post", "/data/punch", &body).await;
+    assert_eq!(
+        errors(res).await,
+        vec![
+            ("latitude".to_string(), "range".to_string()),
+            ("longitude".to_string(), "range".to_string()),
+            ("captured_at".to_string(), "before".to_string()),
+        ]
+    );
+
+    let body = format!(
+        r#"{{"owner_id":1,"direction":"in","latitude":51.5,"longitude":-0.12,"accuracy":12.5,"captured_at":{}}}"#,
+        now
+    );
+    let res = send(&base, &session, "post", "/data/punch", &body).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let punch: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(punch["latitude"], 51.5);
+    assert_eq!(punch["altitude"], Value::Null);
+    assert!(punch["received_at"].as_u64().unwrap() >= now);
+
+    // presence checks come back in the same shape
+    let res = send(&base, &session, "post", "/data/note", r#"{"owner_id":1}"#).await;
+    assert_eq!(
+        errors(res).await,
+        vec![("contents".to_string(), "required".to_string())]
+    );
+
+    let body = r#"{"owner_id":1,"contents":"fine"}"#;
+    let res = send(&base, &session, "post", "/data/note", body).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn long_and_oversized_bodies_are_refused() {
+    let base = start("validation-limits", vec![]).await;
+    let session = register(&base, "alice").await;
+
+    let body = format!(r#"{{"owner_id":1,"contents":"{}"}}"#, "a".repeat(10_001));
+    let res = send(&base, &session, "post", "/data/note", &body).await;
+    assert_eq!(
+        errors(res).await,
+        vec![("contents".to_string(), "max_len".to_string())]
+    );
+
+    let body = format!(r#"{{"owner_id":1,"contents":"{}"}}"#, "a".repeat(100_000));
+    let res = send(&base, &session, "post", "/data/note", &body).await;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
@@ -0,0 +1,280 @@
+// Webhook deliveries against a local receiver: filtering, signatures, a
+// retry after a failed attempt, the delivery log, refusing private
+// addresses for anyone but an admin, deleting a webhook, and a slow
+// webhook not holding up the rest.
+mod common;
+
+use std::{
+    env,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    Router,
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode as ReceiverStatus},
+    routing::post,
+};
+use common::{db_path, get, json, register, send, start};
+use hmac::{Hmac, Mac};
+use oauth2::reqwest::StatusCode;
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::{net::TcpListener, sync::Notify};
+
+const SECRET: &str = "a-long-enough-secret";
+
+type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+// holds every request until the test lets it go
+async fn stall(State(release): State<Arc<Notify>>) -> ReceiverStatus {
+    release.notified().await;
+    ReceiverStatus::OK
+}
+
+// fails the first request, to be retried, and takes the rest
+async fn receive(
+    State(received): State<Received>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ReceiverStatus {
+    let mut received = received.lock().unwrap();
+    received.push((headers, String::from_utf8(body.to_vec()).unwrap()));
+    match received.len() {
+        1 => ReceiverStatus::INTERNAL_SERVER_ERROR,
+        _ => ReceiverStatus::OK,
+    }
+}
+
+// the user's deliveries once `done` holds for them
+async fn deliveries(
+    base: &str,
+    session: &(String, String),
+    done: impl Fn(&[Value]) -> bool,
+) -> Vec<Value> {
+    for _ in 0..100 {
+        let list = json(
+            get(base, session, "/data/webhook_delivery").await,
+            StatusCode::OK,
+        )
+        .await;
+        let list = list.as_array().unwrap().clone();
+        if done(&list) {
+            return list;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("deliveries never settled");
+}
+
+#[tokio::test]
+async fn webhooks() {
+    // the only test in this binary, so nothing else reads the environment
+    unsafe {
+        env::set_var("WEBHOOK_RETRY_BASE_SECS", "1");
+    }
+    let received = Received::default();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let hook_url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let release = Arc::new(Notify::new());
+    let slow_url = hook_url.replace("/hook", "/slow");
+    let app = Router::new()
+        .route("/hook", post(receive))
+        .with_state(received.clone())
+        .merge(
+            Router::new()
+                .route("/slow", post(stall))
+                .with_state(release.clone()),
+        );
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let base = start("webhooks", vec![]).await;
+    sqlite::open(db_path("webhooks"))
+        .unwrap()
+        .execute("INSERT INTO roles (user_id, role) VALUES (1, 'admin')")
+        .unwrap();
+    let admin = register(&base, "admin").await;
+    let bob = register(&base, "bob").await;
+
+    // webhooks can't watch webhooks
+    let body = format!(
+        r#"{{"owner_id":1,"url":"{}","secret":"{}","data_types":"webhook"}}"#,
+        hook_url, SECRET
+    );
+    let res = send(&base, &admin, "post", "/data/webhook", &body).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let body = format!(
+        r#"{{"owner_id":1,"url":"{}","secret":"{}","data_types":"comment","operations":"created"}}"#,
+        hook_url, SECRET
+    );
+    let hook = json(
+        send(&base, &admin, "post", "/data/webhook", &body).await,
+        StatusCode::OK,
+    )
+    .await;
+    assert!(hook.get("secret").is_none());
+    assert_eq!(hook["active"], true);
+
+    // only comments are wanted
+    let body = r#"{"owner_id":1,"contents":"a"}"#;
+    json(
+        send(&base, &admin, "post", "/data/note", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let body = r#"{"owner_id":1,"note_id":1,"contents":"hello"}"#;
+    let comment = json(
+        send(&base, &admin, "post", "/data/comment", body).await,
+        StatusCode::OK,
+    )
+    .await;
+
+    let log = deliveries(&base, &admin, |list| {
+        list.iter().any(|d| d["status"] == "delivered")
+    })
+    .await;
+    assert_eq!(log.len(), 1);
+    let delivery = &log[0];
+    assert_eq!(delivery["webhook_id"], hook["id"]);
+    assert_eq!(delivery["event"], "comment.created");
+    assert_eq!(delivery["attempts"], 2);
+    assert_eq!(delivery["response_status"], 200);
+    let by_hook = format!("/data/webhook_delivery?byWebhookId={}", hook["id"]);
+    let listed = json(get(&base, &admin, &by_hook).await, StatusCode::OK).await;
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+
+    let received = received.lock().unwrap().clone();
+    assert_eq!(received.len(), 2);
+    for (headers, body) in &received {
+        assert_eq!(headers["x-grundit-event"], "comment.created");
+        assert_eq!(headers["x-grundit-delivery"], delivery["id"].to_string());
+        let timestamp = headers["x-grundit-timestamp"].to_str().unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(format!("{}.{}", timestamp, body).as_bytes());
+        let signature = headers["x-grundit-signature"].to_str().unwrap();
+        let signature = signature.strip_prefix("sha256=").unwrap();
+        let hex: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(signature, hex);
+        let payload: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["event"], "comment.created");
+        assert_eq!(payload["data"], comment);
+    }
+
+    // nobody else's hooks or deliveries
+    let path = format!("/data/webhook/{}", hook["id"]);
+    let res = get(&base, &bob, &path).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = send(&base, &bob, "delete", &path, "").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let listed = json(
+        get(&base, &bob, "/data/webhook_delivery").await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(listed, serde_json::json!([]));
+
+    // loopback is only for admins
+    let body = format!(
+        r#"{{"owner_id":2,"url":"{}","secret":"{}"}}"#,
+        hook_url, SECRET
+    );
+    json(
+        send(&base, &bob, "post", "/data/webhook", &body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let body = r#"{"owner_id":2,"contents":"b"}"#;
+    json(
+        send(&base, &bob, "post", "/data/note", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let log = deliveries(&base, &bob, |list| {
+        !list.is_empty() && list.iter().all(|d| d["attempts"] != 0)
+    })
+    .await;
+    assert_eq!(log[0]["event"], "note.created");
+    assert_eq!(log[0]["status"], "pending");
+    assert_eq!(log[0]["response_status"], 0);
+    assert!(
+        log[0]["error"]
+            .as_str()
+            .unwrap()
+            .contains("not a public address")
+    );
+    let res = get(&base, &admin, "/data/webhook_delivery").await;
+    assert_eq!(json(res, StatusCode::OK).await.as_array().unwrap().len(), 1);
+
+    // deleting a webhook takes its pending deliveries, trigger or not
+    let db = sqlite::open(db_path("webhooks")).unwrap();
+    db.execute("DROP TRIGGER webhook_deliveries_webhook_delete")
+        .unwrap();
+    let res = send(&base, &bob, "delete", "/data/webhook/2", "").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let listed = json(
+        get(&base, &bob, "/data/webhook_delivery").await,
+        StatusCode::OK,
+    )
+    .await;
+    assert_eq!(listed, serde_json::json!([]));
+
+    // and any left behind fail rather than wait forever
+    db.execute(
+        "INSERT INTO webhook_deliveries (owner_id, webhook_id, seq, event, payload, status, \
+         next_attempt_at, created_at) VALUES (2, 99, 1, 'note.created', '{}', 'pending', 0, 0)",
+    )
+    .unwrap();
+    let body = r#"{"owner_id":2,"contents":"c"}"#;
+    json(
+        send(&base, &bob, "post", "/data/note", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let log = deliveries(&base, &bob, |list| {
+        list.iter().any(|d| d["status"] == "failed")
+    })
+    .await;
+    assert_eq!(log[0]["attempts"], 1);
+    assert_eq!(log[0]["error"], "webhook deleted");
+
+    // a slow webhook doesn't hold up the others
+    for url in [&slow_url, &hook_url] {
+        let body = format!(
+            r#"{{"owner_id":1,"url":"{}","secret":"{}","data_types":"tag"}}"#,
+            url, SECRET
+        );
+        json(
+            send(&base, &admin, "post", "/data/webhook", &body).await,
+            StatusCode::OK,
+        )
+        .await;
+    }
+    let body = r#"{"owner_id":1,"name":"t"}"#;
+    json(
+        send(&base, &admin, "post", "/data/tag", body).await,
+        StatusCode::OK,
+    )
+    .await;
+    let tagged = |d: &Value| d["event"] == "tag.created";
+    let log = deliveries(&base, &admin, |list| {
+        list.iter().any(|d| tagged(d) && d["status"] == "delivered")
+    })
+    .await;
+    let slow = log.iter().find(|d| tagged(d) && d["status"] != "delivered");
+    assert_eq!(slow.unwrap()["attempts"], 0);
+    release.notify_one();
+    deliveries(&base, &admin, |list| {
+        list.iter()
+            .filter(|d| tagged(d))
+            .all(|d| d["status"] == "delivered")
+    })
+    .await;
+}
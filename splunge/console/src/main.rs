@@ -1,7 +1,146 @@
+use futures_util::{SinkExt, StreamExt};
 use gloo_net::http::Request;
-use lib_grundit::types::{Comment, Note, User};
+use gloo_net::websocket::{Message, futures::WebSocket};
+use lib_grundit::types::{
+    Comment, CommentThread, FeedEvent, FeedRequest, Identity, Note, Notification, User, threads,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
 use yew::prelude::*;
 
+// between attempts to reach the feed while the browser thinks it's online
+const RETRY_MS: i32 = 5_000;
+
+// the server's csrf token, sent back on every state changing request
+fn csrf_token() -> String {
+    web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.dyn_into::<web_sys::HtmlDocument>().ok())
+        .and_then(|d| d.cookie().ok())
+        .and_then(|cookies| {
+            cookies
+                .split(';')
+                .find_map(|c| c.trim().strip_prefix("csrf_token="))
+                .map(|token| token.to_string())
+        })
+        .unwrap_or_default()
+}
+
+fn feed_url() -> String {
+    let location = web_sys::window().unwrap().location();
+    let scheme = match location.protocol().as_deref() {
+        Ok("https:") => "wss",
+        _ => "ws",
+    };
+    format!(
+        "{}://{}/data/subscribe",
+        scheme,
+        location.host().unwrap_or_default()
+    )
+}
+
+// keeps `rows` in step with one subscription's events, by id
+fn apply(rows: &mut Vec<Value>, event: FeedEvent) {
+    match event {
+        FeedEvent::Snapshot { data, .. } => *rows = data,
+        FeedEvent::Created { data, .. } | FeedEvent::Updated { data, .. } => {
+            match rows.iter_mut().find(|row| row["id"] == data["id"]) {
+                Some(row) => *row = data,
+                None => rows.push(data),
+            }
+        }
+        FeedEvent::Deleted { id, .. } => rows.retain(|row| row["id"] != id),
+        FeedEvent::Error { message, .. } => log::error!("feed: {}", message),
+    }
+}
+
+// until the browser is back online, or a while if it thinks it is
+async fn reconnect_delay() {
+    let window = web_sys::window().unwrap();
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let waiting = if window.navigator().on_line() {
+            window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, RETRY_MS)
+                .map(|_| ())
+        } else {
+            let once = web_sys::AddEventListenerOptions::new();
+            once.set_once(true);
+            window.add_event_listener_with_callback_and_add_event_listener_options(
+                "online", &resolve, &once,
+            )
+        };
+        if let Err(e) = waiting {
+            log::error!("{:?}", e);
+        }
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+// one socket's worth of the feed, until it closes
+async fn follow(data_type: &str, rows: &UseStateHandle<Vec<Value>>) {
+    let mut socket = match WebSocket::open(&feed_url()) {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::error!("{:?}", e);
+            return;
+        }
+    };
+    let subscribe = FeedRequest::Subscribe {
+        id: data_type.to_string(),
+        data_type: data_type.to_string(),
+        query: Default::default(),
+    };
+    let text = serde_json::to_string(&subscribe).unwrap();
+    if let Err(e) = socket.send(Message::Text(text)).await {
+        log::error!("{:?}", e);
+        return;
+    }
+    let mut current = vec![];
+    while let Some(message) = socket.next().await {
+        match message {
+            Ok(Message::Text(text)) => match serde_json::from_str(&text) {
+                Ok(event) => {
+                    apply(&mut current, event);
+                    rows.set(current.clone());
+                }
+                Err(e) => log::error!("{:?}", e),
+            },
+            Ok(Message::Bytes(_)) => {}
+            Err(e) => {
+                log::error!("{:?}", e);
+                break;
+            }
+        }
+    }
+}
+
+// every `data_type` the user may list, live from /data/subscribe. Each
+// connection starts with a snapshot, so whatever changed while offline is
+// caught up on reconnecting
+#[hook]
+fn use_feed<T>(data_type: &'static str) -> Vec<T>
+where
+    T: DeserializeOwned + 'static,
+{
+    let rows = use_state(Vec::<Value>::new);
+    {
+        let rows = rows.clone();
+        use_effect_with((), move |_| {
+            wasm_bindgen_futures::spawn_local(async move {
+                loop {
+                    follow(data_type, &rows).await;
+                    reconnect_delay().await;
+                }
+            });
+        });
+    }
+    rows.iter()
+        .filter_map(|row| serde_json::from_value(row.clone()).ok())
+        .collect()
+}
+
 #[derive(Properties, PartialEq)]
 struct UserComponentProps {
     users: Vec<User>,
@@ -33,6 +172,44 @@ struct NoteComponentProps {
     notes: Vec<Note>,
 }
 
+#[derive(Properties, PartialEq)]
+struct RenderedNoteProps {
+    id: i64,
+    version: i64,
+}
+
+// the server's sanitized rendering of a note, fetched again when it changes
+#[function_component(RenderedNote)]
+fn rendered_note(RenderedNoteProps { id, version }: &RenderedNoteProps) -> Html {
+    let rendered = use_state(String::new);
+    {
+        let rendered = rendered.clone();
+        use_effect_with((*id, *version), move |(id, _)| {
+            let url = format!("/data/note/{}/rendered", id);
+            wasm_bindgen_futures::spawn_local(async move {
+                match Request::get(&url).send().await {
+                    Ok(data) => match data.text().await {
+                        Ok(html) => {
+                            rendered.set(html);
+                        }
+                        Err(e) => {
+                            log::error!("{:?}", e);
+                        }
+                    },
+                    Err(e) => {
+                        log::error!("{:?}", e);
+                    }
+                }
+            });
+        });
+    }
+    html! {
+        <div class={classes!("note")}>
+            { Html::from_html_unchecked(AttrValue::from((*rendered).clone())) }
+        </div>
+    }
+}
+
 #[function_component(NoteComponent)]
 fn note_component(NoteComponentProps { notes }: &NoteComponentProps) -> Html {
     notes
@@ -42,7 +219,7 @@ fn note_component(NoteComponentProps { notes }: &NoteComponentProps) -> Html {
                 <div style={"display: flex; padding: 5px;"}>
                     <span style={ "display: flex; flex-direction: column; padding-left: 5px;" }>
                         <span>{note.id}</span>
-                        <span>{format!("{}", note.contents)}</span>
+                        <RenderedNote id={note.id} version={note.version}/>
                         <a href={format!("/data/comment?byNoteId={}", note.id)}>{ "comments" }</a>
                     </span>
                 </div>
@@ -56,35 +233,89 @@ struct CommentComponentProps {
     comments: Vec<Comment>,
 }
 
+// a comment and, indented under it, its replies
+fn comment_thread(thread: &CommentThread) -> Html {
+    let comment = &thread.comment;
+    html! {
+        <div style={"display: flex; flex-direction: column; padding: 5px;"}>
+            <span style={ "display: flex; flex-direction: column; padding-left: 5px;" }>
+                <span>{comment.id}</span>
+                <span>{format!("{}", comment.contents)}</span>
+            </span>
+            <div style={"margin-left: 15px; border-left: 1px solid #ccc;"}>
+                { for thread.replies.iter().map(comment_thread) }
+            </div>
+        </div>
+    }
+}
+
 #[function_component(CommentComponent)]
 fn comment_component(CommentComponentProps { comments }: &CommentComponentProps) -> Html {
-    comments
+    html! {
+        <div style={"display: flex; flex-direction: column;"}>
+            { for threads(comments).iter().map(comment_thread) }
+        </div>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+struct IdentityComponentProps {
+    identities: Vec<Identity>,
+}
+
+#[function_component(IdentityComponent)]
+fn identity_component(IdentityComponentProps { identities }: &IdentityComponentProps) -> Html {
+    let linked = identities
         .iter()
-        .map(|comment| {
+        .map(|identity| {
+            let id = identity.id;
+            let unlink = Callback::from(move |_| {
+                wasm_bindgen_futures::spawn_local(async move {
+                    match Request::delete(&format!("/data/identity/{}", id))
+                        .header("X-CSRF-Token", &csrf_token())
+                        .send()
+                        .await
+                    {
+                        Ok(resp) if resp.ok() => {}
+                        Ok(resp) => log::error!("unlink failed: {}", resp.status()),
+                        Err(e) => log::error!("{:?}", e),
+                    }
+                });
+            });
             html! {
                 <div style={"display: flex; padding: 5px;"}>
                     <span style={ "display: flex; flex-direction: column; padding-left: 5px;" }>
-                        <span>{comment.id}</span>
-                        <span>{format!("{}", comment.contents)}</span>
+                        <span>{format!("{}", identity.provider)}</span>
+                        <span>{format!("{}", identity.email)}</span>
+                        <button onclick={unlink}>{ "unlink" }</button>
                     </span>
                 </div>
             }
         })
-        .collect()
+        .collect::<Html>();
+    html! {
+        <div style={"display: flex; flex-direction: column; padding: 5px;"}>
+            {linked}
+            <a href={"/auth/link/google"}>{ "link Google" }</a>
+            <a href={"/auth/link/github"}>{ "link GitHub" }</a>
+            <a href={"/auth/link/oidc"}>{ "link OpenID Connect" }</a>
+        </div>
+    }
 }
 
-#[function_component]
-fn App() -> Html {
-    let users = use_state(|| vec![]);
+// the count of unread notifications, clicking marks them all read
+#[function_component(NotificationBadge)]
+fn notification_badge() -> Html {
+    let unread = use_state(|| 0);
     {
-        let users = users.clone();
+        let unread = unread.clone();
         use_effect_with((), move |_| {
-            let users = users.clone();
+            let unread = unread.clone();
             wasm_bindgen_futures::spawn_local(async move {
-                match Request::get("/data/user").send().await {
-                    Ok(data) => match data.json::<Vec<User>>().await {
+                match Request::get("/data/notification?unread=true").send().await {
+                    Ok(data) => match data.json::<Vec<Notification>>().await {
                         Ok(json) => {
-                            users.set(json);
+                            unread.set(json.len());
                         }
                         Err(e) => {
                             log::error!("{:?}", e);
@@ -97,40 +328,65 @@ fn App() -> Html {
             });
         });
     }
+    let read_all = {
+        let unread = unread.clone();
+        Callback::from(move |_| {
+            let unread = unread.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                match Request::post("/data/notification/read-all")
+                    .header("X-CSRF-Token", &csrf_token())
+                    .send()
+                    .await
+                {
+                    Ok(resp) if resp.ok() => unread.set(0),
+                    Ok(resp) => log::error!("mark read failed: {}", resp.status()),
+                    Err(e) => log::error!("{:?}", e),
+                }
+            });
+        })
+    };
+    html! {
+        <button class={classes!("navLink")} onclick={read_all}>
+            {"Notifications"}
+            if *unread > 0 {
+                <span style={"margin-left: 5px; padding: 0 5px; border-radius: 8px; background: crimson; color: white;"}>
+                    {*unread}
+                </span>
+            }
+        </button>
+    }
+}
 
-    let notes = use_state(|| vec![]);
+#[function_component]
+fn App() -> Html {
+    let users = use_feed::<User>("user");
+    let notes = use_feed::<Note>("note");
+    let comments = use_feed::<Comment>("comment");
+
+    let identities = use_state(|| vec![]);
     {
-        let notes = notes.clone();
+        let identities = identities.clone();
         use_effect_with((), move |_| {
-            let notes = notes.clone();
+            let identities = identities.clone();
             wasm_bindgen_futures::spawn_local(async move {
-                match Request::get("/data/note").send().await {
-                    Ok(data) => match data.json::<Vec<Note>>().await {
-                        Ok(json) => {
-                            notes.set(json);
-                        }
+                let user = match Request::get("/data/whoami").send().await {
+                    Ok(data) => match data.json::<User>().await {
+                        Ok(user) => user,
                         Err(e) => {
                             log::error!("{:?}", e);
+                            return;
                         }
                     },
                     Err(e) => {
                         log::error!("{:?}", e);
+                        return;
                     }
-                }
-            });
-        });
-    }
-
-    let comments = use_state(|| vec![]);
-    {
-        let comments = comments.clone();
-        use_effect_with((), move |_| {
-            let comments = comments.clone();
-            wasm_bindgen_futures::spawn_local(async move {
-                match Request::get("/data/comment").send().await {
-                    Ok(data) => match data.json::<Vec<Comment>>().await {
+                };
+                let url = format!("/data/identity?byOwnerId={}", user.id);
+                match Request::get(&url).send().await {
+                    Ok(data) => match data.json::<Vec<Identity>>().await {
                         Ok(json) => {
-                            comments.set(json);
+                            identities.set(json);
                         }
                         Err(e) => {
                             log::error!("{:?}", e);
@@ -149,13 +405,15 @@ fn App() -> Html {
             <div id={"navBar"} class={classes!("navBar")}>
                 <button class={classes!("navLink", "active")}>{"Profile"}</button>
                 <button class={classes!("navLink")}>{"Explore"}</button>
+                <NotificationBadge/>
             </div>
             <a href={"/auth/google/login"} style={"padding: 5px;"}>{ "Login" }</a>
             <a href={"/auth/logout"} style={"padding: 5px;"}>{ "Logout" }</a>
             <div style={"display: flex; flex-direction: row;"}>
-                <UserComponent users={(*users).clone()}/>
-                <NoteComponent notes={(*notes).clone()}/>
-                <CommentComponent comments={(*comments).clone()}/>
+                <UserComponent users={users}/>
+                <NoteComponent notes={notes}/>
+                <CommentComponent comments={comments}/>
+                <IdentityComponent identities={(*identities).clone()}/>
             </div>
         </div>
     }
@@ -1,4 +1,4 @@
-use lib_grundit::types::{Punch, RequestPunch, User};
+use lib_grundit::types::{Direction, RequestPunch, User};
 use std::{
     cell::RefCell,
     fmt::{Display, Formatter},
@@ -9,6 +9,8 @@ use wasm_bindgen_futures::{JsFuture, spawn_local};
 use web_sys::{HtmlCanvasElement, Position, console};
 use web_sys::{Request, RequestInit, RequestMode, Response};
 
+mod sync;
+
 const CELL_SIZE: u32 = 5;
 const GRID_COLOR: &'static str = "#CCCCCC";
 const DEAD_COLOR: &'static str = "#FFFFFF";
@@ -36,6 +38,19 @@ fn canvas() -> web_sys::HtmlCanvasElement {
         .unwrap()
 }
 
+// the server's csrf token, sent back on every state changing request
+fn csrf_token() -> Option<String> {
+    let cookies = document()
+        .dyn_into::<web_sys::HtmlDocument>()
+        .ok()?
+        .cookie()
+        .ok()?;
+    cookies
+        .split(';')
+        .find_map(|c| c.trim().strip_prefix("csrf_token="))
+        .map(|token| token.to_string())
+}
+
 pub async fn whoami() -> Result<JsValue, JsValue> {
     let opts = RequestInit::new();
     opts.set_method("GET");
@@ -77,55 +92,6 @@ async fn get_user() -> Result<User, ()> {
     }
 }
 
-pub async fn post_punch(punch: RequestPunch) -> Result<JsValue, JsValue> {
-    let body_string = serde_json::to_string(&punch).unwrap();
-    let body = JsValue::from_str(&body_string);
-    let opts = RequestInit::new();
-    opts.set_method("POST");
-    opts.set_mode(RequestMode::Cors);
-    opts.set_body(&body);
-
-    let url = format!("/data/punch");
-
-    let request = Request::new_with_str_and_init(&url, &opts)?;
-
-    request
-        .headers()
-        .set("Accept", "application/vnd.github.v3+json")?;
-
-    let window = web_sys::window().unwrap();
-    let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
-
-    // `resp_value` is a `Response` object.
-    assert!(resp_value.is_instance_of::<Response>());
-    let resp: Response = resp_value.dyn_into().unwrap();
-
-    // Convert this other `Promise` into a rust `Future`.
-    let json = JsFuture::from(resp.json()?).await?;
-
-    // Send the JSON response back to JS.
-    Ok(json)
-}
-
-pub async fn post_punch_1(punch: RequestPunch, mut punches: Vec<Punch>) {
-    let ans = match post_punch(punch).await {
-        Ok(ans) => ans,
-        Err(e) => {
-            console::error_1(&format!("{:?}", e).into());
-            return;
-        }
-    };
-    match serde_wasm_bindgen::from_value(ans) {
-        Ok(punch) => {
-            punches.push(punch);
-        }
-        Err(e) => {
-            console::error_1(&format!("{:?}", e).into());
-        }
-    }
-    console::log_1(&format!("{:?}", punches).into());
-}
-
 #[wasm_bindgen]
 pub async fn run() -> Result<(), JsValue> {
     let f = Rc::new(RefCell::new(None));
@@ -134,26 +100,47 @@ pub async fn run() -> Result<(), JsValue> {
     {
         match get_user().await {
             Ok(user_data) => {
+                if let Err(e) = sync::remember_user(&user_data).await {
+                    console::error_1(&e);
+                }
                 *user.borrow_mut() = Some(user_data);
             }
-            Err(()) => {}
+            // offline, punch as whoever was here last
+            Err(()) => {
+                *user.borrow_mut() = sync::remembered_user().await;
+            }
         }
     }
 
+    sync::sync_soon();
+    let online = Closure::wrap(Box::new(sync::sync_soon) as Box<dyn FnMut()>);
+    window().set_ononline(Some(online.as_ref().unchecked_ref()));
+    online.forget();
+
     let position_callback = Closure::wrap(Box::new(move |position: Position| {
         console::log_1(&format!("{:?}", user).into());
         if let Some(ref user_data) = *user.borrow() {
+            let coords = position.coords();
             let punch = RequestPunch {
                 id: None,
                 owner_id: Some(user_data.id),
-                geo: Some(format!(
-                    "timestamp: {}, lat: {} lon: {}",
-                    position.timestamp(),
-                    position.coords().latitude(),
-                    position.coords().longitude()
-                )),
+                direction: Some(Direction::In),
+                latitude: Some(coords.latitude()),
+                longitude: Some(coords.longitude()),
+                accuracy: Some(coords.accuracy()),
+                altitude: coords.altitude(),
+                // the position timestamp is in milliseconds
+                captured_at: Some((position.timestamp() / 1000.0) as i64),
+                received_at: None,
+                geofence_id: None,
+                flagged: None,
             };
-            spawn_local(post_punch_1(punch, vec![]));
+            spawn_local(async move {
+                if let Err(e) = sync::queue("POST", "punch", &punch).await {
+                    console::error_1(&e);
+                }
+                sync::sync_soon();
+            });
         }
     }) as Box<dyn FnMut(Position)>);
 
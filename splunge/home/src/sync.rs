@@ -0,0 +1,277 @@
+// Punches and notes made while offline, kept in IndexedDB until the server
+// has them.
+//
+// Writes go in the outbox with an idempotency key made when they're queued,
+// so one sent again after its response was lost isn't made twice. A sync
+// sends the outbox in order and then pulls `/data/changes` into a local copy
+// of the punches and notes. It runs on load, after each write and whenever
+// the browser comes back online.
+//
+// A write the server takes or refuses leaves the outbox. One that can't get
+// through, or is answered 401, 403, 408, 429 or 5xx, stops the sync and
+// waits for the next, since a 403 is as likely a stale CSRF token as a real
+// refusal. A note edit refused with a 409 was made to an older version, and
+// the server's copy wins.
+use std::cell::Cell;
+
+use js_sys::{JSON, Promise};
+use lib_grundit::types::{ChangeSet, Punch, SyncChange, User};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{JsFuture, spawn_local};
+use web_sys::{
+    Event, IdbDatabase, IdbObjectStore, IdbObjectStoreParameters, IdbRequest, IdbTransactionMode,
+    Request, RequestInit, RequestMode, Response, console,
+};
+
+use crate::{csrf_token, window};
+
+const DB_NAME: &str = "grundit";
+const DB_VERSION: u32 = 1;
+const OUTBOX: &str = "outbox";
+// where the last pull got to, and who was signed in
+const META: &str = "meta";
+// kept in step with the server, by id
+const LOCAL: [&str; 2] = ["punch", "note"];
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Write {
+    // given by IndexedDB, the order writes are sent in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seq: Option<i64>,
+    method: String,
+    data_type: String,
+    body: String,
+    key: String,
+}
+
+thread_local! {
+    static SYNCING: Cell<bool> = const { Cell::new(false) };
+    // asked for again while syncing
+    static AGAIN: Cell<bool> = const { Cell::new(false) };
+}
+
+// `POST` to create or `PUT` to update `data`, a `RequestPunch` or
+// `RequestNote`, once it can be sent
+pub async fn queue<T: Serialize>(method: &str, data_type: &str, data: &T) -> Result<(), JsValue> {
+    let write = Write {
+        seq: None,
+        method: method.to_string(),
+        data_type: data_type.to_string(),
+        body: serde_json::to_string(data).map_err(|e| e.to_string())?,
+        key: window().crypto()?.random_uuid(),
+    };
+    let db = open().await?;
+    done(&store(&db, OUTBOX)?.add(&serde_wasm_bindgen::to_value(&write)?)?).await?;
+    Ok(())
+}
+
+pub fn sync_soon() {
+    if SYNCING.replace(true) {
+        AGAIN.set(true);
+        return;
+    }
+    spawn_local(async {
+        loop {
+            AGAIN.set(false);
+            if let Err(e) = sync().await {
+                console::log_1(&format!("sync stopped: {:?}", e).into());
+            }
+            if !AGAIN.get() {
+                break;
+            }
+        }
+        SYNCING.set(false);
+    });
+}
+
+async fn sync() -> Result<(), JsValue> {
+    let db = open().await?;
+    send_outbox(&db).await?;
+    pull(&db).await
+}
+
+async fn send_outbox(db: &IdbDatabase) -> Result<(), JsValue> {
+    let writes: Vec<Write> =
+        serde_wasm_bindgen::from_value(done(&store(db, OUTBOX)?.get_all()?).await?)?;
+    for write in writes {
+        let url = format!("/data/{}", write.data_type);
+        let res = fetch(&write.method, &url, Some(&write.body), Some(&write.key)).await?;
+        match res.status() {
+            401 | 403 | 408 | 429 | 500.. => {
+                return Err(format!("{} from the server", res.status()).into());
+            }
+            200..=299 => {
+                let data = JsFuture::from(res.json()?).await?;
+                if write.data_type == "punch"
+                    && serde_wasm_bindgen::from_value::<Punch>(data.clone())
+                        .is_ok_and(|punch| punch.flagged)
+                {
+                    console::warn_1(&"punched in outside of your geofences".into());
+                }
+                keep(db, &write.data_type, &data).await?;
+            }
+            409 => {
+                console::warn_1(
+                    &format!(
+                        "{} changed since this edit was made, keeping the server's",
+                        write.data_type
+                    )
+                    .into(),
+                );
+                keep(db, &write.data_type, &JsFuture::from(res.json()?).await?).await?;
+            }
+            status => {
+                console::error_1(&format!("{} {} refused: {}", write.method, url, status).into());
+            }
+        }
+        let seq = write.seq.unwrap_or_default() as f64;
+        done(&store(db, OUTBOX)?.delete(&seq.into())?).await?;
+    }
+    Ok(())
+}
+
+async fn pull(db: &IdbDatabase) -> Result<(), JsValue> {
+    let since = done(&store(db, META)?.get(&"since".into())?).await?;
+    let mut since = since.as_f64().unwrap_or_default() as i64;
+    loop {
+        let url = format!("/data/changes?since={}&types={}", since, LOCAL.join(","));
+        let res = fetch("GET", &url, None, None).await?;
+        if !res.ok() {
+            return Err(format!("{} from the server", res.status()).into());
+        }
+        let set: ChangeSet = serde_wasm_bindgen::from_value(JsFuture::from(res.json()?).await?)?;
+        // too far behind for the log, everything comes again
+        if set.reset {
+            for data_type in LOCAL {
+                done(&store(db, data_type)?.clear()?).await?;
+            }
+        }
+        for change in set.changes {
+            match change {
+                SyncChange::Created {
+                    data_type, data, ..
+                }
+                | SyncChange::Updated {
+                    data_type, data, ..
+                } => keep(db, &data_type, &JSON::parse(&data.to_string())?).await?,
+                SyncChange::Deleted { data_type, id, .. } => {
+                    if LOCAL.contains(&data_type.as_str()) {
+                        done(&store(db, &data_type)?.delete(&(id as f64).into())?).await?;
+                    }
+                }
+            }
+        }
+        since = set.next;
+        let next = (since as f64).into();
+        done(&store(db, META)?.put_with_key(&next, &"since".into())?).await?;
+        if !set.more {
+            return Ok(());
+        }
+    }
+}
+
+// a row from the server into the local copy
+async fn keep(db: &IdbDatabase, data_type: &str, data: &JsValue) -> Result<(), JsValue> {
+    if LOCAL.contains(&data_type) {
+        done(&store(db, data_type)?.put(data)?).await?;
+    }
+    Ok(())
+}
+
+// so punches can be made offline by whoever was last signed in
+pub async fn remember_user(user: &User) -> Result<(), JsValue> {
+    let json = serde_json::to_string(user).map_err(|e| e.to_string())?;
+    let db = open().await?;
+    done(&store(&db, META)?.put_with_key(&json.into(), &"user".into())?).await?;
+    Ok(())
+}
+
+pub async fn remembered_user() -> Option<User> {
+    let db = open().await.ok()?;
+    let json = done(&store(&db, META).ok()?.get(&"user".into()).ok()?)
+        .await
+        .ok()?;
+    serde_json::from_str(&json.as_string()?).ok()
+}
+
+async fn fetch(
+    method: &str,
+    url: &str,
+    body: Option<&str>,
+    key: Option<&str>,
+) -> Result<Response, JsValue> {
+    let opts = RequestInit::new();
+    opts.set_method(method);
+    opts.set_mode(RequestMode::Cors);
+    if let Some(body) = body {
+        opts.set_body(&JsValue::from_str(body));
+    }
+    let request = Request::new_with_str_and_init(url, &opts)?;
+    request.headers().set("Accept", "application/json")?;
+    if let Some(token) = csrf_token() {
+        request.headers().set("X-CSRF-Token", &token)?;
+    }
+    if let Some(key) = key {
+        request.headers().set("Idempotency-Key", key)?;
+    }
+    JsFuture::from(window().fetch_with_request(&request))
+        .await?
+        .dyn_into()
+}
+
+async fn open() -> Result<IdbDatabase, JsValue> {
+    let factory = window().indexed_db()?.ok_or("no IndexedDB")?;
+    let request = factory.open_with_u32(DB_NAME, DB_VERSION)?;
+    let upgrading = request.clone();
+    let on_upgrade = Closure::once_into_js(move |_: Event| {
+        if let Err(e) = upgrading
+            .result()
+            .and_then(|db| create_stores(&db.unchecked_into()))
+        {
+            console::error_1(&e);
+        }
+    });
+    request.set_onupgradeneeded(Some(on_upgrade.unchecked_ref()));
+    Ok(done(&request).await?.unchecked_into())
+}
+
+fn create_stores(db: &IdbDatabase) -> Result<(), JsValue> {
+    let outbox = IdbObjectStoreParameters::new();
+    outbox.set_key_path(&"seq".into());
+    outbox.set_auto_increment(true);
+    db.create_object_store_with_optional_parameters(OUTBOX, &outbox)?;
+    db.create_object_store(META)?;
+    for name in LOCAL {
+        let by_id = IdbObjectStoreParameters::new();
+        by_id.set_key_path(&"id".into());
+        db.create_object_store_with_optional_parameters(name, &by_id)?;
+    }
+    Ok(())
+}
+
+// a transaction to itself for each request, as one ends once nothing is
+// waiting on it
+fn store(db: &IdbDatabase, name: &str) -> Result<IdbObjectStore, JsValue> {
+    db.transaction_with_str_and_mode(name, IdbTransactionMode::Readwrite)?
+        .object_store(name)
+}
+
+// the request's result once it has one
+async fn done(request: &IdbRequest) -> Result<JsValue, JsValue> {
+    let promise = Promise::new(&mut |resolve, reject| {
+        let succeeded = request.clone();
+        let on_success = Closure::once_into_js(move |_: Event| {
+            let result = succeeded.result().unwrap_or(JsValue::UNDEFINED);
+            let _ = resolve.call1(&JsValue::NULL, &result);
+        });
+        let failed = request.clone();
+        let on_error = Closure::once_into_js(move |_: Event| {
+            let error = failed.error().ok().flatten().map(JsValue::from);
+            let _ = reject.call1(&JsValue::NULL, &error.unwrap_or(JsValue::UNDEFINED));
+        });
+        request.set_onsuccess(Some(on_success.unchecked_ref()));
+        request.set_onerror(Some(on_error.unchecked_ref()));
+    });
+    JsFuture::from(promise).await
+}